@@ -3,24 +3,27 @@
 // This source code is licensed under the MIT license found in the
 // LICENSE file in the root directory of this source tree.
 
+//! Registration and login benchmarks, run once per ciphersuite and slow hash.
+//! Each ciphersuite is a criterion group whose throughput is reported in
+//! protocol steps per second, so that regressions show up per step.
+
 #[macro_use]
 extern crate criterion;
 
-use criterion::Criterion;
+use criterion::{measurement::WallTime, BenchmarkGroup, Criterion, Throughput};
 use opaque_ke::*;
 use rand::rngs::OsRng;
 
-#[cfg(all(not(feature = "p256"), feature = "u64_backend"))]
-static SUFFIX: &str = "u64_backend";
-#[cfg(all(not(feature = "p256"), feature = "u32_backend"))]
-static SUFFIX: &str = "u32_backend";
-#[cfg(feature = "p256")]
-static SUFFIX: &str = "p256";
+#[cfg(feature = "u64_backend")]
+static BACKEND: &str = "u64_backend";
+#[cfg(all(not(feature = "u64_backend"), feature = "u32_backend"))]
+static BACKEND: &str = "u32_backend";
+#[cfg(not(any(feature = "u64_backend", feature = "u32_backend")))]
+static BACKEND: &str = "fiat_backend";
 
-struct Default;
+struct Ristretto255Sha512;
 
-#[cfg(not(feature = "p256"))]
-impl CipherSuite for Default {
+impl CipherSuite for Ristretto255Sha512 {
     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
@@ -28,102 +31,147 @@ impl CipherSuite for Default {
     type SlowHash = opaque_ke::slow_hash::NoOpHash;
 }
 
-#[cfg(feature = "p256")]
-impl CipherSuite for Default {
-    type OprfGroup = p256_::ProjectivePoint;
-    type KeGroup = p256_::ProjectivePoint;
+struct X25519Sha512;
+
+impl CipherSuite for X25519Sha512 {
+    type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+    type KeGroup = curve25519_dalek::montgomery::MontgomeryPoint;
     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
-    type Hash = sha2::Sha256;
+    type Hash = sha2::Sha512;
     type SlowHash = opaque_ke::slow_hash::NoOpHash;
 }
 
-fn server_setup(c: &mut Criterion) {
+#[cfg(feature = "slow-hash")]
+struct Ristretto255Sha512Argon2;
+
+#[cfg(feature = "slow-hash")]
+impl CipherSuite for Ristretto255Sha512Argon2 {
+    type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+    type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+    type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+    type Hash = sha2::Sha512;
+    type SlowHash = argon2::Argon2<'static>;
+}
+
+fn ciphersuites(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("ristretto255-sha512 ({})", BACKEND));
+    bench_ciphersuite::<Ristretto255Sha512>(&mut group);
+    group.finish();
+
+    let mut group = c.benchmark_group(format!("x25519-sha512 ({})", BACKEND));
+    bench_ciphersuite::<X25519Sha512>(&mut group);
+    group.finish();
+
+    #[cfg(feature = "p256")]
+    {
+        let mut group = c.benchmark_group("p256-sha256");
+        bench_ciphersuite::<opaque_ke::ciphersuite::P256Sha256>(&mut group);
+        group.finish();
+    }
+
+    #[cfg(feature = "slow-hash")]
+    {
+        // Argon2 dominates every step which runs it, so fewer samples suffice
+        let mut group = c.benchmark_group(format!("ristretto255-sha512-argon2 ({})", BACKEND));
+        group.sample_size(10);
+        bench_ciphersuite::<Ristretto255Sha512Argon2>(&mut group);
+        group.finish();
+    }
+}
+
+fn bench_ciphersuite<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
+    group.throughput(Throughput::Elements(1));
+
+    server_setup::<CS>(group);
+    client_registration_start::<CS>(group);
+    server_registration_start::<CS>(group);
+    client_registration_finish::<CS>(group);
+    server_registration_finish::<CS>(group);
+    client_login_start::<CS>(group);
+    server_login_start_real::<CS>(group);
+    server_login_start_fake::<CS>(group);
+    client_login_finish::<CS>(group);
+    server_login_finish::<CS>(group);
+}
+
+fn server_setup<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
     let mut rng = OsRng;
 
-    c.bench_function(&format!("server setup ({})", SUFFIX), move |b| {
+    group.bench_function("server setup", move |b| {
         b.iter(|| {
-            ServerSetup::<Default>::new(&mut rng).unwrap();
+            ServerSetup::<CS>::new(&mut rng).unwrap();
         })
     });
 }
 
-fn client_registration_start(c: &mut Criterion) {
+fn client_registration_start<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
     let mut rng = OsRng;
     let password = b"password";
 
-    c.bench_function(
-        &format!("client registration start ({})", SUFFIX),
-        move |b| {
-            b.iter(|| {
-                ClientRegistration::<Default>::start(&mut rng, &password[..]).unwrap();
-            })
-        },
-    );
+    group.bench_function("client registration start", move |b| {
+        b.iter(|| {
+            ClientRegistration::<CS>::start(&mut rng, &password[..]).unwrap();
+        })
+    });
 }
 
-fn server_registration_start(c: &mut Criterion) {
+fn server_registration_start<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
     let mut rng = OsRng;
     let username = b"username";
     let password = b"password";
-    let server_setup = ServerSetup::<Default>::new(&mut rng).unwrap();
+    let server_setup = ServerSetup::<CS>::new(&mut rng).unwrap();
     let client_registration_start_result =
-        ClientRegistration::<Default>::start(&mut rng, &password[..]).unwrap();
-
-    c.bench_function(
-        &format!("server registration start ({})", SUFFIX),
-        move |b| {
-            b.iter(|| {
-                ServerRegistration::<Default>::start(
-                    &server_setup,
-                    client_registration_start_result.message.clone(),
-                    &username[..],
-                )
-                .unwrap();
-            })
-        },
-    );
+        ClientRegistration::<CS>::start(&mut rng, &password[..]).unwrap();
+
+    group.bench_function("server registration start", move |b| {
+        b.iter(|| {
+            ServerRegistration::<CS>::start(
+                &server_setup,
+                client_registration_start_result.message.clone(),
+                &username[..],
+            )
+            .unwrap();
+        })
+    });
 }
 
-fn client_registration_finish(c: &mut Criterion) {
+fn client_registration_finish<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
     let mut rng = OsRng;
     let username = b"username";
     let password = b"password";
-    let server_setup = ServerSetup::<Default>::new(&mut rng).unwrap();
+    let server_setup = ServerSetup::<CS>::new(&mut rng).unwrap();
     let client_registration_start_result =
-        ClientRegistration::<Default>::start(&mut rng, &password[..]).unwrap();
-    let server_registration_start_result = ServerRegistration::<Default>::start(
+        ClientRegistration::<CS>::start(&mut rng, &password[..]).unwrap();
+    let server_registration_start_result = ServerRegistration::<CS>::start(
         &server_setup,
         client_registration_start_result.message.clone(),
         &username[..],
     )
     .unwrap();
 
-    c.bench_function(
-        &format!("client registration finish ({})", SUFFIX),
-        move |b| {
-            b.iter(|| {
-                client_registration_start_result
-                    .clone()
-                    .state
-                    .finish(
-                        &mut rng,
-                        server_registration_start_result.message.clone(),
-                        ClientRegistrationFinishParameters::default(),
-                    )
-                    .unwrap();
-            })
-        },
-    );
+    group.bench_function("client registration finish", move |b| {
+        b.iter(|| {
+            client_registration_start_result
+                .clone()
+                .state
+                .finish(
+                    &mut rng,
+                    server_registration_start_result.message.clone(),
+                    ClientRegistrationFinishParameters::default(),
+                )
+                .unwrap();
+        })
+    });
 }
 
-fn server_registration_finish(c: &mut Criterion) {
+fn server_registration_finish<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
     let mut rng = OsRng;
     let username = b"username";
     let password = b"password";
-    let server_setup = ServerSetup::<Default>::new(&mut rng).unwrap();
+    let server_setup = ServerSetup::<CS>::new(&mut rng).unwrap();
     let client_registration_start_result =
-        ClientRegistration::<Default>::start(&mut rng, &password[..]).unwrap();
-    let server_registration_start_result = ServerRegistration::<Default>::start(
+        ClientRegistration::<CS>::start(&mut rng, &password[..]).unwrap();
+    let server_registration_start_result = ServerRegistration::<CS>::start(
         &server_setup,
         client_registration_start_result.message.clone(),
         &username[..],
@@ -139,35 +187,32 @@ fn server_registration_finish(c: &mut Criterion) {
         )
         .unwrap();
 
-    c.bench_function(
-        &format!("server registration finish ({})", SUFFIX),
-        move |b| {
-            b.iter(|| {
-                ServerRegistration::finish(client_registration_finish_result.clone().message);
-            })
-        },
-    );
+    group.bench_function("server registration finish", move |b| {
+        b.iter(|| {
+            ServerRegistration::finish(client_registration_finish_result.message.clone());
+        })
+    });
 }
 
-fn client_login_start(c: &mut Criterion) {
+fn client_login_start<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
     let mut rng = OsRng;
     let password = b"password";
 
-    c.bench_function(&format!("client login start ({})", SUFFIX), move |b| {
+    group.bench_function("client login start", move |b| {
         b.iter(|| {
-            ClientLogin::<Default>::start(&mut rng, &password[..]).unwrap();
+            ClientLogin::<CS>::start(&mut rng, &password[..]).unwrap();
         })
     });
 }
 
-fn server_login_start_real(c: &mut Criterion) {
+fn server_login_start_real<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
     let mut rng = OsRng;
     let username = b"username";
     let password = b"password";
-    let server_setup = ServerSetup::<Default>::new(&mut rng).unwrap();
+    let server_setup = ServerSetup::<CS>::new(&mut rng).unwrap();
     let client_registration_start_result =
-        ClientRegistration::<Default>::start(&mut rng, &password[..]).unwrap();
-    let server_registration_start_result = ServerRegistration::<Default>::start(
+        ClientRegistration::<CS>::start(&mut rng, &password[..]).unwrap();
+    let server_registration_start_result = ServerRegistration::<CS>::start(
         &server_setup,
         client_registration_start_result.message.clone(),
         &username[..],
@@ -183,59 +228,53 @@ fn server_login_start_real(c: &mut Criterion) {
         )
         .unwrap();
     let password_file = ServerRegistration::finish(client_registration_finish_result.message);
-    let client_login_start_result = ClientLogin::<Default>::start(&mut rng, &password[..]).unwrap();
+    let client_login_start_result = ClientLogin::<CS>::start(&mut rng, &password[..]).unwrap();
 
-    c.bench_function(
-        &format!("server login start (real) ({})", SUFFIX),
-        move |b| {
-            b.iter(|| {
-                ServerLogin::start(
-                    &mut rng,
-                    &server_setup,
-                    Some(password_file.clone()),
-                    client_login_start_result.clone().message,
-                    &username[..],
-                    ServerLoginStartParameters::default(),
-                )
-                .unwrap();
-            })
-        },
-    );
+    group.bench_function("server login start (real)", move |b| {
+        b.iter(|| {
+            ServerLogin::start(
+                &mut rng,
+                &server_setup,
+                Some(password_file.clone()),
+                client_login_start_result.clone().message,
+                &username[..],
+                ServerLoginStartParameters::default(),
+            )
+            .unwrap();
+        })
+    });
 }
 
-fn server_login_start_fake(c: &mut Criterion) {
+fn server_login_start_fake<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
     let mut rng = OsRng;
     let username = b"username";
     let password = b"password";
-    let server_setup = ServerSetup::<Default>::new(&mut rng).unwrap();
-    let client_login_start_result = ClientLogin::<Default>::start(&mut rng, &password[..]).unwrap();
-
-    c.bench_function(
-        &format!("server login start (fake) ({})", SUFFIX),
-        move |b| {
-            b.iter(|| {
-                ServerLogin::start(
-                    &mut rng,
-                    &server_setup,
-                    None,
-                    client_login_start_result.clone().message,
-                    &username[..],
-                    ServerLoginStartParameters::default(),
-                )
-                .unwrap();
-            })
-        },
-    );
+    let server_setup = ServerSetup::<CS>::new(&mut rng).unwrap();
+    let client_login_start_result = ClientLogin::<CS>::start(&mut rng, &password[..]).unwrap();
+
+    group.bench_function("server login start (fake)", move |b| {
+        b.iter(|| {
+            ServerLogin::start(
+                &mut rng,
+                &server_setup,
+                None,
+                client_login_start_result.clone().message,
+                &username[..],
+                ServerLoginStartParameters::default(),
+            )
+            .unwrap();
+        })
+    });
 }
 
-fn client_login_finish(c: &mut Criterion) {
+fn client_login_finish<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
     let mut rng = OsRng;
     let username = b"username";
     let password = b"password";
-    let server_setup = ServerSetup::<Default>::new(&mut rng).unwrap();
+    let server_setup = ServerSetup::<CS>::new(&mut rng).unwrap();
     let client_registration_start_result =
-        ClientRegistration::<Default>::start(&mut rng, &password[..]).unwrap();
-    let server_registration_start_result = ServerRegistration::<Default>::start(
+        ClientRegistration::<CS>::start(&mut rng, &password[..]).unwrap();
+    let server_registration_start_result = ServerRegistration::<CS>::start(
         &server_setup,
         client_registration_start_result.message.clone(),
         &username[..],
@@ -251,7 +290,7 @@ fn client_login_finish(c: &mut Criterion) {
         )
         .unwrap();
     let password_file = ServerRegistration::finish(client_registration_finish_result.message);
-    let client_login_start_result = ClientLogin::<Default>::start(&mut rng, &password[..]).unwrap();
+    let client_login_start_result = ClientLogin::<CS>::start(&mut rng, &password[..]).unwrap();
     let server_login_start = ServerLogin::start(
         &mut rng,
         &server_setup,
@@ -262,7 +301,7 @@ fn client_login_finish(c: &mut Criterion) {
     )
     .unwrap();
 
-    c.bench_function(&format!("client login finish ({})", SUFFIX), move |b| {
+    group.bench_function("client login finish", move |b| {
         b.iter(|| {
             client_login_start_result
                 .clone()
@@ -276,14 +315,14 @@ fn client_login_finish(c: &mut Criterion) {
     });
 }
 
-fn server_login_finish(c: &mut Criterion) {
+fn server_login_finish<CS: CipherSuite>(group: &mut BenchmarkGroup<'_, WallTime>) {
     let mut rng = OsRng;
     let username = b"username";
     let password = b"password";
-    let server_setup = ServerSetup::<Default>::new(&mut rng).unwrap();
+    let server_setup = ServerSetup::<CS>::new(&mut rng).unwrap();
     let client_registration_start_result =
-        ClientRegistration::<Default>::start(&mut rng, &password[..]).unwrap();
-    let server_registration_start_result = ServerRegistration::<Default>::start(
+        ClientRegistration::<CS>::start(&mut rng, &password[..]).unwrap();
+    let server_registration_start_result = ServerRegistration::<CS>::start(
         &server_setup,
         client_registration_start_result.message.clone(),
         &username[..],
@@ -299,7 +338,7 @@ fn server_login_finish(c: &mut Criterion) {
         )
         .unwrap();
     let password_file = ServerRegistration::finish(client_registration_finish_result.message);
-    let client_login_start_result = ClientLogin::<Default>::start(&mut rng, &password[..]).unwrap();
+    let client_login_start_result = ClientLogin::<CS>::start(&mut rng, &password[..]).unwrap();
     let server_login_start_result = ServerLogin::start(
         &mut rng,
         &server_setup,
@@ -318,28 +357,16 @@ fn server_login_finish(c: &mut Criterion) {
         )
         .unwrap();
 
-    c.bench_function(&format!("server login finish ({})", SUFFIX), move |b| {
+    group.bench_function("server login finish", move |b| {
         b.iter(|| {
             server_login_start_result
                 .clone()
                 .state
-                .finish(client_login_finish_result.clone().message)
+                .finish(client_login_finish_result.message.clone())
                 .unwrap();
         })
     });
 }
 
-criterion_group!(
-    opaque_benches,
-    server_setup,
-    client_registration_start,
-    server_registration_start,
-    client_registration_finish,
-    server_registration_finish,
-    client_login_start,
-    server_login_start_real,
-    server_login_start_fake,
-    client_login_finish,
-    server_login_finish,
-);
+criterion_group!(opaque_benches, ciphersuites);
 criterion_main!(opaque_benches);
@@ -114,7 +114,11 @@ fn register_locker(
         .finish(
             &mut client_rng,
             RegistrationResponse::deserialize(&registration_response_bytes[..]).unwrap(),
-            ClientRegistrationFinishParameters::default(),
+            // The locker is encrypted under the export key, so request it
+            ClientRegistrationFinishParameters {
+                export_key: true,
+                ..ClientRegistrationFinishParameters::default()
+            },
         )
         .unwrap();
     let message_bytes = client_finish_registration_result
@@ -124,7 +128,11 @@ fn register_locker(
 
     // Client encrypts secret message using export key
     let ciphertext = encrypt(
-        &client_finish_registration_result.export_key,
+        client_finish_registration_result
+            .export_key
+            .as_ref()
+            .unwrap()
+            .expose_secret(),
         secret_message.as_bytes(),
     );
 
@@ -172,7 +180,10 @@ fn open_locker(
 
     let result = client_login_start_result.state.finish(
         CredentialResponse::deserialize(&credential_response_bytes[..]).unwrap(),
-        ClientLoginFinishParameters::default(),
+        ClientLoginFinishParameters {
+            export_key: true,
+            ..ClientLoginFinishParameters::default()
+        },
     );
 
     if result.is_err() {
@@ -190,14 +201,20 @@ fn open_locker(
         .unwrap();
 
     // Server sends locker contents, encrypted under the session key, to the client
-    let encrypted_locker_contents =
-        encrypt(&server_login_finish_result.session_key, &locker.contents);
+    let encrypted_locker_contents = encrypt(
+        server_login_finish_result.session_key.expose_secret(),
+        &locker.contents,
+    );
 
     // Client decrypts contents of locker, first under the session key, and then under the export key
     let plaintext = decrypt(
-        &client_login_finish_result.export_key,
+        client_login_finish_result
+            .export_key
+            .as_ref()
+            .unwrap()
+            .expose_secret(),
         &decrypt(
-            &client_login_finish_result.session_key,
+            client_login_finish_result.session_key.expose_secret(),
             &encrypted_locker_contents,
         ),
     );
@@ -0,0 +1,110 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Structured audit events for security logging on the server
+//!
+//! An [AuditSink] is installed for the whole process with `set_audit_sink`,
+//! and removed with `clear_audit_sink`, which require the `std` feature.
+//! Without an installed sink, emitting an event is a no-op.
+
+/// A security-relevant event on the server, along with its non-secret
+/// context
+///
+/// Events are added as the server records more of its decisions, so matches
+/// on this enum must include a wildcard arm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AuditEvent<'a> {
+    /// [ServerRegistration::finish](crate::ServerRegistration::finish)
+    /// produced a password file for the client's static public key
+    RegistrationCompleted {
+        /// The client's static public key
+        client_s_pk: &'a [u8],
+    },
+    /// [ServerLogin::start](crate::ServerLogin::start) was called without a
+    /// password file and answered with a dummy record
+    DummyRecordServed {
+        /// The credential identifier the client attempted to log in with
+        credential_identifier: &'a [u8],
+    },
+    /// [ServerLogin::start](crate::ServerLogin::start) answered with the
+    /// password file of a registered client
+    LoginStarted {
+        /// The credential identifier the client is logging in with
+        credential_identifier: &'a [u8],
+    },
+    /// [ServerLogin::finish](crate::ServerLogin::finish) authenticated the
+    /// client
+    LoginSucceeded,
+    /// [ServerLogin::finish](crate::ServerLogin::finish) failed
+    LoginFailed {
+        /// Why the login failed
+        reason: LoginFailureReason,
+    },
+}
+
+/// The reason reported by [AuditEvent::LoginFailed]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LoginFailureReason {
+    /// The client's MAC did not verify, e.g. because the password is wrong
+    BadMac,
+    /// The client's final message could not be processed
+    InvalidMessage,
+}
+
+/// Receives the [AuditEvent]s emitted by this library
+pub trait AuditSink: Sync {
+    /// Called whenever `event` occurs
+    fn record(&self, event: &AuditEvent<'_>);
+}
+
+/// The error returned by `set_audit_sink` when a sink has already been
+/// installed
+#[derive(Debug)]
+pub struct SetAuditSinkError(());
+
+impl core::fmt::Display for SetAuditSinkError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("an audit sink has already been installed")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SetAuditSinkError {}
+
+#[cfg(feature = "std")]
+static AUDIT_SINK: crate::observer::Global<dyn AuditSink> = crate::observer::Global {
+    is_installed: core::sync::atomic::AtomicBool::new(false),
+    value: once_cell::sync::Lazy::new(|| std::sync::RwLock::new(None)),
+};
+
+/// Installs `sink` for the whole process, until it is removed with
+/// [clear_audit_sink]. Fails if a sink is already installed.
+#[cfg(feature = "std")]
+pub fn set_audit_sink(sink: &'static dyn AuditSink) -> Result<(), SetAuditSinkError> {
+    if crate::observer::install(&AUDIT_SINK, sink) {
+        Ok(())
+    } else {
+        Err(SetAuditSinkError(()))
+    }
+}
+
+/// Removes the installed sink, if any, and returns it, so that another one
+/// can be installed
+#[cfg(feature = "std")]
+pub fn clear_audit_sink() -> Option<&'static dyn AuditSink> {
+    crate::observer::uninstall(&AUDIT_SINK)
+}
+
+/// Reports `event` to the installed sink, if any
+pub(crate) fn record(event: AuditEvent<'_>) {
+    #[cfg(feature = "std")]
+    if let Some(sink) = crate::observer::installed(&AUDIT_SINK) {
+        sink.record(&event);
+    }
+    #[cfg(not(feature = "std"))]
+    let _ = event;
+}
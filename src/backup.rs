@@ -0,0 +1,278 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Encrypted containers bundling registration records with their credential
+//! identifiers, for backing up and restoring the credential database or
+//! migrating it between regions
+//!
+//! Requires the `backup` feature.
+//!
+//! A container consists of [MAGIC], a [VERSION] byte and a random 24-byte
+//! nonce, followed by the XChaCha20-Poly1305 encryption of the records under
+//! the backup key, with the preceding header as associated data. Each record
+//! is encoded as its credential identifier followed by the serialized
+//! [ServerRegistration], both prefixed with their length as a 2-byte
+//! big-endian integer.
+//!
+//! ```
+//! use opaque_ke::backup;
+//! # use opaque_ke::{
+//! #   errors::ProtocolError,
+//! #   ClientRegistration, ClientRegistrationFinishParameters, ServerRegistration, ServerSetup,
+//! # };
+//! # use opaque_ke::CipherSuite;
+//! # struct Default;
+//! # impl CipherSuite for Default {
+//! #     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+//! #     type Hash = sha2::Sha512;
+//! #     type SlowHash = opaque_ke::slow_hash::NoOpHash;
+//! # }
+//! # use rand::{rngs::OsRng, RngCore};
+//! # let mut rng = OsRng;
+//! # let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+//! # let client_registration_start_result = ClientRegistration::<Default>::start(&mut rng, b"password")?;
+//! # let server_registration_start_result = ServerRegistration::<Default>::start(&server_setup, client_registration_start_result.message, b"alice@example.com")?;
+//! # let client_registration_finish_result = client_registration_start_result.state.finish(&mut rng, server_registration_start_result.message, ClientRegistrationFinishParameters::default())?;
+//! # let password_file = ServerRegistration::<Default>::finish(client_registration_finish_result.message);
+//! let mut backup_key = [0u8; backup::KEY_LEN];
+//! rng.fill_bytes(&mut backup_key);
+//!
+//! let container = backup::export(
+//!     &mut rng,
+//!     &backup_key,
+//!     vec![(b"alice@example.com", &password_file)],
+//! )?;
+//! let records = backup::import::<Default>(&backup_key, &container)?;
+//! assert_eq!(records, vec![(b"alice@example.com".to_vec(), password_file)]);
+//! # Ok::<(), ProtocolError>(())
+//! ```
+
+use crate::{
+    ciphersuite::CipherSuite,
+    errors::{
+        utils::{check_slice_size, fill_random},
+        InternalError, ProtocolError,
+    },
+    serialization::{serialize, serialize_len_into, tokenize},
+    ServerRegistration,
+};
+use alloc::vec::Vec;
+use chacha20poly1305::{
+    aead::{AeadInPlace, NewAead},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+/// The bytes every container starts with
+pub const MAGIC: &[u8; 13] = b"OPAQUE-BACKUP";
+/// The version of the container format produced by [export]
+pub const VERSION: u8 = 1;
+/// The length of the backup key
+pub const KEY_LEN: usize = 32;
+
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 1 + NONCE_LEN;
+
+/// Encrypts the given pairs of credential identifier and registration record
+/// into a container under `key`, which must be [KEY_LEN] bytes long
+pub fn export<'r, CS, R, C, I>(
+    rng: &mut R,
+    key: &[u8],
+    records: I,
+) -> Result<Vec<u8>, ProtocolError>
+where
+    CS: CipherSuite + 'r,
+    R: RngCore + CryptoRng,
+    C: AsRef<[u8]>,
+    I: IntoIterator<Item = (C, &'r ServerRegistration<CS>)>,
+{
+    let cipher = cipher(key)?;
+
+    // The records contain the masking keys, so they are encrypted in place
+    // in a buffer which is wiped when dropped
+    let mut plaintext = Zeroizing::new(Vec::new());
+    for (credential_identifier, record) in records {
+        plaintext.extend_from_slice(&serialize(credential_identifier.as_ref(), 2)?);
+        serialize_len_into(record.serialized_len(), 2, &mut plaintext)?;
+        record.serialize_into(&mut plaintext)?;
+    }
+
+    let mut nonce = [0u8; NONCE_LEN];
+    fill_random(rng, &mut nonce)?;
+
+    let mut output = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+    output.extend_from_slice(MAGIC);
+    output.push(VERSION);
+    output.extend_from_slice(&nonce);
+
+    cipher
+        .encrypt_in_place(XNonce::from_slice(&nonce), &output, &mut *plaintext)
+        .map_err(|_| ProtocolError::SerializationError)?;
+    output.extend_from_slice(&plaintext);
+
+    Ok(output)
+}
+
+/// Decrypts a container produced by [export] under `key`, returning its
+/// pairs of credential identifier and registration record in their original
+/// order.
+///
+/// Fails with [`InternalError::BackupDecryptionError`] if the key is wrong or
+/// the container was modified, and with [`ProtocolError::SerializationError`]
+/// if it is not a container of a supported version.
+#[allow(clippy::type_complexity)]
+pub fn import<CS: CipherSuite>(
+    key: &[u8],
+    container: &[u8],
+) -> Result<Vec<(Vec<u8>, ServerRegistration<CS>)>, ProtocolError> {
+    let cipher = cipher(key)?;
+
+    if container.len() < HEADER_LEN
+        || &container[..MAGIC.len()] != MAGIC
+        || container[MAGIC.len()] != VERSION
+    {
+        return Err(ProtocolError::SerializationError);
+    }
+    let (header, ciphertext) = container.split_at(HEADER_LEN);
+
+    let mut plaintext = Zeroizing::new(ciphertext.to_vec());
+    cipher
+        .decrypt_in_place(
+            XNonce::from_slice(&header[MAGIC.len() + 1..]),
+            header,
+            &mut *plaintext,
+        )
+        .map_err(|_| InternalError::BackupDecryptionError)?;
+
+    let mut records = Vec::new();
+    let mut remainder = &plaintext[..];
+    while !remainder.is_empty() {
+        let (credential_identifier, rest) = tokenize(remainder, 2)?;
+        let (record, rest) = tokenize(rest, 2)?;
+        records.push((
+            credential_identifier.to_vec(),
+            ServerRegistration::deserialize(record)?,
+        ));
+        remainder = rest;
+    }
+
+    Ok(records)
+}
+
+fn cipher(key: &[u8]) -> Result<XChaCha20Poly1305, ProtocolError> {
+    let key = check_slice_size(key, KEY_LEN, "backup_key")?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ClientRegistration, ClientRegistrationFinishParameters, RegistrationUpload, ServerSetup,
+    };
+    use alloc::vec;
+    use curve25519_dalek::ristretto::RistrettoPoint;
+    use rand::rngs::OsRng;
+
+    struct Default;
+
+    impl CipherSuite for Default {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = crate::key_exchange::tripledh::TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = crate::slow_hash::NoOpHash;
+    }
+
+    fn register(
+        server_setup: &ServerSetup<Default>,
+        credential_identifier: &[u8],
+    ) -> Result<RegistrationUpload<Default>, ProtocolError> {
+        let mut rng = OsRng;
+        let client_registration_start_result =
+            ClientRegistration::<Default>::start(&mut rng, b"password")?;
+        let server_registration_start_result = ServerRegistration::start(
+            server_setup,
+            client_registration_start_result.message,
+            credential_identifier,
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters::default(),
+        )?;
+        Ok(client_registration_finish_result.message)
+    }
+
+    #[test]
+    fn test_export_import() -> Result<(), ProtocolError> {
+        let mut rng = OsRng;
+        let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+        let alice = ServerRegistration::finish(register(&server_setup, b"alice")?);
+        let bob = ServerRegistration::finish_with_client_identity(
+            register(&server_setup, b"bob")?,
+            b"bob@example.com",
+        );
+        let key = [7u8; KEY_LEN];
+
+        let container = export(
+            &mut rng,
+            &key,
+            vec![(&b"alice"[..], &alice), (&b"bob"[..], &bob)],
+        )?;
+        assert_eq!(
+            import::<Default>(&key, &container)?,
+            vec![(b"alice".to_vec(), alice), (b"bob".to_vec(), bob)]
+        );
+
+        let empty = export::<Default, _, &[u8], _>(&mut rng, &key, vec![])?;
+        assert!(import::<Default>(&key, &empty)?.is_empty());
+
+        // A wrong key or a modified container is detected
+        assert!(matches!(
+            import::<Default>(&[8u8; KEY_LEN], &container),
+            Err(ProtocolError::LibraryError(
+                InternalError::BackupDecryptionError
+            ))
+        ));
+        for index in [MAGIC.len() + 1, HEADER_LEN, container.len() - 1]
+            .iter()
+            .cloned()
+        {
+            let mut modified = container.clone();
+            modified[index] ^= 1;
+            assert!(matches!(
+                import::<Default>(&key, &modified),
+                Err(ProtocolError::LibraryError(
+                    InternalError::BackupDecryptionError
+                ))
+            ));
+        }
+
+        // Unknown formats and versions are rejected before decrypting
+        let mut future_version = container.clone();
+        future_version[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            import::<Default>(&key, &future_version),
+            Err(ProtocolError::SerializationError)
+        ));
+        assert!(matches!(
+            import::<Default>(&key, &container[..HEADER_LEN - 1]),
+            Err(ProtocolError::SerializationError)
+        ));
+        assert!(matches!(
+            import::<Default>(&key[1..], &container),
+            Err(ProtocolError::LibraryError(InternalError::SizeError {
+                name: "backup_key",
+                ..
+            }))
+        ));
+
+        Ok(())
+    }
+}
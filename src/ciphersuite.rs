@@ -5,8 +5,18 @@
 
 //! Defines the CipherSuite trait to specify the underlying primitives for OPAQUE
 
+use crate::envelope::EnvelopeMode;
 use crate::key_exchange::group::KeGroup;
 use crate::{hash::Hash, key_exchange::traits::KeyExchange, slow_hash::SlowHash};
+use crate::{
+    CredentialFinalization, CredentialRequest, CredentialResponse, RegistrationRequest,
+    RegistrationResponse, RegistrationUpload,
+};
+use core::any::type_name;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use digest::{BlockInput, Digest};
+use generic_array::typenum::Unsigned;
 use voprf::group::Group as OprfGroup;
 
 /// Configures the underlying primitives used in OPAQUE
@@ -17,17 +27,452 @@ use voprf::group::Group as OprfGroup;
 /// * `KeyExchange`: The key exchange protocol to use in the login step
 /// * `Hash`: The main hashing function to use
 /// * `SlowHash`: A slow hashing function, typically used for password hashing
+///
+/// Combinations of primitives which cannot be used together are rejected at
+/// compile time, once the [CipherSuite] is first used in a protocol
+/// function. The output of `Hash` must be at least 32 bytes, as it is used for
+/// the OPRF seed and for keys, and must not exceed the block size of `Hash`,
+/// as required by hash-to-curve. For example, SHA-224 is rejected:
+///
+/// ```compile_fail
+/// # use opaque_ke::{ciphersuite::CipherSuite, rand::rngs::OsRng, ClientLogin};
+/// struct Sha224Suite;
+///
+/// impl CipherSuite for Sha224Suite {
+///     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+///     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+///     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+///     type Hash = sha2::Sha224;
+///     type SlowHash = opaque_ke::slow_hash::NoOpHash;
+/// }
+///
+/// ClientLogin::<Sha224Suite>::start(&mut OsRng, b"password");
+/// ```
+///
+/// `OprfGroup` and `KeGroup` need not be the same group, so that for example
+/// a Ristretto255 OPRF can be combined with a P-256 key exchange. The static
+/// keypairs which are derived rather than sampled (in the internal envelope
+/// mode and for unregistered users) are however obtained with the
+/// hash-to-scalar of `OprfGroup`, so its scalars must have the length of the
+/// private keys of `KeGroup`, which is checked at compile time as well.
+///
+/// Unless [`CipherSuite::HASH_TO_CURVE`] is overridden, the password is
+/// hashed to `OprfGroup` with `expand_message_xmd` over `Hash`, which the
+/// hash-to-curve specification only allows with a hash whose output is at
+/// least twice the security level of the group, i.e. about as long as its
+/// scalars. A hash too short for the group is rejected at compile time too.
 pub trait CipherSuite {
     /// A finite cyclic group along with a point representation along with
     /// an extension trait PasswordToCurve that allows some customization on
     /// how to hash a password to a curve point. See `group::Group`.
-    type OprfGroup: OprfGroup;
+    ///
+    /// The `Debug` and `Eq` bounds let the messages implement these traits
+    /// for every `CipherSuite`, without further bounds.
+    type OprfGroup: OprfGroup + Debug + Eq;
     /// A `Group` used for the `KeyExchange`.
     type KeGroup: KeGroup;
     /// A key exchange protocol
     type KeyExchange: KeyExchange<Self::Hash, Self::KeGroup>;
     /// The main hash function use (for HKDF computations and hashing transcripts)
+    ///
+    /// Truncated hashes such as `sha2::Sha512Trunc256` (SHA-512/256) can be
+    /// used as well, to benefit from SHA-512 hardware with 32-byte outputs.
     type Hash: Hash;
     /// A slow hashing function, typically used for password hashing
     type SlowHash: SlowHash<Self::Hash>;
+
+    /// How the client's static keypair is stored in the envelope, see
+    /// [`EnvelopeMode`]. Defaults to [`EnvelopeMode::Internal`], and applies
+    /// to registrations which do not choose a mode and to fake credential
+    /// responses. Every envelope is serialized to the length of this mode, so
+    /// registrations can only choose a mode with a shorter envelope.
+    const ENVELOPE_MODE: EnvelopeMode = EnvelopeMode::Internal;
+
+    /// The length of the export key, which is expanded with HKDF from the
+    /// randomized password. Defaults to the output size of `Hash`, and must
+    /// be between 1 and 255 times that size.
+    const EXPORT_KEY_LEN: usize = <<Self::Hash as Digest>::OutputSize as Unsigned>::USIZE;
+
+    /// The length of the session key. Defaults to the output size of `Hash`,
+    /// as specified by the key exchange, which is otherwise expanded with HKDF
+    /// to this length. It must be between 1 and 255 times the output size.
+    const SESSION_KEY_LEN: usize = <<Self::Hash as Digest>::OutputSize as Unsigned>::USIZE;
+
+    /// A constant context mixed into every HKDF label and into the key
+    /// exchange transcript, ahead of any per-login context. Ciphersuites
+    /// with different contexts derive unrelated keys from the same inputs,
+    /// e.g. to separate staging from production. Defaults to the empty
+    /// string, which matches the specification.
+    const CONTEXT: &'static [u8] = b"";
+
+    /// Whether the OPRF input is the credential identifier, prefixed with its
+    /// length, followed by the password, so that the same password yields
+    /// unrelated OPRF outputs for different accounts. When set, clients must
+    /// start with `start_with_identifier`. Defaults to `false`, which matches
+    /// the specification.
+    const BIND_CREDENTIAL_IDENTIFIER: bool = false;
+
+    /// The length of the identifier of the server key, which credential
+    /// responses carry along with the masked response, so that it is covered
+    /// by the server's MAC. At most the output size of `Hash`. Defaults to 0,
+    /// in which case credential responses carry no key identifier, as in the
+    /// specification.
+    const KEY_ID_LEN: usize = 0;
+
+    /// Overrides how the client hashes its password to `OprfGroup` before
+    /// blinding it, e.g. to interoperate with peers implementing an older
+    /// hash-to-curve draft or another domain separation tag. Defaults to
+    /// `None`, in which case the hash-to-curve suite of `OprfGroup` is used
+    /// with the domain separation tag of the specification. Servers are
+    /// unaffected, but a password file only lets clients using the same
+    /// hashing log in.
+    const HASH_TO_CURVE: Option<HashToCurve<Self::OprfGroup>> = None;
+
+    /// The labels of the key schedule of the key exchange, see
+    /// [`KeyExchangeLabels`]. Defaults to
+    /// [`KeyExchangeLabels::SPECIFICATION`], and should only be overridden by
+    /// deployments which deliberately diverge from the specification, since
+    /// other labels prevent any login with peers using the default ones.
+    /// Separating deployments which otherwise follow the specification is
+    /// better done with [`CipherSuite::CONTEXT`].
+    const KEY_EXCHANGE_LABELS: KeyExchangeLabels = KeyExchangeLabels::SPECIFICATION;
+
+    /// The labels from which the envelope and the masking of the credential
+    /// response derive their keys, see [`EnvelopeLabels`]. Like
+    /// [`CipherSuite::KEY_EXCHANGE_LABELS`], this defaults to
+    /// [`EnvelopeLabels::SPECIFICATION`]. Other labels also change the
+    /// registration records, which must then be registered again.
+    const ENVELOPE_LABELS: EnvelopeLabels = EnvelopeLabels::SPECIFICATION;
+}
+
+/// Hashes a password to an OPRF group element, see
+/// [`CipherSuite::HASH_TO_CURVE`]
+pub type HashToCurve<G> = fn(&[u8]) -> Result<G, voprf::errors::InternalError>;
+
+/// The strings from which the key exchange derives its keys, see
+/// [`CipherSuite::KEY_EXCHANGE_LABELS`]
+///
+/// Every label is prefixed with `prefix` when expanded, and the combined
+/// length must fit in the single byte of the `HkdfLabel` structure. The four
+/// derivation labels must be distinct, so that the session key and the MAC
+/// keys remain independent. Both requirements are checked at compile time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeyExchangeLabels {
+    /// The string starting the transcript hashed by the key exchange
+    pub transcript_preamble: &'static [u8],
+    /// The prefix of every label of the key schedule
+    pub prefix: &'static [u8],
+    /// The label of the handshake secret, from which the MAC keys are derived
+    pub handshake_secret: &'static [u8],
+    /// The label of the session key
+    pub session_key: &'static [u8],
+    /// The label of the MAC key of the server
+    pub server_mac: &'static [u8],
+    /// The label of the MAC key of the client
+    pub client_mac: &'static [u8],
+    /// The customization string of the MAC, for key schedules where the MAC
+    /// is keyed with a label, such as the one of `TripleDHKmac`
+    pub mac: &'static [u8],
+}
+
+impl KeyExchangeLabels {
+    /// The labels of the specification
+    pub const SPECIFICATION: Self = Self {
+        transcript_preamble: b"RFCXXXX",
+        prefix: b"OPAQUE-",
+        handshake_secret: b"HandshakeSecret",
+        session_key: b"SessionKey",
+        server_mac: b"ServerMAC",
+        client_mac: b"ClientMAC",
+        mac: b"MAC",
+    };
+
+    const fn max_label_len(&self) -> usize {
+        let labels = [
+            self.handshake_secret,
+            self.session_key,
+            self.server_mac,
+            self.client_mac,
+            self.mac,
+        ];
+        let mut max = 0;
+        let mut i = 0;
+        while i < labels.len() {
+            if labels[i].len() > max {
+                max = labels[i].len();
+            }
+            i += 1;
+        }
+        max
+    }
+
+    const fn derivation_labels_distinct(&self) -> bool {
+        let labels = [
+            self.handshake_secret,
+            self.session_key,
+            self.server_mac,
+            self.client_mac,
+        ];
+        let mut i = 0;
+        while i < labels.len() {
+            let mut j = i + 1;
+            while j < labels.len() {
+                if bytes_eq(labels[i], labels[j]) {
+                    return false;
+                }
+                j += 1;
+            }
+            i += 1;
+        }
+        true
+    }
+}
+
+impl Default for KeyExchangeLabels {
+    fn default() -> Self {
+        Self::SPECIFICATION
+    }
+}
+
+/// The strings from which the envelope and the masking of the credential
+/// response derive their keys, see [`CipherSuite::ENVELOPE_LABELS`]
+///
+/// The three labels expanded from the envelope nonce must be distinct from
+/// each other and from the fixed labels of the AEAD envelope mode, `AeadKey`
+/// and `AeadNonce`, which is checked at compile time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EnvelopeLabels {
+    /// The label of the key of the envelope MAC
+    pub auth_key: &'static [u8],
+    /// The label of the export key
+    pub export_key: &'static [u8],
+    /// The label of the seed of the client's static private key
+    pub private_key: &'static [u8],
+    /// The label of the masking key of the registration record
+    pub masking_key: &'static [u8],
+    /// The label of the pad masking the credential response
+    pub credential_response_pad: &'static [u8],
+}
+
+impl EnvelopeLabels {
+    /// The labels of the specification
+    pub const SPECIFICATION: Self = Self {
+        auth_key: b"AuthKey",
+        export_key: b"ExportKey",
+        private_key: b"PrivateKey",
+        masking_key: b"MaskingKey",
+        credential_response_pad: b"CredentialResponsePad",
+    };
+
+    const fn nonce_labels_distinct(&self) -> bool {
+        let labels: [&[u8]; 5] = [
+            self.auth_key,
+            self.export_key,
+            self.private_key,
+            b"AeadKey",
+            b"AeadNonce",
+        ];
+        let mut i = 0;
+        while i < labels.len() {
+            let mut j = i + 1;
+            while j < labels.len() {
+                if bytes_eq(labels[i], labels[j]) {
+                    return false;
+                }
+                j += 1;
+            }
+            i += 1;
+        }
+        true
+    }
+}
+
+impl Default for EnvelopeLabels {
+    fn default() -> Self {
+        Self::SPECIFICATION
+    }
+}
+
+const fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// The P256-SHA256 configuration of the specification, which uses P-256 for
+/// both the OPRF and the key exchange, SHA-256 for HKDF, HMAC and the
+/// transcript, and no slow hash. It is validated against the published test
+/// vectors.
+#[cfg(feature = "p256")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct P256Sha256;
+
+#[cfg(feature = "p256")]
+impl CipherSuite for P256Sha256 {
+    type OprfGroup = p256_::ProjectivePoint;
+    type KeGroup = p256_::ProjectivePoint;
+    type KeyExchange = crate::key_exchange::tripledh::TripleDH;
+    type Hash = sha2::Sha256;
+    type SlowHash = crate::slow_hash::NoOpHash;
+}
+
+/// A description of the primitives and lengths of a [CipherSuite], for
+/// tooling, negotiation and diagnostics to report the configuration in use
+///
+/// It depends only on the ciphersuite type, and is obtained with
+/// [`SuiteParams::of`]. The names are the Rust type names of the primitives,
+/// which are meant to be read by humans and may change across compiler
+/// versions, while the lengths are exact. Message lengths are the maximum
+/// over the envelope modes enabled by the features.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SuiteParams {
+    /// The type name of [`CipherSuite::OprfGroup`]
+    pub oprf_group: &'static str,
+    /// The type name of [`CipherSuite::KeGroup`]
+    pub ke_group: &'static str,
+    /// The type name of [`CipherSuite::KeyExchange`]
+    pub key_exchange: &'static str,
+    /// The type name of [`CipherSuite::Hash`]
+    pub hash: &'static str,
+    /// The type name of [`CipherSuite::SlowHash`]
+    pub slow_hash: &'static str,
+    /// The length of a serialized OPRF group element
+    pub oprf_element_len: usize,
+    /// The length of a serialized key exchange public key
+    pub ke_public_key_len: usize,
+    /// The length of a serialized key exchange private key
+    pub ke_private_key_len: usize,
+    /// The output length of [`CipherSuite::Hash`]
+    pub hash_len: usize,
+    /// The length of the envelope, masking and key exchange nonces
+    pub nonce_len: usize,
+    /// [`CipherSuite::EXPORT_KEY_LEN`]
+    pub export_key_len: usize,
+    /// [`CipherSuite::SESSION_KEY_LEN`]
+    pub session_key_len: usize,
+    /// [`CipherSuite::KEY_ID_LEN`]
+    pub key_id_len: usize,
+    /// The length of a serialized [`RegistrationRequest`](crate::RegistrationRequest)
+    pub registration_request_len: usize,
+    /// The length of a serialized [`RegistrationResponse`](crate::RegistrationResponse)
+    pub registration_response_len: usize,
+    /// The length of a serialized [`RegistrationUpload`](crate::RegistrationUpload)
+    pub registration_upload_len: usize,
+    /// The length of a serialized [`CredentialRequest`](crate::CredentialRequest)
+    pub credential_request_len: usize,
+    /// The length of a serialized [`CredentialResponse`](crate::CredentialResponse)
+    pub credential_response_len: usize,
+    /// The length of a serialized
+    /// [`CredentialFinalization`](crate::CredentialFinalization)
+    pub credential_finalization_len: usize,
+}
+
+impl SuiteParams {
+    /// Describes the ciphersuite `CS`
+    pub fn of<CS: CipherSuite>() -> Self {
+        Self {
+            oprf_group: type_name::<CS::OprfGroup>(),
+            ke_group: type_name::<CS::KeGroup>(),
+            key_exchange: type_name::<CS::KeyExchange>(),
+            hash: type_name::<CS::Hash>(),
+            slow_hash: type_name::<CS::SlowHash>(),
+            oprf_element_len: <CS::OprfGroup as OprfGroup>::ElemLen::USIZE,
+            ke_public_key_len: <CS::KeGroup as KeGroup>::PkLen::USIZE,
+            ke_private_key_len: <CS::KeGroup as KeGroup>::SkLen::USIZE,
+            hash_len: <CS::Hash as Digest>::OutputSize::USIZE,
+            nonce_len: crate::envelope::NONCE_LEN,
+            export_key_len: CS::EXPORT_KEY_LEN,
+            session_key_len: CS::SESSION_KEY_LEN,
+            key_id_len: CS::KEY_ID_LEN,
+            registration_request_len: RegistrationRequest::<CS>::max_serialized_len(),
+            registration_response_len: RegistrationResponse::<CS>::max_serialized_len(),
+            registration_upload_len: RegistrationUpload::<CS>::max_serialized_len(),
+            credential_request_len: CredentialRequest::<CS>::max_serialized_len(),
+            credential_response_len: CredentialResponse::<CS>::max_serialized_len(),
+            credential_finalization_len: CredentialFinalization::<CS>::max_serialized_len(),
+        }
+    }
+}
+
+/// Compile-time checks on the combination of primitives in a [CipherSuite]
+pub(crate) struct Validate<CS>(PhantomData<CS>);
+
+impl<CS: CipherSuite> Validate<CS> {
+    const HASH_LEN: usize = <<CS::Hash as Digest>::OutputSize as Unsigned>::USIZE;
+    const BLOCK_LEN: usize = <<CS::Hash as BlockInput>::BlockSize as Unsigned>::USIZE;
+    const OPRF_SCALAR_LEN: usize = <<CS::OprfGroup as OprfGroup>::ScalarLen as Unsigned>::USIZE;
+    const KE_PRIVATE_KEY_LEN: usize = <<CS::KeGroup as KeGroup>::SkLen as Unsigned>::USIZE;
+
+    // Indexing out of bounds fails the constant evaluation, and the compile
+    // error then names the violated requirement
+    const HASH_OUTPUT_AT_LEAST_32_BYTES: () = [()][(Self::HASH_LEN < 32) as usize];
+    const HASH_OUTPUT_AT_MOST_BLOCK_SIZE: () = [()][(Self::HASH_LEN > Self::BLOCK_LEN) as usize];
+    const EXPORT_KEY_LEN_IN_HKDF_RANGE: () =
+        [()][(CS::EXPORT_KEY_LEN == 0 || CS::EXPORT_KEY_LEN > 255 * Self::HASH_LEN) as usize];
+    const SESSION_KEY_LEN_IN_HKDF_RANGE: () =
+        [()][(CS::SESSION_KEY_LEN == 0 || CS::SESSION_KEY_LEN > 255 * Self::HASH_LEN) as usize];
+    const KEY_ID_LEN_AT_MOST_HASH_OUTPUT: () = [()][(CS::KEY_ID_LEN > Self::HASH_LEN) as usize];
+    const HASH_TO_CURVE_SUITS_OPRF_GROUP: () = [()][(CS::HASH_TO_CURVE.is_none()
+        && !xmd_hash_suits_group(Self::HASH_LEN, Self::OPRF_SCALAR_LEN))
+        as usize];
+    const OPRF_SCALAR_LEN_EQUALS_KE_PRIVATE_KEY_LEN: () =
+        [()][(Self::OPRF_SCALAR_LEN != Self::KE_PRIVATE_KEY_LEN) as usize];
+    const SLOW_HASH_OUTPUT_NOT_EMPTY: () =
+        [()][(<CS::SlowHash as SlowHash<CS::Hash>>::OUTPUT_LEN == 0) as usize];
+    const KEY_EXCHANGE_LABELS_FIT_HKDF_LABEL: () = [()][(CS::KEY_EXCHANGE_LABELS.prefix.len()
+        + CS::KEY_EXCHANGE_LABELS.max_label_len()
+        > 255) as usize];
+    const KEY_EXCHANGE_LABELS_DISTINCT: () =
+        [()][!CS::KEY_EXCHANGE_LABELS.derivation_labels_distinct() as usize];
+    const ENVELOPE_LABELS_DISTINCT: () =
+        [()][!CS::ENVELOPE_LABELS.nonce_labels_distinct() as usize];
+
+    /// Forces the evaluation of the checks for `CS`
+    #[allow(clippy::let_unit_value)]
+    pub(crate) fn assert() {
+        let _ = Self::HASH_OUTPUT_AT_LEAST_32_BYTES;
+        let _ = Self::HASH_OUTPUT_AT_MOST_BLOCK_SIZE;
+        let _ = Self::EXPORT_KEY_LEN_IN_HKDF_RANGE;
+        let _ = Self::SESSION_KEY_LEN_IN_HKDF_RANGE;
+        let _ = Self::KEY_ID_LEN_AT_MOST_HASH_OUTPUT;
+        let _ = Self::HASH_TO_CURVE_SUITS_OPRF_GROUP;
+        let _ = Self::OPRF_SCALAR_LEN_EQUALS_KE_PRIVATE_KEY_LEN;
+        let _ = Self::SLOW_HASH_OUTPUT_NOT_EMPTY;
+        let _ = Self::KEY_EXCHANGE_LABELS_FIT_HKDF_LABEL;
+        let _ = Self::KEY_EXCHANGE_LABELS_DISTINCT;
+        let _ = Self::ENVELOPE_LABELS_DISTINCT;
+    }
+}
+
+// Whether `expand_message_xmd` with a hash of `hash_len` bytes can hash to a
+// group whose scalars have `scalar_len` bytes: the hash must output at least
+// twice the security level of the group, which is about half the bit length
+// of its scalars, rounded down to a multiple of 32 bits
+const fn xmd_hash_suits_group(hash_len: usize, scalar_len: usize) -> bool {
+    hash_len >= scalar_len - scalar_len % 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xmd_hash_suits_group() {
+        // ristretto255 and P-256 with SHA-256 and SHA-512
+        assert!(xmd_hash_suits_group(32, 32));
+        assert!(xmd_hash_suits_group(64, 32));
+        // P-384 needs at least SHA-384, and P-521 at least SHA-512
+        assert!(!xmd_hash_suits_group(32, 48));
+        assert!(xmd_hash_suits_group(48, 48));
+        assert!(!xmd_hash_suits_group(48, 66));
+        assert!(xmd_hash_suits_group(64, 66));
+    }
 }
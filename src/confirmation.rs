@@ -0,0 +1,248 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! An optional fourth login message, by which the server explicitly confirms
+//! to the client that it derived the same session key
+//!
+//! In the three-message flow, the client learns that the server holds the
+//! server key from the MAC in the [CredentialResponse](crate::CredentialResponse),
+//! and the server learns that the client holds the session key from the
+//! [CredentialFinalization](crate::CredentialFinalization), but the client
+//! never learns whether the server accepted it. Deployments which require
+//! mutual explicit key confirmation can have the server send a
+//! [KeyConfirmation] after [`ServerLogin::finish`](crate::ServerLogin::finish),
+//! which the client checks before using the session key.
+//!
+//! The confirmation is a MAC of the transcript hash, under a key expanded from
+//! the session key with HKDF, which is distinct from the MAC keys of the key
+//! exchange.
+//!
+//! ```
+//! use opaque_ke::confirmation::KeyConfirmation;
+//! # use opaque_ke::{
+//! #   errors::ProtocolError,
+//! #   ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+//! #   ClientRegistrationFinishParameters, ServerLogin, ServerLoginStartParameters,
+//! #   ServerRegistration, ServerSetup,
+//! # };
+//! # use opaque_ke::CipherSuite;
+//! # struct Default;
+//! # impl CipherSuite for Default {
+//! #     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+//! #     type Hash = sha2::Sha512;
+//! #     type SlowHash = opaque_ke::slow_hash::NoOpHash;
+//! # }
+//! # use rand::rngs::OsRng;
+//! # let mut rng = OsRng;
+//! # let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+//! # let client_registration_start_result = ClientRegistration::<Default>::start(&mut rng, b"password")?;
+//! # let server_registration_start_result = ServerRegistration::<Default>::start(&server_setup, client_registration_start_result.message, b"alice@example.com")?;
+//! # let client_registration_finish_result = client_registration_start_result.state.finish(&mut rng, server_registration_start_result.message, ClientRegistrationFinishParameters::default())?;
+//! # let password_file = ServerRegistration::<Default>::finish(client_registration_finish_result.message);
+//! # let client_login_start_result = ClientLogin::<Default>::start(&mut rng, b"password")?;
+//! # let server_login_start_result = ServerLogin::start(&mut rng, &server_setup, Some(password_file), client_login_start_result.message, b"alice@example.com", ServerLoginStartParameters::default())?;
+//! # let client_login_finish_result = client_login_start_result.state.finish(server_login_start_result.message, ClientLoginFinishParameters::default())?;
+//! // Server: confirm the session key after finishing the login
+//! let server_login_finish_result =
+//!     server_login_start_result.state.finish(client_login_finish_result.message.clone())?;
+//! let confirmation = KeyConfirmation::from_server(&server_login_finish_result)?;
+//!
+//! // Client: check the confirmation before using the session key
+//! KeyConfirmation::<Default>::deserialize(&confirmation.serialize())?
+//!     .verify(&client_login_finish_result)?;
+//! # Ok::<(), ProtocolError>(())
+//! ```
+
+use crate::{
+    ciphersuite::CipherSuite,
+    errors::{utils::check_slice_size, InternalError, ProtocolError},
+    ClientLoginFinishResult, ServerLoginFinishResult,
+};
+use alloc::vec;
+use alloc::vec::Vec;
+use digest::Digest;
+use generic_array::{typenum::Unsigned, GenericArray};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac, NewMac};
+use zeroize::Zeroizing;
+
+const STR_KEY_CONFIRMATION: &[u8; 15] = b"KeyConfirmation";
+
+/// The confirmation of the session key, sent by the server to the client
+pub struct KeyConfirmation<CS: CipherSuite> {
+    mac: GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
+}
+
+impl_clone_for!(struct KeyConfirmation<CS: CipherSuite>, [mac]);
+impl_debug_eq_hash_for!(struct KeyConfirmation<CS: CipherSuite>, [mac]);
+
+impl<CS: CipherSuite> KeyConfirmation<CS> {
+    /// Confirms the session key of a login finished by the server
+    pub fn from_server(
+        server_login_finish_result: &ServerLoginFinishResult<CS>,
+    ) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            mac: mac::<CS>(
+                server_login_finish_result.session_key.expose_secret(),
+                &server_login_finish_result.transcript_hash,
+            )?
+            .finalize()
+            .into_bytes(),
+        })
+    }
+
+    /// Checks that the server derived the same session key as the client,
+    /// for the same handshake.
+    ///
+    /// Fails with [`ProtocolError::InvalidLoginError`] otherwise, in which
+    /// case the session key must be discarded.
+    pub fn verify(
+        &self,
+        client_login_finish_result: &ClientLoginFinishResult<CS>,
+    ) -> Result<(), ProtocolError> {
+        mac::<CS>(
+            client_login_finish_result.session_key.expose_secret(),
+            &client_login_finish_result.transcript_hash,
+        )?
+        .verify(&self.mac)
+        .map_err(|_| ProtocolError::InvalidLoginError)
+    }
+
+    /// Serialization into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        self.mac.to_vec()
+    }
+
+    /// Deserialization from bytes
+    pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
+        let input = check_slice_size(
+            input,
+            <CS::Hash as Digest>::OutputSize::USIZE,
+            "key_confirmation",
+        )?;
+        Ok(Self {
+            mac: GenericArray::clone_from_slice(input),
+        })
+    }
+}
+
+fn mac<CS: CipherSuite>(
+    session_key: &[u8],
+    transcript_hash: &[u8],
+) -> Result<Hmac<CS::Hash>, ProtocolError> {
+    let mut mac_key = Zeroizing::new(vec![0u8; <CS::Hash as Digest>::OutputSize::USIZE]);
+    Hkdf::<CS::Hash>::new(None, session_key)
+        .expand_multi_info(&[CS::CONTEXT, STR_KEY_CONFIRMATION], &mut mac_key)
+        .map_err(|_| InternalError::HkdfError)?;
+    let mut mac =
+        Hmac::<CS::Hash>::new_from_slice(&mac_key).map_err(|_| InternalError::HmacError)?;
+    mac.update(transcript_hash);
+    Ok(mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+        ClientRegistrationFinishParameters, ServerLogin, ServerLoginStartParameters,
+        ServerRegistration, ServerSetup,
+    };
+    use curve25519_dalek::ristretto::RistrettoPoint;
+    use rand::rngs::OsRng;
+
+    struct Default;
+
+    impl CipherSuite for Default {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = crate::key_exchange::tripledh::TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = crate::slow_hash::NoOpHash;
+    }
+
+    fn login(
+        server_setup: &ServerSetup<Default>,
+        password_file: &ServerRegistration<Default>,
+    ) -> Result<
+        (
+            ClientLoginFinishResult<Default>,
+            ServerLoginFinishResult<Default>,
+        ),
+        ProtocolError,
+    > {
+        let client_login_start_result = ClientLogin::<Default>::start(&mut OsRng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut OsRng,
+            server_setup,
+            Some(password_file.clone()),
+            client_login_start_result.message,
+            b"alice",
+            ServerLoginStartParameters::default(),
+        )?;
+        let client_login_finish_result = client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters::default(),
+        )?;
+        let server_login_finish_result = server_login_start_result
+            .state
+            .finish(client_login_finish_result.message.clone())?;
+        Ok((client_login_finish_result, server_login_finish_result))
+    }
+
+    #[test]
+    fn test_key_confirmation() -> Result<(), ProtocolError> {
+        let mut rng = OsRng;
+        let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+        let client_registration_start_result =
+            ClientRegistration::<Default>::start(&mut rng, b"password")?;
+        let server_registration_start_result = ServerRegistration::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"alice",
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters::default(),
+        )?;
+        let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+        let (client_login_finish_result, server_login_finish_result) =
+            login(&server_setup, &password_file)?;
+        let confirmation = KeyConfirmation::from_server(&server_login_finish_result)?;
+        let bytes = confirmation.serialize();
+        assert_eq!(
+            KeyConfirmation::<Default>::deserialize(&bytes)?,
+            confirmation
+        );
+        confirmation.verify(&client_login_finish_result)?;
+
+        // A confirmation only holds for its own handshake, and any
+        // modification is detected
+        let (other_client_login_finish_result, _) = login(&server_setup, &password_file)?;
+        assert!(matches!(
+            confirmation.verify(&other_client_login_finish_result),
+            Err(ProtocolError::InvalidLoginError)
+        ));
+        let mut modified = bytes.clone();
+        modified[0] ^= 1;
+        assert!(matches!(
+            KeyConfirmation::<Default>::deserialize(&modified)?.verify(&client_login_finish_result),
+            Err(ProtocolError::InvalidLoginError)
+        ));
+        assert!(matches!(
+            KeyConfirmation::<Default>::deserialize(&bytes[1..]),
+            Err(ProtocolError::LibraryError(InternalError::SizeError {
+                name: "key_confirmation",
+                ..
+            }))
+        ));
+
+        Ok(())
+    }
+}
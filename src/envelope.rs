@@ -5,8 +5,10 @@
 
 use crate::{
     ciphersuite::CipherSuite,
-    errors::{utils::check_slice_size, InternalError, ProtocolError},
-    hash::Hash,
+    errors::{
+        utils::{check_slice_size, fill_random},
+        InternalError, ProtocolError,
+    },
     key_exchange::group::KeGroup,
     keypair::{KeyPair, PublicKey},
     opaque::{bytestrings_from_identifiers, Identifiers},
@@ -22,18 +24,102 @@ use rand::{CryptoRng, RngCore};
 use voprf::group::Group;
 use zeroize::Zeroize;
 
+#[cfg(feature = "aead-envelope")]
+use chacha20poly1305::{
+    aead::{AeadInPlace, NewAead},
+    Key, XChaCha20Poly1305, XNonce,
+};
+
 // Constant string used as salt for HKDF computation
-const STR_AUTH_KEY: &[u8; 7] = b"AuthKey";
-const STR_EXPORT_KEY: &[u8; 9] = b"ExportKey";
-const STR_PRIVATE_KEY: &[u8; 10] = b"PrivateKey";
 const STR_OPAQUE_DERIVE_AUTH_KEY_PAIR: &[u8; 24] = b"OPAQUE-DeriveAuthKeyPair";
-const NONCE_LEN: usize = 32;
+#[cfg(feature = "aead-envelope")]
+const STR_AEAD_KEY: &[u8; 7] = b"AeadKey";
+#[cfg(feature = "aead-envelope")]
+const STR_AEAD_NONCE: &[u8; 9] = b"AeadNonce";
+pub(crate) const NONCE_LEN: usize = 32;
+#[cfg(feature = "aead-envelope")]
+const AEAD_TAG_LEN: usize = 16;
+
+// The modes which envelopes can be sealed with, given the enabled features
+const ENVELOPE_MODES: &[EnvelopeMode] = &[
+    EnvelopeMode::Internal,
+    #[cfg(feature = "aead-envelope")]
+    EnvelopeMode::Aead,
+];
+
+/// Determines how the client's static keypair is stored in the envelope.
+/// [`CipherSuite::ENVELOPE_MODE`] provides the default, which can be
+/// overridden per registration through
+/// [`ClientRegistrationFinishParameters`](crate::ClientRegistrationFinishParameters).
+///
+/// Every envelope is serialized to the length of the default mode, so that a
+/// registration cannot be told apart from another one, or from a fake
+/// credential response, by its length. A registration can therefore only
+/// choose a mode whose envelope fits in that length, and envelopes of the
+/// internal mode leave the space of the encrypted private key zeroed, which
+/// is how the mode is recovered from the password file at login.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EnvelopeMode {
+    /// The private key is derived from the randomized password and the
+    /// envelope nonce, so that no key material is stored in the envelope
+    Internal,
+    /// A random private key is encrypted with XChaCha20-Poly1305, under a key
+    /// derived from the randomized password, and stored in the envelope
+    #[cfg(feature = "aead-envelope")]
+    Aead,
+}
+
+/// The structure of an envelope, as decoded by
+/// [`ClientLogin::inspect_envelope`](crate::ClientLogin::inspect_envelope)
+#[cfg(feature = "envelope-inspect")]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct EnvelopeInfo {
+    /// The mode the envelope was sealed with
+    pub mode: EnvelopeMode,
+    /// The nonce of the envelope
+    pub nonce: Vec<u8>,
+    /// Whether the envelope stores the encrypted private key of the client,
+    /// which only [`EnvelopeMode::Aead`] does
+    pub stores_private_key: bool,
+    /// The server's static public key, which the envelope is bound to
+    pub server_s_pk: Vec<u8>,
+    /// The client's static public key recovered from the envelope, empty
+    /// if an [`EnvelopeMode::Aead`] envelope failed to decrypt
+    pub client_s_pk: Vec<u8>,
+    /// Whether the MAC of the envelope verified, which fails if the
+    /// identifiers differ from those of the registration, or if the
+    /// registration bound an external client key
+    pub authenticated: bool,
+}
+
+// Where the client's static keypair sealed with an envelope comes from
+pub(crate) enum ClientStaticKey<KG: KeGroup> {
+    // Chosen by the envelope mode
+    Default,
+    // Given by the client, for a mode which stores it in the envelope
+    Stored(KeyPair<KG>),
+    // Held by the client outside of the envelope, e.g. in a platform
+    // keystore, so that only its public key is bound to the envelope
+    External(PublicKey<KG>),
+}
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Zeroize)]
 #[zeroize(drop)]
 pub(crate) enum InnerEnvelopeMode {
     Zero = 0,
     Internal = 1,
+    #[cfg(feature = "aead-envelope")]
+    Aead = 2,
+}
+
+impl From<EnvelopeMode> for InnerEnvelopeMode {
+    fn from(mode: EnvelopeMode) -> Self {
+        match mode {
+            EnvelopeMode::Internal => InnerEnvelopeMode::Internal,
+            #[cfg(feature = "aead-envelope")]
+            EnvelopeMode::Aead => InnerEnvelopeMode::Aead,
+        }
+    }
 }
 
 impl TryFrom<u8> for InnerEnvelopeMode {
@@ -41,6 +127,8 @@ impl TryFrom<u8> for InnerEnvelopeMode {
     fn try_from(x: u8) -> Result<Self, Self::Error> {
         match x {
             1 => Ok(InnerEnvelopeMode::Internal),
+            #[cfg(feature = "aead-envelope")]
+            2 => Ok(InnerEnvelopeMode::Aead),
             _ => Err(ProtocolError::SerializationError),
         }
     }
@@ -56,9 +144,14 @@ impl TryFrom<u8> for InnerEnvelopeMode {
 /// The specification update has simplified this assumption by taking
 /// an XOR-based approach without compromising on security, and to avoid
 /// the confusion around the implementation of an RKR-secure encryption.
+///
+/// With [`EnvelopeMode::Aead`], the envelope additionally carries the
+/// encrypted private key between the nonce and the MAC, and the MAC also
+/// covers this ciphertext. It is empty in all other modes.
 pub(crate) struct Envelope<CS: CipherSuite> {
     mode: InnerEnvelopeMode,
     nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
     hmac: GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
 }
 
@@ -68,49 +161,43 @@ impl<CS: CipherSuite> Clone for Envelope<CS> {
         Self {
             mode: self.mode.clone(),
             nonce: self.nonce.clone(),
+            ciphertext: self.ciphertext.clone(),
             hmac: self.hmac.clone(),
         }
     }
 }
 
-impl_debug_eq_hash_for!(struct Envelope<CS: CipherSuite>, [mode, nonce, hmac]);
+impl_debug_eq_hash_for!(struct Envelope<CS: CipherSuite>, [mode, nonce, ciphertext, hmac]);
 
 // Note that this struct represents an envelope that has been "opened" with the asssociated
 // key. This key is also used to derive the export_key parameter, which is technically
 // unrelated to the envelope's encrypted and authenticated contents.
 pub(crate) struct OpenedEnvelope<CS: CipherSuite> {
     pub(crate) client_static_keypair: KeyPair<CS::KeGroup>,
-    pub(crate) export_key: GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
+    pub(crate) export_key: Option<Vec<u8>>,
     pub(crate) id_u: Vec<u8>,
     pub(crate) id_s: Vec<u8>,
 }
 
-pub(crate) struct OpenedInnerEnvelope<D: Hash> {
-    pub(crate) export_key: GenericArray<u8, <D as Digest>::OutputSize>,
+pub(crate) struct OpenedInnerEnvelope {
+    pub(crate) export_key: Option<Vec<u8>>,
 }
 
 #[cfg(not(test))]
-type SealRawResult<CS> = (
-    Envelope<CS>,
-    GenericArray<u8, <<CS as CipherSuite>::Hash as Digest>::OutputSize>,
-);
+type SealRawResult<CS> = (Envelope<CS>, Option<Vec<u8>>);
 #[cfg(test)]
-type SealRawResult<CS> = (
-    Envelope<CS>,
-    GenericArray<u8, <<CS as CipherSuite>::Hash as Digest>::OutputSize>,
-    Vec<u8>,
-);
+type SealRawResult<CS> = (Envelope<CS>, Option<Vec<u8>>, Vec<u8>);
 #[cfg(not(test))]
 type SealResult<CS> = (
     Envelope<CS>,
     PublicKey<<CS as CipherSuite>::KeGroup>,
-    GenericArray<u8, <<CS as CipherSuite>::Hash as Digest>::OutputSize>,
+    Option<Vec<u8>>,
 );
 #[cfg(test)]
 type SealResult<CS> = (
     Envelope<CS>,
     PublicKey<<CS as CipherSuite>::KeGroup>,
-    GenericArray<u8, <<CS as CipherSuite>::Hash as Digest>::OutputSize>,
+    Option<Vec<u8>>,
     Vec<u8>,
 );
 
@@ -121,20 +208,65 @@ impl<CS: CipherSuite> Envelope<CS> {
         randomized_pwd_hasher: Hkdf<CS::Hash>,
         server_s_pk: &[u8],
         optional_ids: Option<Identifiers>,
+        envelope_mode: EnvelopeMode,
+        client_static_key: ClientStaticKey<CS::KeGroup>,
+        export_key: bool,
     ) -> Result<SealResult<CS>, ProtocolError> {
+        // A longer envelope would reveal the mode of the registration
+        if Self::ciphertext_len(envelope_mode) > Self::ciphertext_len(CS::ENVELOPE_MODE) {
+            return Err(InternalError::IncompatibleEnvelopeModeError.into());
+        }
+
         let mut nonce = vec![0u8; NONCE_LEN];
-        rng.fill_bytes(&mut nonce);
+        fill_random(rng, &mut nonce)?;
 
-        let (mode, client_s_pk) = (
-            InnerEnvelopeMode::Internal,
-            build_inner_envelope_internal::<CS>(randomized_pwd_hasher.clone(), &nonce)?,
-        );
+        let (mode, client_s_pk, ciphertext) = match (envelope_mode, client_static_key) {
+            // The stored keypair would be derived from the password instead
+            (EnvelopeMode::Internal, ClientStaticKey::Stored(_)) => {
+                return Err(InternalError::IncompatibleEnvelopeModeError.into())
+            }
+            (EnvelopeMode::Internal, ClientStaticKey::Default) => (
+                InnerEnvelopeMode::Internal,
+                build_inner_envelope_internal::<CS>(randomized_pwd_hasher.clone(), &nonce)?,
+                Vec::new(),
+            ),
+            // The password still authenticates the envelope, of which the
+            // derived keypair goes unused
+            (EnvelopeMode::Internal, ClientStaticKey::External(client_s_pk)) => {
+                (InnerEnvelopeMode::Internal, client_s_pk, Vec::new())
+            }
+            #[cfg(feature = "aead-envelope")]
+            (EnvelopeMode::Aead, ClientStaticKey::External(_)) => {
+                return Err(InternalError::IncompatibleEnvelopeModeError.into())
+            }
+            #[cfg(feature = "aead-envelope")]
+            (EnvelopeMode::Aead, client_static_key) => {
+                let client_static_keypair = match client_static_key {
+                    ClientStaticKey::Stored(client_static_keypair) => client_static_keypair,
+                    _ => KeyPair::<CS::KeGroup>::generate_random(rng)?,
+                };
+                let (client_s_pk, ciphertext) = build_inner_envelope_aead::<CS>(
+                    randomized_pwd_hasher.clone(),
+                    &nonce,
+                    server_s_pk,
+                    client_static_keypair,
+                )?;
+                (InnerEnvelopeMode::Aead, client_s_pk, ciphertext)
+            }
+        };
 
         let (id_u, id_s) =
             bytestrings_from_identifiers(&optional_ids, &client_s_pk.to_arr(), server_s_pk)?;
         let aad = construct_aad(&id_u, &id_s, server_s_pk);
 
-        let result = Self::seal_raw(randomized_pwd_hasher, &nonce, &aad, mode)?;
+        let result = Self::seal_raw(
+            randomized_pwd_hasher,
+            &nonce,
+            ciphertext,
+            &aad,
+            mode,
+            export_key,
+        )?;
         Ok((
             result.0,
             client_s_pk,
@@ -145,27 +277,31 @@ impl<CS: CipherSuite> Envelope<CS> {
     }
 
     /// Uses a key to convert the plaintext into an envelope, authenticated by the aad field.
-    /// Note that a new nonce is sampled for each call to seal.
+    /// Note that a new nonce is sampled for each call to seal. The export key
+    /// is only derived if `export_key` is set.
     #[allow(clippy::type_complexity)]
     pub(crate) fn seal_raw(
         randomized_pwd_hasher: Hkdf<CS::Hash>,
         nonce: &[u8],
+        ciphertext: Vec<u8>,
         aad: &[u8],
         mode: InnerEnvelopeMode,
+        export_key: bool,
     ) -> Result<SealRawResult<CS>, InternalError> {
-        let mut hmac_key = vec![0u8; Self::hmac_key_size()];
-        let mut export_key = vec![0u8; Self::export_key_size()];
+        let mut hmac_key = GenericArray::<u8, <CS::Hash as Digest>::OutputSize>::default();
 
         randomized_pwd_hasher
-            .expand(&[nonce, STR_AUTH_KEY].concat(), &mut hmac_key)
-            .map_err(|_| InternalError::HkdfError)?;
-        randomized_pwd_hasher
-            .expand(&[nonce, STR_EXPORT_KEY].concat(), &mut export_key)
+            .expand_multi_info(
+                &[nonce, CS::CONTEXT, CS::ENVELOPE_LABELS.auth_key],
+                &mut hmac_key,
+            )
             .map_err(|_| InternalError::HkdfError)?;
+        let export_key = derive_export_key::<CS>(&randomized_pwd_hasher, nonce, export_key)?;
 
         let mut hmac =
             Hmac::<CS::Hash>::new_from_slice(&hmac_key).map_err(|_| InternalError::HmacError)?;
         hmac.update(nonce);
+        hmac.update(&ciphertext);
         hmac.update(aad);
 
         let hmac_bytes = hmac.finalize().into_bytes();
@@ -174,11 +310,12 @@ impl<CS: CipherSuite> Envelope<CS> {
             Self {
                 mode,
                 nonce: nonce.to_vec(),
+                ciphertext,
                 hmac: hmac_bytes,
             },
-            GenericArray::clone_from_slice(&export_key),
+            export_key,
             #[cfg(test)]
-            hmac_key,
+            hmac_key.to_vec(),
         ))
     }
 
@@ -187,6 +324,8 @@ impl<CS: CipherSuite> Envelope<CS> {
         randomized_pwd_hasher: Hkdf<CS::Hash>,
         server_s_pk: &[u8],
         optional_ids: &Option<Identifiers>,
+        external_client_s_pk: Option<&PublicKey<CS::KeGroup>>,
+        export_key: bool,
     ) -> Result<OpenedEnvelope<CS>, ProtocolError> {
         let client_static_keypair = match self.mode {
             InnerEnvelopeMode::Zero => {
@@ -195,16 +334,29 @@ impl<CS: CipherSuite> Envelope<CS> {
             InnerEnvelopeMode::Internal => {
                 recover_keys_internal::<CS>(randomized_pwd_hasher.clone(), &self.nonce)?
             }
+            #[cfg(feature = "aead-envelope")]
+            InnerEnvelopeMode::Aead if external_client_s_pk.is_some() => {
+                return Err(InternalError::IncompatibleEnvelopeModeError.into())
+            }
+            #[cfg(feature = "aead-envelope")]
+            InnerEnvelopeMode::Aead => recover_keys_aead::<CS>(
+                randomized_pwd_hasher.clone(),
+                &self.nonce,
+                &self.ciphertext,
+                server_s_pk,
+            )?,
         };
 
         let (id_u, id_s) = bytestrings_from_identifiers(
             optional_ids,
-            &client_static_keypair.public().to_arr(),
+            &external_client_s_pk
+                .unwrap_or_else(|| client_static_keypair.public())
+                .to_arr(),
             server_s_pk,
         )?;
         let aad = construct_aad(&id_u, &id_s, server_s_pk);
 
-        let opened = self.open_raw(randomized_pwd_hasher, &aad)?;
+        let opened = self.open_raw(randomized_pwd_hasher, &aad, export_key)?;
 
         Ok(OpenedEnvelope {
             client_static_keypair,
@@ -215,91 +367,192 @@ impl<CS: CipherSuite> Envelope<CS> {
     }
 
     /// Attempts to decrypt the envelope using a key, which is successful only if the key and
-    /// aad used to construct the envelope are the same.
+    /// aad used to construct the envelope are the same. The export key is
+    /// only derived if `export_key` is set.
     pub(crate) fn open_raw(
         &self,
         randomized_pwd_hasher: Hkdf<CS::Hash>,
         aad: &[u8],
-    ) -> Result<OpenedInnerEnvelope<CS::Hash>, InternalError> {
-        let mut hmac_key = vec![0u8; Self::hmac_key_size()];
-        let mut export_key = vec![0u8; Self::export_key_size()];
+        export_key: bool,
+    ) -> Result<OpenedInnerEnvelope, InternalError> {
+        let mut hmac_key = GenericArray::<u8, <CS::Hash as Digest>::OutputSize>::default();
 
         randomized_pwd_hasher
-            .expand(
-                &[self.nonce.clone(), STR_AUTH_KEY.to_vec()].concat(),
+            .expand_multi_info(
+                &[&self.nonce, CS::CONTEXT, CS::ENVELOPE_LABELS.auth_key],
                 &mut hmac_key,
             )
             .map_err(|_| InternalError::HkdfError)?;
-        randomized_pwd_hasher
-            .expand(
-                &[self.nonce.clone(), STR_EXPORT_KEY.to_vec()].concat(),
-                &mut export_key,
-            )
-            .map_err(|_| InternalError::HkdfError)?;
 
         let mut hmac =
             Hmac::<CS::Hash>::new_from_slice(&hmac_key).map_err(|_| InternalError::HmacError)?;
         hmac.update(&self.nonce);
+        hmac.update(&self.ciphertext);
         hmac.update(aad);
         if hmac.verify(&self.hmac).is_err() {
             return Err(InternalError::SealOpenHmacError);
         }
 
         Ok(OpenedInnerEnvelope {
-            export_key: GenericArray::<u8, <CS::Hash as Digest>::OutputSize>::clone_from_slice(
-                &export_key,
-            ),
+            export_key: derive_export_key::<CS>(&randomized_pwd_hasher, &self.nonce, export_key)?,
         })
     }
 
-    // Creates a dummy envelope object that serializes to the all-zeros byte string
+    // Decodes the envelope, reporting whether its MAC verifies instead of
+    // failing when it does not
+    #[cfg(feature = "envelope-inspect")]
+    pub(crate) fn inspect(
+        &self,
+        randomized_pwd_hasher: Hkdf<CS::Hash>,
+        server_s_pk: &[u8],
+        optional_ids: &Option<Identifiers>,
+    ) -> Result<EnvelopeInfo, ProtocolError> {
+        let mode = self.mode()?;
+        let client_static_keypair = match self.mode {
+            InnerEnvelopeMode::Zero => {
+                return Err(InternalError::IncompatibleEnvelopeModeError.into())
+            }
+            InnerEnvelopeMode::Internal => {
+                recover_keys_internal::<CS>(randomized_pwd_hasher.clone(), &self.nonce)?
+            }
+            #[cfg(feature = "aead-envelope")]
+            InnerEnvelopeMode::Aead => match recover_keys_aead::<CS>(
+                randomized_pwd_hasher.clone(),
+                &self.nonce,
+                &self.ciphertext,
+                server_s_pk,
+            ) {
+                Ok(client_static_keypair) => client_static_keypair,
+                // A wrong password fails the tag of the private key before
+                // the MAC is reached, and leaves no public key to report
+                Err(ProtocolError::LibraryError(InternalError::SealOpenHmacError)) => {
+                    return Ok(EnvelopeInfo {
+                        mode,
+                        nonce: self.nonce.clone(),
+                        stores_private_key: true,
+                        server_s_pk: server_s_pk.to_vec(),
+                        client_s_pk: Vec::new(),
+                        authenticated: false,
+                    })
+                }
+                Err(e) => return Err(e),
+            },
+        };
+        let client_s_pk = client_static_keypair.public().to_arr();
+
+        let (id_u, id_s) = bytestrings_from_identifiers(optional_ids, &client_s_pk, server_s_pk)?;
+        let aad = construct_aad(&id_u, &id_s, server_s_pk);
+        let authenticated = match self.open_raw(randomized_pwd_hasher, &aad, false) {
+            Ok(_) => true,
+            Err(InternalError::SealOpenHmacError) => false,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(EnvelopeInfo {
+            mode,
+            nonce: self.nonce.clone(),
+            stores_private_key: !self.ciphertext.is_empty(),
+            server_s_pk: server_s_pk.to_vec(),
+            client_s_pk: client_s_pk.to_vec(),
+            authenticated,
+        })
+    }
+
+    pub(crate) fn mode(&self) -> Result<EnvelopeMode, ProtocolError> {
+        match self.mode {
+            InnerEnvelopeMode::Zero => Err(InternalError::IncompatibleEnvelopeModeError.into()),
+            InnerEnvelopeMode::Internal => Ok(EnvelopeMode::Internal),
+            #[cfg(feature = "aead-envelope")]
+            InnerEnvelopeMode::Aead => Ok(EnvelopeMode::Aead),
+        }
+    }
+
+    // Creates a dummy envelope object that serializes to the all-zeros byte
+    // string, with the length of the default mode of the ciphersuite
     pub(crate) fn dummy() -> Self {
         Self {
             mode: InnerEnvelopeMode::Zero,
             nonce: vec![0u8; NONCE_LEN],
-            hmac: GenericArray::clone_from_slice(&vec![
-                0u8;
-                <CS::Hash as Digest>::OutputSize::USIZE
-            ]),
+            ciphertext: vec![0u8; Self::ciphertext_len(CS::ENVELOPE_MODE)],
+            hmac: GenericArray::default(),
         }
     }
 
-    fn hmac_key_size() -> usize {
-        <CS::Hash as Digest>::OutputSize::USIZE
+    // The length of the encrypted private key, which depends on the mode
+    fn ciphertext_len(mode: EnvelopeMode) -> usize {
+        match mode {
+            EnvelopeMode::Internal => 0,
+            #[cfg(feature = "aead-envelope")]
+            EnvelopeMode::Aead => <CS::KeGroup as KeGroup>::SkLen::USIZE + AEAD_TAG_LEN,
+        }
     }
 
-    fn export_key_size() -> usize {
+    // The length of every envelope once serialized, set by the default mode
+    // of the ciphersuite
+    pub(crate) fn len() -> usize {
         <CS::Hash as Digest>::OutputSize::USIZE
+            + NONCE_LEN
+            + Self::ciphertext_len(CS::ENVELOPE_MODE)
     }
 
-    pub(crate) fn len() -> usize {
-        <CS::Hash as Digest>::OutputSize::USIZE + NONCE_LEN
+    // The length of this envelope once serialized
+    pub(crate) fn serialized_len(&self) -> usize {
+        Self::len()
+    }
+
+    // Recovers the mode from the space reserved for the encrypted private
+    // key, which envelopes of the internal mode leave zeroed
+    fn mode_from_ciphertext(ciphertext: &[u8]) -> Option<EnvelopeMode> {
+        if ciphertext.iter().all(|&byte| byte == 0) {
+            return Some(EnvelopeMode::Internal);
+        }
+        ENVELOPE_MODES
+            .iter()
+            .cloned()
+            .find(|&mode| Self::ciphertext_len(mode) == ciphertext.len())
     }
 
+    #[cfg(test)]
     pub(crate) fn serialize(&self) -> Vec<u8> {
-        [&self.nonce[..], &self.hmac[..]].concat()
+        let mut output = Vec::with_capacity(self.serialized_len());
+        self.serialize_into(&mut output);
+        output
     }
-    pub(crate) fn deserialize(bytes: &[u8]) -> Result<Self, ProtocolError> {
-        let mode = InnerEnvelopeMode::Internal; // Better way to hard-code this?
 
-        if bytes.len() < NONCE_LEN {
-            return Err(ProtocolError::SerializationError);
-        }
-        let nonce = bytes[..NONCE_LEN].to_vec();
+    // Appends the serialized envelope to `output`, for callers embedding it
+    // into a larger message
+    pub(crate) fn serialize_into(&self, output: &mut Vec<u8>) {
+        let start = output.len();
+        output.resize(start + Self::len(), 0);
+        self.write_to(&mut output[start..]);
+    }
 
-        let remainder = match mode {
-            InnerEnvelopeMode::Zero => {
-                return Err(InternalError::IncompatibleEnvelopeModeError.into())
-            }
-            InnerEnvelopeMode::Internal => bytes[NONCE_LEN..].to_vec(),
-        };
+    // Writes the serialized envelope into `output`, which holds exactly
+    // `Self::len()` bytes
+    pub(crate) fn write_to(&self, output: &mut [u8]) {
+        let (nonce, rest) = output.split_at_mut(NONCE_LEN);
+        let (ciphertext, hmac) = rest.split_at_mut(Self::ciphertext_len(CS::ENVELOPE_MODE));
+        nonce.copy_from_slice(&self.nonce);
+        // Pads the envelopes of shorter modes to the common length
+        let (ciphertext, padding) = ciphertext.split_at_mut(self.ciphertext.len());
+        ciphertext.copy_from_slice(&self.ciphertext);
+        padding.iter_mut().for_each(|byte| *byte = 0);
+        hmac.copy_from_slice(&self.hmac);
+    }
 
-        let hmac_key_size = Self::hmac_key_size();
-        let hmac = check_slice_size(&remainder, hmac_key_size, "hmac_key_size")?;
+    pub(crate) fn deserialize(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let bytes = check_slice_size(bytes, Self::len(), "envelope_bytes")?;
+        let ciphertext_end = NONCE_LEN + Self::ciphertext_len(CS::ENVELOPE_MODE);
+        let mode = Self::mode_from_ciphertext(&bytes[NONCE_LEN..ciphertext_end])
+            .ok_or(ProtocolError::SerializationError)?;
+        let nonce = bytes[..NONCE_LEN].to_vec();
+        let ciphertext = bytes[NONCE_LEN..NONCE_LEN + Self::ciphertext_len(mode)].to_vec();
+        let hmac = &bytes[ciphertext_end..];
 
         Ok(Self {
-            mode,
+            mode: InnerEnvelopeMode::from(mode),
             nonce,
+            ciphertext,
             hmac: GenericArray::clone_from_slice(hmac),
         })
     }
@@ -310,6 +563,7 @@ impl<CS: CipherSuite> Zeroize for Envelope<CS> {
     fn zeroize(&mut self) {
         self.mode.zeroize();
         self.nonce.zeroize();
+        self.ciphertext.zeroize();
         self.hmac.zeroize();
     }
 }
@@ -328,7 +582,10 @@ fn build_inner_envelope_internal<CS: CipherSuite>(
 ) -> Result<PublicKey<CS::KeGroup>, ProtocolError> {
     let mut keypair_seed = vec![0u8; <CS::KeGroup as KeGroup>::SkLen::USIZE];
     randomized_pwd_hasher
-        .expand(&[nonce, STR_PRIVATE_KEY].concat(), &mut keypair_seed)
+        .expand_multi_info(
+            &[nonce, CS::CONTEXT, CS::ENVELOPE_LABELS.private_key],
+            &mut keypair_seed,
+        )
         .map_err(|_| InternalError::HkdfError)?;
     let client_static_keypair = KeyPair::<CS::KeGroup>::from_private_key_slice(
         &CS::OprfGroup::scalar_as_bytes(CS::OprfGroup::hash_to_scalar::<CS::Hash, _, _>(
@@ -346,7 +603,10 @@ fn recover_keys_internal<CS: CipherSuite>(
 ) -> Result<KeyPair<CS::KeGroup>, ProtocolError> {
     let mut keypair_seed = vec![0u8; <CS::KeGroup as KeGroup>::SkLen::USIZE];
     randomized_pwd_hasher
-        .expand(&[nonce, STR_PRIVATE_KEY].concat(), &mut keypair_seed)
+        .expand_multi_info(
+            &[nonce, CS::CONTEXT, CS::ENVELOPE_LABELS.private_key],
+            &mut keypair_seed,
+        )
         .map_err(|_| InternalError::HkdfError)?;
     let client_static_keypair = KeyPair::<CS::KeGroup>::from_private_key_slice(
         &CS::OprfGroup::scalar_as_bytes(CS::OprfGroup::hash_to_scalar::<CS::Hash, _, _>(
@@ -358,6 +618,83 @@ fn recover_keys_internal<CS: CipherSuite>(
     Ok(client_static_keypair)
 }
 
+// Only derives the export key when the caller asked for it, so that it never
+// exists in memory otherwise
+fn derive_export_key<CS: CipherSuite>(
+    randomized_pwd_hasher: &Hkdf<CS::Hash>,
+    nonce: &[u8],
+    export_key: bool,
+) -> Result<Option<Vec<u8>>, InternalError> {
+    if !export_key {
+        return Ok(None);
+    }
+    let mut export_key = vec![0u8; CS::EXPORT_KEY_LEN];
+    randomized_pwd_hasher
+        .expand_multi_info(
+            &[nonce, CS::CONTEXT, CS::ENVELOPE_LABELS.export_key],
+            &mut export_key,
+        )
+        .map_err(|_| InternalError::HkdfError)?;
+    Ok(Some(export_key))
+}
+
+// Derives the XChaCha20-Poly1305 key and nonce protecting the private key
+#[cfg(feature = "aead-envelope")]
+fn aead_cipher<CS: CipherSuite>(
+    randomized_pwd_hasher: &Hkdf<CS::Hash>,
+    nonce: &[u8],
+) -> Result<(XChaCha20Poly1305, XNonce), InternalError> {
+    let mut aead_key = Key::default();
+    let mut aead_nonce = XNonce::default();
+    randomized_pwd_hasher
+        .expand_multi_info(&[nonce, CS::CONTEXT, STR_AEAD_KEY], &mut aead_key)
+        .map_err(|_| InternalError::HkdfError)?;
+    randomized_pwd_hasher
+        .expand_multi_info(&[nonce, CS::CONTEXT, STR_AEAD_NONCE], &mut aead_nonce)
+        .map_err(|_| InternalError::HkdfError)?;
+
+    let cipher = XChaCha20Poly1305::new(&aead_key);
+    aead_key.zeroize();
+    Ok((cipher, aead_nonce))
+}
+
+#[cfg(feature = "aead-envelope")]
+fn build_inner_envelope_aead<CS: CipherSuite>(
+    randomized_pwd_hasher: Hkdf<CS::Hash>,
+    nonce: &[u8],
+    server_s_pk: &[u8],
+    client_static_keypair: KeyPair<CS::KeGroup>,
+) -> Result<(PublicKey<CS::KeGroup>, Vec<u8>), ProtocolError> {
+    let (cipher, aead_nonce) = aead_cipher::<CS>(&randomized_pwd_hasher, nonce)?;
+
+    let mut ciphertext = client_static_keypair.private().to_vec();
+    cipher
+        .encrypt_in_place(&aead_nonce, server_s_pk, &mut ciphertext)
+        .map_err(|_| InternalError::InvalidInnerEnvelopeError)?;
+
+    Ok((client_static_keypair.public().clone(), ciphertext))
+}
+
+#[cfg(feature = "aead-envelope")]
+fn recover_keys_aead<CS: CipherSuite>(
+    randomized_pwd_hasher: Hkdf<CS::Hash>,
+    nonce: &[u8],
+    ciphertext: &[u8],
+    server_s_pk: &[u8],
+) -> Result<KeyPair<CS::KeGroup>, ProtocolError> {
+    let (cipher, aead_nonce) = aead_cipher::<CS>(&randomized_pwd_hasher, nonce)?;
+
+    // A wrong password is reported the same way as in the internal mode
+    let mut private_key = ciphertext.to_vec();
+    cipher
+        .decrypt_in_place(&aead_nonce, server_s_pk, &mut private_key)
+        .map_err(|_| InternalError::SealOpenHmacError)?;
+    let client_static_keypair = KeyPair::<CS::KeGroup>::from_private_key_slice(&private_key);
+    private_key.zeroize();
+
+    client_static_keypair
+}
+
 fn construct_aad(id_u: &[u8], id_s: &[u8], server_s_pk: &[u8]) -> Vec<u8> {
     [server_s_pk, id_s, id_u].concat()
 }
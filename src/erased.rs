@@ -0,0 +1,537 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Type-erased protocol states over a fixed set of ciphersuites
+//!
+//! The types in the rest of this crate are generic over a [CipherSuite],
+//! which is the right choice when the ciphersuite is known at compile time.
+//! Plugin systems and FFI layers instead tend to pick the ciphersuite at
+//! runtime and need to hold protocol state without generic parameters. This
+//! module offers object-safe traits for that purpose, implemented for the
+//! ciphersuites listed in [Suite]. Messages, records and states are passed
+//! around as bytes in their usual serialization.
+//!
+//! ```
+//! # use opaque_ke::errors::ProtocolError;
+//! use opaque_ke::erased::Suite;
+//! use rand::rngs::OsRng;
+//!
+//! let suite = Suite::Ristretto255Sha512;
+//! let server_setup = suite.new_server_setup(&mut OsRng)?;
+//!
+//! // Registration
+//! let client_start = suite.client_registration_start(&mut OsRng, b"password")?;
+//! let response = server_setup.registration_start(&client_start.message, b"alice")?;
+//! let client_finish = client_start.state.finish(&mut OsRng, &response)?;
+//! let password_file = server_setup.registration_finish(&client_finish.message)?;
+//!
+//! // Login
+//! let client_start = suite.client_login_start(&mut OsRng, b"password")?;
+//! let server_start = server_setup.login_start(
+//!     &mut OsRng,
+//!     Some(&password_file),
+//!     &client_start.message,
+//!     b"alice",
+//! )?;
+//! let client_finish = client_start.state.finish(&server_start.message)?;
+//! let server_finish = server_start.state.finish(&client_finish.message)?;
+//! assert_eq!(client_finish.session_key, server_finish);
+//! # Ok::<(), ProtocolError>(())
+//! ```
+
+use crate::{
+    ciphersuite::CipherSuite,
+    errors::ProtocolError,
+    key_exchange::tripledh::TripleDH,
+    messages::{CredentialFinalization, CredentialRequest, CredentialResponse},
+    messages::{RegistrationRequest, RegistrationResponse, RegistrationUpload},
+    opaque::ServerLoginStartParameters,
+    opaque::{ClientLogin, ClientRegistration, ServerLogin, ServerRegistration, ServerSetup},
+    opaque::{ClientLoginFinishParameters, ClientRegistrationFinishParameters},
+    opaque::{ExportKey, SessionKey},
+    slow_hash::NoOpHash,
+};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use curve25519_dalek::ristretto::RistrettoPoint;
+use rand::{CryptoRng, RngCore};
+
+/// An RNG which can be passed as a trait object, implemented for every
+/// [`RngCore`] + [`CryptoRng`]
+pub trait ErasedRng: RngCore + CryptoRng {}
+
+impl<R: RngCore + CryptoRng + ?Sized> ErasedRng for R {}
+
+/// Ristretto255 for both the OPRF and the key exchange, with SHA-512 and
+/// without a slow hash
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Ristretto255Sha512;
+
+impl CipherSuite for Ristretto255Sha512 {
+    type OprfGroup = RistrettoPoint;
+    type KeGroup = RistrettoPoint;
+    type KeyExchange = TripleDH;
+    type Hash = sha2::Sha512;
+    type SlowHash = NoOpHash;
+}
+
+/// Ristretto255 for both the OPRF and the key exchange, with SHA-512 and
+/// Argon2 with its default parameters as the slow hash
+#[cfg(feature = "slow-hash")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Ristretto255Sha512Argon2;
+
+#[cfg(feature = "slow-hash")]
+impl CipherSuite for Ristretto255Sha512Argon2 {
+    type OprfGroup = RistrettoPoint;
+    type KeGroup = RistrettoPoint;
+    type KeyExchange = TripleDH;
+    type Hash = sha2::Sha512;
+    type SlowHash = argon2::Argon2<'static>;
+}
+
+#[cfg(feature = "p256")]
+pub use crate::ciphersuite::P256Sha256;
+
+/// The ciphersuites available through the type-erased API
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Suite {
+    /// [Ristretto255Sha512]
+    Ristretto255Sha512 = 1,
+    /// [Ristretto255Sha512Argon2]
+    #[cfg(feature = "slow-hash")]
+    Ristretto255Sha512Argon2 = 2,
+    /// [P256Sha256]
+    #[cfg(feature = "p256")]
+    P256Sha256 = 3,
+}
+
+// Runs `$body` with `$cs` bound to the ciphersuite of `$suite`
+macro_rules! with_suite {
+    ($suite:expr, $cs:ident => $body:expr) => {
+        match $suite {
+            Suite::Ristretto255Sha512 => {
+                type $cs = Ristretto255Sha512;
+                $body
+            }
+            #[cfg(feature = "slow-hash")]
+            Suite::Ristretto255Sha512Argon2 => {
+                type $cs = Ristretto255Sha512Argon2;
+                $body
+            }
+            #[cfg(feature = "p256")]
+            Suite::P256Sha256 => {
+                type $cs = P256Sha256;
+                $body
+            }
+        }
+    };
+}
+
+/// Associates a ciphersuite with its [Suite]
+trait SuiteId: CipherSuite + 'static {
+    const SUITE: Suite;
+}
+
+impl SuiteId for Ristretto255Sha512 {
+    const SUITE: Suite = Suite::Ristretto255Sha512;
+}
+
+#[cfg(feature = "slow-hash")]
+impl SuiteId for Ristretto255Sha512Argon2 {
+    const SUITE: Suite = Suite::Ristretto255Sha512Argon2;
+}
+
+#[cfg(feature = "p256")]
+impl SuiteId for P256Sha256 {
+    const SUITE: Suite = Suite::P256Sha256;
+}
+
+impl Suite {
+    /// The byte identifying this ciphersuite, e.g. when storing it alongside
+    /// serialized states
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    /// Looks up a ciphersuite by its [id](Self::id), failing for ciphersuites
+    /// which are unknown or not enabled
+    pub fn from_id(id: u8) -> Result<Self, ProtocolError> {
+        match id {
+            1 => Ok(Self::Ristretto255Sha512),
+            #[cfg(feature = "slow-hash")]
+            2 => Ok(Self::Ristretto255Sha512Argon2),
+            #[cfg(feature = "p256")]
+            3 => Ok(Self::P256Sha256),
+            _ => Err(ProtocolError::SerializationError),
+        }
+    }
+
+    /// Generates a new [ErasedServerSetup] for this ciphersuite
+    pub fn new_server_setup(
+        self,
+        rng: &mut dyn ErasedRng,
+    ) -> Result<Box<dyn ErasedServerSetup>, ProtocolError> {
+        with_suite!(self, CS => Ok(Box::new(ServerSetup::<CS>::new(rng)?)))
+    }
+
+    /// Deserializes an [ErasedServerSetup] of this ciphersuite
+    pub fn deserialize_server_setup(
+        self,
+        input: &[u8],
+    ) -> Result<Box<dyn ErasedServerSetup>, ProtocolError> {
+        with_suite!(self, CS => Ok(Box::new(ServerSetup::<CS>::deserialize(input)?)))
+    }
+
+    /// Starts a registration, see [ClientRegistration::start]
+    pub fn client_registration_start(
+        self,
+        rng: &mut dyn ErasedRng,
+        password: &[u8],
+    ) -> Result<ErasedStartResult<dyn ErasedClientRegistration>, ProtocolError> {
+        with_suite!(self, CS => {
+            let result = ClientRegistration::<CS>::start(rng, password)?;
+            Ok(ErasedStartResult {
+                message: result.message.serialize()?,
+                state: Box::new(result.state),
+            })
+        })
+    }
+
+    /// Deserializes an [ErasedClientRegistration] of this ciphersuite
+    pub fn deserialize_client_registration(
+        self,
+        input: &[u8],
+    ) -> Result<Box<dyn ErasedClientRegistration>, ProtocolError> {
+        with_suite!(self, CS => Ok(Box::new(ClientRegistration::<CS>::deserialize(input)?)))
+    }
+
+    /// Starts a login, see [ClientLogin::start]
+    pub fn client_login_start(
+        self,
+        rng: &mut dyn ErasedRng,
+        password: &[u8],
+    ) -> Result<ErasedStartResult<dyn ErasedClientLogin>, ProtocolError> {
+        with_suite!(self, CS => {
+            let result = ClientLogin::<CS>::start(rng, password)?;
+            Ok(ErasedStartResult {
+                message: result.message.serialize()?,
+                state: Box::new(result.state),
+            })
+        })
+    }
+
+    /// Deserializes an [ErasedClientLogin] of this ciphersuite
+    pub fn deserialize_client_login(
+        self,
+        input: &[u8],
+    ) -> Result<Box<dyn ErasedClientLogin>, ProtocolError> {
+        with_suite!(self, CS => Ok(Box::new(ClientLogin::<CS>::deserialize(input)?)))
+    }
+
+    /// Deserializes an [ErasedServerLogin] of this ciphersuite
+    pub fn deserialize_server_login(
+        self,
+        input: &[u8],
+    ) -> Result<Box<dyn ErasedServerLogin>, ProtocolError> {
+        with_suite!(self, CS => Ok(Box::new(ServerLogin::<CS>::deserialize(input)?)))
+    }
+}
+
+/// The serialized message to send to the other party, along with the state
+/// to keep until its response arrives
+#[derive(Debug)]
+pub struct ErasedStartResult<S: ?Sized> {
+    /// The serialized message
+    pub message: Vec<u8>,
+    /// The protocol state
+    pub state: Box<S>,
+}
+
+/// The outcome of [ErasedClientRegistration::finish]
+#[derive(Debug)]
+pub struct ErasedClientRegistrationFinishResult {
+    /// The serialized [RegistrationUpload] to send to the server
+    pub message: Vec<u8>,
+    /// The client's export key, if requested with `finish_with_export_key`
+    pub export_key: Option<ExportKey>,
+    /// The server's static public key
+    pub server_s_pk: Vec<u8>,
+}
+
+/// The outcome of [ErasedClientLogin::finish]
+#[derive(Debug)]
+pub struct ErasedClientLoginFinishResult {
+    /// The serialized [CredentialFinalization] to send to the server
+    pub message: Vec<u8>,
+    /// The shared session key
+    pub session_key: SessionKey,
+    /// The client's export key, if requested with `finish_with_export_key`
+    pub export_key: Option<ExportKey>,
+    /// The server's static public key
+    pub server_s_pk: Vec<u8>,
+}
+
+/// A type-erased [ServerSetup]
+pub trait ErasedServerSetup: Debug + Send + Sync {
+    /// The ciphersuite of this server setup
+    fn suite(&self) -> Suite;
+
+    /// Serialization into bytes
+    fn serialize(&self) -> Result<Vec<u8>, ProtocolError>;
+
+    /// Answers a serialized [RegistrationRequest] with a serialized
+    /// [RegistrationResponse], see [ServerRegistration::start]
+    fn registration_start(
+        &self,
+        message: &[u8],
+        credential_identifier: &[u8],
+    ) -> Result<Vec<u8>, ProtocolError>;
+
+    /// Turns a serialized [RegistrationUpload] into the serialized password
+    /// file, see [ServerRegistration::finish]
+    fn registration_finish(&self, message: &[u8]) -> Result<Vec<u8>, ProtocolError>;
+
+    /// Answers a serialized [CredentialRequest] with a serialized
+    /// [CredentialResponse], see [ServerLogin::start]
+    fn login_start(
+        &self,
+        rng: &mut dyn ErasedRng,
+        password_file: Option<&[u8]>,
+        message: &[u8],
+        credential_identifier: &[u8],
+    ) -> Result<ErasedStartResult<dyn ErasedServerLogin>, ProtocolError>;
+}
+
+impl<CS: SuiteId> ErasedServerSetup for ServerSetup<CS>
+where
+    ServerSetup<CS>: Send + Sync,
+    ServerLogin<CS>: Send + Sync,
+{
+    fn suite(&self) -> Suite {
+        CS::SUITE
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
+        ServerSetup::serialize(self)
+    }
+
+    fn registration_start(
+        &self,
+        message: &[u8],
+        credential_identifier: &[u8],
+    ) -> Result<Vec<u8>, ProtocolError> {
+        ServerRegistration::start(
+            self,
+            RegistrationRequest::deserialize(message)?,
+            credential_identifier,
+        )?
+        .message
+        .serialize()
+    }
+
+    fn registration_finish(&self, message: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        ServerRegistration::<CS>::finish(RegistrationUpload::deserialize(message)?).serialize()
+    }
+
+    fn login_start(
+        &self,
+        rng: &mut dyn ErasedRng,
+        password_file: Option<&[u8]>,
+        message: &[u8],
+        credential_identifier: &[u8],
+    ) -> Result<ErasedStartResult<dyn ErasedServerLogin>, ProtocolError> {
+        let password_file = password_file
+            .map(ServerRegistration::deserialize)
+            .transpose()?;
+        let result = ServerLogin::start(
+            rng,
+            self,
+            password_file,
+            CredentialRequest::deserialize(message)?,
+            credential_identifier,
+            ServerLoginStartParameters::default(),
+        )?;
+        Ok(ErasedStartResult {
+            message: result.message.serialize()?,
+            state: Box::new(result.state),
+        })
+    }
+}
+
+/// A type-erased [ClientRegistration]
+pub trait ErasedClientRegistration: Debug + Send + Sync {
+    /// The ciphersuite of this state
+    fn suite(&self) -> Suite;
+
+    /// Serialization into bytes
+    fn serialize(&self) -> Result<Vec<u8>, ProtocolError>;
+
+    /// Completes the registration with the server's serialized
+    /// [RegistrationResponse], see [ClientRegistration::finish]
+    fn finish(
+        self: Box<Self>,
+        rng: &mut dyn ErasedRng,
+        message: &[u8],
+    ) -> Result<ErasedClientRegistrationFinishResult, ProtocolError>;
+
+    /// Same as [finish](Self::finish), but also returns the export key
+    fn finish_with_export_key(
+        self: Box<Self>,
+        rng: &mut dyn ErasedRng,
+        message: &[u8],
+    ) -> Result<ErasedClientRegistrationFinishResult, ProtocolError>;
+}
+
+impl<CS: SuiteId> ErasedClientRegistration for ClientRegistration<CS>
+where
+    ClientRegistration<CS>: Send + Sync,
+{
+    fn suite(&self) -> Suite {
+        CS::SUITE
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
+        ClientRegistration::serialize(self)
+    }
+
+    fn finish(
+        self: Box<Self>,
+        rng: &mut dyn ErasedRng,
+        message: &[u8],
+    ) -> Result<ErasedClientRegistrationFinishResult, ProtocolError> {
+        finish_client_registration(*self, rng, message, false)
+    }
+
+    fn finish_with_export_key(
+        self: Box<Self>,
+        rng: &mut dyn ErasedRng,
+        message: &[u8],
+    ) -> Result<ErasedClientRegistrationFinishResult, ProtocolError> {
+        finish_client_registration(*self, rng, message, true)
+    }
+}
+
+fn finish_client_registration<CS: CipherSuite>(
+    state: ClientRegistration<CS>,
+    rng: &mut dyn ErasedRng,
+    message: &[u8],
+    export_key: bool,
+) -> Result<ErasedClientRegistrationFinishResult, ProtocolError> {
+    let result = state.finish(
+        rng,
+        RegistrationResponse::deserialize(message)?,
+        ClientRegistrationFinishParameters {
+            export_key,
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+    Ok(ErasedClientRegistrationFinishResult {
+        message: result.message.serialize()?,
+        export_key: result.export_key,
+        server_s_pk: result.server_s_pk.to_arr().to_vec(),
+    })
+}
+
+/// A type-erased [ClientLogin]
+pub trait ErasedClientLogin: Debug + Send + Sync {
+    /// The ciphersuite of this state
+    fn suite(&self) -> Suite;
+
+    /// Serialization into bytes
+    fn serialize(&self) -> Result<Vec<u8>, ProtocolError>;
+
+    /// Completes the login with the server's serialized
+    /// [CredentialResponse], see [ClientLogin::finish]
+    fn finish(
+        self: Box<Self>,
+        message: &[u8],
+    ) -> Result<ErasedClientLoginFinishResult, ProtocolError>;
+
+    /// Same as [finish](Self::finish), but also returns the export key
+    fn finish_with_export_key(
+        self: Box<Self>,
+        message: &[u8],
+    ) -> Result<ErasedClientLoginFinishResult, ProtocolError>;
+}
+
+impl<CS: SuiteId> ErasedClientLogin for ClientLogin<CS>
+where
+    ClientLogin<CS>: Send + Sync,
+{
+    fn suite(&self) -> Suite {
+        CS::SUITE
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
+        ClientLogin::serialize(self)
+    }
+
+    fn finish(
+        self: Box<Self>,
+        message: &[u8],
+    ) -> Result<ErasedClientLoginFinishResult, ProtocolError> {
+        finish_client_login(*self, message, false)
+    }
+
+    fn finish_with_export_key(
+        self: Box<Self>,
+        message: &[u8],
+    ) -> Result<ErasedClientLoginFinishResult, ProtocolError> {
+        finish_client_login(*self, message, true)
+    }
+}
+
+fn finish_client_login<CS: CipherSuite>(
+    state: ClientLogin<CS>,
+    message: &[u8],
+    export_key: bool,
+) -> Result<ErasedClientLoginFinishResult, ProtocolError> {
+    let result = state.finish(
+        CredentialResponse::deserialize(message)?,
+        ClientLoginFinishParameters {
+            export_key,
+            ..ClientLoginFinishParameters::default()
+        },
+    )?;
+    Ok(ErasedClientLoginFinishResult {
+        message: result.message.serialize()?,
+        session_key: result.session_key,
+        export_key: result.export_key,
+        server_s_pk: result.server_s_pk.to_arr().to_vec(),
+    })
+}
+
+/// A type-erased [ServerLogin]
+pub trait ErasedServerLogin: Debug + Send + Sync {
+    /// The ciphersuite of this state
+    fn suite(&self) -> Suite;
+
+    /// Serialization into bytes
+    fn serialize(&self) -> Result<Vec<u8>, ProtocolError>;
+
+    /// Checks the client's serialized [CredentialFinalization] and returns
+    /// the session key, see [ServerLogin::finish]
+    fn finish(self: Box<Self>, message: &[u8]) -> Result<SessionKey, ProtocolError>;
+}
+
+impl<CS: SuiteId> ErasedServerLogin for ServerLogin<CS>
+where
+    ServerLogin<CS>: Send + Sync,
+{
+    fn suite(&self) -> Suite {
+        CS::SUITE
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
+        ServerLogin::serialize(self)
+    }
+
+    fn finish(self: Box<Self>, message: &[u8]) -> Result<SessionKey, ProtocolError> {
+        Ok(ServerLogin::finish(*self, CredentialFinalization::deserialize(message)?)?.session_key)
+    }
+}
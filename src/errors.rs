@@ -4,15 +4,21 @@
 // LICENSE file in the root directory of this source tree.
 
 //! A list of error types which are produced during an execution of the protocol
+use alloc::sync::Arc;
+use core::any::Any;
 use core::convert::Infallible;
-use core::fmt::Debug;
+use core::fmt::{Debug, Display};
 #[cfg(feature = "std")]
 use std::error::Error;
 
 use displaydoc::Display;
 
 /// Represents an error in the manipulation of internal cryptographic data
+///
+/// Variants are added as new features need them, so matches on this enum must
+/// include a wildcard arm.
 #[derive(Clone, Display, Eq, Hash, PartialEq)]
+#[non_exhaustive]
 pub enum InternalError<T = Infallible> {
     /// Custom [`SecretKey`](crate::keypair::SecretKey) error type
     Custom(T),
@@ -37,6 +43,8 @@ pub enum InternalError<T = Infallible> {
     HmacError,
     /// Computing the slow hashing function failed
     SlowHashError,
+    /// Computing the slow hashing function was cancelled
+    SlowHashCancelledError,
     /** This error occurs when the envelope seal open hmac check fails
     HMAC check in seal open failed. */
     SealOpenHmacError,
@@ -49,6 +57,27 @@ pub enum InternalError<T = Infallible> {
     OprfError(voprf::errors::InternalError),
     /// Error encountered when attempting to produce a keypair
     InvalidKeypairError,
+    /// The ciphersuite binds the credential identifier into the OPRF input,
+    /// but the client started without one
+    MissingCredentialIdentifierError,
+    /// Decrypting a backup container failed, because of a wrong key or a
+    /// modified container
+    BackupDecryptionError,
+    /// Verifying the signature of a registration record failed, because of a
+    /// wrong key or credential identifier, or a modified record
+    RecordSignatureError,
+    /// Decrypting a sealed registration record failed, because of a wrong
+    /// key or a modified record
+    RecordDecryptionError,
+    /// The export key was not requested in the finish parameters
+    ExportKeyNotRequestedError,
+    /// Error from a user-supplied primitive: {0}
+    External(ExternalError),
+    /// The random number generator failed to produce randomness
+    RngError,
+    /// The difficulty of a puzzle exceeds
+    /// [`MAX_DIFFICULTY`](crate::puzzle::MAX_DIFFICULTY)
+    PuzzleDifficultyError,
 }
 
 impl<T: Debug> Debug for InternalError<T> {
@@ -71,6 +100,7 @@ impl<T: Debug> Debug for InternalError<T> {
             Self::HkdfError => f.debug_tuple("HkdfError").finish(),
             Self::HmacError => f.debug_tuple("HmacError").finish(),
             Self::SlowHashError => f.debug_tuple("SlowHashError").finish(),
+            Self::SlowHashCancelledError => f.debug_tuple("SlowHashCancelledError").finish(),
             Self::SealOpenHmacError => f.debug_tuple("SealOpenHmacError").finish(),
             Self::IncompatibleEnvelopeModeError => {
                 f.debug_tuple("IncompatibleEnvelopeModeError").finish()
@@ -78,6 +108,64 @@ impl<T: Debug> Debug for InternalError<T> {
             Self::InvalidInnerEnvelopeError => f.debug_tuple("InvalidInnerEnvelopeError").finish(),
             Self::OprfError(error) => f.debug_tuple("OprfError").field(error).finish(),
             Self::InvalidKeypairError => f.debug_tuple("InvalidKeypairError").finish(),
+            Self::MissingCredentialIdentifierError => {
+                f.debug_tuple("MissingCredentialIdentifierError").finish()
+            }
+            Self::BackupDecryptionError => f.debug_tuple("BackupDecryptionError").finish(),
+            Self::RecordSignatureError => f.debug_tuple("RecordSignatureError").finish(),
+            Self::RecordDecryptionError => f.debug_tuple("RecordDecryptionError").finish(),
+            Self::ExportKeyNotRequestedError => {
+                f.debug_tuple("ExportKeyNotRequestedError").finish()
+            }
+            Self::External(error) => f.debug_tuple("External").field(error).finish(),
+            Self::RngError => f.debug_tuple("RngError").finish(),
+            Self::PuzzleDifficultyError => f.debug_tuple("PuzzleDifficultyError").finish(),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T: defmt::Format> defmt::Format for InternalError<T> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        match self {
+            Self::Custom(custom) => defmt::write!(f, "Custom({})", custom),
+            Self::InvalidByteSequence => defmt::write!(f, "InvalidByteSequence"),
+            Self::SizeError {
+                name,
+                len,
+                actual_len,
+            } => defmt::write!(
+                f,
+                "SizeError {{ name: {=str}, len: {=usize}, actual_len: {=usize} }}",
+                name,
+                len,
+                actual_len
+            ),
+            Self::PointError => defmt::write!(f, "PointError"),
+            Self::HashToCurveError => defmt::write!(f, "HashToCurveError"),
+            Self::HkdfError => defmt::write!(f, "HkdfError"),
+            Self::HmacError => defmt::write!(f, "HmacError"),
+            Self::SlowHashError => defmt::write!(f, "SlowHashError"),
+            Self::SlowHashCancelledError => defmt::write!(f, "SlowHashCancelledError"),
+            Self::SealOpenHmacError => defmt::write!(f, "SealOpenHmacError"),
+            Self::IncompatibleEnvelopeModeError => {
+                defmt::write!(f, "IncompatibleEnvelopeModeError")
+            }
+            Self::InvalidInnerEnvelopeError => defmt::write!(f, "InvalidInnerEnvelopeError"),
+            // voprf errors don't implement `defmt::Format`
+            Self::OprfError(_) => defmt::write!(f, "OprfError"),
+            Self::InvalidKeypairError => defmt::write!(f, "InvalidKeypairError"),
+            Self::MissingCredentialIdentifierError => {
+                defmt::write!(f, "MissingCredentialIdentifierError")
+            }
+            Self::BackupDecryptionError => defmt::write!(f, "BackupDecryptionError"),
+            Self::RecordSignatureError => defmt::write!(f, "RecordSignatureError"),
+            Self::RecordDecryptionError => defmt::write!(f, "RecordDecryptionError"),
+            Self::ExportKeyNotRequestedError => defmt::write!(f, "ExportKeyNotRequestedError"),
+            // External errors are only known to implement `Debug` and `Display`
+            Self::External(_) => defmt::write!(f, "External"),
+            Self::RngError => defmt::write!(f, "RngError"),
+            Self::PuzzleDifficultyError => defmt::write!(f, "PuzzleDifficultyError"),
         }
     }
 }
@@ -88,8 +176,16 @@ impl<T: Error> Error for InternalError<T> {}
 impl InternalError {
     /// Convert `InternalError<Infallible>` into `InternalError<T>
     pub fn into_custom<T>(self) -> InternalError<T> {
+        self.map_custom(|never| match never {})
+    }
+}
+
+impl<T> InternalError<T> {
+    /// Convert `InternalError<T>` into `InternalError<U>` by applying `f` to
+    /// the custom error, if any
+    pub fn map_custom<U>(self, f: impl FnOnce(T) -> U) -> InternalError<U> {
         match self {
-            Self::Custom(_) => unreachable!(),
+            Self::Custom(custom) => InternalError::Custom(f(custom)),
             Self::InvalidByteSequence => InternalError::InvalidByteSequence,
             Self::SizeError {
                 name,
@@ -105,15 +201,97 @@ impl InternalError {
             Self::HkdfError => InternalError::HkdfError,
             Self::HmacError => InternalError::HmacError,
             Self::SlowHashError => InternalError::SlowHashError,
+            Self::SlowHashCancelledError => InternalError::SlowHashCancelledError,
             Self::SealOpenHmacError => InternalError::SealOpenHmacError,
             Self::IncompatibleEnvelopeModeError => InternalError::IncompatibleEnvelopeModeError,
             Self::InvalidInnerEnvelopeError => InternalError::InvalidInnerEnvelopeError,
             Self::OprfError(error) => InternalError::OprfError(error),
             Self::InvalidKeypairError => InternalError::InvalidKeypairError,
+            Self::MissingCredentialIdentifierError => {
+                InternalError::MissingCredentialIdentifierError
+            }
+            Self::BackupDecryptionError => InternalError::BackupDecryptionError,
+            Self::RecordSignatureError => InternalError::RecordSignatureError,
+            Self::RecordDecryptionError => InternalError::RecordDecryptionError,
+            Self::ExportKeyNotRequestedError => InternalError::ExportKeyNotRequestedError,
+            Self::External(error) => InternalError::External(error),
+            Self::RngError => InternalError::RngError,
+            Self::PuzzleDifficultyError => InternalError::PuzzleDifficultyError,
         }
     }
 }
 
+/// An error returned by a user-supplied primitive whose error type is not a
+/// parameter of [`InternalError`], such as a
+/// [`SlowHash`](crate::slow_hash::SlowHash) or a
+/// [`KeGroup`](crate::key_exchange::group::KeGroup). It is passed through the
+/// protocol functions unchanged as [`InternalError::External`], and the
+/// original error can be recovered with [`ExternalError::downcast_ref`].
+/// Storage hooks and [`SecretKey`](crate::keypair::SecretKey)s instead return
+/// their errors as [`InternalError::Custom`].
+///
+/// Clones share the original error, and two errors are only equal if one is
+/// a clone of the other.
+#[derive(Clone)]
+pub struct ExternalError(Arc<dyn Source>);
+
+// Lets the original error be accessed both as `Any` and through its
+// formatting traits
+trait Source: Any + Debug + Display + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<E: Any + Debug + Display + Send + Sync> Source for E {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl ExternalError {
+    /// Wraps `error`
+    pub fn new<E: Debug + Display + Send + Sync + 'static>(error: E) -> Self {
+        Self(Arc::new(error))
+    }
+
+    /// The original error, if it is of type `E`
+    pub fn downcast_ref<E: 'static>(&self) -> Option<&E> {
+        self.0.as_any().downcast_ref()
+    }
+}
+
+impl Debug for ExternalError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        Debug::fmt(&*self.0, f)
+    }
+}
+
+impl Display for ExternalError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        Display::fmt(&*self.0, f)
+    }
+}
+
+impl PartialEq for ExternalError {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ExternalError {}
+
+impl core::hash::Hash for ExternalError {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const () as usize).hash(state);
+    }
+}
+
+impl<T> InternalError<T> {
+    /// Wraps an error of a user-supplied primitive, see [ExternalError]
+    pub fn external<E: Debug + Display + Send + Sync + 'static>(error: E) -> Self {
+        Self::External(ExternalError::new(error))
+    }
+}
+
 impl From<voprf::errors::InternalError> for InternalError {
     fn from(voprf_error: voprf::errors::InternalError) -> Self {
         Self::OprfError(voprf_error)
@@ -127,7 +305,11 @@ impl From<voprf::errors::InternalError> for ProtocolError {
 }
 
 /// Represents an error in protocol handling
+///
+/// Variants are added as new features need them, so matches on this enum must
+/// include a wildcard arm.
 #[derive(Clone, Display, Eq, Hash, PartialEq)]
+#[non_exhaustive]
 pub enum ProtocolError<T = Infallible> {
     /// Internal error encountered
     LibraryError(InternalError<T>),
@@ -136,10 +318,34 @@ pub enum ProtocolError<T = Infallible> {
     /// Error with serializing / deserializing protocol messages
     SerializationError,
     /** This error occurs when the client detects that the server has
-    reflected the OPRF value (beta == alpha) */
+    reflected the OPRF value (beta == alpha), or when either party receives
+    its own ephemeral public key back from its peer */
     ReflectedValueError,
     /// Identity group element was encountered during deserialization, which is invalid
     IdentityGroupElementError,
+    /// The identity or a non-canonical group element was encountered for
+    /// the client's ephemeral public key in KE1
+    InvalidClientEphemeralKeyError,
+    /// The identity or a non-canonical group element was encountered for
+    /// the server's ephemeral public key in KE2
+    InvalidServerEphemeralKeyError,
+    /// The identity or a non-canonical group element was encountered for
+    /// the client's static public key in a registration record
+    InvalidClientStaticKeyError,
+    /// The identity or a non-canonical group element was encountered for
+    /// the server's static public key in a registration or credential
+    /// response
+    InvalidServerStaticKeyError,
+    /// A Diffie-Hellman output computed during the key exchange was the
+    /// identity element
+    DegenerateDiffieHellmanError,
+    /// The solution to the client puzzle demanded by the server is wrong
+    InvalidPuzzleSolutionError,
+    /// A serialized object was produced by a version of the format which is
+    /// not supported
+    VersionMismatchError,
+    /// A message or a state was used more than once
+    ReplayDetectedError,
 }
 
 impl<T: Debug> Debug for ProtocolError<T> {
@@ -152,6 +358,51 @@ impl<T: Debug> Debug for ProtocolError<T> {
             Self::SerializationError => f.debug_tuple("SerializationError").finish(),
             Self::ReflectedValueError => f.debug_tuple("ReflectedValueError").finish(),
             Self::IdentityGroupElementError => f.debug_tuple("IdentityGroupElementError").finish(),
+            Self::InvalidClientEphemeralKeyError => {
+                f.debug_tuple("InvalidClientEphemeralKeyError").finish()
+            }
+            Self::InvalidServerEphemeralKeyError => {
+                f.debug_tuple("InvalidServerEphemeralKeyError").finish()
+            }
+            Self::InvalidClientStaticKeyError => {
+                f.debug_tuple("InvalidClientStaticKeyError").finish()
+            }
+            Self::InvalidServerStaticKeyError => {
+                f.debug_tuple("InvalidServerStaticKeyError").finish()
+            }
+            Self::DegenerateDiffieHellmanError => {
+                f.debug_tuple("DegenerateDiffieHellmanError").finish()
+            }
+            Self::InvalidPuzzleSolutionError => {
+                f.debug_tuple("InvalidPuzzleSolutionError").finish()
+            }
+            Self::VersionMismatchError => f.debug_tuple("VersionMismatchError").finish(),
+            Self::ReplayDetectedError => f.debug_tuple("ReplayDetectedError").finish(),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T: defmt::Format> defmt::Format for ProtocolError<T> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        match self {
+            Self::LibraryError(pake_error) => defmt::write!(f, "LibraryError({})", pake_error),
+            Self::InvalidLoginError => defmt::write!(f, "InvalidLoginError"),
+            Self::SerializationError => defmt::write!(f, "SerializationError"),
+            Self::ReflectedValueError => defmt::write!(f, "ReflectedValueError"),
+            Self::IdentityGroupElementError => defmt::write!(f, "IdentityGroupElementError"),
+            Self::InvalidClientEphemeralKeyError => {
+                defmt::write!(f, "InvalidClientEphemeralKeyError")
+            }
+            Self::InvalidServerEphemeralKeyError => {
+                defmt::write!(f, "InvalidServerEphemeralKeyError")
+            }
+            Self::InvalidClientStaticKeyError => defmt::write!(f, "InvalidClientStaticKeyError"),
+            Self::InvalidServerStaticKeyError => defmt::write!(f, "InvalidServerStaticKeyError"),
+            Self::DegenerateDiffieHellmanError => defmt::write!(f, "DegenerateDiffieHellmanError"),
+            Self::InvalidPuzzleSolutionError => defmt::write!(f, "InvalidPuzzleSolutionError"),
+            Self::VersionMismatchError => defmt::write!(f, "VersionMismatchError"),
+            Self::ReplayDetectedError => defmt::write!(f, "ReplayDetectedError"),
         }
     }
 }
@@ -159,6 +410,49 @@ impl<T: Debug> Debug for ProtocolError<T> {
 #[cfg(feature = "std")]
 impl<T: Error> Error for ProtocolError<T> {}
 
+#[cfg(feature = "std")]
+impl<T> ProtocolError<T> {
+    /// The [`std::io::ErrorKind`] that best describes this error, used when
+    /// converting into a [`std::io::Error`]
+    pub fn io_error_kind(&self) -> std::io::ErrorKind {
+        use std::io::ErrorKind;
+
+        match self {
+            Self::InvalidLoginError
+            | Self::InvalidPuzzleSolutionError
+            | Self::ReplayDetectedError => ErrorKind::PermissionDenied,
+            Self::SerializationError
+            | Self::VersionMismatchError
+            | Self::ReflectedValueError
+            | Self::IdentityGroupElementError
+            | Self::InvalidClientEphemeralKeyError
+            | Self::InvalidServerEphemeralKeyError
+            | Self::InvalidClientStaticKeyError
+            | Self::InvalidServerStaticKeyError
+            | Self::DegenerateDiffieHellmanError
+            | Self::LibraryError(InternalError::InvalidByteSequence)
+            | Self::LibraryError(InternalError::SizeError { .. })
+            | Self::LibraryError(InternalError::PointError)
+            | Self::LibraryError(InternalError::BackupDecryptionError)
+            | Self::LibraryError(InternalError::RecordSignatureError)
+            | Self::LibraryError(InternalError::RecordDecryptionError) => ErrorKind::InvalidData,
+            Self::LibraryError(InternalError::SlowHashCancelledError) => ErrorKind::Interrupted,
+            Self::LibraryError(InternalError::MissingCredentialIdentifierError)
+            | Self::LibraryError(InternalError::ExportKeyNotRequestedError) => {
+                ErrorKind::InvalidInput
+            }
+            Self::LibraryError(_) => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Error + Send + Sync + 'static> From<ProtocolError<T>> for std::io::Error {
+    fn from(e: ProtocolError<T>) -> std::io::Error {
+        std::io::Error::new(e.io_error_kind(), e)
+    }
+}
+
 // This is meant to express future(ly) non-trivial ways of converting the
 // internal error into a ProtocolError
 impl<T> From<InternalError<T>> for ProtocolError<T> {
@@ -179,14 +473,30 @@ impl<T> From<::core::convert::Infallible> for ProtocolError<T> {
 impl ProtocolError {
     /// Convert `ProtocolError<Infallible>` into `ProtocolError<T>
     pub fn into_custom<T>(self) -> ProtocolError<T> {
+        self.map_custom(|never| match never {})
+    }
+}
+
+impl<T> ProtocolError<T> {
+    /// Convert `ProtocolError<T>` into `ProtocolError<U>` by applying `f` to
+    /// the custom error, if any
+    pub fn map_custom<U>(self, f: impl FnOnce(T) -> U) -> ProtocolError<U> {
         match self {
             Self::LibraryError(internal_error) => {
-                ProtocolError::LibraryError(internal_error.into_custom())
+                ProtocolError::LibraryError(internal_error.map_custom(f))
             }
             Self::InvalidLoginError => ProtocolError::InvalidLoginError,
             Self::SerializationError => ProtocolError::SerializationError,
             Self::ReflectedValueError => ProtocolError::ReflectedValueError,
             Self::IdentityGroupElementError => ProtocolError::IdentityGroupElementError,
+            Self::InvalidClientEphemeralKeyError => ProtocolError::InvalidClientEphemeralKeyError,
+            Self::InvalidServerEphemeralKeyError => ProtocolError::InvalidServerEphemeralKeyError,
+            Self::InvalidClientStaticKeyError => ProtocolError::InvalidClientStaticKeyError,
+            Self::InvalidServerStaticKeyError => ProtocolError::InvalidServerStaticKeyError,
+            Self::DegenerateDiffieHellmanError => ProtocolError::DegenerateDiffieHellmanError,
+            Self::InvalidPuzzleSolutionError => ProtocolError::InvalidPuzzleSolutionError,
+            Self::VersionMismatchError => ProtocolError::VersionMismatchError,
+            Self::ReplayDetectedError => ProtocolError::ReplayDetectedError,
         }
     }
 }
@@ -194,6 +504,25 @@ impl ProtocolError {
 pub(crate) mod utils {
     use super::*;
 
+    /// Replaces `error` with `replacement`, unless it comes from a
+    /// user-supplied primitive
+    pub fn unless_external(error: InternalError, replacement: ProtocolError) -> ProtocolError {
+        match error {
+            InternalError::External(error) => InternalError::External(error).into(),
+            _ => replacement,
+        }
+    }
+
+    /// Fills `dest` from `rng`, reporting its failures as
+    /// [`InternalError::RngError`] instead of panicking
+    pub fn fill_random<R: rand::RngCore + ?Sized>(
+        rng: &mut R,
+        dest: &mut [u8],
+    ) -> Result<(), InternalError> {
+        rng.try_fill_bytes(dest)
+            .map_err(|_| InternalError::RngError)
+    }
+
     pub fn check_slice_size<'a, T>(
         slice: &'a [u8],
         expected_len: usize,
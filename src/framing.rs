@@ -0,0 +1,277 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Framing for carrying registration and login messages over a single
+//! connection
+//!
+//! A frame consists of a [MessageType] byte, followed by the length of the
+//! message as a 2-byte big-endian integer, followed by the serialized
+//! message. When reading from a stream, [frame_len] tells how many bytes
+//! make up the next frame, which can then be parsed with
+//! [Message::deserialize], or routed on its type with [peek_message_type].
+//!
+//! Links with a small MTU, such as BLE GATT or LoRa, can instead carry a frame
+//! as a sequence of fragments from [Message::fragment]. Each fragment starts
+//! with its index and the number of fragments of the frame, one byte each, so
+//! that a [Reassembler] on the other end recovers the frame even if fragments
+//! arrive out of order or repeated.
+
+use crate::{
+    ciphersuite::CipherSuite,
+    errors::ProtocolError,
+    messages::{
+        CredentialFinalization, CredentialRequest, CredentialResponse, RegistrationRequest,
+        RegistrationResponse, RegistrationUpload,
+    },
+    serialization::{os2ip, serialize, tokenize},
+};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+const LEN_BYTES: usize = 2;
+
+/// The length of the type byte and the length prefix which precede every
+/// message
+pub const HEADER_LEN: usize = 1 + LEN_BYTES;
+
+/// The length of the index and the fragment count which precede every
+/// fragment
+pub const FRAGMENT_HEADER_LEN: usize = 2;
+
+macro_rules! impl_framing {
+    ($($(#[$attr:meta])* $name:ident = $byte:expr,)+) => {
+        /// Identifies the kind of message carried by a frame
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        #[repr(u8)]
+        pub enum MessageType {
+            $($(#[$attr])* $name = $byte,)+
+        }
+
+        impl MessageType {
+            /// Parses a type byte, failing for unknown message types
+            pub fn from_byte(byte: u8) -> Result<Self, ProtocolError> {
+                match byte {
+                    $($byte => Ok(Self::$name),)+
+                    _ => Err(ProtocolError::SerializationError),
+                }
+            }
+
+            /// The maximum length of a frame of this type, for the ciphersuite
+            /// `CS`
+            pub fn max_frame_len<CS: CipherSuite>(self) -> usize {
+                HEADER_LEN + match self {
+                    $(Self::$name => $name::<CS>::max_serialized_len(),)+
+                }
+            }
+        }
+
+        /// Any of the messages exchanged during registration and login
+        pub enum Message<CS: CipherSuite> {
+            $($(#[$attr])* $name($name<CS>),)+
+        }
+
+        impl<CS: CipherSuite> Message<CS> {
+            /// The length of the longest frame of any message type
+            pub fn max_frame_len() -> usize {
+                [$(MessageType::$name.max_frame_len::<CS>(),)+]
+                    .iter()
+                    .cloned()
+                    .max()
+                    .unwrap_or_default()
+            }
+
+            /// The type byte that this message is framed with
+            pub fn message_type(&self) -> MessageType {
+                match self {
+                    $(Self::$name(_) => MessageType::$name,)+
+                }
+            }
+
+            /// Serialization into a frame
+            pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
+                let message = match self {
+                    $(Self::$name(message) => message.serialize()?,)+
+                };
+                Ok([&[self.message_type() as u8][..], &serialize(&message, LEN_BYTES)?].concat())
+            }
+
+            /// Serialization into a frame, split into fragments of at most
+            /// `max_fragment_len` bytes each
+            pub fn fragment(&self, max_fragment_len: usize) -> Result<Vec<Vec<u8>>, ProtocolError> {
+                fragment(&self.serialize()?, max_fragment_len)
+            }
+
+            /// Deserialization from exactly one frame, dispatching on its type
+            /// byte
+            pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
+                let (message_type, rest) = match input.split_first() {
+                    Some((byte, rest)) => (MessageType::from_byte(*byte)?, rest),
+                    None => return Err(ProtocolError::SerializationError),
+                };
+                let (message, remainder) = tokenize(rest, LEN_BYTES)?;
+                if !remainder.is_empty() {
+                    return Err(ProtocolError::SerializationError);
+                }
+
+                Ok(match message_type {
+                    $(MessageType::$name => Self::$name($name::deserialize(message)?),)+
+                })
+            }
+        }
+
+        impl<CS: CipherSuite> Clone for Message<CS> {
+            fn clone(&self) -> Self {
+                match self {
+                    $(Self::$name(message) => Self::$name(message.clone()),)+
+                }
+            }
+        }
+
+        impl<CS: CipherSuite> core::fmt::Debug for Message<CS> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                match self {
+                    $(Self::$name(message) => f.debug_tuple(stringify!($name)).field(message).finish(),)+
+                }
+            }
+        }
+
+        $(
+            impl<CS: CipherSuite> From<$name<CS>> for Message<CS> {
+                fn from(message: $name<CS>) -> Self {
+                    Self::$name(message)
+                }
+            }
+        )+
+    };
+}
+
+impl_framing!(
+    /// [RegistrationRequest]
+    RegistrationRequest = 1,
+    /// [RegistrationResponse]
+    RegistrationResponse = 2,
+    /// [RegistrationUpload]
+    RegistrationUpload = 3,
+    /// [CredentialRequest]
+    CredentialRequest = 4,
+    /// [CredentialResponse]
+    CredentialResponse = 5,
+    /// [CredentialFinalization]
+    CredentialFinalization = 6,
+);
+
+/// Returns the length of the frame at the start of `input`, including its
+/// header, or `None` if not enough of the header has been received yet
+pub fn frame_len(input: &[u8]) -> Result<Option<usize>, ProtocolError> {
+    if input.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    MessageType::from_byte(input[0])?;
+    Ok(Some(HEADER_LEN + os2ip(&input[1..HEADER_LEN])?))
+}
+
+/// Returns the type of the message in `frame` without deserializing it, for
+/// routing a frame to the right handler of a single endpoint. Fails if
+/// `frame` is not exactly one frame of a known type.
+pub fn peek_message_type(frame: &[u8]) -> Result<MessageType, ProtocolError> {
+    if frame_len(frame)? != Some(frame.len()) {
+        return Err(ProtocolError::SerializationError);
+    }
+    MessageType::from_byte(frame[0])
+}
+
+/// Splits `frame` into fragments of at most `max_fragment_len` bytes each,
+/// headers included. Fails if this requires more than 255 fragments.
+pub fn fragment(frame: &[u8], max_fragment_len: usize) -> Result<Vec<Vec<u8>>, ProtocolError> {
+    let chunk_len = max_fragment_len
+        .checked_sub(FRAGMENT_HEADER_LEN)
+        .filter(|&chunk_len| chunk_len > 0)
+        .ok_or(ProtocolError::SerializationError)?;
+    let chunks = frame.chunks(chunk_len);
+    let count = u8::try_from(chunks.len()).map_err(|_| ProtocolError::SerializationError)?;
+
+    Ok(chunks
+        .zip(0..count)
+        .map(|(chunk, index)| [&[index, count][..], chunk].concat())
+        .collect())
+}
+
+/// Collects the fragments of a frame, in any order, until the frame is
+/// complete
+///
+/// The total length of the fragments is bounded by the limit given on
+/// creation, for which [`Message::max_frame_len`] is a suitable value. Any
+/// invalid fragment fails [push](Self::push) and discards the fragments
+/// received so far. The reassembler does not time out by itself: if a
+/// fragment is lost for good, the caller should [reset](Self::reset) it
+/// before receiving the next frame.
+#[derive(Clone, Debug)]
+pub struct Reassembler {
+    max_frame_len: usize,
+    fragments: Vec<Option<Vec<u8>>>,
+    received_len: usize,
+}
+
+impl Reassembler {
+    /// Creates a reassembler for frames of at most `max_frame_len` bytes
+    pub fn new(max_frame_len: usize) -> Self {
+        Self {
+            max_frame_len,
+            fragments: Vec::new(),
+            received_len: 0,
+        }
+    }
+
+    /// Adds a fragment, returning the frame once all of its fragments were
+    /// received, after which the reassembler is ready for the next frame
+    pub fn push(&mut self, fragment: &[u8]) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let result = self.try_push(fragment);
+        if !matches!(result, Ok(None)) {
+            self.reset();
+        }
+        result
+    }
+
+    /// Discards the fragments received so far
+    pub fn reset(&mut self) {
+        self.fragments.clear();
+        self.received_len = 0;
+    }
+
+    fn try_push(&mut self, fragment: &[u8]) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let (index, count, chunk) = match fragment {
+            [index, count, chunk @ ..] if index < count => {
+                (usize::from(*index), usize::from(*count), chunk)
+            }
+            _ => return Err(ProtocolError::SerializationError),
+        };
+        if self.fragments.is_empty() {
+            self.fragments = vec![None; count];
+        } else if self.fragments.len() != count {
+            return Err(ProtocolError::SerializationError);
+        }
+
+        // Fragments may be repeated by the link, in which case the first copy
+        // is kept
+        if self.fragments[index].is_some() {
+            return Ok(None);
+        }
+        self.received_len += chunk.len();
+        if self.received_len > self.max_frame_len {
+            return Err(ProtocolError::SerializationError);
+        }
+        self.fragments[index] = Some(chunk.to_vec());
+
+        if self.fragments.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+        let frame: Vec<u8> = self.fragments.iter().flatten().flatten().cloned().collect();
+        if frame_len(&frame)? != Some(frame.len()) {
+            return Err(ProtocolError::SerializationError);
+        }
+        Ok(Some(frame))
+    }
+}
@@ -9,13 +9,43 @@ macro_rules! impl_debug_eq_hash_for {
         $(where $($type: core::fmt::Debug,)+)?
         {
             fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-                f.debug_struct("$name")
-                .field("$field1", &self.$field1)
-                $(.field("$field2", &self.$field2))*
+                f.debug_struct(stringify!($name))
+                .field(stringify!($field1), &self.$field1)
+                $(.field(stringify!($field2), &self.$field2))*
                 .finish()
             }
         }
 
+        impl_eq_hash_for!(
+            struct $name$(<$($gen$(: $bound)?),+>)?,
+            [$field1$(, $field2)*],
+            $([$($type),+])?
+        );
+    };
+    (tuple $name:ident$(<$($gen:ident$(: $bound:tt)?),+$(,)?>)?, [$field1:tt$(, $field2:tt)*$(,)?]$(, )?$([$($type:ty),+$(,)?]$(,)?)?) => {
+        impl$(<$($gen$(: $bound)?),+>)? core::fmt::Debug for $name$(<$($gen),+>)?
+        $(where $($type: core::fmt::Debug,)+)?
+        {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.debug_tuple(stringify!($name))
+                .field(&self.$field1)
+                $(.field(&self.$field2))*
+                .finish()
+            }
+        }
+
+        impl_eq_hash_for!(
+            tuple $name$(<$($gen$(: $bound)?),+>)?,
+            [$field1$(, $field2)*],
+            $([$($type),+])?
+        );
+    };
+}
+
+/// Used by types holding secrets, which implement `Debug` by hand in order to
+/// redact them.
+macro_rules! impl_eq_hash_for {
+    (struct $name:ident$(<$($gen:ident$(: $bound:tt)?),+$(,)?>)?, [$field1:ident$(, $field2:ident)*$(,)?]$(, )?$([$($type:ty),+$(,)?]$(,)?)?) => {
         impl$(<$($gen$(: $bound)?),+>)? Eq for $name$(<$($gen),+>)?
         $(where $($type: Eq,)+)?
         {}
@@ -39,17 +69,6 @@ macro_rules! impl_debug_eq_hash_for {
         }
     };
     (tuple $name:ident$(<$($gen:ident$(: $bound:tt)?),+$(,)?>)?, [$field1:tt$(, $field2:tt)*$(,)?]$(, )?$([$($type:ty),+$(,)?]$(,)?)?) => {
-        impl$(<$($gen$(: $bound)?),+>)? core::fmt::Debug for $name$(<$($gen),+>)?
-        $(where $($type: core::fmt::Debug,)+)?
-        {
-            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-                f.debug_tuple("$name")
-                .field(&self.$field1)
-                $(.field(&self.$field2))*
-                .finish()
-            }
-        }
-
         impl$(<$($gen$(: $bound)?),+>)? Eq for $name$(<$($gen),+>)?
         $(where $($type: Eq,)+)?
         {}
@@ -74,6 +93,16 @@ macro_rules! impl_debug_eq_hash_for {
     };
 }
 
+/// Printed in place of `N` secret bytes by the `Debug` implementations, as
+/// `[REDACTED; N]`
+pub(crate) struct Redacted(pub(crate) usize);
+
+impl core::fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "[REDACTED; {}]", self.0)
+    }
+}
+
 macro_rules! impl_clone_for {
     (struct $name:ident$(<$($gen:ident$(: $bound:tt)?),+$(,)?>)?, [$field1:ident$(, $field2:ident)*$(,)?]$(, )?$([$($type:ty),+$(,)?]$(,)?)?) => {
         impl$(<$($gen$(: $bound)?),+>)? Clone for $name$(<$($gen),+>)?
@@ -101,6 +130,62 @@ macro_rules! impl_clone_for {
     };
 }
 
+/// Inner macro adding `canonical_bytes` to the messages and registration
+/// records, whose serialization is canonical.
+macro_rules! impl_canonical_bytes_for {
+    ($t:ident) => {
+        impl<CS: CipherSuite> $t<CS> {
+            /// The canonical encoding of this value, over which external
+            /// signatures can be computed.
+            ///
+            /// Every value has exactly one encoding, which is the only one
+            /// accepted by `deserialize`, and which is the same on every
+            /// platform and in every later release of this library.
+            pub fn canonical_bytes(&self) -> Result<Vec<u8>, ProtocolError> {
+                self.serialize()
+            }
+        }
+    };
+}
+
+/// Inner macro adding the serialization of the messages, on top of their
+/// `serialized_len` and `write_to`, which fill an exactly sized slice.
+macro_rules! impl_serialize_to_slice_for {
+    ($t:ident) => {
+        impl<CS: CipherSuite> $t<CS> {
+            /// Serializes this message into the start of `output`, returning
+            /// the number of bytes written, for callers which manage their
+            /// own fixed-size buffers. `max_serialized_len` bounds the number
+            /// of bytes needed, and a shorter `output` is an error.
+            pub fn serialize_to_slice(&self, output: &mut [u8]) -> Result<usize, ProtocolError> {
+                let len = self.serialized_len();
+                if output.len() < len {
+                    return Err(InternalError::SizeError {
+                        name: "output",
+                        len,
+                        actual_len: output.len(),
+                    }
+                    .into());
+                }
+                self.write_to(&mut output[..len]);
+                Ok(len)
+            }
+
+            /// Serialization into bytes
+            pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
+                Ok(self.to_vec())
+            }
+
+            // Serialization into bytes, for the callers within this crate
+            pub(crate) fn to_vec(&self) -> Vec<u8> {
+                let mut output = alloc::vec![0; self.serialized_len()];
+                self.write_to(&mut output);
+                output
+            }
+        }
+    };
+}
+
 /// Inner macro used for deriving `serde`'s `Serialize` and `Deserialize` traits.
 macro_rules! impl_serialize_and_deserialize_for {
     ($t:ident) => {
@@ -111,12 +196,14 @@ macro_rules! impl_serialize_and_deserialize_for {
                 S: serde::Serializer,
             {
                 use serde::ser::Error;
+                use zeroize::Zeroizing;
 
+                // Client states contain secrets, so wipe the intermediate encodings
+                let bytes = Zeroizing::new(self.serialize().map_err(Error::custom)?);
                 if serializer.is_human_readable() {
-                    serializer
-                        .serialize_str(&base64::encode(&self.serialize().map_err(Error::custom)?))
+                    serializer.serialize_str(&Zeroizing::new(base64::encode(&bytes[..])))
                 } else {
-                    serializer.serialize_bytes(&self.serialize().map_err(Error::custom)?)
+                    serializer.serialize_bytes(&bytes)
                 }
             }
         }
@@ -131,7 +218,9 @@ macro_rules! impl_serialize_and_deserialize_for {
 
                 if deserializer.is_human_readable() {
                     let s = <&str>::deserialize(deserializer)?;
-                    Self::deserialize(&base64::decode(s).map_err(Error::custom)?)
+                    Self::deserialize(&zeroize::Zeroizing::new(
+                        base64::decode(s).map_err(Error::custom)?,
+                    ))
                 } else {
                     Self::deserialize(<&[u8]>::deserialize(deserializer)?)
                 }
@@ -0,0 +1,113 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Conversion of Ed25519 keys into keys of the x25519 key exchange group
+//!
+//! Requires the `ed25519` feature. A client whose long-term identity key is
+//! an Ed25519 key can reuse it as its static key in a ciphersuite whose
+//! [`KeGroup`](super::KeGroup) is [MontgomeryPoint], by converting the key
+//! with [keypair_from_ed25519] and registering its public key in
+//! [`ClientRegistrationFinishParameters::external_client_s_pk`](crate::ClientRegistrationFinishParameters),
+//! before logging in with
+//! [`ClientLogin::finish_with_key`](crate::ClientLogin::finish_with_key).
+//! The server can convert the Ed25519 public key it already knows with
+//! [public_key_from_ed25519] to check the registered key.
+//!
+//! The conversion is the birational map between edwards25519 and curve25519,
+//! and the secret scalar is the one of RFC 8032, clamped as specified by
+//! RFC 7748, so that the converted keys match those of libsodium's
+//! `crypto_sign_ed25519_sk_to_curve25519` and
+//! `crypto_sign_ed25519_pk_to_curve25519`.
+//!
+//! Keystores which only expose Ed25519 signatures cannot be used this way,
+//! since the key exchange needs the secret scalar for Diffie-Hellman.
+
+use super::x25519::clamp_sk;
+use crate::{
+    errors::{utils::check_slice_size, InternalError},
+    keypair::{KeyPair, PrivateKey, PublicKey},
+};
+use curve25519_dalek::{edwards::CompressedEdwardsY, montgomery::MontgomeryPoint};
+use generic_array::GenericArray;
+use sha2::{Digest, Sha512};
+use zeroize::Zeroize;
+
+/// The length of an Ed25519 secret key seed and public key
+pub const ED25519_KEY_LEN: usize = 32;
+
+/// Converts an Ed25519 public key, in the 32-byte encoding of RFC 8032, into
+/// an x25519 public key, rejecting invalid encodings and points of small
+/// order with [`InternalError::PointError`]
+pub fn public_key_from_ed25519(input: &[u8]) -> Result<PublicKey<MontgomeryPoint>, InternalError> {
+    let compressed =
+        CompressedEdwardsY::from_slice(check_slice_size(input, ED25519_KEY_LEN, "ed25519_pk")?);
+    match compressed.decompress() {
+        Some(point) if !point.is_small_order() && point.compress() == compressed => {
+            Ok(PublicKey::from_arr(GenericArray::clone_from_slice(
+                point.to_montgomery().as_bytes(),
+            )))
+        }
+        _ => Err(InternalError::PointError),
+    }
+}
+
+/// Converts an Ed25519 secret key, given as the 32-byte seed of RFC 8032,
+/// into an x25519 keypair whose public key is the conversion of the Ed25519
+/// public key
+pub fn keypair_from_ed25519(seed: &[u8]) -> Result<KeyPair<MontgomeryPoint>, InternalError> {
+    let seed = check_slice_size(seed, ED25519_KEY_LEN, "ed25519_seed")?;
+    let mut hash = Sha512::digest(seed);
+    let mut scalar_bytes = [0u8; 32];
+    scalar_bytes.copy_from_slice(&hash[..32]);
+    hash.zeroize();
+
+    let sk = PrivateKey::<MontgomeryPoint>::from_arr(clamp_sk(scalar_bytes));
+    scalar_bytes.zeroize();
+    KeyPair::from_private_key(sk).map_err(|_| InternalError::InvalidKeypairError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_exchange::group::KeGroup, keypair::SecretKey};
+
+    // Test 1 of RFC 8032, section 7.1
+    const SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+    const ED25519_PK: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+    const X25519_PK: &str = "d85e07ec22b0ad881537c2f44d662d1a143cf830c57aca4305d85c7a90f6b62e";
+
+    #[test]
+    fn test_ed25519_conversion() -> Result<(), InternalError> {
+        let keypair = keypair_from_ed25519(&hex::decode(SEED).unwrap())?;
+        let pk = public_key_from_ed25519(&hex::decode(ED25519_PK).unwrap())?;
+        assert_eq!(hex::encode(&pk[..]), X25519_PK);
+        assert_eq!(keypair.public(), &pk);
+        assert_eq!(keypair.private().public_key()?, pk);
+
+        // The converted keys agree with generated ones
+        let other_sk = MontgomeryPoint::random_sk(&mut rand::rngs::OsRng)?;
+        let other_pk = MontgomeryPoint::public_key(&other_sk);
+        assert_eq!(
+            keypair
+                .private()
+                .diffie_hellman(PublicKey::from_arr(other_pk.to_arr()))?,
+            MontgomeryPoint::from_pk_slice(GenericArray::from_slice(&pk[..]))?
+                .diffie_hellman(&other_sk)
+                .to_vec()
+        );
+
+        // Small order points, such as the identity, are rejected
+        let mut identity = [0u8; ED25519_KEY_LEN];
+        identity[0] = 1;
+        assert!(matches!(
+            public_key_from_ed25519(&identity),
+            Err(InternalError::PointError)
+        ));
+        assert!(public_key_from_ed25519(&hex::decode(ED25519_PK).unwrap()[1..]).is_err());
+        assert!(keypair_from_ed25519(&[0u8; 31]).is_err());
+
+        Ok(())
+    }
+}
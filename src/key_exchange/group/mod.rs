@@ -5,6 +5,12 @@
 
 //! Includes the KeGroup trait and definitions for the
 //! key exchange groups
+//!
+//! Public keys are sent in their canonical encodings, which can be told
+//! apart from random strings. No uniform encoding such as Elligator2 is
+//! offered: Ristretto255 has no inverse map from its elements to uniform
+//! strings, curve25519-dalek 3 keeps private the field arithmetic needed to
+//! build one for X25519, and p256 provides no Elligator Squared encoding.
 
 use crate::errors::InternalError;
 use generic_array::{ArrayLength, GenericArray};
@@ -17,11 +23,16 @@ pub trait KeGroup: Sized + Clone {
     /// Length of the secret key
     type SkLen: ArrayLength<u8> + 'static;
 
-    /// Return a public key from its fixed-length bytes representation
+    /// Return a public key from its fixed-length bytes representation.
+    /// Implementations must reject non-canonical encodings as well as the
+    /// identity element with [`InternalError::PointError`]
     fn from_pk_slice(element_bits: &GenericArray<u8, Self::PkLen>) -> Result<Self, InternalError>;
 
-    /// Generate a random secret key
-    fn random_sk<R: RngCore + CryptoRng>(rng: &mut R) -> GenericArray<u8, Self::SkLen>;
+    /// Generate a random secret key, failing with
+    /// [`InternalError::RngError`] if `rng` does
+    fn random_sk<R: RngCore + CryptoRng>(
+        rng: &mut R,
+    ) -> Result<GenericArray<u8, Self::SkLen>, InternalError>;
 
     /// Return a public key from its secret key
     fn public_key(sk: &GenericArray<u8, Self::SkLen>) -> Self;
@@ -33,6 +44,8 @@ pub trait KeGroup: Sized + Clone {
     fn diffie_hellman(&self, sk: &GenericArray<u8, Self::SkLen>) -> GenericArray<u8, Self::PkLen>;
 }
 
+#[cfg(feature = "ed25519")]
+pub mod ed25519;
 #[cfg(feature = "p256")]
 pub mod p256;
 pub mod ristretto255;
@@ -4,10 +4,17 @@
 // LICENSE file in the root directory of this source tree.
 
 //! Key Exchange group implementation for p256
+//!
+//! Public keys are encoded in the compressed SEC1 form of 33 bytes, as the
+//! specification requires, when `p256::ProjectivePoint` is used as the
+//! [KeGroup] of a [CipherSuite](crate::CipherSuite). For interoperating with
+//! peers which only handle the uncompressed form of 65 bytes, use
+//! [P256Uncompressed] as the [KeGroup] instead. Since the Diffie-Hellman
+//! outputs are encoded the same way, both sides must agree on the form.
 
 use super::KeGroup;
-use crate::errors::InternalError;
-use generic_array::typenum::{U32, U33};
+use crate::errors::{utils::fill_random, InternalError};
+use generic_array::typenum::{U32, U33, U65};
 use generic_array::GenericArray;
 use rand::{CryptoRng, RngCore};
 
@@ -16,15 +23,27 @@ impl KeGroup for p256_::ProjectivePoint {
     type SkLen = U32;
 
     fn from_pk_slice(element_bits: &GenericArray<u8, Self::PkLen>) -> Result<Self, InternalError> {
-        use p256_::elliptic_curve::group::GroupEncoding;
+        use p256_::elliptic_curve::group::{Group, GroupEncoding};
 
-        Option::from(Self::from_bytes(element_bits)).ok_or(InternalError::PointError)
+        Option::<Self>::from(Self::from_bytes(element_bits))
+            .filter(|point| !bool::from(point.is_identity()))
+            .ok_or(InternalError::PointError)
     }
 
-    fn random_sk<R: RngCore + CryptoRng>(rng: &mut R) -> GenericArray<u8, Self::SkLen> {
-        use p256_::elliptic_curve::Field;
+    fn random_sk<R: RngCore + CryptoRng>(
+        rng: &mut R,
+    ) -> Result<GenericArray<u8, Self::SkLen>, InternalError> {
+        // Rejection sampling, rather than `Field::random`, so that a failing
+        // rng is reported instead of panicking
+        loop {
+            let mut bytes = GenericArray::default();
+            fill_random(rng, &mut bytes)?;
+            let scalar = p256_::Scalar::from_bytes_reduced(&bytes);
 
-        p256_::Scalar::random(rng).into()
+            if bytes.iter().any(|&byte| byte != 0) && GenericArray::from(scalar) == bytes {
+                break Ok(bytes);
+            }
+        }
     }
 
     fn public_key(sk: &GenericArray<u8, Self::SkLen>) -> Self {
@@ -45,3 +64,45 @@ impl KeGroup for p256_::ProjectivePoint {
         (self * &p256_::Scalar::from_bytes_reduced(sk)).to_arr()
     }
 }
+
+/// P-256 as a [KeGroup] whose public keys and Diffie-Hellman outputs use the
+/// uncompressed SEC1 encoding of 65 bytes, rejecting compressed encodings
+#[derive(Clone, Copy, Debug)]
+pub struct P256Uncompressed(pub p256_::ProjectivePoint);
+
+impl KeGroup for P256Uncompressed {
+    type PkLen = U65;
+    type SkLen = U32;
+
+    fn from_pk_slice(element_bits: &GenericArray<u8, Self::PkLen>) -> Result<Self, InternalError> {
+        use p256_::elliptic_curve::sec1::FromEncodedPoint;
+
+        // A 65-byte encoding can only be parsed as an uncompressed point, and
+        // the identity has no such encoding
+        let encoded_point =
+            p256_::EncodedPoint::from_bytes(element_bits).map_err(|_| InternalError::PointError)?;
+        Option::<p256_::AffinePoint>::from(p256_::AffinePoint::from_encoded_point(&encoded_point))
+            .map(|point| Self(point.into()))
+            .ok_or(InternalError::PointError)
+    }
+
+    fn random_sk<R: RngCore + CryptoRng>(
+        rng: &mut R,
+    ) -> Result<GenericArray<u8, Self::SkLen>, InternalError> {
+        p256_::ProjectivePoint::random_sk(rng)
+    }
+
+    fn public_key(sk: &GenericArray<u8, Self::SkLen>) -> Self {
+        Self(p256_::ProjectivePoint::public_key(sk))
+    }
+
+    fn to_arr(&self) -> GenericArray<u8, Self::PkLen> {
+        use p256_::elliptic_curve::sec1::ToEncodedPoint;
+
+        GenericArray::clone_from_slice(self.0.to_affine().to_encoded_point(false).as_bytes())
+    }
+
+    fn diffie_hellman(&self, sk: &GenericArray<u8, Self::SkLen>) -> GenericArray<u8, Self::PkLen> {
+        Self(self.0 * &p256_::Scalar::from_bytes_reduced(sk)).to_arr()
+    }
+}
@@ -6,10 +6,11 @@
 //! Key Exchange group implementation for ristretto255
 
 use super::KeGroup;
-use crate::errors::InternalError;
+use crate::errors::{utils::fill_random, InternalError};
 use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
 use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
 use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
 use generic_array::typenum::U32;
 use generic_array::GenericArray;
 use rand::{CryptoRng, RngCore};
@@ -21,16 +22,19 @@ impl KeGroup for RistrettoPoint {
     fn from_pk_slice(element_bits: &GenericArray<u8, Self::PkLen>) -> Result<Self, InternalError> {
         CompressedRistretto::from_slice(element_bits)
             .decompress()
+            .filter(|point| !point.is_identity())
             .ok_or(InternalError::PointError)
     }
 
-    fn random_sk<R: RngCore + CryptoRng>(rng: &mut R) -> GenericArray<u8, Self::SkLen> {
+    fn random_sk<R: RngCore + CryptoRng>(
+        rng: &mut R,
+    ) -> Result<GenericArray<u8, Self::SkLen>, InternalError> {
         loop {
             let scalar = {
                 #[cfg(not(test))]
                 {
                     let mut scalar_bytes = [0u8; 64];
-                    rng.fill_bytes(&mut scalar_bytes);
+                    fill_random(rng, &mut scalar_bytes)?;
                     Scalar::from_bytes_mod_order_wide(&scalar_bytes)
                 }
 
@@ -38,13 +42,13 @@ impl KeGroup for RistrettoPoint {
                 #[cfg(test)]
                 {
                     let mut scalar_bytes = [0u8; 32];
-                    rng.fill_bytes(&mut scalar_bytes);
+                    fill_random(rng, &mut scalar_bytes)?;
                     Scalar::from_bytes_mod_order(scalar_bytes)
                 }
             };
 
             if scalar != Scalar::zero() {
-                break scalar.to_bytes().into();
+                break Ok(scalar.to_bytes().into());
             }
         }
     }
@@ -4,29 +4,85 @@
 // LICENSE file in the root directory of this source tree.
 
 //! Key Exchange group implementation for x25519
+//!
+//! Secret keys are used as little-endian scalars without clamping, so that
+//! the keys generated by [`KeGroup::random_sk`], which are nonzero scalars
+//! reduced modulo the group order, are used as they are. Raw X25519 secrets
+//! imported from elsewhere must go through [clamp_sk] first, as specified by
+//! [RFC 7748](https://www.rfc-editor.org/rfc/rfc7748#section-5), after which
+//! the public key and the Diffie-Hellman outputs match those of any RFC 7748
+//! implementation. [validate_sk] checks that a secret key has one of these
+//! two forms.
 
 use super::KeGroup;
-use crate::errors::InternalError;
+use crate::errors::{utils::fill_random, InternalError};
 use curve25519_dalek::{constants::X25519_BASEPOINT, montgomery::MontgomeryPoint, scalar::Scalar};
 use generic_array::{typenum::U32, GenericArray};
 use rand::{CryptoRng, RngCore};
 
+/// Clamps a raw 32-byte X25519 secret as specified by RFC 7748, clearing the
+/// three lowest bits and the highest bit and setting the second highest bit
+pub fn clamp_sk(mut raw: [u8; 32]) -> GenericArray<u8, U32> {
+    raw[0] &= 0xf8;
+    raw[31] &= 0x7f;
+    raw[31] |= 0x40;
+    raw.into()
+}
+
+/// Returns `true` if `sk` is clamped as specified by RFC 7748
+pub fn is_clamped(sk: &GenericArray<u8, U32>) -> bool {
+    sk[0] & 0x07 == 0 && sk[31] & 0xc0 == 0x40
+}
+
+/// Checks that `sk` is either clamped, or a nonzero scalar reduced modulo the
+/// group order as generated by [`KeGroup::random_sk`], failing with
+/// [`InternalError::InvalidKeypairError`] otherwise. Any other secret would
+/// not produce the same keys as other implementations.
+pub fn validate_sk(sk: &GenericArray<u8, U32>) -> Result<(), InternalError> {
+    let bytes: [u8; 32] = *sk.as_ref();
+    let is_reduced =
+        Scalar::from_canonical_bytes(bytes).map_or(false, |scalar| scalar != Scalar::zero());
+
+    if is_clamped(sk) || is_reduced {
+        Ok(())
+    } else {
+        Err(InternalError::InvalidKeypairError)
+    }
+}
+
 /// The implementation of such a subgroup for Ristretto
 impl KeGroup for MontgomeryPoint {
     type PkLen = U32;
     type SkLen = U32;
 
     fn from_pk_slice(element_bits: &GenericArray<u8, Self::PkLen>) -> Result<Self, InternalError> {
-        Ok(Self(*element_bits.as_ref()))
+        let mut bytes: [u8; 32] = *element_bits.as_ref();
+
+        // The top bit of the u-coordinate is masked as specified by RFC 7748,
+        // after which it must be fully reduced modulo 2^255 - 19 and must not
+        // encode the identity
+        bytes[31] &= 0x7f;
+        let is_canonical = !(bytes[31] == 0x7f
+            && bytes[1..31].iter().all(|&byte| byte == 0xff)
+            && bytes[0] >= 0xed);
+        let is_identity = bytes.iter().all(|&byte| byte == 0);
+
+        if !is_canonical || is_identity {
+            return Err(InternalError::PointError);
+        }
+
+        Ok(Self(bytes))
     }
 
-    fn random_sk<R: RngCore + CryptoRng>(rng: &mut R) -> GenericArray<u8, Self::SkLen> {
+    fn random_sk<R: RngCore + CryptoRng>(
+        rng: &mut R,
+    ) -> Result<GenericArray<u8, Self::SkLen>, InternalError> {
         loop {
             let scalar = {
                 #[cfg(not(test))]
                 {
                     let mut scalar_bytes = [0u8; 64];
-                    rng.fill_bytes(&mut scalar_bytes);
+                    fill_random(rng, &mut scalar_bytes)?;
                     Scalar::from_bytes_mod_order_wide(&scalar_bytes)
                 }
 
@@ -34,13 +90,13 @@ impl KeGroup for MontgomeryPoint {
                 #[cfg(test)]
                 {
                     let mut scalar_bytes = [0u8; 32];
-                    rng.fill_bytes(&mut scalar_bytes);
+                    fill_random(rng, &mut scalar_bytes)?;
                     Scalar::from_bytes_mod_order(scalar_bytes)
                 }
             };
 
             if scalar != Scalar::zero() {
-                break GenericArray::clone_from_slice(&scalar.to_bytes());
+                break Ok(GenericArray::clone_from_slice(&scalar.to_bytes()));
             }
         }
     }
@@ -100,7 +156,10 @@ mod tests {
         } = client.finish(
             &mut OsRng,
             message,
-            ClientRegistrationFinishParameters::default(),
+            ClientRegistrationFinishParameters {
+                export_key: true,
+                ..ClientRegistrationFinishParameters::default()
+            },
         )?;
         let server_registration = ServerRegistration::finish(message);
 
@@ -125,7 +184,13 @@ mod tests {
             session_key: client_session_key,
             export_key: login_export_key,
             ..
-        } = client.finish(message, ClientLoginFinishParameters::default())?;
+        } = client.finish(
+            message,
+            ClientLoginFinishParameters {
+                export_key: true,
+                ..ClientLoginFinishParameters::default()
+            },
+        )?;
         let server_session_key = server.finish(message)?.session_key;
 
         assert_eq!(register_export_key, login_export_key);
@@ -151,4 +216,82 @@ mod tests {
 
         Ok(())
     }
+
+    // Test vectors from RFC 7748, section 6.1
+    #[test]
+    fn test_clamped_sk_matches_rfc_7748() -> Result<(), ProtocolError> {
+        let decode = |hex_str: &str| {
+            let mut bytes = [0u8; 32];
+            bytes.copy_from_slice(&hex::decode(hex_str).unwrap());
+            bytes
+        };
+        let alice_sk = decode("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob_sk = decode("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+
+        // The raw secrets are not clamped, nor reduced scalars
+        for raw in [alice_sk, bob_sk].iter() {
+            assert!(!is_clamped(raw.into()));
+            assert!(matches!(
+                validate_sk(raw.into()),
+                Err(InternalError::InvalidKeypairError)
+            ));
+        }
+
+        let alice_sk = clamp_sk(alice_sk);
+        let bob_sk = clamp_sk(bob_sk);
+        validate_sk(&alice_sk)?;
+        validate_sk(&bob_sk)?;
+        assert_eq!(clamp_sk(alice_sk.into()), alice_sk);
+
+        let alice_pk = MontgomeryPoint::public_key(&alice_sk);
+        let bob_pk = MontgomeryPoint::public_key(&bob_sk);
+        assert_eq!(
+            hex::encode(alice_pk.to_arr()),
+            "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+        );
+        assert_eq!(
+            hex::encode(bob_pk.to_arr()),
+            "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"
+        );
+
+        let shared_secret = "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742";
+        assert_eq!(hex::encode(bob_pk.diffie_hellman(&alice_sk)), shared_secret);
+        assert_eq!(hex::encode(alice_pk.diffie_hellman(&bob_sk)), shared_secret);
+
+        // Generated keys are reduced scalars, and zero is rejected
+        validate_sk(&MontgomeryPoint::random_sk(&mut rand::rngs::OsRng)?)?;
+        assert!(validate_sk(&GenericArray::default()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_pk_slice_rejects_identity_and_non_canonical() {
+        use rand::rngs::OsRng;
+
+        let mut rng = OsRng;
+        let sk = MontgomeryPoint::random_sk(&mut rng).unwrap();
+        let pk = MontgomeryPoint::public_key(&sk).to_arr();
+        assert_eq!(MontgomeryPoint::from_pk_slice(&pk).unwrap().to_arr(), pk);
+
+        // The top bit is masked rather than rejected
+        let mut high_bit = pk;
+        high_bit[31] |= 0x80;
+        assert_eq!(
+            MontgomeryPoint::from_pk_slice(&high_bit).unwrap().to_arr(),
+            pk
+        );
+
+        let mut p = [0xffu8; 32];
+        p[0] = 0xed;
+        p[31] = 0x7f;
+        let mut high_bit_identity = [0u8; 32];
+        high_bit_identity[31] = 0x80;
+        for bad_pk in [[0u8; 32], p, high_bit_identity].iter() {
+            assert!(matches!(
+                MontgomeryPoint::from_pk_slice(bad_pk.into()),
+                Err(InternalError::PointError)
+            ));
+        }
+    }
 }
@@ -5,12 +5,13 @@
 
 use crate::key_exchange::group::KeGroup;
 use crate::{
-    ciphersuite::CipherSuite,
+    ciphersuite::{CipherSuite, KeyExchangeLabels},
     errors::ProtocolError,
     hash::Hash,
-    keypair::{PrivateKey, PublicKey, SecretKey},
+    keypair::{KeyPair, PublicKey, SecretKey},
 };
 use alloc::vec::Vec;
+use core::fmt::Debug;
 use rand::{CryptoRng, RngCore};
 use zeroize::Zeroize;
 
@@ -27,21 +28,32 @@ pub type GenerateKe2Result<K, D, G> = (
     generic_array::GenericArray<u8, <D as digest::Digest>::OutputSize>,
 );
 #[cfg(not(test))]
-pub type GenerateKe3Result<K, D, G> = (Vec<u8>, <K as KeyExchange<D, G>>::KE3Message);
+pub type GenerateKe3Result<K, D, G> = (
+    Vec<u8>,
+    <K as KeyExchange<D, G>>::KE3Message,
+    generic_array::GenericArray<u8, <D as digest::Digest>::OutputSize>,
+);
 #[cfg(test)]
 pub type GenerateKe3Result<K, D, G> = (
     Vec<u8>,
     <K as KeyExchange<D, G>>::KE3Message,
+    generic_array::GenericArray<u8, <D as digest::Digest>::OutputSize>,
+    Vec<u8>,
+    generic_array::GenericArray<u8, <D as digest::Digest>::OutputSize>,
+);
+
+// The session key, followed by the hash of the full transcript
+pub type FinishKeResult<D> = (
     Vec<u8>,
     generic_array::GenericArray<u8, <D as digest::Digest>::OutputSize>,
 );
 
 pub trait KeyExchange<D: Hash, G: KeGroup> {
-    type KE1State: FromBytes + ToBytes + Zeroize + Clone;
-    type KE2State: FromBytes + ToBytes + Zeroize + Clone;
-    type KE1Message: FromBytes + ToBytes + Clone;
-    type KE2Message: FromBytes + ToBytes + Clone;
-    type KE3Message: FromBytes + ToBytes + Clone;
+    type KE1State: FromBytes + ToBytes + Zeroize + Clone + Debug + Eq + core::hash::Hash;
+    type KE2State: FromBytes + ToBytes + Zeroize + Clone + Debug + Eq + core::hash::Hash;
+    type KE1Message: FromBytes + ToBytes + Clone + Debug + Eq + core::hash::Hash;
+    type KE2Message: FromBytes + ToBytes + Clone + Debug + Eq + core::hash::Hash;
+    type KE3Message: FromBytes + ToBytes + Clone + Debug + Eq + core::hash::Hash;
 
     fn generate_ke1<R: RngCore + CryptoRng>(
         rng: &mut R,
@@ -55,31 +67,45 @@ pub trait KeyExchange<D: Hash, G: KeGroup> {
         ke1_message: Self::KE1Message,
         client_s_pk: PublicKey<G>,
         server_s_sk: S,
+        server_e_kp: KeyPair<G>,
         id_u: Vec<u8>,
         id_s: Vec<u8>,
         context: Vec<u8>,
+        labels: &KeyExchangeLabels,
     ) -> Result<GenerateKe2Result<Self, D, G>, ProtocolError<S::Error>>;
 
     #[allow(clippy::too_many_arguments, clippy::type_complexity)]
-    fn generate_ke3(
+    fn generate_ke3<S: SecretKey<G>>(
         l2_component: Vec<u8>,
         ke2_message: Self::KE2Message,
         ke1_state: &Self::KE1State,
         serialized_credential_request: &[u8],
         server_s_pk: PublicKey<G>,
-        client_s_sk: PrivateKey<G>,
+        client_s_sk: S,
         id_u: Vec<u8>,
         id_s: Vec<u8>,
         context: Vec<u8>,
-    ) -> Result<GenerateKe3Result<Self, D, G>, ProtocolError>;
+        labels: &KeyExchangeLabels,
+    ) -> Result<GenerateKe3Result<Self, D, G>, ProtocolError<S::Error>>;
 
     #[allow(clippy::type_complexity)]
     fn finish_ke(
         ke3_message: Self::KE3Message,
         ke2_state: &Self::KE2State,
-    ) -> Result<Vec<u8>, ProtocolError>;
+        labels: &KeyExchangeLabels,
+    ) -> Result<FinishKeResult<D>, ProtocolError>;
+
+    fn ke2_server_nonce(ke2_message: &Self::KE2Message) -> &[u8];
+
+    fn ke2_server_e_pk(ke2_message: &Self::KE2Message) -> Vec<u8>;
+
+    fn ke2_mac(ke2_message: &Self::KE2Message) -> &[u8];
+
+    fn ke1_message_size() -> usize;
 
     fn ke2_message_size() -> usize;
+
+    fn ke3_message_size() -> usize;
 }
 
 pub trait FromBytes: Sized {
@@ -88,4 +114,10 @@ pub trait FromBytes: Sized {
 
 pub trait ToBytes {
     fn to_bytes(&self) -> Vec<u8>;
+
+    // Writes the output of `to_bytes` into `output`, which has exactly its
+    // length. Messages override this to serialize without allocating.
+    fn write_bytes(&self, output: &mut [u8]) {
+        output.copy_from_slice(&self.to_bytes());
+    }
 }
@@ -5,18 +5,21 @@
 
 //! An implementation of the Triple Diffie-Hellman key exchange protocol
 use crate::{
-    ciphersuite::CipherSuite,
+    ciphersuite::{CipherSuite, KeyExchangeLabels},
     errors::{
-        utils::{check_slice_size, check_slice_size_atleast},
+        utils::{check_slice_size, check_slice_size_atleast, fill_random, unless_external},
         InternalError, ProtocolError,
     },
     hash::Hash,
+    impls::Redacted,
     key_exchange::{
         group::KeGroup,
-        traits::{FromBytes, GenerateKe2Result, GenerateKe3Result, KeyExchange, ToBytes},
+        traits::{
+            FinishKeResult, FromBytes, GenerateKe2Result, GenerateKe3Result, KeyExchange, ToBytes,
+        },
     },
     keypair::{KeyPair, PrivateKey, PublicKey, SecretKey},
-    serialization::serialize,
+    serialization::i2osp,
 };
 use alloc::vec;
 use alloc::vec::Vec;
@@ -26,10 +29,11 @@ use generic_array::{
     typenum::{Unsigned, U32},
     ArrayLength, GenericArray,
 };
-use hkdf::Hkdf;
+use hkdf::{Hkdf, HkdfExtract};
 use hmac::{Hmac, Mac, NewMac};
 use rand::{CryptoRng, RngCore};
-use zeroize::Zeroize;
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, Zeroizing};
 
 ///////////////
 // Constants //
@@ -37,12 +41,6 @@ use zeroize::Zeroize;
 ///////////////
 
 pub(crate) type NonceLen = U32;
-static STR_RFC: &[u8] = b"RFCXXXX";
-static STR_CLIENT_MAC: &[u8] = b"ClientMAC";
-static STR_HANDSHAKE_SECRET: &[u8] = b"HandshakeSecret";
-static STR_SERVER_MAC: &[u8] = b"ServerMAC";
-static STR_SESSION_KEY: &[u8] = b"SessionKey";
-static STR_OPAQUE: &[u8] = b"OPAQUE-";
 
 ////////////////////////////
 // High-level API Structs //
@@ -53,6 +51,15 @@ static STR_OPAQUE: &[u8] = b"OPAQUE-";
 /// The Triple Diffie-Hellman key exchange implementation
 pub struct TripleDH;
 
+#[cfg(feature = "kmac")]
+#[allow(clippy::upper_case_acronyms)]
+/// The Triple Diffie-Hellman key exchange, with a key schedule based on
+/// KMAC256 instead of HKDF and HMAC, meant for ciphersuites using a SHA-3
+/// hash. The messages are the same as for [TripleDH], while the derived keys
+/// and MACs differ. Note that the envelope and the credential response are
+/// still protected with HKDF and HMAC over the hash of the ciphersuite.
+pub struct TripleDHKmac;
+
 /// The client state produced after the first key exchange message
 #[cfg_attr(feature = "serialize", derive(serde::Deserialize, serde::Serialize))]
 pub struct Ke1State<KG: KeGroup> {
@@ -70,15 +77,22 @@ impl_debug_eq_hash_for!(
 );
 
 /// The first key exchange message
-#[derive(PartialEq, Eq, Debug, Hash, Clone)]
 #[cfg_attr(feature = "serialize", derive(serde::Deserialize, serde::Serialize))]
 pub struct Ke1Message<KG: KeGroup> {
     pub(crate) client_nonce: GenericArray<u8, NonceLen>,
     pub(crate) client_e_pk: PublicKey<KG>,
 }
 
+impl_clone_for!(
+    struct Ke1Message<KG: KeGroup>,
+    [client_nonce, client_e_pk],
+);
+impl_debug_eq_hash_for!(
+    struct Ke1Message<KG: KeGroup>,
+    [client_nonce, client_e_pk],
+);
+
 /// The server state produced after the second key exchange message
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "serialize", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "serialize", serde(bound = ""))]
 pub struct Ke2State<HashLen: ArrayLength<u8>> {
@@ -87,8 +101,46 @@ pub struct Ke2State<HashLen: ArrayLength<u8>> {
     session_key: GenericArray<u8, HashLen>,
 }
 
+// These can't be derived without bounding `HashLen` itself
+impl<HashLen: ArrayLength<u8>> Clone for Ke2State<HashLen> {
+    fn clone(&self) -> Self {
+        Self {
+            km3: self.km3.clone(),
+            hashed_transcript: self.hashed_transcript.clone(),
+            session_key: self.session_key.clone(),
+        }
+    }
+}
+
+impl<HashLen: ArrayLength<u8>> Eq for Ke2State<HashLen> {}
+
+impl<HashLen: ArrayLength<u8>> PartialEq for Ke2State<HashLen> {
+    fn eq(&self, other: &Self) -> bool {
+        self.km3 == other.km3
+            && self.hashed_transcript == other.hashed_transcript
+            && self.session_key == other.session_key
+    }
+}
+
+impl<HashLen: ArrayLength<u8>> core::hash::Hash for Ke2State<HashLen> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.km3.hash(state);
+        self.hashed_transcript.hash(state);
+        self.session_key.hash(state);
+    }
+}
+
+impl<HashLen: ArrayLength<u8>> core::fmt::Debug for Ke2State<HashLen> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Ke2State")
+            .field("km3", &Redacted(HashLen::USIZE))
+            .field("hashed_transcript", &self.hashed_transcript)
+            .field("session_key", &Redacted(HashLen::USIZE))
+            .finish()
+    }
+}
+
 /// The second key exchange message
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "serialize", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "serialize", serde(bound = ""))]
 pub struct Ke2Message<KG: KeGroup, HashLen: ArrayLength<u8>> {
@@ -97,20 +149,87 @@ pub struct Ke2Message<KG: KeGroup, HashLen: ArrayLength<u8>> {
     mac: GenericArray<u8, HashLen>,
 }
 
+impl<KG: KeGroup, HashLen: ArrayLength<u8>> Clone for Ke2Message<KG, HashLen> {
+    fn clone(&self) -> Self {
+        Self {
+            server_nonce: self.server_nonce,
+            server_e_pk: self.server_e_pk.clone(),
+            mac: self.mac.clone(),
+        }
+    }
+}
+
+impl<KG: KeGroup, HashLen: ArrayLength<u8>> core::fmt::Debug for Ke2Message<KG, HashLen> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Ke2Message")
+            .field("server_nonce", &self.server_nonce)
+            .field("server_e_pk", &self.server_e_pk)
+            .field("mac", &self.mac)
+            .finish()
+    }
+}
+
+impl<KG: KeGroup, HashLen: ArrayLength<u8>> Eq for Ke2Message<KG, HashLen> {}
+
+impl<KG: KeGroup, HashLen: ArrayLength<u8>> PartialEq for Ke2Message<KG, HashLen> {
+    fn eq(&self, other: &Self) -> bool {
+        self.server_nonce == other.server_nonce
+            && self.server_e_pk == other.server_e_pk
+            && self.mac == other.mac
+    }
+}
+
+impl<KG: KeGroup, HashLen: ArrayLength<u8>> core::hash::Hash for Ke2Message<KG, HashLen> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.server_nonce.hash(state);
+        self.server_e_pk.hash(state);
+        self.mac.hash(state);
+    }
+}
+
 /// The third key exchange message
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "serialize", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "serialize", serde(bound = ""))]
 pub struct Ke3Message<HashLen: ArrayLength<u8>> {
     mac: GenericArray<u8, HashLen>,
 }
 
+impl<HashLen: ArrayLength<u8>> Clone for Ke3Message<HashLen> {
+    fn clone(&self) -> Self {
+        Self {
+            mac: self.mac.clone(),
+        }
+    }
+}
+
+impl<HashLen: ArrayLength<u8>> core::fmt::Debug for Ke3Message<HashLen> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Ke3Message")
+            .field("mac", &self.mac)
+            .finish()
+    }
+}
+
+impl<HashLen: ArrayLength<u8>> Eq for Ke3Message<HashLen> {}
+
+impl<HashLen: ArrayLength<u8>> PartialEq for Ke3Message<HashLen> {
+    fn eq(&self, other: &Self) -> bool {
+        self.mac == other.mac
+    }
+}
+
+impl<HashLen: ArrayLength<u8>> core::hash::Hash for Ke3Message<HashLen> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.mac.hash(state);
+    }
+}
+
 ////////////////////////////////
 // High-level Implementations //
 // ========================== //
 ////////////////////////////////
 
-impl<D: Hash, KG: KeGroup> KeyExchange<D, KG> for TripleDH {
+impl<D: Hash, KG: KeGroup, K: KeySchedule<D>> KeyExchange<D, KG> for K {
     type KE1State = Ke1State<KG>;
     type KE2State = Ke2State<<D as FixedOutput>::OutputSize>;
     type KE1Message = Ke1Message<KG>;
@@ -121,7 +240,7 @@ impl<D: Hash, KG: KeGroup> KeyExchange<D, KG> for TripleDH {
         rng: &mut R,
     ) -> Result<(Self::KE1State, Self::KE1Message), ProtocolError> {
         let client_e_kp = KeyPair::<KG>::generate_random(rng)?;
-        let client_nonce = generate_nonce::<R>(rng);
+        let client_nonce = generate_nonce::<R>(rng)?;
 
         let ke1_message = Ke1Message {
             client_nonce,
@@ -145,40 +264,51 @@ impl<D: Hash, KG: KeGroup> KeyExchange<D, KG> for TripleDH {
         ke1_message: Self::KE1Message,
         client_s_pk: PublicKey<KG>,
         server_s_sk: S,
+        server_e_kp: KeyPair<KG>,
         id_u: Vec<u8>,
         id_s: Vec<u8>,
         context: Vec<u8>,
+        labels: &KeyExchangeLabels,
     ) -> Result<GenerateKe2Result<Self, D, KG>, ProtocolError<S::Error>> {
-        let server_e_kp =
-            KeyPair::<KG>::generate_random(rng).map_err(|_| InternalError::InvalidKeypairError)?;
-        let server_nonce = generate_nonce::<R>(rng);
+        let server_nonce = generate_nonce::<R>(rng).map_err(InternalError::into_custom)?;
+
+        // Reject a client that echoes back the server's ephemeral public key
+        if ke1_message.client_e_pk == *server_e_kp.public() {
+            return Err(ProtocolError::ReflectedValueError);
+        }
 
         let mut transcript_hasher = D::new()
-            .chain(STR_RFC)
-            .chain(&serialize(&context, 2).map_err(ProtocolError::into_custom)?)
+            .chain(labels.transcript_preamble)
+            .chain(&i2osp(context.len(), 2).map_err(ProtocolError::into_custom)?)
+            .chain(&context)
             .chain(&id_u)
             .chain(&serialized_credential_request[..])
             .chain(&id_s)
             .chain(&l2_bytes[..])
             .chain(&server_nonce[..])
             .chain(&server_e_kp.public().to_arr());
+        let hashed_transcript = transcript_hasher.clone().finalize();
+
+        let dh = TripleDHComponents(
+            Zeroizing::new(
+                server_e_kp
+                    .private()
+                    .diffie_hellman(ke1_message.client_e_pk.clone())
+                    .map_err(InternalError::into_custom)?,
+            ),
+            Zeroizing::new(server_s_sk.diffie_hellman(ke1_message.client_e_pk)?),
+            Zeroizing::new(
+                server_e_kp
+                    .private()
+                    .diffie_hellman(client_s_pk)
+                    .map_err(InternalError::into_custom)?,
+            ),
+        );
+        let result = derive_3dh_keys::<K, D>(dh, &hashed_transcript, labels)
+            .map_err(ProtocolError::into_custom)?;
 
-        let result = derive_3dh_keys::<D, KG, S>(
-            TripleDHComponents {
-                pk1: ke1_message.client_e_pk.clone(),
-                sk1: server_e_kp.private().clone(),
-                pk2: ke1_message.client_e_pk,
-                sk2: server_s_sk,
-                pk3: client_s_pk,
-                sk3: server_e_kp.private().clone(),
-            },
-            &transcript_hasher.clone().finalize(),
-        )?;
-
-        let mut mac_hasher =
-            Hmac::<D>::new_from_slice(&result.1).map_err(|_| InternalError::HmacError)?;
-        mac_hasher.update(&transcript_hasher.clone().finalize());
-        let mac = mac_hasher.finalize().into_bytes();
+        let mac =
+            K::mac(&result.1, &hashed_transcript, labels).map_err(InternalError::into_custom)?;
 
         transcript_hasher.update(&mac);
 
@@ -201,57 +331,74 @@ impl<D: Hash, KG: KeGroup> KeyExchange<D, KG> for TripleDH {
     }
 
     #[allow(clippy::type_complexity)]
-    fn generate_ke3(
+    fn generate_ke3<S: SecretKey<KG>>(
         l2_component: Vec<u8>,
         ke2_message: Self::KE2Message,
         ke1_state: &Self::KE1State,
         serialized_credential_request: &[u8],
         server_s_pk: PublicKey<KG>,
-        client_s_sk: PrivateKey<KG>,
+        client_s_sk: S,
         id_u: Vec<u8>,
         id_s: Vec<u8>,
         context: Vec<u8>,
-    ) -> Result<GenerateKe3Result<Self, D, KG>, ProtocolError> {
+        labels: &KeyExchangeLabels,
+    ) -> Result<GenerateKe3Result<Self, D, KG>, ProtocolError<S::Error>> {
+        // Reject a server that echoes back the client's ephemeral public key
+        if ke2_message.server_e_pk
+            == ke1_state
+                .client_e_sk
+                .public_key()
+                .map_err(InternalError::into_custom)?
+        {
+            return Err(ProtocolError::ReflectedValueError);
+        }
+
         let mut transcript_hasher = D::new()
-            .chain(STR_RFC)
-            .chain(&serialize(&context, 2)?)
+            .chain(labels.transcript_preamble)
+            .chain(&i2osp(context.len(), 2).map_err(ProtocolError::into_custom)?)
+            .chain(&context)
             .chain(&id_u)
             .chain(&serialized_credential_request)
             .chain(&id_s)
             .chain(&l2_component[..])
-            .chain(&ke2_message.to_bytes_without_info_or_mac());
-
-        let result = derive_3dh_keys::<D, KG, PrivateKey<KG>>(
-            TripleDHComponents {
-                pk1: ke2_message.server_e_pk.clone(),
-                sk1: ke1_state.client_e_sk.clone(),
-                pk2: server_s_pk,
-                sk2: ke1_state.client_e_sk.clone(),
-                pk3: ke2_message.server_e_pk.clone(),
-                sk3: client_s_sk,
-            },
-            &transcript_hasher.clone().finalize(),
-        )?;
-
-        let mut server_mac =
-            Hmac::<D>::new_from_slice(&result.1).map_err(|_| InternalError::HmacError)?;
-        server_mac.update(&transcript_hasher.clone().finalize());
-
-        if server_mac.verify(&ke2_message.mac).is_err() {
+            .chain(&ke2_message.server_nonce[..])
+            .chain(&ke2_message.server_e_pk.to_arr());
+        let hashed_transcript = transcript_hasher.clone().finalize();
+
+        let dh = TripleDHComponents(
+            Zeroizing::new(
+                ke1_state
+                    .client_e_sk
+                    .diffie_hellman(ke2_message.server_e_pk.clone())
+                    .map_err(InternalError::into_custom)?,
+            ),
+            Zeroizing::new(
+                ke1_state
+                    .client_e_sk
+                    .diffie_hellman(server_s_pk)
+                    .map_err(InternalError::into_custom)?,
+            ),
+            Zeroizing::new(client_s_sk.diffie_hellman(ke2_message.server_e_pk.clone())?),
+        );
+        let result = derive_3dh_keys::<K, D>(dh, &hashed_transcript, labels)
+            .map_err(ProtocolError::into_custom)?;
+
+        if !K::verify(&result.1, &hashed_transcript, &ke2_message.mac, labels)
+            .map_err(InternalError::into_custom)?
+        {
             return Err(ProtocolError::InvalidLoginError);
         }
 
-        transcript_hasher.update(ke2_message.mac.to_vec());
-
-        let mut client_mac =
-            Hmac::<D>::new_from_slice(&result.2).map_err(|_| InternalError::HmacError)?;
-        client_mac.update(&transcript_hasher.finalize());
+        transcript_hasher.update(&ke2_message.mac);
+        let hashed_transcript = transcript_hasher.finalize();
 
         Ok((
             result.0.to_vec(),
             Ke3Message {
-                mac: client_mac.finalize().into_bytes(),
+                mac: K::mac(&result.2, &hashed_transcript, labels)
+                    .map_err(InternalError::into_custom)?,
             },
+            hashed_transcript,
             #[cfg(test)]
             result.3,
             #[cfg(test)]
@@ -263,21 +410,46 @@ impl<D: Hash, KG: KeGroup> KeyExchange<D, KG> for TripleDH {
     fn finish_ke(
         ke3_message: Self::KE3Message,
         ke2_state: &Self::KE2State,
-    ) -> Result<Vec<u8>, ProtocolError> {
-        let mut client_mac =
-            Hmac::<D>::new_from_slice(&ke2_state.km3).map_err(|_| InternalError::HmacError)?;
-        client_mac.update(&ke2_state.hashed_transcript);
-
-        if client_mac.verify(&ke3_message.mac).is_err() {
+        labels: &KeyExchangeLabels,
+    ) -> Result<FinishKeResult<D>, ProtocolError> {
+        if !K::verify(
+            &ke2_state.km3,
+            &ke2_state.hashed_transcript,
+            &ke3_message.mac,
+            labels,
+        )? {
             return Err(ProtocolError::InvalidLoginError);
         }
 
-        Ok(ke2_state.session_key.to_vec())
+        Ok((
+            ke2_state.session_key.to_vec(),
+            ke2_state.hashed_transcript.clone(),
+        ))
+    }
+
+    fn ke2_server_nonce(ke2_message: &Self::KE2Message) -> &[u8] {
+        &ke2_message.server_nonce
+    }
+
+    fn ke2_server_e_pk(ke2_message: &Self::KE2Message) -> Vec<u8> {
+        ke2_message.server_e_pk.to_arr().to_vec()
+    }
+
+    fn ke2_mac(ke2_message: &Self::KE2Message) -> &[u8] {
+        &ke2_message.mac
+    }
+
+    fn ke1_message_size() -> usize {
+        NonceLen::USIZE + <KG as KeGroup>::PkLen::USIZE
     }
 
     fn ke2_message_size() -> usize {
         NonceLen::USIZE + <KG as KeGroup>::PkLen::USIZE + <D as FixedOutput>::OutputSize::USIZE
     }
+
+    fn ke3_message_size() -> usize {
+        <D as FixedOutput>::OutputSize::USIZE
+    }
 }
 
 /////////////////////////
@@ -286,15 +458,9 @@ impl<D: Hash, KG: KeGroup> KeyExchange<D, KG> for TripleDH {
 /////////////////////////
 
 #[allow(clippy::upper_case_acronyms)]
-// The triple of public and private components used in the 3DH computation
-struct TripleDHComponents<KG: KeGroup, S: SecretKey<KG>> {
-    pk1: PublicKey<KG>,
-    sk1: PrivateKey<KG>,
-    pk2: PublicKey<KG>,
-    sk2: S,
-    pk3: PublicKey<KG>,
-    sk3: PrivateKey<KG>,
-}
+// The triple of Diffie-Hellman outputs of the 3DH computation, computed by
+// each party with its own private keys, any of which may be held remotely
+struct TripleDHComponents(Zeroizing<Vec<u8>>, Zeroizing<Vec<u8>>, Zeroizing<Vec<u8>>);
 
 // Consists of a session key, followed by two mac keys: (session_key, km2, km3)
 #[cfg(not(test))]
@@ -317,105 +483,240 @@ type TripleDHDerivationResult<D> = (
 // ========================================== //
 ////////////////////////////////////////////////
 
-// Helper functions
+// The primitives behind the key schedule of a 3DH variant: the derivation of
+// the session key and of the two MAC keys, and the MAC itself
+pub(crate) trait KeySchedule<D: Hash> {
+    fn derive_keys(
+        shared_secrets: [&[u8]; 3],
+        hashed_derivation_transcript: &[u8],
+        labels: &KeyExchangeLabels,
+    ) -> Result<TripleDHDerivationResult<D>, ProtocolError>;
+
+    fn mac(
+        key: &[u8],
+        message: &[u8],
+        labels: &KeyExchangeLabels,
+    ) -> Result<GenericArray<u8, <D as FixedOutput>::OutputSize>, InternalError>;
+
+    fn verify(
+        key: &[u8],
+        message: &[u8],
+        tag: &[u8],
+        labels: &KeyExchangeLabels,
+    ) -> Result<bool, InternalError> {
+        Ok(Self::mac(key, message, labels)?[..].ct_eq(tag).into())
+    }
+}
 
-// Internal function which takes the public and private components of the client and server keypairs, along
-// with some auxiliary metadata, to produce the session key and two MAC keys
-fn derive_3dh_keys<D: Hash, KG: KeGroup, S: SecretKey<KG>>(
-    dh: TripleDHComponents<KG, S>,
-    hashed_derivation_transcript: &[u8],
-) -> Result<TripleDHDerivationResult<D>, ProtocolError<S::Error>> {
-    let ikm: Vec<u8> = [
-        &dh.sk1
-            .diffie_hellman(dh.pk1)
-            .map_err(InternalError::into_custom)?[..],
-        &dh.sk2.diffie_hellman(dh.pk2)?[..],
-        &dh.sk3
-            .diffie_hellman(dh.pk3)
-            .map_err(InternalError::into_custom)?[..],
-    ]
-    .concat();
-
-    let extracted_ikm = Hkdf::<D>::new(None, &ikm);
-    let handshake_secret = derive_secrets::<D>(
-        &extracted_ikm,
-        STR_HANDSHAKE_SECRET,
-        hashed_derivation_transcript,
-    )
-    .map_err(ProtocolError::into_custom)?;
-    let session_key = derive_secrets::<D>(
-        &extracted_ikm,
-        STR_SESSION_KEY,
-        hashed_derivation_transcript,
-    )
-    .map_err(ProtocolError::into_custom)?;
+// The key schedule of the specification, based on HKDF and HMAC
+impl<D: Hash> KeySchedule<D> for TripleDH {
+    fn derive_keys(
+        shared_secrets: [&[u8]; 3],
+        hashed_derivation_transcript: &[u8],
+        labels: &KeyExchangeLabels,
+    ) -> Result<TripleDHDerivationResult<D>, ProtocolError> {
+        // The shared secrets are fed into HKDF-Extract one at a time, which is
+        // equivalent to extracting from their concatenation
+        let mut extract = HkdfExtract::<D>::new(None);
+        for shared_secret in shared_secrets.iter() {
+            extract.input_ikm(shared_secret);
+        }
+        let (_, extracted_ikm) = extract.finalize();
+        let handshake_secret = derive_secrets::<D>(
+            &extracted_ikm,
+            labels.prefix,
+            labels.handshake_secret,
+            hashed_derivation_transcript,
+        )?;
+        let session_key = derive_secrets::<D>(
+            &extracted_ikm,
+            labels.prefix,
+            labels.session_key,
+            hashed_derivation_transcript,
+        )?;
 
-    let km2 = hkdf_expand_label::<D>(
-        &handshake_secret,
-        STR_SERVER_MAC,
-        b"",
-        <D as Digest>::OutputSize::USIZE,
-    )
-    .map_err(ProtocolError::into_custom)?;
-    let km3 = hkdf_expand_label::<D>(
-        &handshake_secret,
-        STR_CLIENT_MAC,
-        b"",
-        <D as Digest>::OutputSize::USIZE,
-    )
-    .map_err(ProtocolError::into_custom)?;
+        let km2 = hkdf_expand_label::<D>(
+            &handshake_secret,
+            labels.prefix,
+            labels.server_mac,
+            b"",
+            <D as Digest>::OutputSize::USIZE,
+        )?;
+        let km3 = hkdf_expand_label::<D>(
+            &handshake_secret,
+            labels.prefix,
+            labels.client_mac,
+            b"",
+            <D as Digest>::OutputSize::USIZE,
+        )?;
+
+        Ok((
+            GenericArray::clone_from_slice(&session_key),
+            GenericArray::clone_from_slice(&km2),
+            GenericArray::clone_from_slice(&km3),
+            #[cfg(test)]
+            handshake_secret,
+        ))
+    }
 
-    Ok((
-        GenericArray::clone_from_slice(&session_key),
-        GenericArray::clone_from_slice(&km2),
-        GenericArray::clone_from_slice(&km3),
-        #[cfg(test)]
-        handshake_secret,
-    ))
+    fn mac(
+        key: &[u8],
+        message: &[u8],
+        _labels: &KeyExchangeLabels,
+    ) -> Result<GenericArray<u8, <D as FixedOutput>::OutputSize>, InternalError> {
+        let mut mac = Hmac::<D>::new_from_slice(key).map_err(|_| InternalError::HmacError)?;
+        mac.update(message);
+        Ok(mac.finalize().into_bytes())
+    }
+
+    fn verify(
+        key: &[u8],
+        message: &[u8],
+        tag: &[u8],
+        _labels: &KeyExchangeLabels,
+    ) -> Result<bool, InternalError> {
+        let mut mac = Hmac::<D>::new_from_slice(key).map_err(|_| InternalError::HmacError)?;
+        mac.update(message);
+        Ok(mac.verify(tag).is_ok())
+    }
+}
+
+// A key schedule in the style of NIST SP 800-108 and SP 800-185, where every
+// derivation and MAC is a KMAC256 invocation whose customization string is
+// the label, for use with SHA-3 hashes
+#[cfg(feature = "kmac")]
+impl<D: Hash> KeySchedule<D> for TripleDHKmac {
+    fn derive_keys(
+        shared_secrets: [&[u8]; 3],
+        hashed_derivation_transcript: &[u8],
+        labels: &KeyExchangeLabels,
+    ) -> Result<TripleDHDerivationResult<D>, ProtocolError> {
+        let mut ikm = shared_secrets.concat();
+        let handshake_secret = kmac256::<D>(
+            &ikm,
+            labels.prefix,
+            labels.handshake_secret,
+            hashed_derivation_transcript,
+        );
+        let session_key = kmac256::<D>(
+            &ikm,
+            labels.prefix,
+            labels.session_key,
+            hashed_derivation_transcript,
+        );
+        ikm.zeroize();
+
+        let km2 = kmac256::<D>(&handshake_secret, labels.prefix, labels.server_mac, b"");
+        let km3 = kmac256::<D>(&handshake_secret, labels.prefix, labels.client_mac, b"");
+
+        Ok((
+            session_key,
+            km2,
+            km3,
+            #[cfg(test)]
+            handshake_secret.to_vec(),
+        ))
+    }
+
+    fn mac(
+        key: &[u8],
+        message: &[u8],
+        labels: &KeyExchangeLabels,
+    ) -> Result<GenericArray<u8, <D as FixedOutput>::OutputSize>, InternalError> {
+        Ok(kmac256::<D>(key, labels.prefix, labels.mac, message))
+    }
+}
+
+// KMAC256 with an output of the size of `D`, customized with the prefixed label
+#[cfg(feature = "kmac")]
+fn kmac256<D: Hash>(
+    key: &[u8],
+    prefix: &[u8],
+    label: &[u8],
+    data: &[u8],
+) -> GenericArray<u8, <D as FixedOutput>::OutputSize> {
+    use tiny_keccak::{Hasher, Kmac};
+
+    let mut kmac = Kmac::v256(key, &[prefix, label].concat());
+    kmac.update(data);
+    let mut output = GenericArray::default();
+    kmac.finalize(&mut output);
+    output
+}
+
+// Helper functions
+
+// Internal function which takes the Diffie-Hellman outputs of the client and server keypairs, along
+// with some auxiliary metadata, to produce the session key and two MAC keys. Fails if any of the
+// Diffie-Hellman outputs is the identity element, which every KeGroup serializes to all zeros
+fn derive_3dh_keys<K: KeySchedule<D>, D: Hash>(
+    dh: TripleDHComponents,
+    hashed_derivation_transcript: &[u8],
+    labels: &KeyExchangeLabels,
+) -> Result<TripleDHDerivationResult<D>, ProtocolError> {
+    let is_degenerate = [&dh.0, &dh.1, &dh.2]
+        .iter()
+        .fold(subtle::Choice::from(0), |acc, dh| {
+            acc | dh.ct_eq(&vec![0u8; dh.len()])
+        });
+
+    if bool::from(is_degenerate) {
+        return Err(ProtocolError::DegenerateDiffieHellmanError);
+    }
+    K::derive_keys([&dh.0, &dh.1, &dh.2], hashed_derivation_transcript, labels)
 }
 
 fn hkdf_expand_label<D: Hash>(
     secret: &[u8],
+    prefix: &[u8],
     label: &[u8],
     context: &[u8],
     length: usize,
 ) -> Result<Vec<u8>, ProtocolError> {
     let h = Hkdf::<D>::from_prk(secret).map_err(|_| InternalError::HkdfError)?;
-    hkdf_expand_label_extracted(&h, label, context, length)
+    hkdf_expand_label_extracted(&h, prefix, label, context, length)
 }
 
 fn hkdf_expand_label_extracted<D: Hash>(
     hkdf: &Hkdf<D>,
+    prefix: &[u8],
     label: &[u8],
     context: &[u8],
     length: usize,
 ) -> Result<Vec<u8>, ProtocolError> {
     let mut okm = vec![0u8; length];
 
-    let mut hkdf_label: Vec<u8> = Vec::new();
-
+    // The HkdfLabel structure is passed to HKDF-Expand in parts, rather than
+    // being assembled first
     let length_u16: u16 = u16::try_from(length).map_err(|_| ProtocolError::SerializationError)?;
-    hkdf_label.extend_from_slice(&length_u16.to_be_bytes());
-
-    let mut opaque_label: Vec<u8> = Vec::new();
-    opaque_label.extend_from_slice(STR_OPAQUE);
-    opaque_label.extend_from_slice(label);
-    hkdf_label.extend_from_slice(&serialize(&opaque_label, 1)?);
-
-    hkdf_label.extend_from_slice(&serialize(context, 1)?);
-
-    hkdf.expand(&hkdf_label, &mut okm)
-        .map_err(|_| InternalError::HkdfError)?;
+    let label_len =
+        u8::try_from(prefix.len() + label.len()).map_err(|_| ProtocolError::SerializationError)?;
+    let context_len = u8::try_from(context.len()).map_err(|_| ProtocolError::SerializationError)?;
+
+    hkdf.expand_multi_info(
+        &[
+            &length_u16.to_be_bytes(),
+            &[label_len],
+            prefix,
+            label,
+            &[context_len],
+            context,
+        ],
+        &mut okm,
+    )
+    .map_err(|_| InternalError::HkdfError)?;
     Ok(okm)
 }
 
 fn derive_secrets<D: Hash>(
     hkdf: &Hkdf<D>,
+    prefix: &[u8],
     label: &[u8],
     hashed_derivation_transcript: &[u8],
 ) -> Result<Vec<u8>, ProtocolError> {
     hkdf_expand_label_extracted::<D>(
         hkdf,
+        prefix,
         label,
         hashed_derivation_transcript,
         <D as Digest>::OutputSize::USIZE,
@@ -423,10 +724,12 @@ fn derive_secrets<D: Hash>(
 }
 
 // Generate a random nonce up to NonceLen::USIZE bytes.
-fn generate_nonce<R: RngCore + CryptoRng>(rng: &mut R) -> GenericArray<u8, NonceLen> {
-    let mut nonce_bytes = vec![0u8; NonceLen::USIZE];
-    rng.fill_bytes(&mut nonce_bytes);
-    GenericArray::clone_from_slice(&nonce_bytes)
+fn generate_nonce<R: RngCore + CryptoRng>(
+    rng: &mut R,
+) -> Result<GenericArray<u8, NonceLen>, InternalError> {
+    let mut nonce = GenericArray::default();
+    fill_random(rng, &mut nonce)?;
+    Ok(nonce)
 }
 
 // Serialization and deserialization implementations
@@ -436,7 +739,7 @@ impl<KG: KeGroup> FromBytes for Ke1State<KG> {
         let key_len = <KG as KeGroup>::PkLen::USIZE;
 
         let nonce_len = NonceLen::USIZE;
-        let checked_bytes = check_slice_size_atleast(bytes, key_len + nonce_len, "ke1_state")?;
+        let checked_bytes = check_slice_size(bytes, key_len + nonce_len, "ke1_state")?;
 
         Ok(Self {
             client_e_sk: PrivateKey::from_bytes(&checked_bytes[..key_len])?,
@@ -456,6 +759,40 @@ impl<KG: KeGroup> ToBytes for Ke1State<KG> {
 
 impl<KG: KeGroup> FromBytes for Ke1Message<KG> {
     fn from_bytes<CS: CipherSuite>(ke1_message_bytes: &[u8]) -> Result<Self, ProtocolError> {
+        Self::deserialize(ke1_message_bytes)
+    }
+}
+
+impl<KG: KeGroup> ToBytes for Ke1Message<KG> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn write_bytes(&self, output: &mut [u8]) {
+        let (client_nonce, client_e_pk) = output.split_at_mut(NonceLen::USIZE);
+        client_nonce.copy_from_slice(&self.client_nonce);
+        client_e_pk.copy_from_slice(&self.client_e_pk);
+    }
+}
+
+impl<KG: KeGroup> Ke1Message<KG> {
+    /// The client's nonce
+    pub fn client_nonce(&self) -> &GenericArray<u8, NonceLen> {
+        &self.client_nonce
+    }
+
+    /// The client's ephemeral public key
+    pub fn client_e_pk(&self) -> &PublicKey<KG> {
+        &self.client_e_pk
+    }
+
+    /// Serialization into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        [&self.client_nonce[..], &self.client_e_pk.to_arr()].concat()
+    }
+
+    /// Deserialization from bytes
+    pub fn deserialize(ke1_message_bytes: &[u8]) -> Result<Self, ProtocolError> {
         let nonce_len = NonceLen::USIZE;
         let checked_nonce = check_slice_size(
             ke1_message_bytes,
@@ -465,17 +802,14 @@ impl<KG: KeGroup> FromBytes for Ke1Message<KG> {
 
         Ok(Self {
             client_nonce: GenericArray::clone_from_slice(&checked_nonce[..nonce_len]),
-            client_e_pk: PublicKey::from_bytes(&checked_nonce[nonce_len..])?,
+            client_e_pk: KeyPair::<KG>::check_public_key(PublicKey::from_bytes(
+                &checked_nonce[nonce_len..],
+            )?)
+            .map_err(|e| unless_external(e, ProtocolError::InvalidClientEphemeralKeyError))?,
         })
     }
 }
 
-impl<KG: KeGroup> ToBytes for Ke1Message<KG> {
-    fn to_bytes(&self) -> Vec<u8> {
-        [&self.client_nonce[..], &self.client_e_pk.to_arr()].concat()
-    }
-}
-
 impl<HashLen: ArrayLength<u8>> FromBytes for Ke2State<HashLen> {
     fn from_bytes<CS: CipherSuite>(input: &[u8]) -> Result<Self, ProtocolError> {
         let hash_len = HashLen::USIZE;
@@ -504,6 +838,53 @@ impl<HashLen: ArrayLength<u8>> ToBytes for Ke2State<HashLen> {
 
 impl<KG: KeGroup, HashLen: ArrayLength<u8>> FromBytes for Ke2Message<KG, HashLen> {
     fn from_bytes<CS: CipherSuite>(input: &[u8]) -> Result<Self, ProtocolError> {
+        Self::deserialize(input)
+    }
+}
+
+impl<KG: KeGroup, HashLen: ArrayLength<u8>> ToBytes for Ke2Message<KG, HashLen> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn write_bytes(&self, output: &mut [u8]) {
+        let (server_nonce, rest) = output.split_at_mut(NonceLen::USIZE);
+        let (server_e_pk, mac) = rest.split_at_mut(<KG as KeGroup>::PkLen::USIZE);
+        server_nonce.copy_from_slice(&self.server_nonce);
+        server_e_pk.copy_from_slice(&self.server_e_pk);
+        mac.copy_from_slice(&self.mac);
+    }
+}
+
+impl<KG: KeGroup, HashLen: ArrayLength<u8>> Ke2Message<KG, HashLen> {
+    /// The server's nonce
+    pub fn server_nonce(&self) -> &GenericArray<u8, NonceLen> {
+        &self.server_nonce
+    }
+
+    /// The server's ephemeral public key
+    pub fn server_e_pk(&self) -> &PublicKey<KG> {
+        &self.server_e_pk
+    }
+
+    /// The MAC authenticating the server
+    pub fn mac(&self) -> &GenericArray<u8, HashLen> {
+        &self.mac
+    }
+
+    /// Serialization into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        [
+            &self.server_nonce[..],
+            &self.server_e_pk.to_arr(),
+            &self.mac[..],
+        ]
+        .concat()
+    }
+
+    /// Deserialization from bytes, which fails if the ephemeral public key is
+    /// invalid
+    pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
         let key_len = <KG as KeGroup>::PkLen::USIZE;
         let nonce_len = NonceLen::USIZE;
         let checked_nonce = check_slice_size_atleast(input, nonce_len, "ke2_message nonce")?;
@@ -520,32 +901,48 @@ impl<KG: KeGroup, HashLen: ArrayLength<u8>> FromBytes for Ke2Message<KG, HashLen
         )?;
 
         // Check the public key bytes
-        let server_e_pk = KeyPair::<CS::KeGroup>::check_public_key(PublicKey::from_bytes(
+        let server_e_pk = KeyPair::<KG>::check_public_key(PublicKey::from_bytes(
             &unchecked_server_e_pk[..key_len],
-        )?)?;
+        )?)
+        .map_err(|e| unless_external(e, ProtocolError::InvalidServerEphemeralKeyError))?;
 
         Ok(Self {
             server_nonce: GenericArray::clone_from_slice(&checked_nonce[..nonce_len]),
-            server_e_pk: PublicKey::from_bytes(&server_e_pk)?,
+            server_e_pk,
             mac: GenericArray::clone_from_slice(checked_mac),
         })
     }
 }
 
-impl<KG: KeGroup, HashLen: ArrayLength<u8>> ToBytes for Ke2Message<KG, HashLen> {
-    fn to_bytes(&self) -> Vec<u8> {
-        [&self.to_bytes_without_info_or_mac(), &self.mac[..]].concat()
+impl<HashLen: ArrayLength<u8>> FromBytes for Ke3Message<HashLen> {
+    fn from_bytes<CS: CipherSuite>(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        Self::deserialize(bytes)
     }
 }
 
-impl<KG: KeGroup, HashLen: ArrayLength<u8>> Ke2Message<KG, HashLen> {
-    fn to_bytes_without_info_or_mac(&self) -> Vec<u8> {
-        [&self.server_nonce[..], &self.server_e_pk.to_arr()].concat()
+impl<HashLen: ArrayLength<u8>> ToBytes for Ke3Message<HashLen> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn write_bytes(&self, output: &mut [u8]) {
+        output.copy_from_slice(&self.mac);
     }
 }
 
-impl<HashLen: ArrayLength<u8>> FromBytes for Ke3Message<HashLen> {
-    fn from_bytes<CS: CipherSuite>(bytes: &[u8]) -> Result<Self, ProtocolError> {
+impl<HashLen: ArrayLength<u8>> Ke3Message<HashLen> {
+    /// The MAC authenticating the client
+    pub fn mac(&self) -> &GenericArray<u8, HashLen> {
+        &self.mac
+    }
+
+    /// Serialization into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        self.mac.to_vec()
+    }
+
+    /// Deserialization from bytes
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, ProtocolError> {
         let checked_bytes = check_slice_size(bytes, HashLen::USIZE, "ke3_message")?;
 
         Ok(Self {
@@ -554,12 +951,6 @@ impl<HashLen: ArrayLength<u8>> FromBytes for Ke3Message<HashLen> {
     }
 }
 
-impl<HashLen: ArrayLength<u8>> ToBytes for Ke3Message<HashLen> {
-    fn to_bytes(&self) -> Vec<u8> {
-        self.mac.to_vec()
-    }
-}
-
 // Zeroize on drop implementations
 
 // This can't be derived because of the use of a generic parameter
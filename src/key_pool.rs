@@ -0,0 +1,142 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! A pool of pre-generated server ephemeral key pairs, for smoothing the
+//! latency of login starts under bursty load
+//!
+//! Requires the `std` feature. An [EphemeralKeyPool] is shared between the
+//! login handlers, which pass it to
+//! [`ServerLogin::start_with_key_pool`](crate::ServerLogin::start_with_key_pool),
+//! and a background task, which keeps it full with [EphemeralKeyPool::refill].
+//! When the pool runs dry, the key pair is generated as usual, so a pool
+//! never causes a login to fail.
+//!
+//! Each key pair is removed from the pool when it is taken, so that it is
+//! used for a single login.
+//!
+//! ```
+//! use opaque_ke::key_pool::EphemeralKeyPool;
+//! use std::sync::Arc;
+//! # use opaque_ke::{
+//! #   errors::ProtocolError,
+//! #   ClientLogin, ClientRegistration, ClientRegistrationFinishParameters, ServerLogin,
+//! #   ServerLoginStartParameters, ServerRegistration, ServerSetup,
+//! # };
+//! # use opaque_ke::CipherSuite;
+//! # struct Default;
+//! # impl CipherSuite for Default {
+//! #     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+//! #     type Hash = sha2::Sha512;
+//! #     type SlowHash = opaque_ke::slow_hash::NoOpHash;
+//! # }
+//! # use rand::rngs::OsRng;
+//! # let mut rng = OsRng;
+//! # let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+//! # let client_login_start_result = ClientLogin::<Default>::start(&mut rng, b"password")?;
+//! let pool = Arc::new(EphemeralKeyPool::new(64));
+//!
+//! // In a background task
+//! let background_pool = Arc::clone(&pool);
+//! std::thread::spawn(move || background_pool.refill(&mut OsRng))
+//!     .join()
+//!     .unwrap()?;
+//!
+//! // In the login handlers
+//! let server_login_start_result = ServerLogin::start_with_key_pool(
+//!     &mut rng,
+//!     &pool,
+//!     &server_setup,
+//!     None,
+//!     client_login_start_result.message,
+//!     b"alice@example.com",
+//!     ServerLoginStartParameters::default(),
+//! )?;
+//! assert_eq!(pool.len(), 63);
+//! # Ok::<(), ProtocolError>(())
+//! ```
+
+use crate::{errors::InternalError, key_exchange::group::KeGroup, keypair::KeyPair};
+use alloc::vec::Vec;
+use rand::{CryptoRng, RngCore};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// A bounded pool of server ephemeral key pairs, which can be shared between
+/// threads
+pub struct EphemeralKeyPool<KG: KeGroup> {
+    keypairs: Mutex<Vec<KeyPair<KG>>>,
+    capacity: usize,
+}
+
+impl<KG: KeGroup> EphemeralKeyPool<KG> {
+    /// Creates an empty pool holding up to `capacity` key pairs
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            keypairs: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// The maximum number of key pairs held by the pool
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of key pairs currently in the pool
+    pub fn len(&self) -> usize {
+        self.keypairs().len()
+    }
+
+    /// Returns `true` if the pool has run dry
+    pub fn is_empty(&self) -> bool {
+        self.keypairs().is_empty()
+    }
+
+    /// Generates key pairs until the pool is full, returning how many were
+    /// added. The key pairs are generated without holding the lock, so that
+    /// login starts are not blocked by a refill.
+    pub fn refill<R: RngCore + CryptoRng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> Result<usize, InternalError> {
+        let rng = &mut &mut *rng;
+        let mut added = 0;
+
+        loop {
+            if self.len() >= self.capacity {
+                return Ok(added);
+            }
+
+            let keypair = KeyPair::<KG>::generate_random(rng)?;
+            let mut keypairs = self.keypairs();
+            if keypairs.len() >= self.capacity {
+                return Ok(added);
+            }
+            keypairs.push(keypair);
+            added += 1;
+        }
+    }
+
+    /// Removes a key pair from the pool, if any is left
+    pub(crate) fn take(&self) -> Option<KeyPair<KG>> {
+        self.keypairs().pop()
+    }
+
+    // The vector is never left half-updated, so a panic while the lock was
+    // held does not invalidate it
+    fn keypairs(&self) -> MutexGuard<'_, Vec<KeyPair<KG>>> {
+        self.keypairs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<KG: KeGroup> core::fmt::Debug for EphemeralKeyPool<KG> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EphemeralKeyPool")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
@@ -7,7 +7,8 @@
 
 #![allow(unsafe_code)]
 
-use crate::errors::{InternalError, ProtocolError};
+use crate::errors::{utils::check_slice_size, InternalError, ProtocolError};
+use crate::impls::Redacted;
 use crate::key_exchange::group::KeGroup;
 use alloc::vec::Vec;
 use core::fmt::Debug;
@@ -114,7 +115,7 @@ impl<KG: KeGroup> KeyPair<KG> {
     pub(crate) fn generate_random<R: RngCore + CryptoRng>(
         rng: &mut R,
     ) -> Result<Self, InternalError> {
-        let sk = KG::random_sk(rng);
+        let sk = KG::random_sk(rng)?;
         let pk = KG::public_key(&sk);
         Ok(Self {
             pk: PublicKey(Key(pk.to_arr())),
@@ -123,7 +124,8 @@ impl<KG: KeGroup> KeyPair<KG> {
     }
 }
 
-#[cfg(test)]
+// proptest is unavailable on wasm32
+#[cfg(all(test, not(target_arch = "wasm32")))]
 impl<KG: KeGroup + Debug> KeyPair<KG> {
     /// Test-only strategy returning a proptest Strategy based on
     /// generate_random
@@ -178,6 +180,18 @@ impl<L: ArrayLength<u8>> core::hash::Hash for Key<L> {
     }
 }
 
+impl<L: ArrayLength<u8>> PartialOrd for Key<L> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<L: ArrayLength<u8>> Ord for Key<L> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
 // This can't be derived because of the use of a generic parameter
 impl<L: ArrayLength<u8>> Zeroize for Key<L> {
     fn zeroize(&mut self) {
@@ -205,6 +219,14 @@ impl<L: ArrayLength<u8>> Key<L> {
     pub fn to_arr(&self) -> GenericArray<u8, L> {
         self.0.clone()
     }
+
+    /// Convert to a fixed-size array, failing if `N` does not match the key
+    /// length
+    pub fn to_array<const N: usize>(&self) -> Result<[u8; N], InternalError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(check_slice_size(&self.0, N, "key_bytes")?);
+        Ok(array)
+    }
 }
 
 /// Wrapper around a Key to enforce that it's a private one.
@@ -216,11 +238,19 @@ impl_clone_for!(
     tuple PrivateKey<KG: KeGroup>,
     [0],
 );
-impl_debug_eq_hash_for!(
+impl_eq_hash_for!(
     tuple PrivateKey<KG: KeGroup>,
     [0],
 );
 
+impl<KG: KeGroup> Debug for PrivateKey<KG> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("PrivateKey")
+            .field(&Redacted(KG::SkLen::USIZE))
+            .finish()
+    }
+}
+
 // This can't be derived because of the use of a generic parameter
 impl<KG: KeGroup> Zeroize for PrivateKey<KG> {
     fn zeroize(&mut self) {
@@ -248,6 +278,15 @@ impl<KG: KeGroup> PrivateKey<KG> {
         PrivateKey(Key(key_bytes))
     }
 
+    /// Convert from a fixed-size array, failing if `N` does not match the key
+    /// length
+    pub fn from_array<const N: usize>(mut key_bytes: [u8; N]) -> Result<Self, InternalError> {
+        let key = check_slice_size(&key_bytes, KG::SkLen::USIZE, "key_bytes")
+            .map(|bytes| Self::from_arr(GenericArray::clone_from_slice(bytes)));
+        key_bytes[..].zeroize();
+        key
+    }
+
     /// Convert from slice
     pub fn from_bytes(key_bytes: &[u8]) -> Result<Self, InternalError> {
         if key_bytes.len() == KG::SkLen::USIZE {
@@ -311,6 +350,19 @@ impl_debug_eq_hash_for!(
     [0],
 );
 
+// Orders public keys by their serialization, e.g. for use in a `BTreeSet`
+impl<KG: KeGroup> PartialOrd for PublicKey<KG> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<KG: KeGroup> Ord for PublicKey<KG> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
 // This can't be derived because of the use of a generic parameter
 impl<KG: KeGroup> Zeroize for PublicKey<KG> {
     fn zeroize(&mut self) {
@@ -338,6 +390,13 @@ impl<KG: KeGroup> PublicKey<KG> {
         Self(Key(key_bytes))
     }
 
+    /// Convert from a fixed-size array, failing if `N` does not match the key
+    /// length
+    pub fn from_array<const N: usize>(key_bytes: [u8; N]) -> Result<Self, InternalError> {
+        let key_bytes = check_slice_size(&key_bytes, KG::PkLen::USIZE, "key_bytes")?;
+        Ok(Self::from_arr(GenericArray::clone_from_slice(key_bytes)))
+    }
+
     /// Convert from slice
     pub fn from_bytes(key_bytes: &[u8]) -> Result<Self, InternalError> {
         if key_bytes.len() == KG::PkLen::USIZE {
@@ -346,6 +405,16 @@ impl<KG: KeGroup> PublicKey<KG> {
             Err(InternalError::InvalidByteSequence)
         }
     }
+
+    /// Serialization into a fixed number of bytes
+    pub fn serialize(&self) -> GenericArray<u8, KG::PkLen> {
+        self.to_arr()
+    }
+
+    /// Deserialization from bytes, which must encode a valid group element
+    pub fn deserialize(input: &[u8]) -> Result<Self, InternalError> {
+        KeyPair::<KG>::check_public_key(Self::from_bytes(input)?)
+    }
 }
 
 #[cfg(test)]
@@ -355,6 +424,7 @@ mod tests {
     use core::slice::from_raw_parts;
     use curve25519_dalek::ristretto::RistrettoPoint;
     use generic_array::typenum::Unsigned;
+    #[cfg(not(target_arch = "wasm32"))]
     use proptest::prelude::*;
     use rand::rngs::OsRng;
 
@@ -397,6 +467,63 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_public_key_collections() -> Result<(), ProtocolError> {
+        use std::collections::{BTreeSet, HashSet};
+
+        let mut rng = OsRng;
+        let keys = (0..4)
+            .map(|_| {
+                KeyPair::<RistrettoPoint>::generate_random(&mut rng).map(|kp| kp.public().clone())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let pinned: BTreeSet<_> = keys.iter().cloned().collect();
+        assert_eq!(pinned.len(), keys.len());
+        assert!(pinned
+            .iter()
+            .zip(pinned.iter().skip(1))
+            .all(|(a, b)| a.serialize() < b.serialize()));
+        let pinned: HashSet<_> = keys.iter().cloned().collect();
+
+        for key in keys {
+            let bytes = key.serialize();
+            assert_eq!(bytes.len(), 32);
+            assert!(pinned.contains(&PublicKey::deserialize(&bytes)?));
+        }
+        assert!(PublicKey::<RistrettoPoint>::deserialize(&[0xff; 32]).is_err());
+        assert!(PublicKey::<RistrettoPoint>::deserialize(&[0; 31]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_array_conversion() -> Result<(), ProtocolError> {
+        let keypair = KeyPair::<RistrettoPoint>::generate_random(&mut OsRng)?;
+
+        let pk: [u8; 32] = keypair.public().to_array()?;
+        assert_eq!(
+            &PublicKey::<RistrettoPoint>::from_array(pk)?,
+            keypair.public()
+        );
+        let sk: [u8; 32] = keypair.private().to_array()?;
+        assert_eq!(
+            &PrivateKey::<RistrettoPoint>::from_array(sk)?,
+            keypair.private()
+        );
+
+        assert!(matches!(
+            keypair.public().to_array::<64>(),
+            Err(InternalError::SizeError {
+                len: 64,
+                actual_len: 32,
+                ..
+            })
+        ));
+        assert!(PublicKey::<RistrettoPoint>::from_array([0u8; 31]).is_err());
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
     proptest! {
         #[test]
         fn test_ristretto_check(kp in KeyPair::<RistrettoPoint>::uniform_keypair_strategy()) {
@@ -482,7 +609,7 @@ mod tests {
 
         const PASSWORD: &str = "password";
 
-        let sk = RistrettoPoint::random_sk(&mut OsRng);
+        let sk = RistrettoPoint::random_sk(&mut OsRng).unwrap();
         let sk = RemoteKey(PrivateKey(Key(sk)));
         let keypair = KeyPair::from_private_key(sk).unwrap();
 
@@ -526,4 +653,136 @@ mod tests {
             .unwrap();
         server.finish(message).unwrap();
     }
+
+    #[test]
+    fn remote_client_key() {
+        use crate::{
+            CipherSuite, ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+            ClientRegistrationFinishParameters, ServerLogin, ServerLoginStartParameters,
+            ServerRegistration, ServerSetup,
+        };
+        use curve25519_dalek::ristretto::RistrettoPoint;
+        use rand::rngs::OsRng;
+
+        struct Default;
+
+        impl CipherSuite for Default {
+            type OprfGroup = RistrettoPoint;
+            type KeGroup = RistrettoPoint;
+            type KeyExchange = crate::key_exchange::tripledh::TripleDH;
+            type Hash = sha2::Sha512;
+            type SlowHash = crate::slow_hash::NoOpHash;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct KeystoreLocked;
+
+        // Stands in for a key of a platform keystore, which refuses to be
+        // used while the keystore is locked
+        #[derive(Clone, Zeroize)]
+        struct KeystoreKey(PrivateKey<RistrettoPoint>, bool);
+
+        impl SecretKey<RistrettoPoint> for KeystoreKey {
+            type Error = KeystoreLocked;
+
+            fn diffie_hellman(
+                &self,
+                pk: PublicKey<RistrettoPoint>,
+            ) -> Result<Vec<u8>, InternalError<Self::Error>> {
+                if self.1 {
+                    return Err(InternalError::Custom(KeystoreLocked));
+                }
+                self.0
+                    .diffie_hellman(pk)
+                    .map_err(InternalError::into_custom)
+            }
+
+            fn public_key(&self) -> Result<PublicKey<RistrettoPoint>, InternalError<Self::Error>> {
+                self.0.public_key().map_err(InternalError::into_custom)
+            }
+
+            fn serialize(&self) -> Vec<u8> {
+                self.0.serialize()
+            }
+
+            fn deserialize(input: &[u8]) -> Result<Self, InternalError<Self::Error>> {
+                PrivateKey::deserialize(input)
+                    .map(|sk| Self(sk, false))
+                    .map_err(InternalError::into_custom)
+            }
+        }
+
+        const PASSWORD: &str = "password";
+
+        let sk = RistrettoPoint::random_sk(&mut OsRng).unwrap();
+        let keypair = KeyPair::from_private_key(KeystoreKey(PrivateKey(Key(sk)), false)).unwrap();
+        let server_setup = ServerSetup::<Default>::new(&mut OsRng).unwrap();
+
+        let client_registration_start_result =
+            ClientRegistration::<Default>::start(&mut OsRng, PASSWORD.as_bytes()).unwrap();
+        let server_registration_start_result =
+            ServerRegistration::start(&server_setup, client_registration_start_result.message, &[])
+                .unwrap();
+        let client_registration_finish_result = client_registration_start_result
+            .state
+            .finish(
+                &mut OsRng,
+                server_registration_start_result.message,
+                ClientRegistrationFinishParameters {
+                    external_client_s_pk: Some(keypair.public().clone()),
+                    export_key: true,
+                    ..ClientRegistrationFinishParameters::default()
+                },
+            )
+            .unwrap();
+        let export_key = client_registration_finish_result.export_key;
+        let file = ServerRegistration::finish(client_registration_finish_result.message);
+        assert_eq!(file.client_s_pk(), keypair.public());
+
+        let login = |password: &[u8], keypair: &KeyPair<RistrettoPoint, KeystoreKey>| {
+            let client_login_start_result =
+                ClientLogin::<Default>::start(&mut OsRng, password).unwrap();
+            let server_login_start_result = ServerLogin::start(
+                &mut OsRng,
+                &server_setup,
+                Some(file.clone()),
+                client_login_start_result.message,
+                &[],
+                ServerLoginStartParameters::default(),
+            )
+            .unwrap();
+            let client_login_finish_result = client_login_start_result.state.finish_with_key(
+                server_login_start_result.message,
+                ClientLoginFinishParameters {
+                    export_key: true,
+                    ..ClientLoginFinishParameters::default()
+                },
+                keypair,
+            )?;
+            let server_login_finish_result = server_login_start_result
+                .state
+                .finish(client_login_finish_result.message)
+                .map_err(ProtocolError::into_custom)?;
+            assert_eq!(client_login_finish_result.export_key, export_key);
+            assert_eq!(
+                client_login_finish_result.session_key,
+                server_login_finish_result.session_key
+            );
+            Ok::<_, ProtocolError<KeystoreLocked>>(())
+        };
+
+        login(PASSWORD.as_bytes(), &keypair).unwrap();
+        assert!(matches!(
+            login(b"wrong password", &keypair),
+            Err(ProtocolError::InvalidLoginError)
+        ));
+        let locked_keypair =
+            KeyPair::from_private_key(KeystoreKey(PrivateKey(Key(sk)), true)).unwrap();
+        assert!(matches!(
+            login(PASSWORD.as_bytes(), &locked_keypair),
+            Err(ProtocolError::LibraryError(InternalError::Custom(
+                KeystoreLocked
+            )))
+        ));
+    }
 }
@@ -142,7 +142,7 @@
 //! a [RegistrationResponse] from the server, and
 //! a [ClientRegistration] from the first step of registration.
 //! The client runs [ClientRegistration::finish] to produce a [ClientRegistrationFinishResult], which consists of a [RegistrationUpload]
-//! to be sent to the server and an `export_key` field which can be used optionally as described in the [Export Key](#export-key) section.
+//! to be sent to the server and an `export_key` field which can be requested optionally as described in the [Export Key](#export-key) section.
 //! ```
 //! # use opaque_ke::{
 //! #   errors::ProtocolError,
@@ -428,6 +428,9 @@
 //!
 //! The session key can be accessed from the `session_key` field of [ClientLoginFinishResult] and [ServerLoginFinishResult]. See
 //! the combination of [Client Login Finish](#client-login-finish) and [Server Login Finish](#server-login-finish) for example usage.
+//! It is returned as a [SessionKey], which is zeroized on drop, cannot be cloned, and only gives access to its bytes through
+//! [SessionKey::expose_secret]. Its length defaults to the output size
+//! of the ciphersuite's hash, and can be set with [CipherSuite::SESSION_KEY_LEN], e.g. to use it directly as an AES-256 key.
 //!
 //! ## Checking Server Consistency
 //!
@@ -511,7 +514,13 @@
 //! these secrets so that they remain hidden from the server (see [examples/digital_locker.rs](https://github.com/novifinancial/opaque-ke/blob/main/examples/digital_locker.rs)
 //! for a working example).
 //!
-//! You can access the export key from the `export_key` field of [ClientRegistrationFinishResult] and [ClientLoginFinishResult].
+//! The export key is only derived when requested, by setting the `export_key` field of [ClientRegistrationFinishParameters] and
+//! [ClientLoginFinishParameters], so that applications which do not use it never hold it in memory. You can then access it from
+//! the `export_key` field of [ClientRegistrationFinishResult] and [ClientLoginFinishResult], which is `None` otherwise.
+//! Like the session key, it is returned as an [ExportKey], which is zeroized on drop, cannot be cloned, and only gives access to
+//! its bytes through [ExportKey::expose_secret].
+//! Its length defaults to the output size of the ciphersuite's hash, and can be set with [CipherSuite::EXPORT_KEY_LEN], e.g. to
+//! obtain a 32-byte encryption key from a suite using SHA-512.
 //!
 //! ```
 //! # use opaque_ke::{
@@ -541,7 +550,10 @@
 //! let client_registration_finish_result = client_registration_start_result.state.finish(
 //!     &mut client_rng,
 //!     server_registration_start_result.message,
-//!     ClientRegistrationFinishParameters::default()
+//!     ClientRegistrationFinishParameters {
+//!         export_key: true,
+//!         ..ClientRegistrationFinishParameters::default()
+//!     },
 //! )?;
 //! # let password_file_bytes = ServerRegistration::<Default>::finish(client_registration_finish_result.message).serialize()?;
 //! # let client_login_start_result = ClientLogin::<Default>::start(
@@ -558,9 +570,13 @@
 //! // And then later, during login...
 //! let client_login_finish_result = client_login_start_result.state.finish(
 //!     server_login_start_result.message,
-//!     ClientLoginFinishParameters::default(),
+//!     ClientLoginFinishParameters {
+//!         export_key: true,
+//!         ..ClientLoginFinishParameters::default()
+//!     },
 //! )?;
 //!
+//! assert!(client_login_finish_result.export_key.is_some());
 //! assert_eq!(
 //!     client_registration_finish_result.export_key,
 //!     client_login_finish_result.export_key,
@@ -617,7 +633,7 @@
 //! # Ok::<(), ProtocolError>(())
 //! ```
 //!
-//! The same identifiers must also be supplied using [ServerLoginStartParameters::WithIdentifiers] in [Server Login Start](#server-login-start):
+//! The same identifiers must also be supplied using the `identifiers` field of [ServerLoginStartParameters] in [Server Login Start](#server-login-start):
 //! ```
 //! # use opaque_ke::{
 //! #   errors::ProtocolError,
@@ -657,17 +673,21 @@
 //!     Some(password_file),
 //!     client_login_start_result.message,
 //!     b"alice@example.com",
-//!     ServerLoginStartParameters::WithIdentifiers(
-//!         Identifiers::ClientAndServerIdentifiers(
+//!     ServerLoginStartParameters::new(
+//!         None,
+//!         Some(Identifiers::ClientAndServerIdentifiers(
 //!             b"Alice_the_Cryptographer".to_vec(),
 //!             b"Facebook".to_vec(),
-//!         ),
+//!         )),
 //!     ),
 //! )?;
 //! # Ok::<(), ProtocolError>(())
 //! ```
 //!
-//! as well as [ClientLoginFinishParameters] in [Client Login Finish](#client-login-finish):
+//! Rather than keeping the client identifier in a separate lookup table, the server can store it in the password file with
+//! [ServerRegistration::finish_with_client_identity], and read it back with [ServerRegistration::client_identity] before login.
+//!
+//! The identifiers must also be supplied to [ClientLoginFinishParameters] in [Client Login Finish](#client-login-finish):
 //! ```
 //! # use opaque_ke::{
 //! #   errors::ProtocolError,
@@ -703,7 +723,7 @@
 //! #     &password_file_bytes[..],
 //! #   )?;
 //! # let server_login_start_result =
-//! #     ServerLogin::start(&mut server_rng, &server_setup, Some(password_file), client_login_start_result.message, b"alice@example.com", ServerLoginStartParameters::WithIdentifiers(Identifiers::ClientAndServerIdentifiers(b"Alice_the_Cryptographer".to_vec(), b"Facebook".to_vec())))?;
+//! #     ServerLogin::start(&mut server_rng, &server_setup, Some(password_file), client_login_start_result.message, b"alice@example.com", ServerLoginStartParameters::new(None, Some(Identifiers::ClientAndServerIdentifiers(b"Alice_the_Cryptographer".to_vec(), b"Facebook".to_vec()))))?;
 //! let client_login_finish_result = client_login_start_result.state.finish(
 //!     server_login_start_result.message,
 //!     ClientLoginFinishParameters::new(
@@ -729,12 +749,20 @@
 //! A key exchange protocol typically allows for the specifying of shared "context" information between the two parties before the exchange is complete,
 //! so as to bind the integrity of application-specific data or configuration parameters to the security of the key exchange.
 //! During the login phase, the client and server can specify this context using:
-//! - The second login message, where the server can populate [ServerLoginStartParameters::WithContext], and
-//! - The third login message, where the client can populate [ClientLoginFinishParameters].
+//! - The second login message, where the server can populate the `context` field of [ServerLoginStartParameters], and
+//! - The third login message, where the client can populate the `context` field of [ClientLoginFinishParameters].
+//!
+//! For both of these messages, the `identifiers` field can be populated as well to specify
+//! [custom identifiers](#custom-identifiers), e.g. with
+//! `ServerLoginStartParameters::new(Some(context), Some(Identifiers::ClientAndServerIdentifiers(username, server_name)))`.
+//!
+//! ## Binding the Credential Identifier
 //!
-//! For both of these messages, the `WithContextAndIdentifiers` variant can be used to specify these fields in addition to
-//! [custom identifiers](#custom-identifiers), with the ordering of the fields as
-//! `WithContextAndIdentifiers(context, Identifiers::ClientAndServerIdentifiers(username, server_name))`.
+//! Some deployments include the account's credential identifier in the OPRF input alongside the password, so that the same password
+//! produces unrelated OPRF outputs for different accounts. The client opts into this with [ClientRegistration::start_with_identifier]
+//! and [ClientLogin::start_with_identifier], passing the same credential identifier that the server uses in [ServerRegistration::start]
+//! and [ServerLogin::start]. Setting [CipherSuite::BIND_CREDENTIAL_IDENTIFIER] makes this mandatory for a ciphersuite, in which case
+//! [ClientRegistration::start] and [ClientLogin::start] fail.
 //!
 //! ## Dummy Server Login
 //!
@@ -803,6 +831,27 @@
 //! let server_setup = ServerSetup::<Default, YourRemoteKey>::new_with_key(&mut OsRng, keypair);
 //! ```
 //!
+//! Clients can likewise keep their static private key in a platform keystore, such as the Secure Enclave or the
+//! Android Keystore, by registering its public key in
+//! [`ClientRegistrationFinishParameters::external_client_s_pk`] and logging in with [`ClientLogin::finish_with_key`].
+//!
+//! ## Embedded Targets
+//!
+//! This library never draws randomness on its own: every function that needs it takes an `rng` argument implementing
+//! [`RngCore`](rand::RngCore) and [`CryptoRng`](rand::CryptoRng). On devices without an operating system, this can
+//! be a driver for the hardware RNG peripheral. Alternatively, a
+//! [custom `getrandom` backend](https://docs.rs/getrandom/0.2/getrandom/macro.register_custom_getrandom.html) can be
+//! registered by the application, after which `rand::rngs::OsRng` (with the `getrandom` feature of `rand`) is usable.
+//! For `wasm32-unknown-unknown`, the `std` feature wires up the Web Crypto API, which is exposed as `wasm::WebCryptoRng`.
+//!
+//! There is however no heap-free profile: the protocol states are backed by `Vec`s, as is the OPRF implementation, so
+//! the client path allocates and a global allocator is required. Stack usage is not measured per ciphersuite either,
+//! and is dominated by the group arithmetic of the chosen [CipherSuite] and by its `SlowHash`, so it should be
+//! measured on the target device.
+//! The `Vec`-returning functions are not gated behind an `alloc` feature, since the OPRF implementation needs an
+//! allocator as well. Messages can however be written into fixed-size buffers with `serialize_to_slice`, which does
+//! not allocate, and read without copying through borrowed views such as [CredentialRequestRef].
+//!
 //! # Features
 //!
 //! - The `slow-hash` feature, when enabled, introduces a dependency on `argon2` and implements the `SlowHash` trait for `Argon2`
@@ -810,7 +859,9 @@
 //! hashing function when the client's password is expected to have low entropy, instead of relying on [slow_hash::NoOpHash]
 //! as done in the above example. The more computationally intensive the `SlowHash` function is, the more resistant the server's
 //! password file records will be against offline dictionary and precomputation attacks; see
-//! [the OPAQUE paper](https://eprint.iacr.org/2018/163.pdf) for more details.
+//! [the OPAQUE paper](https://eprint.iacr.org/2018/163.pdf) for more details. It also provides
+//! `slow_hash::Argon2WithSecret`, which keys Argon2id with an application-supplied pepper and associated data, and
+//! `slow_hash::IteratedArgon2`, which runs the passes of Argon2id one at a time so that their completion can be reported.
 //!
 //! - The `serialize` feature, enabled by default, provides convenience functions for serializing and deserializing with
 //! [serde](https://serde.rs/).
@@ -819,20 +870,57 @@
 //! [curve25519-dalek](https://doc.dalek.rs/curve25519_dalek/index.html#backends-and-features) and allow for selecting
 //! the corresponding backend for the curve arithmetic used. The `u64_backend` feature is included as the default.
 //!
-//! - The `p256` feature enables the use of `p256::ProjectivePoint` as a `Group` for `CipherSuite`. Note that this
+//! - The `fiat_u32_backend` and `fiat_u64_backend` features select the [fiat-crypto](https://github.com/mit-plv/fiat-crypto) backends of curve25519-dalek instead, whose field arithmetic is formally verified. Enable one of them with `default-features = false` in place of `u32_backend` or `u64_backend`; the public API is unchanged.
+//!
+//! - The `p256` feature enables the use of `p256::ProjectivePoint` as a `Group` for `CipherSuite`, and provides the specification's P256-SHA256 configuration as `ciphersuite::P256Sha256`. Note that this
 //! is currently an experimental feature ⚠️, and is not yet ready for production use.
 //!
-//! - The `bench` feature is used only for running performance benchmarks for this implementation.
+//! - The `rayon` feature provides batch helpers for processing registrations in parallel, e.g. when importing accounts.
+//!
+//! - The `erased` feature provides object-safe wrappers over a fixed set of ciphersuites in the `erased` module, for plugin systems and FFI layers which select the ciphersuite at runtime.
+//!
+//! - The `sha2-asm` feature enables the assembly implementations of SHA-256 and SHA-512 in [sha2](https://docs.rs/sha2/0.9), which then back the HKDF and HMAC computations of any `CipherSuite` using these hashes. Without it, `sha2` already uses SHA-NI and AVX2 when detected at runtime on x86.
+//!
+//! - The `aead-envelope` feature adds `EnvelopeMode::Aead`, selected by default with `CipherSuite::ENVELOPE_MODE` or per registration with `ClientRegistrationFinishParameters::envelope_mode`, where the envelope stores a random client private key encrypted with XChaCha20-Poly1305 instead of deriving it from the password. This grows the envelope by the private key length plus a 16-byte tag. Every envelope of a ciphersuite is serialized to the length of its default mode, so that neither the mode of a registration nor whether it exists can be told apart by the length of the credential response: with `EnvelopeMode::Aead` as the default, internal-mode registrations zero the space of the encrypted private key, which is how login recognizes the mode, while with `EnvelopeMode::Internal` as the default, registrations cannot choose `EnvelopeMode::Aead`.
+//!
+//! - The `backup` feature provides the `backup` module, which exports registration records along with their credential identifiers into a versioned container encrypted with XChaCha20-Poly1305, and imports them back, for backing up the credential database or migrating it between regions.
+//!
+//! - The `sealed-records` feature provides `ServerRegistration::seal` and `ServerRegistration::unseal`, which encrypt registration records at rest with XChaCha20-Poly1305 under a key encryption key named by a key identifier, so that the database never stores raw records and the key can be rotated. Records are bound to the credential identifier they are stored under, so that they cannot be swapped between users. It also provides `ServerSetup::wrap_record` and `ServerSetup::unwrap_record`, which seal each record under a key derived from the server setup and a per-user salt, so that deleting the salt erases the record from every backup.
+//!
+//! - The `signed-records` feature provides the `signed` module, which signs registration records with Ed25519 when they are created and verifies them when they are loaded, to detect tampering with or corruption of the credential database.
+//!
+//! - The `ed25519` feature provides the `key_exchange::group::ed25519` module, which converts Ed25519 identity keys into keys of the x25519 key exchange group, so that a client can reuse its Ed25519 key as an external static key.
+//!
+//! - The `envelope-inspect` feature provides `ClientLogin::inspect_envelope`, which decodes the envelope of a registration record with the password, reporting its mode, its nonce, the keys it binds and whether its MAC verifies, for auditing and debugging registration records.
+//!
+//! - The `memory-store` feature provides `store::memory::MemoryStore`, an in-memory implementation of the `store::CredentialStore` trait, so that a complete server can run before a database is plugged in.
+//!
+//! - With the `std` feature, the `key_pool` module provides `EphemeralKeyPool`, a pool of server ephemeral key pairs which a background task generates ahead of time and `ServerLogin::start_with_key_pool` consumes, to smooth latency spikes under bursty login load.
+//!
+//! - The `kmac` feature provides `key_exchange::tripledh::TripleDHKmac`, a variant of the 3DH key exchange whose key schedule uses KMAC256 instead of HKDF and HMAC, for ciphersuites built on SHA-3 hashes.
+//!
+//! - The `testing` feature provides proptest strategies and roundtrip helpers in the `testing` module, so that downstream crates can property-test their integration layers against this one, as well as a loopback harness in the `test_utils` module which runs registration and login over pluggable transport and storage, and a check that a login handler does not reveal which accounts exist.
+//!
+//! - The `interop-tests` feature enables a test which replays the recorded protocol transcripts of `tests/transcripts`, from this implementation and from others, through the client and server state machines, and checks every message byte for byte. New transcripts of this implementation are recorded with the ignored `record_transcript` test.
+//!
+//! - The `defmt` feature implements `defmt::Format` for errors and public messages, for logging on embedded targets.
+//!
+//! - The `bench` feature is used only for running performance benchmarks for this implementation. It also provides the `timing` module, which measures the record-present and record-absent paths of `ServerLogin::start` on the same inputs, to check on given hardware that unregistered clients cannot be told apart by timing.
 //!
 
 #![cfg_attr(not(feature = "bench"), deny(missing_docs))]
 #![deny(unsafe_code)]
 // #![cfg_attr(not(feature = "std"), no_std)]
 
-#[cfg(not(any(feature = "u64_backend", feature = "u32_backend",)))]
+#[cfg(not(any(
+    feature = "u64_backend",
+    feature = "u32_backend",
+    feature = "fiat_u64_backend",
+    feature = "fiat_u32_backend",
+)))]
 compile_error!(
     "no dalek arithmetic backend cargo feature enabled! \
-     please enable one of: u64_backend, u32_backend"
+     please enable one of: u64_backend, u32_backend, fiat_u64_backend, fiat_u32_backend"
 );
 
 extern crate alloc;
@@ -844,14 +932,37 @@ pub mod errors;
 mod impls;
 #[macro_use]
 mod serialization;
+pub mod audit;
+#[cfg(feature = "backup")]
+pub mod backup;
 pub mod ciphersuite;
+pub mod confirmation;
 mod envelope;
+#[cfg(feature = "erased")]
+pub mod erased;
+pub mod framing;
 pub mod hash;
 pub mod key_exchange;
+#[cfg(feature = "std")]
+pub mod key_pool;
 pub mod keypair;
 mod messages;
+pub mod observer;
 mod opaque;
+pub mod puzzle;
+pub mod rollover;
+#[cfg(feature = "signed-records")]
+pub mod signed;
 pub mod slow_hash;
+pub mod store;
+#[cfg(feature = "testing")]
+pub mod test_utils;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "bench")]
+pub mod timing;
+#[cfg(all(target_arch = "wasm32", feature = "std"))]
+pub mod wasm;
 
 #[cfg(test)]
 mod tests;
@@ -862,18 +973,29 @@ pub use rand;
 
 pub use ciphersuite::CipherSuite;
 
+#[cfg(feature = "envelope-inspect")]
+pub use crate::envelope::EnvelopeInfo;
+pub use crate::envelope::EnvelopeMode;
+
 pub use crate::messages::{
-    CredentialFinalization, CredentialRequest, CredentialResponse, RegistrationRequest,
-    RegistrationResponse, RegistrationUpload,
+    CredentialFinalization, CredentialFinalizationRef, CredentialRequest, CredentialRequestRef,
+    CredentialResponse, CredentialResponseRef, RegistrationRequest, RegistrationResponse,
+    RegistrationUpload,
 };
+pub use crate::opaque::RETRANSMISSION_NONCE_LEN;
 pub use crate::opaque::{
-    ClientLogin, ClientRegistration, ServerLogin, ServerRegistration, ServerSetup,
+    ClientLogin, ClientLoginPrecomputation, ClientRegistration, ServerLogin, ServerRegistration,
+    ServerSetup,
 };
 pub use crate::opaque::{
     ClientLoginFinishParameters, ClientRegistrationFinishParameters, ServerLoginStartParameters,
 };
 pub use crate::opaque::{
     ClientLoginFinishResult, ClientLoginStartResult, ClientRegistrationFinishResult,
-    ClientRegistrationStartResult, Identifiers, ServerLoginFinishResult, ServerLoginStartResult,
-    ServerRegistrationStartResult,
+    ClientRegistrationStartResult, ExportKey, Identifiers, RegistrationUpgrade,
+    ServerLoginFinishResult, ServerLoginStartResult, ServerRegistrationStartResult,
+    ServerRegistrationStream, SessionKey,
 };
+#[cfg(feature = "sealed-records")]
+pub use crate::opaque::{ERASURE_SALT_LEN, RECORD_SEALING_KEY_LEN};
+pub use crate::opaque::{SERVER_LOGIN_STATE_VERSION, SERVER_REGISTRATION_VERSION};
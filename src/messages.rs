@@ -9,22 +9,31 @@ use crate::{
     ciphersuite::CipherSuite,
     envelope::Envelope,
     errors::{
-        utils::{check_slice_size, check_slice_size_atleast},
-        ProtocolError,
+        utils::{check_slice_size, check_slice_size_atleast, fill_random, unless_external},
+        InternalError, ProtocolError,
     },
+    impls::Redacted,
     key_exchange::{
         group::KeGroup,
         traits::{FromBytes, KeyExchange, ToBytes},
     },
     keypair::{KeyPair, PublicKey, SecretKey},
+    observer,
     opaque::ServerSetup,
+    serialization::Hex,
 };
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
 use digest::Digest;
 use generic_array::{typenum::Unsigned, GenericArray};
 use rand::{CryptoRng, RngCore};
 use voprf::group::Group;
 
+// The length of the nonce masking the credential response
+const MASKING_NONCE_LEN: usize = 32;
+
 ////////////////////////////
 // High-level API Structs //
 // ====================== //
@@ -70,6 +79,7 @@ pub struct CredentialResponse<CS: CipherSuite> {
     pub(crate) evaluation_element: voprf::EvaluationElement<CS::OprfGroup, CS::Hash>,
     pub(crate) masking_nonce: Vec<u8>,
     pub(crate) masked_response: Vec<u8>,
+    pub(crate) key_id: Vec<u8>,
     pub(crate) ke2_message: <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE2Message,
 }
 
@@ -93,47 +103,90 @@ impl<CS: CipherSuite> RegistrationRequest<CS> {
         self.blinded_element.clone()
     }
 
-    /// Serialization into bytes
-    pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
-        Ok(self.blinded_element.serialize())
+    /// The length of this message once serialized, which does not depend on
+    /// its contents
+    pub fn max_serialized_len() -> usize {
+        <CS::OprfGroup as Group>::ElemLen::USIZE
+    }
+
+    pub(crate) fn serialized_len(&self) -> usize {
+        Self::max_serialized_len()
+    }
+
+    pub(crate) fn write_to(&self, output: &mut [u8]) {
+        output.copy_from_slice(&self.blinded_element.value().to_arr());
     }
 
     /// Deserialization from bytes
     pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
-        Ok(Self {
-            blinded_element: voprf::BlindedElement::deserialize(input)?,
+        observer::observe_deserialization("RegistrationRequest", || {
+            Ok(Self {
+                blinded_element: voprf::BlindedElement::deserialize(input)?,
+            })
         })
     }
+
+    /// Serialization into lowercase hex, for logging and debugging
+    pub fn to_hex(&self) -> Result<String, ProtocolError> {
+        Ok(Hex(&self.serialize()?).to_string())
+    }
+
+    /// Inexpensive checks that `input` is a well-formed message, i.e. that
+    /// it has the expected length and holds a valid group element other than
+    /// the identity, so that servers can reject garbage before evaluating the
+    /// OPRF. Passing them does not authenticate the client in any way.
+    pub fn validate(input: &[u8]) -> Result<(), ProtocolError> {
+        let input = check_slice_size(input, Self::max_serialized_len(), "registration_request")?;
+        validate_blinded_element::<CS>(input)
+    }
 }
 
 impl<CS: CipherSuite> RegistrationResponse<CS> {
-    /// Serialization into bytes
-    pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
-        Ok([
-            self.evaluation_element.serialize(),
-            self.server_s_pk.to_vec(),
-        ]
-        .concat())
+    /// The length of this message once serialized, which does not depend on
+    /// its contents
+    pub fn max_serialized_len() -> usize {
+        <CS::OprfGroup as Group>::ElemLen::USIZE + <CS::KeGroup as KeGroup>::PkLen::USIZE
     }
 
-    /// Deserialization from bytes
-    pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
-        let elem_len = <CS::OprfGroup as Group>::ElemLen::USIZE;
-        let key_len = <CS::KeGroup as KeGroup>::PkLen::USIZE;
-        let checked_slice =
-            check_slice_size(input, elem_len + key_len, "registration_response_bytes")?;
+    pub(crate) fn serialized_len(&self) -> usize {
+        Self::max_serialized_len()
+    }
 
-        // Ensure that public key is valid
-        let server_s_pk = KeyPair::<CS::KeGroup>::check_public_key(PublicKey::from_bytes(
-            &checked_slice[elem_len..],
-        )?)?;
+    pub(crate) fn write_to(&self, output: &mut [u8]) {
+        let (evaluation_element, server_s_pk) =
+            output.split_at_mut(<CS::OprfGroup as Group>::ElemLen::USIZE);
+        evaluation_element.copy_from_slice(&self.evaluation_element.value().to_arr());
+        server_s_pk.copy_from_slice(&self.server_s_pk);
+    }
 
-        Ok(Self {
-            evaluation_element: voprf::EvaluationElement::deserialize(&checked_slice[..elem_len])?,
-            server_s_pk,
+    /// Deserialization from bytes
+    pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
+        observer::observe_deserialization("RegistrationResponse", || {
+            let elem_len = <CS::OprfGroup as Group>::ElemLen::USIZE;
+            let key_len = <CS::KeGroup as KeGroup>::PkLen::USIZE;
+            let checked_slice =
+                check_slice_size(input, elem_len + key_len, "registration_response_bytes")?;
+
+            // Ensure that public key is valid
+            let server_s_pk = KeyPair::<CS::KeGroup>::check_public_key(PublicKey::from_bytes(
+                &checked_slice[elem_len..],
+            )?)
+            .map_err(|e| unless_external(e, ProtocolError::InvalidServerStaticKeyError))?;
+
+            Ok(Self {
+                evaluation_element: voprf::EvaluationElement::deserialize(
+                    &checked_slice[..elem_len],
+                )?,
+                server_s_pk,
+            })
         })
     }
 
+    /// Serialization into lowercase hex, for logging and debugging
+    pub fn to_hex(&self) -> Result<String, ProtocolError> {
+        Ok(Hex(&self.serialize()?).to_string())
+    }
+
     #[cfg(test)]
     /// Only used for tests, where we can set the beta value to test for the reflection
     /// error case
@@ -146,88 +199,155 @@ impl<CS: CipherSuite> RegistrationResponse<CS> {
 }
 
 impl<CS: CipherSuite> RegistrationUpload<CS> {
-    /// Serialization into bytes
-    pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
-        Ok([
-            self.client_s_pk.to_arr().to_vec(),
-            self.masking_key.to_vec(),
-            self.envelope.serialize(),
-        ]
-        .concat())
+    // The length of this upload once serialized
+    pub(crate) fn serialized_len(&self) -> usize {
+        Self::max_serialized_len()
+    }
+
+    /// The length of this message once serialized, which does not depend on
+    /// its contents, nor on the envelope mode of the registration
+    pub fn max_serialized_len() -> usize {
+        <CS::KeGroup as KeGroup>::PkLen::USIZE
+            + <CS::Hash as Digest>::OutputSize::USIZE
+            + Envelope::<CS>::len()
+    }
+
+    // Appends the serialized upload to `output`, for callers serializing many
+    // uploads into a single buffer
+    pub(crate) fn serialize_into(&self, output: &mut Vec<u8>) {
+        let start = output.len();
+        output.resize(start + self.serialized_len(), 0);
+        self.write_to(&mut output[start..]);
+    }
+
+    pub(crate) fn write_to(&self, output: &mut [u8]) {
+        let (client_s_pk, rest) = output.split_at_mut(<CS::KeGroup as KeGroup>::PkLen::USIZE);
+        let (masking_key, envelope) = rest.split_at_mut(<CS::Hash as Digest>::OutputSize::USIZE);
+        client_s_pk.copy_from_slice(&self.client_s_pk);
+        masking_key.copy_from_slice(&self.masking_key);
+        self.envelope.write_to(envelope);
     }
 
     /// Deserialization from bytes
     pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
-        let key_len = <CS::KeGroup as KeGroup>::PkLen::USIZE;
-        let hash_len = <CS::Hash as Digest>::OutputSize::USIZE;
-        let checked_slice =
-            check_slice_size_atleast(input, key_len + hash_len, "registration_upload_bytes")?;
-        let envelope = Envelope::<CS>::deserialize(&checked_slice[key_len + hash_len..])?;
-        Ok(Self {
-            envelope,
-            masking_key: GenericArray::clone_from_slice(
-                &checked_slice[key_len..key_len + hash_len],
-            ),
-            client_s_pk: KeyPair::<CS::KeGroup>::check_public_key(PublicKey::from_bytes(
-                &checked_slice[..key_len],
-            )?)?,
+        observer::observe_deserialization("RegistrationUpload", || {
+            let key_len = <CS::KeGroup as KeGroup>::PkLen::USIZE;
+            let hash_len = <CS::Hash as Digest>::OutputSize::USIZE;
+            let input = check_slice_size(
+                input,
+                Self::max_serialized_len(),
+                "registration_upload_bytes",
+            )?;
+            let envelope = Envelope::<CS>::deserialize(&input[key_len + hash_len..])?;
+            Ok(Self {
+                envelope,
+                masking_key: GenericArray::clone_from_slice(&input[key_len..key_len + hash_len]),
+                client_s_pk: KeyPair::<CS::KeGroup>::check_public_key(PublicKey::from_bytes(
+                    &input[..key_len],
+                )?)
+                .map_err(|e| unless_external(e, ProtocolError::InvalidClientStaticKeyError))?,
+            })
         })
     }
 
+    // Whether a serialized upload can have this length
+    pub(crate) fn is_valid_len(len: usize) -> bool {
+        len == Self::max_serialized_len()
+    }
+
     // Creates a dummy instance used for faking a [CredentialResponse]
     pub(crate) fn dummy<R: RngCore + CryptoRng, S: SecretKey<CS::KeGroup>>(
         rng: &mut R,
         server_setup: &ServerSetup<CS, S>,
-    ) -> Self {
-        let mut masking_key = alloc::vec![0u8; <CS::Hash as Digest>::OutputSize::USIZE];
-        rng.fill_bytes(&mut masking_key);
+    ) -> Result<Self, InternalError> {
+        let mut masking_key = GenericArray::default();
+        fill_random(rng, &mut masking_key)?;
 
-        Self {
+        Ok(Self {
             envelope: Envelope::<CS>::dummy(),
-            masking_key: GenericArray::clone_from_slice(&masking_key),
+            masking_key,
             client_s_pk: server_setup.fake_keypair.public().clone(),
-        }
+        })
     }
 }
 
 impl<CS: CipherSuite> CredentialRequest<CS> {
-    /// Serialization into bytes
-    pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
-        Ok([
-            self.blinded_element.serialize(),
-            self.ke1_message.to_bytes(),
-        ]
-        .concat())
+    /// The length of this message once serialized, which does not depend on
+    /// its contents
+    pub fn max_serialized_len() -> usize {
+        <CS::OprfGroup as Group>::ElemLen::USIZE + CS::KeyExchange::ke1_message_size()
+    }
+
+    pub(crate) fn serialized_len(&self) -> usize {
+        Self::max_serialized_len()
+    }
+
+    pub(crate) fn write_to(&self, output: &mut [u8]) {
+        let (blinded_element, ke1_message) =
+            output.split_at_mut(<CS::OprfGroup as Group>::ElemLen::USIZE);
+        blinded_element.copy_from_slice(&self.blinded_element.value().to_arr());
+        self.ke1_message.write_bytes(ke1_message);
     }
 
     /// Deserialization from bytes
     pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
-        let elem_len = <CS::OprfGroup as Group>::ElemLen::USIZE;
+        observer::observe_deserialization("CredentialRequest", || {
+            let elem_len = <CS::OprfGroup as Group>::ElemLen::USIZE;
 
-        let checked_slice = check_slice_size_atleast(input, elem_len, "login_first_message_bytes")?;
+            let checked_slice =
+                check_slice_size_atleast(input, elem_len, "login_first_message_bytes")?;
 
-        // Check that the message is actually containing an element of the
-        // correct subgroup
-        let blinded_element = voprf::BlindedElement::<CS::OprfGroup, CS::Hash>::deserialize(
-            &checked_slice[..elem_len],
-        )?;
+            // Check that the message is actually containing an element of the
+            // correct subgroup
+            let blinded_element = voprf::BlindedElement::<CS::OprfGroup, CS::Hash>::deserialize(
+                &checked_slice[..elem_len],
+            )?;
 
-        // Throw an error if the identity group element is encountered
-        if blinded_element.value().is_identity() {
-            return Err(ProtocolError::IdentityGroupElementError);
-        }
+            // Throw an error if the identity group element is encountered
+            if blinded_element.value().is_identity() {
+                return Err(ProtocolError::IdentityGroupElementError);
+            }
 
-        let ke1_message =
-            <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE1Message::from_bytes::<CS>(
-                &checked_slice[elem_len..],
-            )?;
+            let ke1_message =
+                <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE1Message::from_bytes::<
+                    CS,
+                >(&checked_slice[elem_len..])?;
 
-        Ok(Self {
-            blinded_element,
-            ke1_message,
+            Ok(Self {
+                blinded_element,
+                ke1_message,
+            })
         })
     }
 
+    /// Serialization into lowercase hex, for logging and debugging
+    pub fn to_hex(&self) -> Result<String, ProtocolError> {
+        Ok(Hex(&self.serialize()?).to_string())
+    }
+
+    /// Inexpensive checks that `input` is a well-formed message, i.e. that
+    /// it has the expected length and holds a valid blinded element and a
+    /// valid ephemeral public key, neither of which is the identity, so that
+    /// servers can reject garbage before the OPRF evaluation and the
+    /// Diffie-Hellman computations. Passing them does not authenticate the
+    /// client in any way.
+    pub fn validate(input: &[u8]) -> Result<(), ProtocolError> {
+        let elem_len = <CS::OprfGroup as Group>::ElemLen::USIZE;
+        let input = check_slice_size(input, Self::max_serialized_len(), "credential_request")?;
+        validate_blinded_element::<CS>(&input[..elem_len])?;
+        <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE1Message::from_bytes::<CS>(
+            &input[elem_len..],
+        )?;
+        Ok(())
+    }
+
+    /// The key exchange part of this message (`KE1`)
+    pub fn ke1_message(
+        &self,
+    ) -> &<CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE1Message {
+        &self.ke1_message
+    }
+
     /// Only used for testing purposes
     #[cfg(test)]
     pub fn get_blinded_element_for_testing(
@@ -238,70 +358,112 @@ impl<CS: CipherSuite> CredentialRequest<CS> {
 }
 
 impl<CS: CipherSuite> CredentialResponse<CS> {
-    /// Serialization into bytes
-    pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
-        Ok([
-            Self::serialize_without_ke(
-                &self.evaluation_element.value(),
-                &self.masking_nonce,
-                &self.masked_response,
-            ),
-            self.ke2_message.to_bytes(),
-        ]
-        .concat())
+    pub(crate) fn serialized_len(&self) -> usize {
+        <CS::OprfGroup as Group>::ElemLen::USIZE
+            + self.masking_nonce.len()
+            + self.masked_response.len()
+            + self.key_id.len()
+            + CS::KeyExchange::ke2_message_size()
+    }
+
+    pub(crate) fn write_to(&self, output: &mut [u8]) {
+        let (evaluation_element, rest) =
+            output.split_at_mut(<CS::OprfGroup as Group>::ElemLen::USIZE);
+        let (masking_nonce, rest) = rest.split_at_mut(self.masking_nonce.len());
+        let (masked_response, rest) = rest.split_at_mut(self.masked_response.len());
+        let (key_id, ke2_message) = rest.split_at_mut(self.key_id.len());
+        evaluation_element.copy_from_slice(&self.evaluation_element.value().to_arr());
+        masking_nonce.copy_from_slice(&self.masking_nonce);
+        masked_response.copy_from_slice(&self.masked_response);
+        key_id.copy_from_slice(&self.key_id);
+        self.ke2_message.write_bytes(ke2_message);
+    }
+
+    /// The nonce used to mask the server's public key and the envelope
+    pub fn masking_nonce(&self) -> &[u8] {
+        &self.masking_nonce
+    }
+
+    /// The server's public key and the envelope, masked
+    pub fn masked_response(&self) -> &[u8] {
+        &self.masked_response
+    }
+
+    /// The identifier of the server key, of [`CipherSuite::KEY_ID_LEN`]
+    /// bytes. It is only authenticated once the client finishes the login,
+    /// which then returns it as
+    /// [`ClientLoginFinishResult::key_id`](crate::ClientLoginFinishResult::key_id).
+    pub fn key_id(&self) -> &[u8] {
+        &self.key_id
+    }
+
+    /// The key exchange part of this message (`KE2`)
+    pub fn ke2_message(
+        &self,
+    ) -> &<CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE2Message {
+        &self.ke2_message
+    }
+
+    /// The server's nonce, as sent in `KE2`
+    pub fn server_nonce(&self) -> &[u8] {
+        CS::KeyExchange::ke2_server_nonce(&self.ke2_message)
+    }
+
+    /// The serialized ephemeral public key of the server, as sent in `KE2`
+    pub fn server_e_pk(&self) -> Vec<u8> {
+        CS::KeyExchange::ke2_server_e_pk(&self.ke2_message)
+    }
+
+    /// The MAC authenticating the server, as sent in `KE2`
+    pub fn server_mac(&self) -> &[u8] {
+        CS::KeyExchange::ke2_mac(&self.ke2_message)
     }
 
     pub(crate) fn serialize_without_ke(
         beta: &CS::OprfGroup,
         masking_nonce: &[u8],
         masked_response: &[u8],
+        key_id: &[u8],
     ) -> Vec<u8> {
-        [&beta.to_arr(), masking_nonce, masked_response].concat()
+        [&beta.to_arr(), masking_nonce, masked_response, key_id].concat()
+    }
+
+    /// The length of this message once serialized, which does not depend on
+    /// its contents, nor on the envelope mode of the password file
+    pub fn max_serialized_len() -> usize {
+        <CS::OprfGroup as Group>::ElemLen::USIZE
+            + MASKING_NONCE_LEN
+            + <CS::KeGroup as KeGroup>::PkLen::USIZE
+            + Envelope::<CS>::len()
+            + CS::KEY_ID_LEN
+            + CS::KeyExchange::ke2_message_size()
     }
 
     /// Deserialization from bytes
     pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
-        let elem_len = <CS::OprfGroup as Group>::ElemLen::USIZE;
-        let key_len = <CS::KeGroup as KeGroup>::PkLen::USIZE;
-        let nonce_len: usize = 32;
-        let envelope_len = Envelope::<CS>::len();
-        let masked_response_len = key_len + envelope_len;
-        let ke2_message_len = CS::KeyExchange::ke2_message_size();
-
-        let checked_slice = check_slice_size_atleast(
-            input,
-            elem_len + nonce_len + masked_response_len + ke2_message_len,
-            "credential_response_bytes",
-        )?;
-
-        // Check that the message is actually containing an element of the
-        // correct subgroup
-        let beta_bytes = &checked_slice[..elem_len];
-        let evaluation_element =
-            voprf::EvaluationElement::<CS::OprfGroup, CS::Hash>::deserialize(beta_bytes)?;
-
-        // Throw an error if the identity group element is encountered
-        if evaluation_element.value().is_identity() {
-            return Err(ProtocolError::IdentityGroupElementError);
-        }
-
-        let masking_nonce = checked_slice[elem_len..elem_len + nonce_len].to_vec();
-        let masked_response = checked_slice
-            [elem_len + nonce_len..elem_len + nonce_len + masked_response_len]
-            .to_vec();
-        let ke2_message =
-            <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE2Message::from_bytes::<CS>(
-                &checked_slice[elem_len + nonce_len + masked_response_len..],
-            )?;
-
-        Ok(Self {
-            evaluation_element,
-            masking_nonce,
-            masked_response,
-            ke2_message,
+        observer::observe_deserialization("CredentialResponse", || {
+            let view = CredentialResponseRef::<CS>::split(input)?;
+            let evaluation_element = deserialize_evaluation_element::<CS>(view.evaluation_element)?;
+            let ke2_message =
+                <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE2Message::from_bytes::<
+                    CS,
+                >(view.ke2_message)?;
+
+            Ok(Self {
+                evaluation_element,
+                masking_nonce: view.masking_nonce.to_vec(),
+                masked_response: view.masked_response.to_vec(),
+                key_id: view.key_id.to_vec(),
+                ke2_message,
+            })
         })
     }
 
+    /// Serialization into lowercase hex, for logging and debugging
+    pub fn to_hex(&self) -> Result<String, ProtocolError> {
+        Ok(Hex(&self.serialize()?).to_string())
+    }
+
     #[cfg(test)]
     /// Only used for tests, where we can set the beta value to test for the reflection
     /// error case
@@ -310,24 +472,214 @@ impl<CS: CipherSuite> CredentialResponse<CS> {
             evaluation_element: voprf::EvaluationElement::from_value_unchecked(beta),
             masking_nonce: self.masking_nonce.clone(),
             masked_response: self.masked_response.clone(),
+            key_id: self.key_id.clone(),
             ke2_message: self.ke2_message.clone(),
         }
     }
 }
 
 impl<CS: CipherSuite> CredentialFinalization<CS> {
-    /// Serialization into bytes
-    pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
-        Ok(self.ke3_message.to_bytes())
+    /// The key exchange part of this message (`KE3`)
+    pub fn ke3_message(
+        &self,
+    ) -> &<CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE3Message {
+        &self.ke3_message
+    }
+
+    /// The length of this message once serialized, which does not depend on
+    /// its contents
+    pub fn max_serialized_len() -> usize {
+        CS::KeyExchange::ke3_message_size()
+    }
+
+    pub(crate) fn serialized_len(&self) -> usize {
+        Self::max_serialized_len()
+    }
+
+    pub(crate) fn write_to(&self, output: &mut [u8]) {
+        self.ke3_message.write_bytes(output);
     }
 
     /// Deserialization from bytes
     pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
-        let ke3_message =
-            <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE3Message::from_bytes::<CS>(
-                input,
-            )?;
-        Ok(Self { ke3_message })
+        observer::observe_deserialization("CredentialFinalization", || {
+            let ke3_message =
+                <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE3Message::from_bytes::<
+                    CS,
+                >(input)?;
+            Ok(Self { ke3_message })
+        })
+    }
+
+    /// Serialization into lowercase hex, for logging and debugging
+    pub fn to_hex(&self) -> Result<String, ProtocolError> {
+        Ok(Hex(&self.serialize()?).to_string())
+    }
+}
+
+////////////////////
+// Borrowed Views //
+// ============== //
+////////////////////
+
+/// A [CredentialRequest] (`KE1`) borrowed from its serialization. Parsing
+/// performs the checks of [CredentialRequest::deserialize], but the fields
+/// are exposed as slices of the input instead of being copied.
+pub struct CredentialRequestRef<'a, CS: CipherSuite> {
+    input: &'a [u8],
+    _cs: PhantomData<CS>,
+}
+
+impl<'a, CS: CipherSuite> CredentialRequestRef<'a, CS> {
+    /// Parses and validates `input` without copying it
+    pub fn parse(input: &'a [u8]) -> Result<Self, ProtocolError> {
+        CredentialRequest::<CS>::validate(input)?;
+        Ok(Self {
+            input,
+            _cs: PhantomData,
+        })
+    }
+
+    /// The serialized blinded element
+    pub fn blinded_element(&self) -> &'a [u8] {
+        &self.input[..<CS::OprfGroup as Group>::ElemLen::USIZE]
+    }
+
+    /// The serialized key exchange part of this message
+    pub fn ke1_message(&self) -> &'a [u8] {
+        &self.input[<CS::OprfGroup as Group>::ElemLen::USIZE..]
+    }
+
+    /// The whole serialized message
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.input
+    }
+
+    /// Copies the message into an owned [CredentialRequest]
+    pub fn to_owned(&self) -> Result<CredentialRequest<CS>, ProtocolError> {
+        CredentialRequest::deserialize(self.input)
+    }
+}
+
+/// A [CredentialResponse] (`KE2`) borrowed from its serialization. Parsing
+/// performs the checks of [CredentialResponse::deserialize], but the fields
+/// are exposed as slices of the input instead of being copied.
+pub struct CredentialResponseRef<'a, CS: CipherSuite> {
+    evaluation_element: &'a [u8],
+    masking_nonce: &'a [u8],
+    masked_response: &'a [u8],
+    key_id: &'a [u8],
+    ke2_message: &'a [u8],
+    input: &'a [u8],
+    _cs: PhantomData<CS>,
+}
+
+impl<'a, CS: CipherSuite> CredentialResponseRef<'a, CS> {
+    /// Parses and validates `input` without copying it
+    pub fn parse(input: &'a [u8]) -> Result<Self, ProtocolError> {
+        let view = Self::split(input)?;
+        deserialize_evaluation_element::<CS>(view.evaluation_element)?;
+        <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE2Message::from_bytes::<CS>(
+            view.ke2_message,
+        )?;
+        Ok(view)
+    }
+
+    // Splits `input` into its fields, only checking their lengths
+    fn split(input: &'a [u8]) -> Result<Self, ProtocolError> {
+        let elem_len = <CS::OprfGroup as Group>::ElemLen::USIZE;
+        let key_len = <CS::KeGroup as KeGroup>::PkLen::USIZE;
+        let nonce_len = MASKING_NONCE_LEN;
+        let key_id_len = CS::KEY_ID_LEN;
+        let ke2_message_len = CS::KeyExchange::ke2_message_size();
+
+        let masked_response_len = key_len + Envelope::<CS>::len();
+
+        let checked_slice = check_slice_size_atleast(
+            input,
+            elem_len + nonce_len + masked_response_len + key_id_len + ke2_message_len,
+            "credential_response_bytes",
+        )?;
+        let key_id_start = elem_len + nonce_len + masked_response_len;
+
+        Ok(Self {
+            evaluation_element: &checked_slice[..elem_len],
+            masking_nonce: &checked_slice[elem_len..elem_len + nonce_len],
+            masked_response: &checked_slice[elem_len + nonce_len..key_id_start],
+            key_id: &checked_slice[key_id_start..key_id_start + key_id_len],
+            ke2_message: &checked_slice[key_id_start + key_id_len..],
+            input,
+            _cs: PhantomData,
+        })
+    }
+
+    /// The serialized evaluation element
+    pub fn evaluation_element(&self) -> &'a [u8] {
+        self.evaluation_element
+    }
+
+    /// The nonce used to mask the server's public key and the envelope
+    pub fn masking_nonce(&self) -> &'a [u8] {
+        self.masking_nonce
+    }
+
+    /// The server's public key and the envelope, masked
+    pub fn masked_response(&self) -> &'a [u8] {
+        self.masked_response
+    }
+
+    /// The identifier of the server key, see [CredentialResponse::key_id]
+    pub fn key_id(&self) -> &'a [u8] {
+        self.key_id
+    }
+
+    /// The serialized key exchange part of this message
+    pub fn ke2_message(&self) -> &'a [u8] {
+        self.ke2_message
+    }
+
+    /// The whole serialized message
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.input
+    }
+
+    /// Copies the message into an owned [CredentialResponse]
+    pub fn to_owned(&self) -> Result<CredentialResponse<CS>, ProtocolError> {
+        CredentialResponse::deserialize(self.input)
+    }
+}
+
+/// A [CredentialFinalization] (`KE3`) borrowed from its serialization.
+/// Parsing performs the checks of [CredentialFinalization::deserialize], but
+/// the message is not copied.
+pub struct CredentialFinalizationRef<'a, CS: CipherSuite> {
+    input: &'a [u8],
+    _cs: PhantomData<CS>,
+}
+
+impl<'a, CS: CipherSuite> CredentialFinalizationRef<'a, CS> {
+    /// Parses and validates `input` without copying it
+    pub fn parse(input: &'a [u8]) -> Result<Self, ProtocolError> {
+        check_slice_size(
+            input,
+            CredentialFinalization::<CS>::max_serialized_len(),
+            "credential_finalization",
+        )?;
+        Ok(Self {
+            input,
+            _cs: PhantomData,
+        })
+    }
+
+    /// The serialized key exchange part of this message, which is the whole
+    /// message
+    pub fn ke3_message(&self) -> &'a [u8] {
+        self.input
+    }
+
+    /// Copies the message into an owned [CredentialFinalization]
+    pub fn to_owned(&self) -> Result<CredentialFinalization<CS>, ProtocolError> {
+        CredentialFinalization::deserialize(self.input)
     }
 }
 
@@ -340,8 +692,35 @@ impl_clone_for!(
     struct RegistrationRequest<CS: CipherSuite>,
     [blinded_element],
 );
-impl_debug_eq_hash_for!(struct RegistrationRequest<CS: CipherSuite>, [blinded_element], [CS::OprfGroup, CS::Hash]);
+impl_debug_eq_hash_for!(
+    struct RegistrationRequest<CS: CipherSuite>,
+    [blinded_element],
+    [voprf::BlindedElement<CS::OprfGroup, CS::Hash>],
+);
 impl_serialize_and_deserialize_for!(RegistrationRequest);
+impl_canonical_bytes_for!(RegistrationRequest);
+impl_serialize_to_slice_for!(RegistrationRequest);
+
+impl<CS: CipherSuite> fmt::Display for RegistrationRequest<CS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_fields(
+            f,
+            "RegistrationRequest",
+            &[("blinded_element", &self.blinded_element.serialize())],
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<CS: CipherSuite> defmt::Format for RegistrationRequest<CS> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(
+            f,
+            "RegistrationRequest {{ blinded_element: {=[u8]:x} }}",
+            &self.blinded_element.serialize()[..],
+        )
+    }
+}
 
 impl_clone_for!(
     struct RegistrationResponse<CS: CipherSuite>,
@@ -350,19 +729,58 @@ impl_clone_for!(
 impl_debug_eq_hash_for!(
     struct RegistrationResponse<CS: CipherSuite>,
     [evaluation_element, server_s_pk],
-    [CS::OprfGroup, CS::Hash],
+    [voprf::EvaluationElement<CS::OprfGroup, CS::Hash>],
 );
 impl_serialize_and_deserialize_for!(RegistrationResponse);
+impl_canonical_bytes_for!(RegistrationResponse);
+impl_serialize_to_slice_for!(RegistrationResponse);
+
+impl<CS: CipherSuite> fmt::Display for RegistrationResponse<CS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_fields(
+            f,
+            "RegistrationResponse",
+            &[
+                ("evaluation_element", &self.evaluation_element.serialize()),
+                ("server_s_pk", &self.server_s_pk.to_arr()),
+            ],
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<CS: CipherSuite> defmt::Format for RegistrationResponse<CS> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(
+            f,
+            "RegistrationResponse {{ evaluation_element: {=[u8]:x}, server_s_pk: {=[u8]:x} }}",
+            &self.evaluation_element.serialize()[..],
+            &self.server_s_pk.to_arr()[..],
+        )
+    }
+}
 
 impl_clone_for!(
     struct RegistrationUpload<CS: CipherSuite>,
     [envelope, masking_key, client_s_pk],
 );
-impl_debug_eq_hash_for!(
+impl_eq_hash_for!(
     struct RegistrationUpload<CS: CipherSuite>,
     [envelope, masking_key, client_s_pk],
 );
+
+impl<CS: CipherSuite> core::fmt::Debug for RegistrationUpload<CS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RegistrationUpload")
+            .field("envelope", &self.envelope)
+            .field("masking_key", &Redacted(self.masking_key.len()))
+            .field("client_s_pk", &self.client_s_pk)
+            .finish()
+    }
+}
 impl_serialize_and_deserialize_for!(RegistrationUpload);
+impl_canonical_bytes_for!(RegistrationUpload);
+impl_serialize_to_slice_for!(RegistrationUpload);
 
 impl_clone_for!(
     struct CredentialRequest<CS: CipherSuite>,
@@ -371,31 +789,213 @@ impl_clone_for!(
 impl_debug_eq_hash_for!(
     struct CredentialRequest<CS: CipherSuite>,
     [blinded_element, ke1_message],
-    [
-        CS::OprfGroup,
-        <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE1Message
-    ],
+    [voprf::BlindedElement<CS::OprfGroup, CS::Hash>],
 );
 impl_serialize_and_deserialize_for!(CredentialRequest);
+impl_canonical_bytes_for!(CredentialRequest);
+impl_serialize_to_slice_for!(CredentialRequest);
+
+impl<CS: CipherSuite> fmt::Display for CredentialRequest<CS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_fields(
+            f,
+            "CredentialRequest",
+            &[
+                ("blinded_element", &self.blinded_element.serialize()),
+                ("ke1_message", &self.ke1_message.to_bytes()),
+            ],
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<CS: CipherSuite> defmt::Format for CredentialRequest<CS> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(
+            f,
+            "CredentialRequest {{ blinded_element: {=[u8]:x}, ke1_message: {=[u8]:x} }}",
+            &self.blinded_element.serialize()[..],
+            &self.ke1_message.to_bytes()[..],
+        )
+    }
+}
 
 impl_clone_for!(
     struct CredentialResponse<CS: CipherSuite>,
-    [evaluation_element, masking_nonce, masked_response, ke2_message],
+    [evaluation_element, masking_nonce, masked_response, key_id, ke2_message],
 );
 impl_debug_eq_hash_for!(
     struct CredentialResponse<CS: CipherSuite>,
-    [evaluation_element, masking_nonce, masked_response, ke2_message],
-    [
-        CS::OprfGroup,
-        <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE2Message,
-    ],
+    [evaluation_element, masking_nonce, masked_response, key_id, ke2_message],
+    [voprf::EvaluationElement<CS::OprfGroup, CS::Hash>],
 );
 impl_serialize_and_deserialize_for!(CredentialResponse);
+impl_canonical_bytes_for!(CredentialResponse);
+impl_serialize_to_slice_for!(CredentialResponse);
+
+impl<CS: CipherSuite> fmt::Display for CredentialResponse<CS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_fields(
+            f,
+            "CredentialResponse",
+            &[
+                ("evaluation_element", &self.evaluation_element.serialize()),
+                ("masking_nonce", &self.masking_nonce),
+                ("masked_response", &self.masked_response),
+                ("key_id", &self.key_id),
+                ("ke2_message", &self.ke2_message.to_bytes()),
+            ],
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<CS: CipherSuite> defmt::Format for CredentialResponse<CS> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(
+            f,
+            "CredentialResponse {{ evaluation_element: {=[u8]:x}, masking_nonce: {=[u8]:x}, masked_response: {=[u8]:x}, key_id: {=[u8]:x}, ke2_message: {=[u8]:x} }}",
+            &self.evaluation_element.serialize()[..],
+            &self.masking_nonce[..],
+            &self.masked_response[..],
+            &self.key_id[..],
+            &self.ke2_message.to_bytes()[..],
+        )
+    }
+}
 
 impl_clone_for!(struct CredentialFinalization<CS: CipherSuite>, [ke3_message]);
 impl_debug_eq_hash_for!(
     struct CredentialFinalization<CS: CipherSuite>,
     [ke3_message],
-    [<CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE3Message],
 );
 impl_serialize_and_deserialize_for!(CredentialFinalization);
+impl_canonical_bytes_for!(CredentialFinalization);
+impl_serialize_to_slice_for!(CredentialFinalization);
+
+impl<CS: CipherSuite> fmt::Display for CredentialFinalization<CS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_fields(
+            f,
+            "CredentialFinalization",
+            &[("ke3_message", &self.ke3_message.to_bytes())],
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<CS: CipherSuite> defmt::Format for CredentialFinalization<CS> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(
+            f,
+            "CredentialFinalization {{ ke3_message: {=[u8]:x} }}",
+            &self.ke3_message.to_bytes()[..],
+        )
+    }
+}
+
+impl<CS: CipherSuite> Clone for CredentialRequestRef<'_, CS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<CS: CipherSuite> Copy for CredentialRequestRef<'_, CS> {}
+
+impl<CS: CipherSuite> fmt::Debug for CredentialRequestRef<'_, CS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_fields(
+            f,
+            "CredentialRequestRef",
+            &[
+                ("blinded_element", self.blinded_element()),
+                ("ke1_message", self.ke1_message()),
+            ],
+        )
+    }
+}
+
+impl<CS: CipherSuite> Clone for CredentialResponseRef<'_, CS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<CS: CipherSuite> Copy for CredentialResponseRef<'_, CS> {}
+
+impl<CS: CipherSuite> fmt::Debug for CredentialResponseRef<'_, CS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_fields(
+            f,
+            "CredentialResponseRef",
+            &[
+                ("evaluation_element", self.evaluation_element),
+                ("masking_nonce", self.masking_nonce),
+                ("masked_response", self.masked_response),
+                ("key_id", self.key_id),
+                ("ke2_message", self.ke2_message),
+            ],
+        )
+    }
+}
+
+impl<CS: CipherSuite> Clone for CredentialFinalizationRef<'_, CS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<CS: CipherSuite> Copy for CredentialFinalizationRef<'_, CS> {}
+
+impl<CS: CipherSuite> fmt::Debug for CredentialFinalizationRef<'_, CS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_fields(
+            f,
+            "CredentialFinalizationRef",
+            &[("ke3_message", self.input)],
+        )
+    }
+}
+
+// Deserializes an evaluation element, which must not be the identity
+fn deserialize_evaluation_element<CS: CipherSuite>(
+    input: &[u8],
+) -> Result<voprf::EvaluationElement<CS::OprfGroup, CS::Hash>, ProtocolError> {
+    // Check that the message is actually containing an element of the
+    // correct subgroup
+    let evaluation_element =
+        voprf::EvaluationElement::<CS::OprfGroup, CS::Hash>::deserialize(input)?;
+
+    // Throw an error if the identity group element is encountered
+    if evaluation_element.value().is_identity() {
+        return Err(ProtocolError::IdentityGroupElementError);
+    }
+    Ok(evaluation_element)
+}
+
+// Checks that `input` encodes a blinded element which is not the identity
+fn validate_blinded_element<CS: CipherSuite>(input: &[u8]) -> Result<(), ProtocolError> {
+    let blinded_element = voprf::BlindedElement::<CS::OprfGroup, CS::Hash>::deserialize(input)?;
+    if blinded_element.value().is_identity() {
+        return Err(ProtocolError::IdentityGroupElementError);
+    }
+    Ok(())
+}
+
+// Writes `name (N bytes) { field (N bytes): hex, ... }`
+fn display_fields(f: &mut fmt::Formatter<'_>, name: &str, fields: &[(&str, &[u8])]) -> fmt::Result {
+    let len: usize = fields.iter().map(|(_, bytes)| bytes.len()).sum();
+    write!(f, "{} ({} bytes) {{", name, len)?;
+    for (i, (field, bytes)) in fields.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        write!(
+            f,
+            "{} {} ({} bytes): {}",
+            separator,
+            field,
+            bytes.len(),
+            Hex(bytes)
+        )?;
+    }
+    write!(f, " }}")
+}
@@ -0,0 +1,171 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Hooks for observing the outcomes of the protocol, e.g. in order to feed
+//! metrics counters
+//!
+//! A [ProtocolObserver] is installed for the whole process with
+//! `set_observer`, and removed with `clear_observer`, which require the `std`
+//! feature. Without an installed observer, reporting an event is a no-op.
+
+use crate::errors::ProtocolError;
+#[cfg(feature = "std")]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// An outcome of the protocol reported to a [ProtocolObserver]. Events never
+/// carry secret material.
+///
+/// Events are added as the library reports more outcomes, so matches on
+/// this enum must include a wildcard arm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ProtocolEvent {
+    /// [ClientRegistration::finish](crate::ClientRegistration::finish)
+    /// succeeded
+    ClientRegistrationFinished,
+    /// [ClientLogin::finish](crate::ClientLogin::finish) succeeded
+    ClientLoginSucceeded,
+    /// [ClientLogin::finish](crate::ClientLogin::finish) failed a MAC check,
+    /// either because the password is wrong or because the server could not
+    /// be authenticated
+    ClientLoginMacFailed,
+    /// [ServerLogin::finish](crate::ServerLogin::finish) succeeded
+    ServerLoginSucceeded,
+    /// [ServerLogin::finish](crate::ServerLogin::finish) failed to
+    /// authenticate the client
+    ServerLoginMacFailed,
+    /// The server reflected the client's blinded element back
+    ReflectedValueDetected,
+    /// Deserializing the named message failed
+    DeserializationFailed(&'static str),
+}
+
+/// Receives the [ProtocolEvent]s emitted by this library
+pub trait ProtocolObserver: Sync {
+    /// Called whenever `event` occurs
+    fn observe(&self, event: ProtocolEvent);
+}
+
+/// The error returned by `set_observer` when an observer has already been
+/// installed
+#[derive(Debug)]
+pub struct SetObserverError(());
+
+impl core::fmt::Display for SetObserverError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("an observer has already been installed")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SetObserverError {}
+
+// A value which can be installed for the whole process, and uninstalled.
+// The flag is set while a value is installed, so that reporting an event
+// without one only costs an atomic load instead of taking the lock.
+#[cfg(feature = "std")]
+pub(crate) struct Global<T: ?Sized + 'static> {
+    pub(crate) is_installed: AtomicBool,
+    pub(crate) value: once_cell::sync::Lazy<std::sync::RwLock<Option<&'static T>>>,
+}
+
+// Returns false if a value has already been installed
+#[cfg(feature = "std")]
+pub(crate) fn install<T: ?Sized>(global: &Global<T>, value: &'static T) -> bool {
+    let mut installed = global
+        .value
+        .write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    if installed.is_some() {
+        return false;
+    }
+    *installed = Some(value);
+    global.is_installed.store(true, Ordering::Release);
+    true
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn uninstall<T: ?Sized>(global: &Global<T>) -> Option<&'static T> {
+    let mut installed = global
+        .value
+        .write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    global.is_installed.store(false, Ordering::Release);
+    installed.take()
+}
+
+// The lock is released before the value is used, so that it can install or
+// uninstall values itself
+#[cfg(feature = "std")]
+pub(crate) fn installed<T: ?Sized>(global: &Global<T>) -> Option<&'static T> {
+    if !global.is_installed.load(Ordering::Acquire) {
+        return None;
+    }
+    *global
+        .value
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(feature = "std")]
+static OBSERVER: Global<dyn ProtocolObserver> = Global {
+    is_installed: AtomicBool::new(false),
+    value: once_cell::sync::Lazy::new(|| std::sync::RwLock::new(None)),
+};
+
+/// Installs `observer` for the whole process, until it is removed with
+/// [clear_observer]. Fails if an observer is already installed.
+#[cfg(feature = "std")]
+pub fn set_observer(observer: &'static dyn ProtocolObserver) -> Result<(), SetObserverError> {
+    if install(&OBSERVER, observer) {
+        Ok(())
+    } else {
+        Err(SetObserverError(()))
+    }
+}
+
+/// Removes the installed observer, if any, and returns it, so that another
+/// one can be installed
+#[cfg(feature = "std")]
+pub fn clear_observer() -> Option<&'static dyn ProtocolObserver> {
+    uninstall(&OBSERVER)
+}
+
+/// Reports `event` to the installed observer, if any
+pub(crate) fn notify(event: ProtocolEvent) {
+    #[cfg(feature = "std")]
+    if let Some(observer) = installed(&OBSERVER) {
+        observer.observe(event);
+    }
+    #[cfg(not(feature = "std"))]
+    let _ = event;
+}
+
+/// Reports the outcome of a login finish through `succeeded` or `mac_failed`
+pub(crate) fn notify_login<T, E>(
+    result: Result<T, ProtocolError<E>>,
+    succeeded: ProtocolEvent,
+    mac_failed: ProtocolEvent,
+) -> Result<T, ProtocolError<E>> {
+    match &result {
+        Ok(_) => notify(succeeded),
+        Err(ProtocolError::InvalidLoginError) => notify(mac_failed),
+        Err(ProtocolError::ReflectedValueError) => notify(ProtocolEvent::ReflectedValueDetected),
+        Err(_) => (),
+    }
+    result
+}
+
+/// Runs `deserialize`, reporting a failure to deserialize `message`
+pub(crate) fn observe_deserialization<T>(
+    message: &'static str,
+    deserialize: impl FnOnce() -> Result<T, ProtocolError>,
+) -> Result<T, ProtocolError> {
+    let result = deserialize();
+    if result.is_err() {
+        notify(ProtocolEvent::DeserializationFailed(message));
+    }
+    result
+}
@@ -5,41 +5,94 @@
 
 //! Provides the main OPAQUE API
 
+#[cfg(feature = "envelope-inspect")]
+use crate::envelope::EnvelopeInfo;
+#[cfg(feature = "std")]
+use crate::key_pool::EphemeralKeyPool;
 use crate::{
-    ciphersuite::CipherSuite,
-    envelope::Envelope,
-    errors::{utils::check_slice_size, InternalError, ProtocolError},
-    hash::Hash,
+    audit::{self, AuditEvent, LoginFailureReason},
+    ciphersuite::{CipherSuite, Validate},
+    envelope::{ClientStaticKey, Envelope, EnvelopeMode, OpenedEnvelope},
+    errors::{
+        utils::{check_slice_size, check_slice_size_atleast, fill_random, unless_external},
+        InternalError, ProtocolError,
+    },
+    impls::Redacted,
     key_exchange::{
         group::KeGroup,
         traits::{FromBytes, KeyExchange, ToBytes},
     },
     keypair::{KeyPair, PrivateKey, PublicKey, SecretKey},
-    serialization::{serialize, tokenize},
-    slow_hash::SlowHash,
+    observer::{self, ProtocolEvent},
+    puzzle::Puzzle,
+    serialization::{i2osp, serialize, serialize_len_into, tokenize},
+    slow_hash::{CancellationToken, ProgressCallback, SlowHash},
     CredentialFinalization, CredentialRequest, CredentialResponse, RegistrationRequest,
     RegistrationResponse, RegistrationUpload,
 };
+#[cfg(feature = "rayon")]
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+#[cfg(feature = "sealed-records")]
+use chacha20poly1305::{
+    aead::{AeadInPlace, NewAead},
+    Key, Tag, XChaCha20Poly1305, XNonce,
+};
+use core::iter::FusedIterator;
 use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
 use digest::Digest;
 use generic_array::{typenum::Unsigned, GenericArray};
 use hkdf::Hkdf;
 use rand::{CryptoRng, RngCore};
 use subtle::ConstantTimeEq;
 use voprf::group::Group;
-use zeroize::Zeroize;
+use zeroize::{Zeroize, Zeroizing};
 
 ///////////////
 // Constants //
 // ========= //
 ///////////////
 
-const STR_CREDENTIAL_RESPONSE_PAD: &[u8; 21] = b"CredentialResponsePad";
-const STR_MASKING_KEY: &[u8; 10] = b"MaskingKey";
 const STR_OPRF_KEY: &[u8; 7] = b"OprfKey";
 const STR_OPAQUE_DERIVE_KEY_PAIR: &[u8; 20] = b"OPAQUE-DeriveKeyPair";
+const STR_PSEUDONYM: &[u8; 9] = b"Pseudonym";
+const STR_STORAGE_KEY: &[u8; 10] = b"StorageKey";
+const STR_TENANT_OPRF_SEED: &[u8; 14] = b"TenantOprfSeed";
+const STR_TENANT_FAKE_KEY_PAIR: &[u8; 17] = b"TenantFakeKeyPair";
+const STR_TENANT_KEY_PAIR: &[u8; 13] = b"TenantKeyPair";
+const STR_KEY_ID: &[u8; 5] = b"KeyId";
+const STR_CLIENT_SEED_KEY_PAIR: &[u8; 17] = b"ClientSeedKeyPair";
+const STR_SESSION_KEY: &[u8; 10] = b"SessionKey";
+const STR_SLOW_HASH_SALT: &[u8; 12] = b"SlowHashSalt";
+const STR_RETRANSMISSION_SEED: &[u8; 18] = b"RetransmissionSeed";
+const STR_DUMMY_SLOW_HASH_ID: &[u8; 15] = b"DummySlowHashId";
+const STR_SERVER_LOGIN_STATE: &[u8; 16] = b"ServerLoginState";
+#[cfg(feature = "sealed-records")]
+const STR_RECORD_WRAPPING_KEY: &[u8; 17] = b"RecordWrappingKey";
+
+/// The version of the format of serialized [ServerLogin] states
+pub const SERVER_LOGIN_STATE_VERSION: u8 = 1;
+/// The version of the format of serialized [ServerRegistration] records
+/// which carry more than the registration upload
+pub const SERVER_REGISTRATION_VERSION: u8 = 1;
+const SUITE_TAG_LEN: usize = 4;
+
+/// The length of [`ServerLoginStartParameters::retransmission_nonce`]
+pub const RETRANSMISSION_NONCE_LEN: usize = 32;
+
+/// The length of the key encryption keys of [`ServerRegistration::seal`]
+#[cfg(feature = "sealed-records")]
+pub const RECORD_SEALING_KEY_LEN: usize = 32;
+/// The length of the per-user salts of [`ServerSetup::wrap_record`]
+#[cfg(feature = "sealed-records")]
+pub const ERASURE_SALT_LEN: usize = 32;
+#[cfg(feature = "sealed-records")]
+const SEALED_RECORD_NONCE_LEN: usize = 24;
+#[cfg(feature = "sealed-records")]
+const SEALED_RECORD_TAG_LEN: usize = 16;
 
 ////////////////////////////
 // High-level API Structs //
@@ -69,64 +122,197 @@ impl_clone_for!(
     struct ServerSetup<CS: CipherSuite>,
     [oprf_seed, keypair, fake_keypair],
 );
-impl_debug_eq_hash_for!(
+impl_eq_hash_for!(
     struct ServerSetup<CS: CipherSuite>,
     [oprf_seed, oprf_seed, fake_keypair],
 );
 
+impl<CS: CipherSuite, S: SecretKey<CS::KeGroup> + core::fmt::Debug> core::fmt::Debug
+    for ServerSetup<CS, S>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ServerSetup")
+            .field("oprf_seed", &Redacted(self.oprf_seed.len()))
+            .field("keypair", &self.keypair)
+            .field("fake_keypair", &self.fake_keypair)
+            .finish()
+    }
+}
+
 /// The state elements the client holds to register itself
+///
+/// The state can be cloned or serialized, e.g. to survive an application
+/// restart. Since it holds the OPRF blind, it must be kept as secret as the
+/// password, and it should be discarded once
+/// [finish](ClientRegistration::finish) has succeeded.
 pub struct ClientRegistration<CS: CipherSuite> {
     pub(crate) oprf_client: voprf::NonVerifiableClient<CS::OprfGroup, CS::Hash>,
     pub(crate) blinded_element: voprf::BlindedElement<CS::OprfGroup, CS::Hash>,
 }
 
 impl_clone_for!(struct ClientRegistration<CS: CipherSuite>, [oprf_client, blinded_element]);
-impl_debug_eq_hash_for!(
+impl_eq_hash_for!(
     struct ClientRegistration<CS: CipherSuite>,
     [oprf_client],
     [voprf::NonVerifiableClient<CS::OprfGroup, CS::Hash>],
 );
-impl_serialize_and_deserialize_for!(ClientRegistration);
 
-/// The state elements the server holds to record a registration
-pub struct ServerRegistration<CS: CipherSuite>(RegistrationUpload<CS>);
+impl<CS: CipherSuite> core::fmt::Debug for ClientRegistration<CS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ClientRegistration")
+            .field(
+                "oprf_client",
+                &Redacted(Zeroizing::new(self.oprf_client.serialize()).len()),
+            )
+            .field("blinded_element", &self.blinded_element)
+            .finish()
+    }
+}
+impl_serialize_and_deserialize_for!(ClientRegistration);
 
-impl_clone_for!(tuple ServerRegistration<CS: CipherSuite>, [0]);
+/// The state elements the server holds to record a registration, along with
+/// the client identity, if the server chose to keep it, and the identifier of
+/// the slow hash the envelope was sealed with
+///
+/// A record which only holds the registration upload serializes to the
+/// upload, as in earlier releases. Any other record starts with
+/// [SERVER_REGISTRATION_VERSION] and the slow hash identifier, followed by
+/// the upload and the client identity, if any, each prefixed with its length
+/// as a 2-byte big-endian integer. A zero byte is appended in the rare case
+/// where this would have the length of an upload.
+pub struct ServerRegistration<CS: CipherSuite>(RegistrationUpload<CS>, Option<Vec<u8>>, u8);
+
+impl_clone_for!(tuple ServerRegistration<CS: CipherSuite>, [0, 1, 2]);
 impl_debug_eq_hash_for!(
     tuple ServerRegistration<CS: CipherSuite>,
-    [0],
+    [0, 1, 2],
 );
 impl_serialize_and_deserialize_for!(ServerRegistration);
+impl_canonical_bytes_for!(ServerRegistration);
+
+/// An iterator over the records of a stream, created by
+/// [`ServerRegistration::deserialize_stream`]
+pub struct ServerRegistrationStream<'a, CS: CipherSuite> {
+    remainder: &'a [u8],
+    _cs: PhantomData<CS>,
+}
 
 /// The state elements the client holds to perform a login
+///
+/// The state can be cloned or serialized, but it holds the OPRF blind and the
+/// client's ephemeral secret key, so it must be kept as secret as the
+/// password. Finishing several copies against different responses would reuse
+/// the ephemeral key across sessions, so once a state or one of its clones
+/// has been passed to [finish](ClientLogin::finish), finishing another clone
+/// fails with [`ProtocolError::ReplayDetectedError`]. A deserialized state is
+/// not a clone, so only one copy should ever be deserialized to be finished.
 pub struct ClientLogin<CS: CipherSuite> {
     oprf_client: voprf::NonVerifiableClient<CS::OprfGroup, CS::Hash>,
     ke1_state: <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE1State,
     serialized_credential_request: Vec<u8>,
+    finished: FinishGuard,
 }
 
-impl_clone_for!(struct ClientLogin<CS: CipherSuite>, [oprf_client, ke1_state, serialized_credential_request]);
-impl_debug_eq_hash_for!(
+impl_clone_for!(
+    struct ClientLogin<CS: CipherSuite>,
+    [oprf_client, ke1_state, serialized_credential_request, finished],
+);
+impl_eq_hash_for!(
     struct ClientLogin<CS: CipherSuite>,
     [oprf_client, ke1_state, serialized_credential_request],
-    [voprf::NonVerifiableClient<CS::OprfGroup, CS::Hash>, <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE1State],
+    [voprf::NonVerifiableClient<CS::OprfGroup, CS::Hash>],
 );
+
+impl<CS: CipherSuite> core::fmt::Debug for ClientLogin<CS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ClientLogin")
+            .field(
+                "oprf_client",
+                &Redacted(Zeroizing::new(self.oprf_client.serialize()).len()),
+            )
+            .field("ke1_state", &self.ke1_state)
+            .field(
+                "serialized_credential_request",
+                &self.serialized_credential_request,
+            )
+            .finish()
+    }
+}
 impl_serialize_and_deserialize_for!(ClientLogin);
 
+/// The password-independent part of a client login start, created by
+/// [`ClientLogin::precompute`] ahead of time, e.g. while the user is typing
+///
+/// It holds the OPRF blind and the client's ephemeral secret key, so it must
+/// be kept as secret as the password. It is consumed by
+/// [`start`](Self::start), and cannot be cloned, so that the ephemeral key is
+/// used for a single login.
+pub struct ClientLoginPrecomputation<CS: CipherSuite> {
+    blind: <CS::OprfGroup as Group>::Scalar,
+    ke1_state: <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE1State,
+    ke1_message: <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE1Message,
+}
+
+impl<CS: CipherSuite> core::fmt::Debug for ClientLoginPrecomputation<CS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ClientLoginPrecomputation")
+            .field(
+                "blind",
+                &Redacted(<CS::OprfGroup as Group>::ScalarLen::USIZE),
+            )
+            .field("ke1_state", &self.ke1_state)
+            .field("ke1_message", &self.ke1_message)
+            .finish()
+    }
+}
+
 /// The state elements the server holds to record a login
+///
+/// The state can be cloned or serialized, so that a load-balanced server can
+/// keep it in a shared storage layer between [start](ServerLogin::start) and
+/// [finish](ServerLogin::finish). It contains the session key, so it must be
+/// stored confidentially. Once a state or one of its clones has been passed
+/// to [finish](ServerLogin::finish), finishing another clone fails with
+/// [`ProtocolError::ReplayDetectedError`]. This library cannot detect
+/// serialized copies being finished more than once, since a deserialized
+/// state is not a clone: a replayed [CredentialFinalization] would be
+/// accepted by every copy, so the storage layer must remove the state
+/// atomically when it is taken out to be finished.
+///
+/// A serialized state starts with [SERVER_LOGIN_STATE_VERSION] and a 4-byte
+/// tag of the ciphersuite, computed with its hash over its
+/// [CONTEXT](CipherSuite::CONTEXT) and the lengths of its keys. Every later
+/// release of this library keeps accepting the states of the versions it
+/// supports, so that start and finish can run on different nodes during a
+/// rolling upgrade, while a state produced under another ciphersuite or
+/// context fails to deserialize instead of failing the login.
 pub struct ServerLogin<CS: CipherSuite> {
     ke2_state: <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE2State,
+    finished: FinishGuard,
     _cs: PhantomData<CS>,
 }
 
-impl_clone_for!(struct ServerLogin<CS: CipherSuite>, [ke2_state, _cs]);
+impl_clone_for!(struct ServerLogin<CS: CipherSuite>, [ke2_state, finished, _cs]);
 impl_debug_eq_hash_for!(
     struct ServerLogin<CS: CipherSuite>,
     [ke2_state, _cs],
-    [<CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE2State],
 );
 impl_serialize_and_deserialize_for!(ServerLogin);
 
+// Shared by a login state and its clones, so that only one of them can be
+// finished
+#[derive(Clone, Default)]
+struct FinishGuard(Arc<AtomicBool>);
+
+impl FinishGuard {
+    fn claim<E>(&self) -> Result<(), ProtocolError<E>> {
+        if self.0.swap(true, Ordering::SeqCst) {
+            return Err(ProtocolError::ReplayDetectedError);
+        }
+        Ok(())
+    }
+}
+
 ////////////////////////////////
 // High-level Implementations //
 // ========================== //
@@ -137,23 +323,39 @@ impl_serialize_and_deserialize_for!(ServerLogin);
 
 impl<CS: CipherSuite> ServerSetup<CS, PrivateKey<CS::KeGroup>> {
     /// Generate a new instance of server setup
-    pub fn new<R: CryptoRng + RngCore>(rng: &mut R) -> Result<Self, InternalError> {
+    pub fn new<R: CryptoRng + RngCore + ?Sized>(rng: &mut R) -> Result<Self, InternalError> {
+        let rng = &mut &mut *rng;
         let keypair = KeyPair::<CS::KeGroup>::generate_random(rng)?;
         Self::new_with_key(rng, keypair)
     }
+
+    /// Same as [`tenant`](Self::tenant), but also derives a keypair for the
+    /// tenant, so that the tenant's clients authenticate a server key of its
+    /// own
+    pub fn tenant_with_keypair(&self, tenant_id: &[u8]) -> Result<Self, ProtocolError> {
+        let mut tenant = self.tenant(tenant_id)?;
+        tenant.keypair =
+            derive_tenant_keypair::<CS>(&self.oprf_seed, tenant_id, STR_TENANT_KEY_PAIR)?;
+        Ok(tenant)
+    }
 }
 
 impl<CS: CipherSuite, S: SecretKey<CS::KeGroup>> ServerSetup<CS, S> {
     /// Create [`ServerSetup`] with the given keypair
-    pub fn new_with_key<R: CryptoRng + RngCore>(
+    pub fn new_with_key<R: CryptoRng + RngCore + ?Sized>(
         rng: &mut R,
         keypair: KeyPair<CS::KeGroup, S>,
     ) -> Result<Self, InternalError> {
-        let mut seed = vec![0u8; <CS::Hash as Digest>::OutputSize::USIZE];
-        rng.fill_bytes(&mut seed);
+        Validate::<CS>::assert();
+        // The RNG may be a trait object, while the helpers below expect a
+        // sized type, which a mutable reference to it always is
+        let rng = &mut &mut *rng;
+
+        let mut oprf_seed = GenericArray::default();
+        fill_random(rng, &mut oprf_seed)?;
 
         Ok(Self {
-            oprf_seed: GenericArray::clone_from_slice(&seed[..]),
+            oprf_seed,
             keypair,
             fake_keypair: KeyPair::<CS::KeGroup>::generate_random(rng)?,
         })
@@ -171,6 +373,8 @@ impl<CS: CipherSuite, S: SecretKey<CS::KeGroup>> ServerSetup<CS, S> {
 
     /// Deserialization from bytes
     pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError<S::Error>> {
+        Validate::<CS>::assert();
+
         let seed_len = <CS::Hash as Digest>::OutputSize::USIZE;
         let key_len = <CS::KeGroup as KeGroup>::SkLen::USIZE;
         let checked_slice = check_slice_size(input, seed_len + key_len + key_len, "server_setup")?;
@@ -187,6 +391,145 @@ impl<CS: CipherSuite, S: SecretKey<CS::KeGroup>> ServerSetup<CS, S> {
     pub fn keypair(&self) -> &KeyPair<CS::KeGroup, S> {
         &self.keypair
     }
+
+    /// Returns the identifier of the keypair carried by credential responses
+    /// when [`ServerLoginStartParameters::key_id`] is not set, which is
+    /// derived from the public key and is of [`CipherSuite::KEY_ID_LEN`]
+    /// bytes
+    pub fn key_id(&self) -> Vec<u8> {
+        key_id_from_public_key::<CS>(self.keypair.public())
+    }
+
+    /// Derives the server setup of the tenant identified by `tenant_id`,
+    /// which shares the keypair of this setup but has its own OPRF seed.
+    ///
+    /// Records registered with a tenant's setup can only be used for logins
+    /// with the same tenant's setup, which is derived anew from this setup
+    /// and the same `tenant_id` every time, so that only this setup has to be
+    /// stored.
+    pub fn tenant(&self, tenant_id: &[u8]) -> Result<Self, ProtocolError> {
+        let mut oprf_seed = GenericArray::default();
+        Hkdf::<CS::Hash>::from_prk(&self.oprf_seed)
+            .map_err(|_| InternalError::HkdfError)?
+            .expand_multi_info(
+                &[&serialize(tenant_id, 2)?, CS::CONTEXT, STR_TENANT_OPRF_SEED],
+                &mut oprf_seed,
+            )
+            .map_err(|_| InternalError::HkdfError)?;
+
+        Ok(Self {
+            oprf_seed,
+            keypair: self.keypair.clone(),
+            fake_keypair: derive_tenant_keypair::<CS>(
+                &self.oprf_seed,
+                tenant_id,
+                STR_TENANT_FAKE_KEY_PAIR,
+            )?,
+        })
+    }
+
+    /// Maps `credential_identifier` to the key under which its registration
+    /// record should be stored, by hashing it with a key derived from the
+    /// OPRF seed.
+    ///
+    /// A leaked database keyed this way does not reveal the list of
+    /// credential identifiers, as long as the server setup stays secret. The
+    /// server still needs the credential identifier itself for
+    /// [`ServerRegistration::start`] and [`ServerLogin::start`].
+    pub fn storage_key(
+        &self,
+        credential_identifier: &[u8],
+    ) -> Result<GenericArray<u8, <CS::Hash as Digest>::OutputSize>, ProtocolError> {
+        let mut storage_key = GenericArray::default();
+        Hkdf::<CS::Hash>::from_prk(&self.oprf_seed)
+            .map_err(|_| InternalError::HkdfError)?
+            .expand_multi_info(
+                &[credential_identifier, CS::CONTEXT, STR_STORAGE_KEY],
+                &mut storage_key,
+            )
+            .map_err(|_| InternalError::HkdfError)?;
+        Ok(storage_key)
+    }
+
+    /// Returns the OPRF public key of `credential_identifier`, which commits
+    /// to the OPRF key derived for it from the OPRF seed, so that it can be
+    /// published to auditors.
+    ///
+    /// This library only implements the non-verifiable mode of the OPRF, so
+    /// that credential responses carry neither the commitment nor a proof
+    /// that the evaluation used the committed key. The commitment lets
+    /// auditors check that the OPRF key of a credential identifier stays the
+    /// same across deployments and migrations of the server setup.
+    ///
+    /// The key is computed on demand, so that logins do not pay for it.
+    pub fn oprf_public_key(
+        &self,
+        credential_identifier: &[u8],
+    ) -> Result<GenericArray<u8, <CS::OprfGroup as Group>::ElemLen>, ProtocolError> {
+        let oprf_key = Zeroizing::new(oprf_key_from_seed::<CS>(
+            &self.oprf_seed,
+            credential_identifier,
+        )?);
+        oprf_public_key_from_key::<CS>(&oprf_key)
+    }
+}
+
+#[cfg(feature = "sealed-records")]
+impl<CS: CipherSuite, S: SecretKey<CS::KeGroup>> ServerSetup<CS, S> {
+    /// Seals `record` with [`ServerRegistration::seal`] under a key derived
+    /// from the OPRF seed, `credential_identifier` and `salt`, a random
+    /// per-user salt of [ERASURE_SALT_LEN] bytes.
+    ///
+    /// This allows crypto-shredding: the salts are kept apart from the
+    /// records, in a small store which is not backed up, or whose backups
+    /// expire quickly. Deleting the salt of a user makes every copy of the
+    /// user's record unrecoverable, including those in backups of the
+    /// records, even though the server setup is kept.
+    pub fn wrap_record<R: RngCore + CryptoRng + ?Sized>(
+        &self,
+        rng: &mut R,
+        credential_identifier: &[u8],
+        salt: &[u8],
+        record: &ServerRegistration<CS>,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let wrapping_key = self.record_wrapping_key(credential_identifier, salt)?;
+        record.seal(rng, credential_identifier, &[], &wrapping_key[..])
+    }
+
+    /// Opens a record wrapped by [`wrap_record`](Self::wrap_record) with the
+    /// same `credential_identifier` and `salt`.
+    ///
+    /// Fails with [`InternalError::RecordDecryptionError`] otherwise, or if
+    /// the wrapped record was modified.
+    pub fn unwrap_record(
+        &self,
+        credential_identifier: &[u8],
+        salt: &[u8],
+        input: &[u8],
+    ) -> Result<ServerRegistration<CS>, ProtocolError> {
+        let wrapping_key = self.record_wrapping_key(credential_identifier, salt)?;
+        ServerRegistration::unseal(input, credential_identifier, &wrapping_key[..])
+    }
+
+    fn record_wrapping_key(
+        &self,
+        credential_identifier: &[u8],
+        salt: &[u8],
+    ) -> Result<Zeroizing<[u8; RECORD_SEALING_KEY_LEN]>, ProtocolError> {
+        let salt = check_slice_size(salt, ERASURE_SALT_LEN, "erasure_salt")?;
+        let mut wrapping_key = Zeroizing::new([0u8; RECORD_SEALING_KEY_LEN]);
+        Hkdf::<CS::Hash>::new(Some(salt), &self.oprf_seed)
+            .expand_multi_info(
+                &[
+                    &serialize(credential_identifier, 2)?,
+                    CS::CONTEXT,
+                    STR_RECORD_WRAPPING_KEY,
+                ],
+                &mut *wrapping_key,
+            )
+            .map_err(|_| InternalError::HkdfError)?;
+        Ok(wrapping_key)
+    }
 }
 
 // Registration
@@ -195,9 +538,12 @@ impl<CS: CipherSuite, S: SecretKey<CS::KeGroup>> ServerSetup<CS, S> {
 impl<CS: CipherSuite> ClientRegistration<CS> {
     /// Serialization into bytes
     pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
+        // The serialized OPRF client contains the password and the blind, so
+        // make sure that the intermediate copies are wiped
+        let serialized_oprf_client = Zeroizing::new(self.oprf_client.serialize());
         Ok([
-            serialize(&self.oprf_client.serialize(), 2)?,
-            serialize(&self.blinded_element.serialize(), 2)?,
+            &serialize(&serialized_oprf_client, 2).map(Zeroizing::new)?[..],
+            &serialize(&self.blinded_element.serialize(), 2)?[..],
         ]
         .concat())
     }
@@ -205,15 +551,15 @@ impl<CS: CipherSuite> ClientRegistration<CS> {
     /// Deserialization from bytes
     pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
         let (serialized_oprf_client, remainder) = tokenize(input, 2)?;
-        let (serialized_blinded_element, remainder) = tokenize(&remainder, 2)?;
+        let (serialized_blinded_element, remainder) = tokenize(remainder, 2)?;
 
         if !remainder.is_empty() {
             return Err(ProtocolError::SerializationError);
         }
 
         Ok(Self {
-            oprf_client: voprf::NonVerifiableClient::deserialize(&serialized_oprf_client)?,
-            blinded_element: voprf::BlindedElement::deserialize(&serialized_blinded_element)?,
+            oprf_client: voprf::NonVerifiableClient::deserialize(serialized_oprf_client)?,
+            blinded_element: voprf::BlindedElement::deserialize(serialized_blinded_element)?,
         })
     }
 
@@ -228,11 +574,41 @@ impl<CS: CipherSuite> ClientRegistration<CS> {
     }
 
     /// Returns an initial "blinded" request to send to the server, as well as a ClientRegistration
-    pub fn start<R: RngCore + CryptoRng>(
+    ///
+    /// Fails with [`InternalError::MissingCredentialIdentifierError`] if
+    /// [`CipherSuite::BIND_CREDENTIAL_IDENTIFIER`] is set, in which case
+    /// [`start_with_identifier`](Self::start_with_identifier) must be used.
+    pub fn start<R: RngCore + CryptoRng + ?Sized>(
+        blinding_factor_rng: &mut R,
+        password: &[u8],
+    ) -> Result<ClientRegistrationStartResult<CS>, ProtocolError> {
+        if CS::BIND_CREDENTIAL_IDENTIFIER {
+            return Err(InternalError::MissingCredentialIdentifierError.into());
+        }
+
+        Self::start_with_oprf_input(blinding_factor_rng, password)
+    }
+
+    /// Same as [`start`](Self::start), but binds `credential_identifier`, the
+    /// one the server registers the client under, into the OPRF input. The
+    /// same identifier must then be given to
+    /// [`ClientLogin::start_with_identifier`] on every login.
+    pub fn start_with_identifier<R: RngCore + CryptoRng + ?Sized>(
         blinding_factor_rng: &mut R,
         password: &[u8],
+        credential_identifier: &[u8],
     ) -> Result<ClientRegistrationStartResult<CS>, ProtocolError> {
-        let blind_result = blind::<CS, _>(blinding_factor_rng, password)?;
+        let oprf_input = bind_credential_identifier(credential_identifier, password)?;
+        Self::start_with_oprf_input(blinding_factor_rng, &oprf_input)
+    }
+
+    fn start_with_oprf_input<R: RngCore + CryptoRng + ?Sized>(
+        blinding_factor_rng: &mut R,
+        password: &[u8],
+    ) -> Result<ClientRegistrationStartResult<CS>, ProtocolError> {
+        Validate::<CS>::assert();
+
+        let blind_result = blind::<CS, _>(&mut &mut *blinding_factor_rng, password)?;
 
         Ok(ClientRegistrationStartResult {
             message: RegistrationRequest::<CS> {
@@ -245,14 +621,51 @@ impl<CS: CipherSuite> ClientRegistration<CS> {
         })
     }
 
+    /// Same as [`start`](Self::start), but first passes the password through
+    /// `preprocess`, e.g. to apply a client-side pepper or to derive the
+    /// secret from a password manager's vault key. Its output is used in
+    /// place of the password and zeroized afterwards.
+    ///
+    /// The same preprocessing must be applied on every login, with
+    /// [`ClientLogin::start_with_preprocessing`]. Callers which already hold
+    /// a pre-hashed secret can instead pass it to [`start`](Self::start)
+    /// directly.
+    pub fn start_with_preprocessing<R, F>(
+        blinding_factor_rng: &mut R,
+        password: &[u8],
+        preprocess: F,
+    ) -> Result<ClientRegistrationStartResult<CS>, ProtocolError>
+    where
+        R: RngCore + CryptoRng + ?Sized,
+        F: FnOnce(&[u8]) -> Vec<u8>,
+    {
+        let password = Zeroizing::new(preprocess(password));
+        Self::start(blinding_factor_rng, &password)
+    }
+
+    /// Derives the client's static keypair from `seed`, e.g. the bytes of a
+    /// seed phrase held by the user, as registration does when given
+    /// [`ClientRegistrationFinishParameters::client_seed`].
+    ///
+    /// This lets a client restore its identity key on a new device before
+    /// its first login there. The encoding of the seed phrase, e.g. as
+    /// mnemonic words, is left to the application.
+    pub fn keypair_from_seed(seed: &[u8]) -> Result<KeyPair<CS::KeGroup>, ProtocolError> {
+        Ok(KeyPair::generate_random(&mut SeedRng::<CS>::new(
+            seed,
+            STR_CLIENT_SEED_KEY_PAIR,
+        ))?)
+    }
+
     /// "Unblinds" the server's answer and returns a final message containing
     /// cryptographic identifiers, to be sent to the server on setup finalization
-    pub fn finish<R: CryptoRng + RngCore>(
+    pub fn finish<R: CryptoRng + RngCore + ?Sized>(
         self,
         rng: &mut R,
         registration_response: RegistrationResponse<CS>,
         params: ClientRegistrationFinishParameters<CS>,
     ) -> Result<ClientRegistrationFinishResult<CS>, ProtocolError> {
+        let rng = &mut &mut *rng;
         // Check for reflected value from server and halt if detected
         if self
             .blinded_element
@@ -260,19 +673,26 @@ impl<CS: CipherSuite> ClientRegistration<CS> {
             .ct_eq(&registration_response.evaluation_element.value())
             .into()
         {
+            observer::notify(ProtocolEvent::ReflectedValueDetected);
             return Err(ProtocolError::ReflectedValueError);
         }
 
         #[cfg_attr(not(test), allow(unused_variables))]
         let (randomized_pwd, randomized_pwd_hasher) = get_password_derived_key::<CS>(
-            self.oprf_client.clone(),
+            &self.oprf_client,
             registration_response.evaluation_element,
             params.slow_hash,
+            params.slow_hash_salt,
+            params.progress,
+            params.cancellation,
         )?;
 
-        let mut masking_key = vec![0u8; <CS::Hash as Digest>::OutputSize::USIZE];
+        let mut masking_key = GenericArray::<u8, <CS::Hash as Digest>::OutputSize>::default();
         randomized_pwd_hasher
-            .expand(STR_MASKING_KEY, &mut masking_key)
+            .expand_multi_info(
+                &[CS::CONTEXT, CS::ENVELOPE_LABELS.masking_key],
+                &mut masking_key,
+            )
             .map_err(|_| InternalError::HkdfError)?;
 
         let result = Envelope::<CS>::seal(
@@ -280,15 +700,26 @@ impl<CS: CipherSuite> ClientRegistration<CS> {
             randomized_pwd_hasher,
             &registration_response.server_s_pk,
             params.identifiers,
+            params.envelope_mode.unwrap_or(CS::ENVELOPE_MODE),
+            match (params.client_seed, params.external_client_s_pk) {
+                (None, None) => ClientStaticKey::Default,
+                (Some(seed), None) => ClientStaticKey::Stored(Self::keypair_from_seed(seed)?),
+                (None, Some(client_s_pk)) => ClientStaticKey::External(client_s_pk),
+                (Some(_), Some(_)) => {
+                    return Err(InternalError::IncompatibleEnvelopeModeError.into())
+                }
+            },
+            params.export_key,
         )?;
 
+        observer::notify(ProtocolEvent::ClientRegistrationFinished);
         Ok(ClientRegistrationFinishResult {
             message: RegistrationUpload {
                 envelope: result.0,
-                masking_key: GenericArray::clone_from_slice(&masking_key[..]),
+                masking_key,
                 client_s_pk: result.1,
             },
-            export_key: result.2,
+            export_key: result.2.map(ExportKey),
             server_s_pk: registration_response.server_s_pk,
             #[cfg(test)]
             state: self,
@@ -301,14 +732,181 @@ impl<CS: CipherSuite> ClientRegistration<CS> {
 }
 
 impl<CS: CipherSuite> ServerRegistration<CS> {
-    /// Serialization into bytes
+    /// Serialization into bytes. Without a client identity nor a slow hash
+    /// identifier, this is the serialized [`RegistrationUpload`].
     pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
-        self.0.serialize()
+        let mut output = Vec::with_capacity(self.serialized_len());
+        self.serialize_into(&mut output)?;
+        Ok(output)
+    }
+
+    /// Appends the serialization of this record to `output`, so that many
+    /// records can be serialized into a single buffer. Leaves `output`
+    /// unchanged on failure.
+    pub fn serialize_into(&self, output: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        if self.is_bare() {
+            self.0.serialize_into(output);
+            return Ok(());
+        }
+
+        let start = output.len();
+        output.extend_from_slice(&[SERVER_REGISTRATION_VERSION, self.2]);
+        let result = serialize_len_into(self.0.serialized_len(), 2, output).and_then(|()| {
+            self.0.serialize_into(output);
+            match &self.1 {
+                Some(client_identity) => {
+                    serialize_len_into(client_identity.len(), 2, output)?;
+                    output.extend_from_slice(client_identity);
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        });
+        if let Err(e) = result {
+            output.truncate(start);
+            return Err(e);
+        }
+        // The length of a serialized upload tells a bare record apart, so pad
+        // the rare record which would collide with it
+        if RegistrationUpload::<CS>::is_valid_len(output.len() - start) {
+            output.push(0);
+        }
+        Ok(())
+    }
+
+    /// The length of this record once serialized
+    pub fn serialized_len(&self) -> usize {
+        if self.is_bare() {
+            return self.0.serialized_len();
+        }
+        let len = 2
+            + 2
+            + self.0.serialized_len()
+            + self
+                .1
+                .as_ref()
+                .map_or(0, |client_identity| 2 + client_identity.len());
+        len + usize::from(RegistrationUpload::<CS>::is_valid_len(len))
+    }
+
+    // Whether this record only holds the registration upload, and serializes
+    // to it
+    fn is_bare(&self) -> bool {
+        self.1.is_none() && self.2 == 0
+    }
+
+    /// Appends `records` to `output` as a stream in which each serialized
+    /// record is prefixed with its length as a 2-byte big-endian integer,
+    /// without allocating for each record. Leaves the records appended before
+    /// a failure in `output`.
+    pub fn serialize_stream<'r, I>(records: I, output: &mut Vec<u8>) -> Result<(), ProtocolError>
+    where
+        CS: 'r,
+        I: IntoIterator<Item = &'r Self>,
+    {
+        for record in records {
+            serialize_len_into(record.serialized_len(), 2, output)?;
+            record.serialize_into(output)?;
+        }
+        Ok(())
+    }
+
+    /// Iterates over the records of a stream produced by
+    /// [`ServerRegistration::serialize_stream`], deserializing each record in
+    /// turn without copying the stream. A malformed record yields an error
+    /// and iteration continues with the next one, while a truncated stream
+    /// yields an error and ends the iteration.
+    pub fn deserialize_stream(input: &[u8]) -> ServerRegistrationStream<'_, CS> {
+        ServerRegistrationStream {
+            remainder: input,
+            _cs: PhantomData,
+        }
     }
 
     /// Deserialization from bytes
     pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
-        Ok(Self(RegistrationUpload::deserialize(input)?))
+        if RegistrationUpload::<CS>::is_valid_len(input.len()) {
+            return Ok(Self(RegistrationUpload::deserialize(input)?, None, 0));
+        }
+
+        let checked_bytes = check_slice_size_atleast(input, 2, "server_registration")?;
+        let (header, remainder) = checked_bytes.split_at(2);
+        if header[0] != SERVER_REGISTRATION_VERSION {
+            return Err(ProtocolError::SerializationError);
+        }
+        let slow_hash_id = header[1];
+        let (upload_bytes, remainder) = tokenize(remainder, 2)?;
+        let padded = RegistrationUpload::<CS>::is_valid_len(input.len() - 1);
+        let client_identity = match remainder {
+            [] => None,
+            [0] if padded => None,
+            _ => {
+                let (client_identity, remainder) = tokenize(remainder, 2)?;
+                match remainder {
+                    [] => (),
+                    [0] if padded => (),
+                    _ => return Err(ProtocolError::SerializationError),
+                }
+                Some(client_identity.to_vec())
+            }
+        };
+
+        // A bare record is only serialized as the upload
+        let record = Self(
+            RegistrationUpload::deserialize(upload_bytes)?,
+            client_identity,
+            slow_hash_id,
+        );
+        if record.is_bare() {
+            return Err(ProtocolError::SerializationError);
+        }
+        Ok(record)
+    }
+
+    /// The static public key the client registered with
+    pub fn client_s_pk(&self) -> &PublicKey<CS::KeGroup> {
+        &self.0.client_s_pk
+    }
+
+    /// The key used to mask the server's public key and the envelope in the
+    /// [`CredentialResponse`]. It only depends on the password and the OPRF
+    /// key, so it is the same when a client registers the same password
+    /// again under the same credential identifier.
+    pub fn masking_key(&self) -> &[u8] {
+        &self.0.masking_key
+    }
+
+    /// The mode the client sealed its envelope with
+    pub fn envelope_mode(&self) -> Result<EnvelopeMode, ProtocolError> {
+        self.0.envelope.mode()
+    }
+
+    /// The client identity stored with this record by
+    /// [`ServerRegistration::finish_with_client_identity`], to be supplied
+    /// in [`ServerLoginStartParameters`] at login
+    pub fn client_identity(&self) -> Option<&[u8]> {
+        self.1.as_deref()
+    }
+
+    /// The identifier of the slow hash the envelope of this record was sealed
+    /// with, as recorded by [`ServerRegistration::with_slow_hash_id`], which
+    /// is 0 by default
+    ///
+    /// The server sends it to the client along with the
+    /// [`CredentialResponse`], as returned in
+    /// [`ServerLoginStartResult::slow_hash_id`], so that the client stretches
+    /// the password with the matching slow hash only, see
+    /// [`ClientLogin::finish_with_upgrade`].
+    pub fn slow_hash_id(&self) -> u8 {
+        self.2
+    }
+
+    /// Records `slow_hash_id` as the identifier of the slow hash the envelope
+    /// was sealed with, which the application assigns to each of its slow
+    /// hash configurations
+    pub fn with_slow_hash_id(mut self, slow_hash_id: u8) -> Self {
+        self.2 = slow_hash_id;
+        self
     }
 
     /// From the client's "blinded" password, returns a response to be
@@ -318,12 +916,19 @@ impl<CS: CipherSuite> ServerRegistration<CS> {
         message: RegistrationRequest<CS>,
         credential_identifier: &[u8],
     ) -> Result<ServerRegistrationStartResult<CS>, ProtocolError> {
-        let oprf_key = oprf_key_from_seed::<CS::OprfGroup, CS::Hash>(
+        let oprf_key = Zeroizing::new(oprf_key_from_seed::<CS>(
             &server_setup.oprf_seed,
             credential_identifier,
-        )?;
+        )?);
+        Self::start_with_oprf_key(server_setup, message, &oprf_key)
+    }
 
-        let server = voprf::NonVerifiableServer::new_with_key(&oprf_key)?;
+    fn start_with_oprf_key<S: SecretKey<CS::KeGroup>>(
+        server_setup: &ServerSetup<CS, S>,
+        message: RegistrationRequest<CS>,
+        oprf_key: &[u8],
+    ) -> Result<ServerRegistrationStartResult<CS>, ProtocolError> {
+        let server = voprf::NonVerifiableServer::new_with_key(oprf_key)?;
         let evaluate_result = server.evaluate(message.blinded_element, None)?;
 
         Ok(ServerRegistrationStartResult {
@@ -332,35 +937,242 @@ impl<CS: CipherSuite> ServerRegistration<CS> {
                 server_s_pk: server_setup.keypair.public().clone(),
             },
             #[cfg(test)]
-            oprf_key: GenericArray::clone_from_slice(&oprf_key),
+            oprf_key: GenericArray::clone_from_slice(oprf_key),
         })
     }
 
     /// From the client's cryptographic identifiers, fully populates and
     /// returns a ServerRegistration
     pub fn finish(message: RegistrationUpload<CS>) -> Self {
-        Self(message)
+        audit::record(AuditEvent::RegistrationCompleted {
+            client_s_pk: &message.client_s_pk.to_arr(),
+        });
+        Self(message, None, 0)
+    }
+
+    /// Like [`ServerRegistration::finish`], but also stores the client
+    /// identity the client registered with, which is serialized along with
+    /// the record so that no separate lookup is needed at login
+    pub fn finish_with_client_identity(
+        message: RegistrationUpload<CS>,
+        client_identity: &[u8],
+    ) -> Self {
+        let mut record = Self::finish(message);
+        record.1 = Some(client_identity.to_vec());
+        record
     }
 
     // Creates a dummy instance used for faking a [CredentialResponse]
     pub(crate) fn dummy<R: RngCore + CryptoRng, S: SecretKey<CS::KeGroup>>(
         rng: &mut R,
         server_setup: &ServerSetup<CS, S>,
-    ) -> Self {
-        Self(RegistrationUpload::dummy(rng, server_setup))
+    ) -> Result<Self, InternalError> {
+        Ok(Self(RegistrationUpload::dummy(rng, server_setup)?, None, 0))
     }
 }
 
+#[cfg(feature = "sealed-records")]
+impl<CS: CipherSuite> ServerRegistration<CS> {
+    /// Encrypts this record with XChaCha20-Poly1305 under `kek`, a
+    /// [RECORD_SEALING_KEY_LEN]-byte key encryption key, for storing it at
+    /// rest.
+    ///
+    /// The sealed record starts with `key_id`, prefixed with its length as a
+    /// 2-byte big-endian integer, and a random 24-byte nonce, which are
+    /// authenticated along with the record. The key identifier tells which
+    /// key to [unseal](Self::unseal) with, so that the key encryption key can
+    /// be rotated while records sealed under the previous one remain.
+    ///
+    /// The `credential_identifier` the record is stored under is
+    /// authenticated as well, but not stored, so that a record moved to
+    /// another user fails to unseal.
+    pub fn seal<R: RngCore + CryptoRng + ?Sized>(
+        &self,
+        rng: &mut R,
+        credential_identifier: &[u8],
+        key_id: &[u8],
+        kek: &[u8],
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let cipher = record_cipher(kek)?;
+
+        let mut nonce = [0u8; SEALED_RECORD_NONCE_LEN];
+        fill_random(rng, &mut nonce)?;
+        let mut output = serialize(key_id, 2)?;
+        output.extend_from_slice(&nonce);
+        let aad = sealed_record_aad(&output, credential_identifier)?;
+
+        // The record contains the masking key, so wipe the plaintext once it
+        // is encrypted
+        let mut ciphertext = Zeroizing::new(self.serialize()?);
+        let tag = cipher
+            .encrypt_in_place_detached(XNonce::from_slice(&nonce), &aad, &mut ciphertext)
+            .map_err(|_| ProtocolError::SerializationError)?;
+        output.extend_from_slice(&ciphertext);
+        output.extend_from_slice(&tag);
+        Ok(output)
+    }
+
+    /// The identifier of the key that a record produced by
+    /// [seal](Self::seal) was sealed under
+    pub fn sealed_key_id(input: &[u8]) -> Result<&[u8], ProtocolError> {
+        Ok(tokenize(input, 2)?.0)
+    }
+
+    /// Decrypts a record produced by [seal](Self::seal) under `kek`, the key
+    /// identified by [`sealed_key_id`](Self::sealed_key_id), and the same
+    /// `credential_identifier`.
+    ///
+    /// Fails with [`InternalError::RecordDecryptionError`] if the key or the
+    /// credential identifier is wrong, or the sealed record was modified.
+    pub fn unseal(
+        input: &[u8],
+        credential_identifier: &[u8],
+        kek: &[u8],
+    ) -> Result<Self, ProtocolError> {
+        let cipher = record_cipher(kek)?;
+
+        let (key_id, remainder) = tokenize(input, 2)?;
+        let header_len = 2 + key_id.len() + SEALED_RECORD_NONCE_LEN;
+        if remainder.len() < SEALED_RECORD_NONCE_LEN + SEALED_RECORD_TAG_LEN {
+            return Err(InternalError::RecordDecryptionError.into());
+        }
+        let (header, ciphertext) = input.split_at(header_len);
+        let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - SEALED_RECORD_TAG_LEN);
+        let aad = sealed_record_aad(header, credential_identifier)?;
+
+        let mut plaintext = Zeroizing::new(ciphertext.to_vec());
+        cipher
+            .decrypt_in_place_detached(
+                XNonce::from_slice(&header[header_len - SEALED_RECORD_NONCE_LEN..]),
+                &aad,
+                &mut plaintext,
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| InternalError::RecordDecryptionError)?;
+        Self::deserialize(&plaintext)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<CS: CipherSuite> ServerRegistration<CS>
+where
+    RegistrationRequest<CS>: Send,
+    ServerRegistrationStartResult<CS>: Send,
+    RegistrationUpload<CS>: Send,
+{
+    /// Runs [`ServerRegistration::start`] on each pair of message and
+    /// credential identifier produced by `requests` in parallel. At most
+    /// `batch_size` requests are held in memory at a time, and each result is
+    /// passed to `sink` in the order of `requests`
+    ///
+    /// The OPRF key of each credential identifier is derived once per batch,
+    /// so that repeated requests for the same identifier, e.g. retried
+    /// registrations, only cost their evaluation. The keys are zeroized once
+    /// the batch is done. Each evaluation still takes one scalar
+    /// multiplication: requests under distinct identifiers use distinct OPRF
+    /// keys, and the batch evaluation of voprf only amortizes the proofs of
+    /// the verifiable mode, which OPAQUE does not use. Logins are not batched
+    /// this way, as a login under a repeated identifier is rare.
+    pub fn start_batch<S, C, I, F>(
+        server_setup: &ServerSetup<CS, S>,
+        requests: I,
+        batch_size: usize,
+        sink: F,
+    ) where
+        S: SecretKey<CS::KeGroup>,
+        ServerSetup<CS, S>: Sync,
+        C: AsRef<[u8]> + Send,
+        I: IntoIterator<Item = (RegistrationRequest<CS>, C)>,
+        F: FnMut(Result<ServerRegistrationStartResult<CS>, ProtocolError>),
+    {
+        par_batched(
+            requests,
+            batch_size,
+            |batch| {
+                let mut oprf_keys: BTreeMap<Vec<u8>, Zeroizing<Vec<u8>>> = BTreeMap::new();
+                for (_, credential_identifier) in batch {
+                    let credential_identifier = credential_identifier.as_ref();
+                    if oprf_keys.contains_key(credential_identifier) {
+                        continue;
+                    }
+                    // A failed derivation is reported by `start` below
+                    if let Ok(oprf_key) =
+                        oprf_key_from_seed::<CS>(&server_setup.oprf_seed, credential_identifier)
+                    {
+                        oprf_keys.insert(credential_identifier.to_vec(), Zeroizing::new(oprf_key));
+                    }
+                }
+                oprf_keys
+            },
+            |oprf_keys, (message, credential_identifier)| {
+                let credential_identifier = credential_identifier.as_ref();
+                match oprf_keys.get(credential_identifier) {
+                    Some(oprf_key) => Self::start_with_oprf_key(server_setup, message, oprf_key),
+                    None => Self::start(server_setup, message, credential_identifier),
+                }
+            },
+            sink,
+        )
+    }
+
+    /// Runs [`ServerRegistration::deserialize`] on each serialized
+    /// registration produced by `inputs` in parallel. At most `batch_size`
+    /// inputs are held in memory at a time, and each result is passed to
+    /// `sink` in the order of `inputs`
+    pub fn deserialize_batch<B, I, F>(inputs: I, batch_size: usize, sink: F)
+    where
+        B: AsRef<[u8]> + Send,
+        I: IntoIterator<Item = B>,
+        F: FnMut(Result<Self, ProtocolError>),
+    {
+        par_batched(
+            inputs,
+            batch_size,
+            |_| (),
+            |_, input| Self::deserialize(input.as_ref()),
+            sink,
+        )
+    }
+}
+
+impl<'a, CS: CipherSuite> Iterator for ServerRegistrationStream<'a, CS> {
+    type Item = Result<ServerRegistration<CS>, ProtocolError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remainder.is_empty() {
+            return None;
+        }
+
+        match tokenize(self.remainder, 2) {
+            Ok((record, remainder)) => {
+                self.remainder = remainder;
+                Some(ServerRegistration::deserialize(record))
+            }
+            Err(e) => {
+                self.remainder = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<'a, CS: CipherSuite> FusedIterator for ServerRegistrationStream<'a, CS> {}
+
 // Login
 // =====
 
 impl<CS: CipherSuite> ClientLogin<CS> {
     /// Serialization into bytes
     pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
+        // The serialized OPRF client contains the password and the blind, and
+        // the KE1 state contains the ephemeral secret key, so make sure that
+        // the intermediate copies are wiped
+        let serialized_oprf_client = Zeroizing::new(self.oprf_client.serialize());
+        let ke1_state_bytes = Zeroizing::new(self.ke1_state.to_bytes());
         let output: Vec<u8> = [
-            serialize(&self.oprf_client.serialize(), 2)?,
-            serialize(&self.serialized_credential_request, 2)?,
-            serialize(&self.ke1_state.to_bytes(), 2)?,
+            &serialize(&serialized_oprf_client, 2).map(Zeroizing::new)?[..],
+            &serialize(&self.serialized_credential_request, 2)?[..],
+            &serialize(&ke1_state_bytes, 2).map(Zeroizing::new)?[..],
         ]
         .concat();
         Ok(output)
@@ -369,8 +1181,8 @@ impl<CS: CipherSuite> ClientLogin<CS> {
     /// Deserialization from bytes
     pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
         let (serialized_oprf_client, remainder) = tokenize(input, 2)?;
-        let (serialized_credential_request, remainder) = tokenize(&remainder, 2)?;
-        let (ke1_state_bytes, remainder) = tokenize(&remainder, 2)?;
+        let (serialized_credential_request, remainder) = tokenize(remainder, 2)?;
+        let (ke1_state_bytes, remainder) = tokenize(remainder, 2)?;
 
         if !remainder.is_empty() {
             return Err(ProtocolError::SerializationError);
@@ -378,12 +1190,13 @@ impl<CS: CipherSuite> ClientLogin<CS> {
 
         let ke1_state =
             <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE1State::from_bytes::<CS>(
-                &ke1_state_bytes[..],
+                ke1_state_bytes,
             )?;
         Ok(Self {
-            oprf_client: voprf::NonVerifiableClient::deserialize(&serialized_oprf_client)?,
+            oprf_client: voprf::NonVerifiableClient::deserialize(serialized_oprf_client)?,
             ke1_state,
-            serialized_credential_request,
+            serialized_credential_request: serialized_credential_request.to_vec(),
+            finished: FinishGuard::default(),
         })
     }
 
@@ -401,36 +1214,207 @@ impl<CS: CipherSuite> ClientLogin<CS> {
 
 impl<CS: CipherSuite> ClientLogin<CS> {
     /// Returns an initial "blinded" password request to send to the server, as well as a ClientLogin
-    pub fn start<R: RngCore + CryptoRng>(
+    ///
+    /// Fails with [`InternalError::MissingCredentialIdentifierError`] if
+    /// [`CipherSuite::BIND_CREDENTIAL_IDENTIFIER`] is set, in which case
+    /// [`start_with_identifier`](Self::start_with_identifier) must be used.
+    pub fn start<R: RngCore + CryptoRng + ?Sized>(
         rng: &mut R,
         password: &[u8],
     ) -> Result<ClientLoginStartResult<CS>, ProtocolError> {
-        let blind_result = blind::<CS, _>(rng, password)?;
+        if CS::BIND_CREDENTIAL_IDENTIFIER {
+            return Err(InternalError::MissingCredentialIdentifierError.into());
+        }
+
+        Self::start_with_oprf_input(rng, password)
+    }
+
+    /// Same as [`start`](Self::start), but binds `credential_identifier` into
+    /// the OPRF input, which must match the one given to
+    /// [`ClientRegistration::start_with_identifier`]
+    pub fn start_with_identifier<R: RngCore + CryptoRng + ?Sized>(
+        rng: &mut R,
+        password: &[u8],
+        credential_identifier: &[u8],
+    ) -> Result<ClientLoginStartResult<CS>, ProtocolError> {
+        let oprf_input = bind_credential_identifier(credential_identifier, password)?;
+        Self::start_with_oprf_input(rng, &oprf_input)
+    }
+
+    fn start_with_oprf_input<R: RngCore + CryptoRng + ?Sized>(
+        rng: &mut R,
+        password: &[u8],
+    ) -> Result<ClientLoginStartResult<CS>, ProtocolError> {
+        Self::precompute(rng)?.start_with_oprf_input(password)
+    }
+
+    /// Does the work of [`start`](Self::start) that does not depend on the
+    /// password, i.e. sampling the OPRF blind and generating the ephemeral
+    /// key pair, so that it can be done before the password is available.
+    /// The login is then started with [`ClientLoginPrecomputation::start`].
+    pub fn precompute<R: RngCore + CryptoRng + ?Sized>(
+        rng: &mut R,
+    ) -> Result<ClientLoginPrecomputation<CS>, ProtocolError> {
+        Validate::<CS>::assert();
+        let rng = &mut &mut *rng;
+
+        let blind = random_nonzero_blind::<CS, _>(rng)?;
         let (ke1_state, ke1_message) = CS::KeyExchange::generate_ke1(rng)?;
 
-        let credential_request = CredentialRequest {
-            blinded_element: blind_result.message,
+        Ok(ClientLoginPrecomputation {
+            blind,
+            ke1_state,
             ke1_message,
-        };
-        let serialized_credential_request = credential_request.serialize()?;
-
-        Ok(ClientLoginStartResult {
-            message: credential_request,
-            state: Self {
-                oprf_client: blind_result.state,
-                ke1_state,
-                serialized_credential_request,
-            },
         })
     }
 
+    /// Same as [`start`](Self::start), but first passes the password through
+    /// `preprocess`, which must be the same as the one given to
+    /// [`ClientRegistration::start_with_preprocessing`]. Its output is used in
+    /// place of the password and zeroized afterwards.
+    pub fn start_with_preprocessing<R, F>(
+        rng: &mut R,
+        password: &[u8],
+        preprocess: F,
+    ) -> Result<ClientLoginStartResult<CS>, ProtocolError>
+    where
+        R: RngCore + CryptoRng + ?Sized,
+        F: FnOnce(&[u8]) -> Vec<u8>,
+    {
+        let password = Zeroizing::new(preprocess(password));
+        Self::start(rng, &password)
+    }
+
     /// "Unblinds" the server's answer and returns the opened assets from
     /// the server
+    ///
+    /// A wrong password fails with [`ProtocolError::InvalidLoginError`], and
+    /// a corrected password requires a new login from
+    /// [`start`](Self::start). The server's answer is the evaluation of the
+    /// OPRF on the blinded password, which cannot be turned into the
+    /// evaluation on another password without the server: this is what
+    /// prevents offline guessing, so a retry always costs a round trip.
     pub fn finish(
         self,
         credential_response: CredentialResponse<CS>,
         params: ClientLoginFinishParameters<CS>,
     ) -> Result<ClientLoginFinishResult<CS>, ProtocolError> {
+        observer::notify_login(
+            self.finish_unobserved(credential_response, params, None, None::<&KeyPair<_>>)
+                .map(|(result, _)| result),
+            ProtocolEvent::ClientLoginSucceeded,
+            ProtocolEvent::ClientLoginMacFailed,
+        )
+    }
+
+    /// Same as [`finish`](Self::finish), but also reseals the registration
+    /// with `upgrade_slow_hash`, e.g. while migrating registrations to a new
+    /// slow hash configuration.
+    ///
+    /// The server records which slow hash each envelope was sealed with in
+    /// [`ServerRegistration::slow_hash_id`], and sends this identifier along
+    /// with the [`CredentialResponse`]. The slow hash of `params` must be the
+    /// one it identifies, so that only this slow hash is computed to open the
+    /// envelope, and `upgrade_slow_hash` is only computed once the envelope
+    /// opened. An identifier which is already the current one calls for
+    /// [`finish`](Self::finish) instead.
+    ///
+    /// On success, [`ClientLoginFinishResult::upgrade`] carries a new
+    /// registration upload, sealed with `upgrade_slow_hash`. Once the login
+    /// finishes on the server, the client should send it over the
+    /// authenticated session so that the server replaces the record with
+    /// [`ServerRegistration::finish`] and records the identifier of the new
+    /// slow hash with [`ServerRegistration::with_slow_hash_id`].
+    ///
+    /// The identifier is sent in the clear, so unregistered clients must be
+    /// sent identifiers with the same distribution as registered ones, which
+    /// [`ServerLoginStartResult::slow_hash_id`] does when given the weights
+    /// of [`ServerLoginStartParameters::dummy_slow_hash_ids`].
+    pub fn finish_with_upgrade<R: RngCore + CryptoRng + ?Sized>(
+        self,
+        rng: &mut R,
+        credential_response: CredentialResponse<CS>,
+        params: ClientLoginFinishParameters<CS>,
+        upgrade_slow_hash: &CS::SlowHash,
+    ) -> Result<ClientLoginFinishResult<CS>, ProtocolError> {
+        let identifiers = params.identifiers.clone();
+        let export_key = params.export_key;
+        let result = self
+            .finish_unobserved(
+                credential_response,
+                params,
+                Some(upgrade_slow_hash),
+                None::<&KeyPair<_>>,
+            )
+            .and_then(|(mut result, upgrade)| {
+                if let Some((randomized_pwd_hasher, envelope_mode, client_static_keypair)) = upgrade
+                {
+                    result.upgrade = Some(RegistrationUpgrade::seal(
+                        &mut &mut *rng,
+                        randomized_pwd_hasher,
+                        &result.server_s_pk,
+                        identifiers,
+                        envelope_mode,
+                        client_static_keypair,
+                        export_key,
+                    )?);
+                }
+                Ok(result)
+            });
+        observer::notify_login(
+            result,
+            ProtocolEvent::ClientLoginSucceeded,
+            ProtocolEvent::ClientLoginMacFailed,
+        )
+    }
+
+    /// Same as [`finish`](Self::finish), for a client whose static keypair
+    /// is held outside of the envelope, e.g. in a platform keystore such as
+    /// the Secure Enclave or the Android Keystore.
+    ///
+    /// The private key is only used through [`SecretKey::diffie_hellman`],
+    /// which can delegate the operation to the keystore, whose errors are
+    /// returned as [`InternalError::Custom`]. The registration must have
+    /// been made with the public key of `client_static_keypair` in
+    /// [`ClientRegistrationFinishParameters::external_client_s_pk`].
+    ///
+    /// The password still opens the envelope, which authenticates the
+    /// server and yields the export key, but the server authenticates the
+    /// client by the keystore key.
+    pub fn finish_with_key<S: SecretKey<CS::KeGroup>>(
+        self,
+        credential_response: CredentialResponse<CS>,
+        params: ClientLoginFinishParameters<CS>,
+        client_static_keypair: &KeyPair<CS::KeGroup, S>,
+    ) -> Result<ClientLoginFinishResult<CS>, ProtocolError<S::Error>> {
+        observer::notify_login(
+            self.finish_unobserved(
+                credential_response,
+                params,
+                None,
+                Some(client_static_keypair),
+            )
+            .map(|(result, _)| result),
+            ProtocolEvent::ClientLoginSucceeded,
+            ProtocolEvent::ClientLoginMacFailed,
+        )
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn open_with_upgrade(
+        &self,
+        credential_response: &CredentialResponse<CS>,
+        params: &ClientLoginFinishParameters<CS>,
+        upgrade_slow_hash: Option<&CS::SlowHash>,
+        external_client_s_pk: Option<&PublicKey<CS::KeGroup>>,
+    ) -> Result<
+        (
+            PublicKey<CS::KeGroup>,
+            OpenedEnvelope<CS>,
+            Option<(Hkdf<CS::Hash>, EnvelopeMode, Option<KeyPair<CS::KeGroup>>)>,
+        ),
+        ProtocolError,
+    > {
         // Check if beta value from server is equal to alpha value from client
         let credential_request =
             CredentialRequest::<CS>::deserialize(&self.serialized_credential_request[..])?;
@@ -443,33 +1427,148 @@ impl<CS: CipherSuite> ClientLogin<CS> {
             return Err(ProtocolError::ReflectedValueError);
         }
 
-        let (_, randomized_pwd_hasher) = get_password_derived_key::<CS>(
-            self.oprf_client.clone(),
-            credential_response.evaluation_element.clone(),
-            params.slow_hash,
+        let randomized_pwd_hasher = self.stretch(credential_response, params, params.slow_hash)?;
+        let (server_s_pk, envelope_mode, opened_envelope) = self.open(
+            credential_response,
+            params,
+            randomized_pwd_hasher,
+            external_client_s_pk,
         )?;
+        // The replacement is only stretched once the envelope opened, so that
+        // a wrong password costs a single slow hash
+        let upgrade = match upgrade_slow_hash {
+            Some(upgrade_slow_hash) => {
+                let upgrade_hasher =
+                    self.stretch(credential_response, params, Some(upgrade_slow_hash))?;
+                // A keypair stored in the envelope is kept by its replacement
+                let client_static_keypair = if envelope_mode == EnvelopeMode::Internal {
+                    None
+                } else {
+                    Some(opened_envelope.client_static_keypair.clone())
+                };
+                Some((upgrade_hasher, envelope_mode, client_static_keypair))
+            }
+            None => None,
+        };
+        Ok((server_s_pk, opened_envelope, upgrade))
+    }
 
-        let mut masking_key = vec![0u8; <CS::Hash as Digest>::OutputSize::USIZE];
-        randomized_pwd_hasher
-            .expand(STR_MASKING_KEY, &mut masking_key)
-            .map_err(|_| InternalError::HkdfError)?;
+    #[allow(clippy::type_complexity)]
+    fn finish_unobserved<S: SecretKey<CS::KeGroup>>(
+        self,
+        credential_response: CredentialResponse<CS>,
+        params: ClientLoginFinishParameters<CS>,
+        upgrade_slow_hash: Option<&CS::SlowHash>,
+        client_static_keypair: Option<&KeyPair<CS::KeGroup, S>>,
+    ) -> Result<
+        (
+            ClientLoginFinishResult<CS>,
+            Option<(Hkdf<CS::Hash>, EnvelopeMode, Option<KeyPair<CS::KeGroup>>)>,
+        ),
+        ProtocolError<S::Error>,
+    > {
+        self.finished.claim()?;
+        let (server_s_pk, opened_envelope, upgrade) = self
+            .open_with_upgrade(
+                &credential_response,
+                &params,
+                upgrade_slow_hash,
+                client_static_keypair.map(KeyPair::public),
+            )
+            .map_err(ProtocolError::into_custom)?;
 
-        let (server_s_pk, envelope) = unmask_response::<CS>(
-            &masking_key,
+        let credential_response_component = CredentialResponse::<CS>::serialize_without_ke(
+            &credential_response.evaluation_element.value(),
             &credential_response.masking_nonce,
             &credential_response.masked_response,
-        )
-        .map_err(|e| match e {
-            ProtocolError::SerializationError => ProtocolError::InvalidLoginError,
-            err => err,
-        })?;
+            &credential_response.key_id,
+        );
+        let key_id = credential_response.key_id.clone();
+
+        let context = transcript_context::<CS>(params.context.unwrap_or_default());
+        let result = match client_static_keypair {
+            Some(client_static_keypair) => CS::KeyExchange::generate_ke3(
+                credential_response_component,
+                credential_response.ke2_message,
+                &self.ke1_state,
+                &self.serialized_credential_request,
+                server_s_pk.clone(),
+                client_static_keypair.private().clone(),
+                opened_envelope.id_u.clone(),
+                opened_envelope.id_s.clone(),
+                context,
+                &CS::KEY_EXCHANGE_LABELS,
+            )?,
+            None => CS::KeyExchange::generate_ke3(
+                credential_response_component,
+                credential_response.ke2_message,
+                &self.ke1_state,
+                &self.serialized_credential_request,
+                server_s_pk.clone(),
+                opened_envelope.client_static_keypair.private().clone(),
+                opened_envelope.id_u.clone(),
+                opened_envelope.id_s.clone(),
+                context,
+                &CS::KEY_EXCHANGE_LABELS,
+            )
+            .map_err(ProtocolError::into_custom)?,
+        };
+
+        let finish_result = ClientLoginFinishResult {
+            message: CredentialFinalization {
+                ke3_message: result.1,
+            },
+            session_key: SessionKey::derive::<CS>(result.0).map_err(ProtocolError::into_custom)?,
+            export_key: opened_envelope.export_key.map(ExportKey),
+            server_s_pk,
+            key_id,
+            transcript_hash: result.2,
+            upgrade: None,
+            #[cfg(test)]
+            state: self,
+            #[cfg(test)]
+            handshake_secret: result.3,
+            #[cfg(test)]
+            client_mac_key: result.4,
+        };
+        Ok((finish_result, upgrade))
+    }
+
+    fn stretch(
+        &self,
+        credential_response: &CredentialResponse<CS>,
+        params: &ClientLoginFinishParameters<CS>,
+        slow_hash: Option<&CS::SlowHash>,
+    ) -> Result<Hkdf<CS::Hash>, ProtocolError> {
+        let (_, randomized_pwd_hasher) = get_password_derived_key::<CS>(
+            &self.oprf_client,
+            credential_response.evaluation_element.clone(),
+            slow_hash,
+            params.slow_hash_salt,
+            params.progress,
+            params.cancellation,
+        )?;
+        Ok(randomized_pwd_hasher)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn open(
+        &self,
+        credential_response: &CredentialResponse<CS>,
+        params: &ClientLoginFinishParameters<CS>,
+        randomized_pwd_hasher: Hkdf<CS::Hash>,
+        external_client_s_pk: Option<&PublicKey<CS::KeGroup>>,
+    ) -> Result<(PublicKey<CS::KeGroup>, EnvelopeMode, OpenedEnvelope<CS>), ProtocolError> {
+        let (server_s_pk, envelope) = unmask::<CS>(credential_response, &randomized_pwd_hasher)?;
         let server_s_pk_bytes = server_s_pk.to_arr().to_vec();
 
-        let opened_envelope = &envelope
+        let opened_envelope = envelope
             .open(
                 randomized_pwd_hasher,
                 &server_s_pk_bytes,
                 &params.identifiers,
+                external_client_s_pk,
+                params.export_key,
             )
             .map_err(|e| match e {
                 ProtocolError::LibraryError(InternalError::SealOpenHmacError) => {
@@ -478,37 +1577,81 @@ impl<CS: CipherSuite> ClientLogin<CS> {
                 err => err,
             })?;
 
-        let credential_response_component = CredentialResponse::<CS>::serialize_without_ke(
-            &credential_response.evaluation_element.value(),
-            &credential_response.masking_nonce,
-            &credential_response.masked_response,
-        );
+        Ok((server_s_pk, envelope.mode()?, opened_envelope))
+    }
+}
 
-        let result = CS::KeyExchange::generate_ke3(
-            credential_response_component,
-            credential_response.ke2_message,
-            &self.ke1_state,
-            &self.serialized_credential_request,
-            server_s_pk.clone(),
-            opened_envelope.client_static_keypair.private().clone(),
-            opened_envelope.id_u.clone(),
-            opened_envelope.id_s.clone(),
-            params.context.unwrap_or_default(),
-        )?;
+#[cfg(feature = "envelope-inspect")]
+impl<CS: CipherSuite> ClientLogin<CS> {
+    /// Decodes the envelope of the registration record carried by
+    /// `credential_response`, for auditing and debugging registration
+    /// records
+    ///
+    /// The password bound to this state unmasks the envelope and checks its
+    /// MAC, whose outcome is reported in [`EnvelopeInfo::authenticated`]
+    /// rather than as an error. This runs the slow hash once more, and
+    /// leaves the state to be finished as usual.
+    pub fn inspect_envelope(
+        &self,
+        credential_response: &CredentialResponse<CS>,
+        params: &ClientLoginFinishParameters<CS>,
+    ) -> Result<EnvelopeInfo, ProtocolError> {
+        let randomized_pwd_hasher = self.stretch(credential_response, params, params.slow_hash)?;
+        let (server_s_pk, envelope) = unmask::<CS>(credential_response, &randomized_pwd_hasher)?;
+        envelope.inspect(
+            randomized_pwd_hasher,
+            &server_s_pk.to_arr(),
+            &params.identifiers,
+        )
+    }
+}
 
-        Ok(ClientLoginFinishResult {
-            message: CredentialFinalization {
-                ke3_message: result.1,
+impl<CS: CipherSuite> ClientLoginPrecomputation<CS> {
+    /// Binds the password to the precomputed blind and ephemeral key pair,
+    /// returning the same as [`ClientLogin::start`]
+    ///
+    /// Fails with [`InternalError::MissingCredentialIdentifierError`] if
+    /// [`CipherSuite::BIND_CREDENTIAL_IDENTIFIER`] is set, in which case
+    /// [`start_with_identifier`](Self::start_with_identifier) must be used.
+    pub fn start(self, password: &[u8]) -> Result<ClientLoginStartResult<CS>, ProtocolError> {
+        if CS::BIND_CREDENTIAL_IDENTIFIER {
+            return Err(InternalError::MissingCredentialIdentifierError.into());
+        }
+
+        self.start_with_oprf_input(password)
+    }
+
+    /// Same as [`start`](Self::start), but binds `credential_identifier` into
+    /// the OPRF input, as [`ClientLogin::start_with_identifier`] does
+    pub fn start_with_identifier(
+        self,
+        password: &[u8],
+        credential_identifier: &[u8],
+    ) -> Result<ClientLoginStartResult<CS>, ProtocolError> {
+        let oprf_input = bind_credential_identifier(credential_identifier, password)?;
+        self.start_with_oprf_input(&oprf_input)
+    }
+
+    fn start_with_oprf_input(
+        self,
+        password: &[u8],
+    ) -> Result<ClientLoginStartResult<CS>, ProtocolError> {
+        let blind_result = blind_with::<CS>(self.blind, password)?;
+
+        let credential_request = CredentialRequest {
+            blinded_element: blind_result.message,
+            ke1_message: self.ke1_message.clone(),
+        };
+        let serialized_credential_request = credential_request.to_vec();
+
+        Ok(ClientLoginStartResult {
+            message: credential_request,
+            state: ClientLogin {
+                oprf_client: blind_result.state,
+                ke1_state: self.ke1_state.clone(),
+                serialized_credential_request,
+                finished: FinishGuard::default(),
             },
-            session_key: result.0,
-            export_key: opened_envelope.export_key.clone(),
-            server_s_pk,
-            #[cfg(test)]
-            state: self,
-            #[cfg(test)]
-            handshake_secret: result.2,
-            #[cfg(test)]
-            client_mac_key: result.3,
         })
     }
 }
@@ -516,50 +1659,184 @@ impl<CS: CipherSuite> ClientLogin<CS> {
 impl<CS: CipherSuite> ServerLogin<CS> {
     /// Serialization into bytes
     pub fn serialize(&self) -> Result<Vec<u8>, ProtocolError> {
-        Ok(self.ke2_state.to_bytes())
+        Ok([
+            &[SERVER_LOGIN_STATE_VERSION][..],
+            &suite_tag::<CS>()?,
+            &self.ke2_state.to_bytes(),
+        ]
+        .concat())
     }
 
-    /// Deserialization from bytes
+    /// Deserialization from bytes, failing with
+    /// [`ProtocolError::VersionMismatchError`] if the state is of an unknown
+    /// version and with [`ProtocolError::SerializationError`] if it was
+    /// produced under another ciphersuite
     pub fn deserialize(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let checked_bytes = check_slice_size_atleast(bytes, 1 + SUITE_TAG_LEN, "server_login")?;
+        let (header, ke2_state) = checked_bytes.split_at(1 + SUITE_TAG_LEN);
+        if header[0] != SERVER_LOGIN_STATE_VERSION {
+            return Err(ProtocolError::VersionMismatchError);
+        }
+        if header[1..] != suite_tag::<CS>()? {
+            return Err(ProtocolError::SerializationError);
+        }
+
         Ok(Self {
             _cs: PhantomData,
             ke2_state:
                 <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::KE2State::from_bytes::<CS>(
-                    bytes,
+                    ke2_state,
                 )?,
+            finished: FinishGuard::default(),
         })
     }
 
     /// From the client's "blinded" password, returns a challenge to be
     /// sent back to the client, as well as a ServerLogin
-    pub fn start<R: RngCore + CryptoRng, S: SecretKey<CS::KeGroup>>(
+    pub fn start<R: RngCore + CryptoRng + ?Sized, S: SecretKey<CS::KeGroup>>(
+        rng: &mut R,
+        server_setup: &ServerSetup<CS, S>,
+        password_file: Option<ServerRegistration<CS>>,
+        credential_request: CredentialRequest<CS>,
+        credential_identifier: &[u8],
+        params: ServerLoginStartParameters,
+    ) -> Result<ServerLoginStartResult<CS>, ProtocolError<S::Error>> {
+        Self::start_with_ephemeral_keypair(
+            rng,
+            server_setup,
+            password_file,
+            credential_request,
+            credential_identifier,
+            params,
+            None,
+        )
+    }
+
+    /// Same as [`start`](Self::start), but takes the server's ephemeral key
+    /// pair from `key_pool` instead of generating it, unless the pool has run
+    /// dry or [`ServerLoginStartParameters::retransmission_nonce`] is set
+    #[cfg(feature = "std")]
+    pub fn start_with_key_pool<R: RngCore + CryptoRng + ?Sized, S: SecretKey<CS::KeGroup>>(
+        rng: &mut R,
+        key_pool: &EphemeralKeyPool<CS::KeGroup>,
+        server_setup: &ServerSetup<CS, S>,
+        password_file: Option<ServerRegistration<CS>>,
+        credential_request: CredentialRequest<CS>,
+        credential_identifier: &[u8],
+        params: ServerLoginStartParameters,
+    ) -> Result<ServerLoginStartResult<CS>, ProtocolError<S::Error>> {
+        // The retransmitted response must derive the same ephemeral key pair
+        let server_e_kp = if params.retransmission_nonce.is_some() {
+            None
+        } else {
+            key_pool.take()
+        };
+
+        Self::start_with_ephemeral_keypair(
+            rng,
+            server_setup,
+            password_file,
+            credential_request,
+            credential_identifier,
+            params,
+            server_e_kp,
+        )
+    }
+
+    fn start_with_ephemeral_keypair<R: RngCore + CryptoRng + ?Sized, S: SecretKey<CS::KeGroup>>(
         rng: &mut R,
         server_setup: &ServerSetup<CS, S>,
         password_file: Option<ServerRegistration<CS>>,
         credential_request: CredentialRequest<CS>,
         credential_identifier: &[u8],
         params: ServerLoginStartParameters,
+        server_e_kp: Option<KeyPair<CS::KeGroup>>,
+    ) -> Result<ServerLoginStartResult<CS>, ProtocolError<S::Error>> {
+        if let Some((puzzle, solution)) = &params.puzzle {
+            puzzle
+                .verify(&credential_request, *solution)
+                .map_err(ProtocolError::into_custom)?;
+        }
+
+        if let Some(retransmission_nonce) = &params.retransmission_nonce {
+            let retransmission_nonce = check_slice_size(
+                retransmission_nonce,
+                RETRANSMISSION_NONCE_LEN,
+                "retransmission_nonce",
+            )
+            .map_err(|e| ProtocolError::LibraryError(e.into_custom()))?;
+            let seed = retransmission_seed::<CS>(
+                &server_setup.oprf_seed,
+                credential_identifier,
+                &credential_request,
+                retransmission_nonce,
+            )
+            .map_err(ProtocolError::into_custom)?;
+            Self::start_with_rng(
+                &mut SeedRng::<CS>::new(&seed, STR_RETRANSMISSION_SEED),
+                server_setup,
+                password_file,
+                credential_request,
+                credential_identifier,
+                params,
+                server_e_kp,
+            )
+        } else {
+            Self::start_with_rng(
+                &mut &mut *rng,
+                server_setup,
+                password_file,
+                credential_request,
+                credential_identifier,
+                params,
+                server_e_kp,
+            )
+        }
+    }
+
+    fn start_with_rng<R: RngCore + CryptoRng, S: SecretKey<CS::KeGroup>>(
+        rng: &mut R,
+        server_setup: &ServerSetup<CS, S>,
+        password_file: Option<ServerRegistration<CS>>,
+        credential_request: CredentialRequest<CS>,
+        credential_identifier: &[u8],
+        params: ServerLoginStartParameters,
+        server_e_kp: Option<KeyPair<CS::KeGroup>>,
     ) -> Result<ServerLoginStartResult<CS>, ProtocolError<S::Error>> {
         let record = match password_file {
-            Some(x) => x,
-            None => ServerRegistration::dummy(rng, server_setup),
+            Some(x) => {
+                audit::record(AuditEvent::LoginStarted {
+                    credential_identifier,
+                });
+                x
+            }
+            None => {
+                audit::record(AuditEvent::DummyRecordServed {
+                    credential_identifier,
+                });
+                let slow_hash_id = dummy_slow_hash_id::<CS>(
+                    &server_setup.oprf_seed,
+                    credential_identifier,
+                    &params.dummy_slow_hash_ids,
+                )
+                .map_err(ProtocolError::into_custom)?;
+                ServerRegistration::dummy(rng, server_setup)
+                    .map_err(InternalError::into_custom)?
+                    .with_slow_hash_id(slow_hash_id)
+            }
         };
+        let slow_hash_id = record.2;
 
         let client_s_pk = record.0.client_s_pk.clone();
 
-        let (context, optional_ids) = match params {
-            ServerLoginStartParameters::WithContext(context) => (context, None),
-            ServerLoginStartParameters::WithIdentifiers(ids) => (Vec::new(), Some(ids)),
-            ServerLoginStartParameters::WithContextAndIdentifiers(context, ids) => {
-                (context, Some(ids))
-            }
-        };
+        let context = params.context.unwrap_or_default();
+        let optional_ids = params.identifiers;
 
         let server_s_sk = server_setup.keypair.private();
         let server_s_pk = server_s_sk.public_key()?;
 
         let mut masking_nonce = vec![0u8; 32];
-        rng.fill_bytes(&mut masking_nonce);
+        fill_random(rng, &mut masking_nonce).map_err(InternalError::into_custom)?;
 
         let masked_response = mask_response(
             &record.0.masking_key,
@@ -576,15 +1853,12 @@ impl<CS: CipherSuite> ServerLogin<CS> {
         )
         .map_err(ProtocolError::into_custom)?;
 
-        let credential_request_bytes = credential_request
-            .serialize()
-            .map_err(ProtocolError::into_custom)?;
+        let credential_request_bytes = credential_request.to_vec();
 
-        let oprf_key = oprf_key_from_seed::<CS::OprfGroup, CS::Hash>(
-            &server_setup.oprf_seed,
-            credential_identifier,
-        )
-        .map_err(ProtocolError::into_custom)?;
+        let oprf_key = Zeroizing::new(
+            oprf_key_from_seed::<CS>(&server_setup.oprf_seed, credential_identifier)
+                .map_err(ProtocolError::into_custom)?,
+        );
         let server = voprf::NonVerifiableServer::new_with_key(&oprf_key)
             .map_err(|e| ProtocolError::into_custom(e.into()))?;
         let evaluate_result = server
@@ -592,12 +1866,25 @@ impl<CS: CipherSuite> ServerLogin<CS> {
             .map_err(|e| ProtocolError::into_custom(e.into()))?;
         let evaluation_element = evaluate_result.message;
 
+        let key_id = match params.key_id {
+            Some(key_id) => check_slice_size(&key_id, CS::KEY_ID_LEN, "key_id")
+                .map_err(|e| ProtocolError::LibraryError(e.into_custom()))?
+                .to_vec(),
+            None => key_id_from_public_key::<CS>(&server_s_pk),
+        };
+
         let credential_response_component = CredentialResponse::<CS>::serialize_without_ke(
             &evaluation_element.value(),
             &masking_nonce,
             &masked_response,
+            &key_id,
         );
 
+        let server_e_kp = match server_e_kp {
+            Some(server_e_kp) => server_e_kp,
+            None => KeyPair::generate_random(rng).map_err(InternalError::into_custom)?,
+        };
+
         let result = CS::KeyExchange::generate_ke2(
             rng,
             credential_request_bytes,
@@ -605,15 +1892,18 @@ impl<CS: CipherSuite> ServerLogin<CS> {
             credential_request.ke1_message,
             client_s_pk,
             server_s_sk.clone(),
+            server_e_kp,
             id_u,
             id_s,
-            context,
+            transcript_context::<CS>(context),
+            &CS::KEY_EXCHANGE_LABELS,
         )?;
 
         let credential_response = CredentialResponse {
             evaluation_element,
             masking_nonce,
             masked_response,
+            key_id,
             ke2_message: result.1,
         };
 
@@ -622,7 +1912,9 @@ impl<CS: CipherSuite> ServerLogin<CS> {
             state: Self {
                 _cs: PhantomData,
                 ke2_state: result.0,
+                finished: FinishGuard::default(),
             },
+            slow_hash_id,
             #[cfg(test)]
             handshake_secret: result.2,
             #[cfg(test)]
@@ -638,13 +1930,30 @@ impl<CS: CipherSuite> ServerLogin<CS> {
         self,
         message: CredentialFinalization<CS>,
     ) -> Result<ServerLoginFinishResult<CS>, ProtocolError> {
-        let session_key = <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::finish_ke(
+        self.finished.claim()?;
+        let result = <CS::KeyExchange as KeyExchange<CS::Hash, CS::KeGroup>>::finish_ke(
             message.ke3_message,
             &self.ke2_state,
+            &CS::KEY_EXCHANGE_LABELS,
+        );
+        audit::record(match &result {
+            Ok(_) => AuditEvent::LoginSucceeded,
+            Err(ProtocolError::InvalidLoginError) => AuditEvent::LoginFailed {
+                reason: LoginFailureReason::BadMac,
+            },
+            Err(_) => AuditEvent::LoginFailed {
+                reason: LoginFailureReason::InvalidMessage,
+            },
+        });
+        let (session_key, transcript_hash) = observer::notify_login(
+            result,
+            ProtocolEvent::ServerLoginSucceeded,
+            ProtocolEvent::ServerLoginMacFailed,
         )?;
 
         Ok(ServerLoginFinishResult {
-            session_key,
+            session_key: SessionKey::derive::<CS>(session_key)?,
+            transcript_hash,
             _cs: PhantomData,
             #[cfg(test)]
             state: self,
@@ -652,13 +1961,40 @@ impl<CS: CipherSuite> ServerLogin<CS> {
     }
 }
 
+#[cfg(feature = "rayon")]
+impl<CS: CipherSuite> ServerLogin<CS>
+where
+    ServerLogin<CS>: Send,
+    CredentialFinalization<CS>: Send,
+    ServerLoginFinishResult<CS>: Send,
+{
+    /// Runs [`ServerLogin::finish`] on each pair of state and message
+    /// produced by `logins` in parallel, e.g. when many clients reconnect at
+    /// once. At most `batch_size` logins are held in memory at a time, and
+    /// each result is passed to `sink` in the order of `logins`, so that a
+    /// failed MAC only fails its own login
+    pub fn finish_many<I, F>(logins: I, batch_size: usize, sink: F)
+    where
+        I: IntoIterator<Item = (Self, CredentialFinalization<CS>)>,
+        F: FnMut(Result<ServerLoginFinishResult<CS>, ProtocolError>),
+    {
+        par_batched(
+            logins,
+            batch_size,
+            |_| (),
+            |_, (state, message)| state.finish(message),
+            sink,
+        )
+    }
+}
+
 /////////////////////////
 // Convenience Structs //
 //==================== //
 /////////////////////////
 
 /// Options for specifying custom identifiers
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum Identifiers {
     /// Supply only a client identifier
     ClientIdentifier(Vec<u8>),
@@ -675,6 +2011,37 @@ pub struct ClientRegistrationFinishParameters<'h, CS: CipherSuite> {
     pub identifiers: Option<Identifiers>,
     /// Specifying a configuration for the slow hash
     pub slow_hash: Option<&'h CS::SlowHash>,
+    /// Specifying a callback reporting the progress of the slow hash, at
+    /// the granularity described in [`ProgressCallback`]
+    pub progress: Option<ProgressCallback<'h>>,
+    /// Specifying a token which can be used to cancel the slow hash,
+    /// between the steps described in [`CancellationToken`]
+    pub cancellation: Option<&'h CancellationToken>,
+    /// Specifying how the envelope stores the client's static keypair,
+    /// overriding [`CipherSuite::ENVELOPE_MODE`]. The mode is recorded in the
+    /// envelope, so that login needs no matching parameter. Only a mode whose
+    /// envelope is no longer than that of the default mode is accepted, see
+    /// [`EnvelopeMode`].
+    pub envelope_mode: Option<EnvelopeMode>,
+    /// Specifying a seed from which the client's static keypair is derived,
+    /// see [`ClientRegistration::keypair_from_seed`]. Only an envelope mode
+    /// which stores the keypair, such as `EnvelopeMode::Aead`, accepts a
+    /// seed.
+    pub client_seed: Option<&'h [u8]>,
+    /// Specifying the public key of a static keypair which the client holds
+    /// outside of the envelope, e.g. in a platform keystore, see
+    /// [`ClientLogin::finish_with_key`]. Only `EnvelopeMode::Internal`
+    /// accepts it, and it cannot be combined with a seed.
+    pub external_client_s_pk: Option<PublicKey<CS::KeGroup>>,
+    /// Specifying that the export key is derived and returned in
+    /// [`ClientRegistrationFinishResult::export_key`], which it is not by
+    /// default
+    pub export_key: bool,
+    /// Specifying an application salt which is mixed into the input of the
+    /// slow hash, so that the stretched values of a password differ between
+    /// applications. Login must pass the same salt in
+    /// [`ClientLoginFinishParameters::slow_hash_salt`].
+    pub slow_hash_salt: Option<&'h [u8]>,
 }
 
 impl<'h, CS: CipherSuite> Default for ClientRegistrationFinishParameters<'h, CS> {
@@ -682,6 +2049,13 @@ impl<'h, CS: CipherSuite> Default for ClientRegistrationFinishParameters<'h, CS>
         Self {
             identifiers: None,
             slow_hash: None,
+            progress: None,
+            cancellation: None,
+            envelope_mode: None,
+            client_seed: None,
+            external_client_s_pk: None,
+            export_key: false,
+            slow_hash_salt: None,
         }
     }
 }
@@ -692,6 +2066,13 @@ impl<'h, CS: CipherSuite> ClientRegistrationFinishParameters<'h, CS> {
         Self {
             identifiers,
             slow_hash,
+            progress: None,
+            cancellation: None,
+            envelope_mode: None,
+            client_seed: None,
+            external_client_s_pk: None,
+            export_key: false,
+            slow_hash_salt: None,
         }
     }
 }
@@ -714,12 +2095,27 @@ impl<CS: CipherSuite> Clone for ClientRegistrationStartResult<CS> {
     }
 }
 
+impl<CS: CipherSuite> core::fmt::Debug for ClientRegistrationStartResult<CS>
+where
+    RegistrationRequest<CS>: core::fmt::Debug,
+    ClientRegistration<CS>: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ClientRegistrationStartResult")
+            .field("message", &self.message)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
 /// Contains the fields that are returned by a client registration finish
 pub struct ClientRegistrationFinishResult<CS: CipherSuite> {
     /// The registration upload message to be sent to the server
     pub message: RegistrationUpload<CS>,
-    /// The export key output by client registration
-    pub export_key: GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
+    /// The export key output by client registration, of
+    /// [`CipherSuite::EXPORT_KEY_LEN`] bytes, if requested with
+    /// [`ClientRegistrationFinishParameters::export_key`]
+    pub export_key: Option<ExportKey>,
     /// The server's static public key
     pub server_s_pk: PublicKey<CS::KeGroup>,
     /// Instance of the ClientRegistration, only used in tests for checking zeroize
@@ -733,20 +2129,31 @@ pub struct ClientRegistrationFinishResult<CS: CipherSuite> {
     pub randomized_pwd: GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
 }
 
-// Cannot be derived because it would require for CS to be Clone.
-impl<CS: CipherSuite> Clone for ClientRegistrationFinishResult<CS> {
-    fn clone(&self) -> Self {
-        Self {
-            message: self.message.clone(),
-            export_key: self.export_key.clone(),
-            server_s_pk: self.server_s_pk.clone(),
-            #[cfg(test)]
-            state: self.state.clone(),
-            #[cfg(test)]
-            auth_key: self.auth_key.clone(),
-            #[cfg(test)]
-            randomized_pwd: self.randomized_pwd.clone(),
-        }
+impl<CS: CipherSuite> ClientRegistrationFinishResult<CS> {
+    /// Derives the pseudonym of the client for the service named by
+    /// `service_identifier` from the export key, as
+    /// [`ClientLoginFinishResult::pseudonym`] does on login
+    pub fn pseudonym(
+        &self,
+        service_identifier: &[u8],
+    ) -> Result<GenericArray<u8, <CS::Hash as Digest>::OutputSize>, ProtocolError> {
+        derive_pseudonym::<CS>(
+            self.export_key
+                .as_ref()
+                .ok_or(InternalError::ExportKeyNotRequestedError)?
+                .expose_secret(),
+            service_identifier,
+        )
+    }
+}
+
+impl<CS: CipherSuite> core::fmt::Debug for ClientRegistrationFinishResult<CS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ClientRegistrationFinishResult")
+            .field("message", &self.message)
+            .field("export_key", &self.export_key)
+            .field("server_s_pk", &self.server_s_pk)
+            .finish()
     }
 }
 
@@ -771,6 +2178,17 @@ impl<CS: CipherSuite> Clone for ServerRegistrationStartResult<CS> {
     }
 }
 
+impl<CS: CipherSuite> core::fmt::Debug for ServerRegistrationStartResult<CS>
+where
+    RegistrationResponse<CS>: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ServerRegistrationStartResult")
+            .field("message", &self.message)
+            .finish()
+    }
+}
+
 /// Contains the fields that are returned by a client login start
 pub struct ClientLoginStartResult<CS: CipherSuite> {
     /// The message to send to the server to begin the login protocol
@@ -789,6 +2207,19 @@ impl<CS: CipherSuite> Clone for ClientLoginStartResult<CS> {
     }
 }
 
+impl<CS: CipherSuite> core::fmt::Debug for ClientLoginStartResult<CS>
+where
+    CredentialRequest<CS>: core::fmt::Debug,
+    ClientLogin<CS>: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ClientLoginStartResult")
+            .field("message", &self.message)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
 /// Optional parameters for client login finish
 #[derive(Clone)]
 pub struct ClientLoginFinishParameters<'h, CS: CipherSuite> {
@@ -798,6 +2229,18 @@ pub struct ClientLoginFinishParameters<'h, CS: CipherSuite> {
     pub identifiers: Option<Identifiers>,
     /// Specifying a configuration for the slow hash
     pub slow_hash: Option<&'h CS::SlowHash>,
+    /// Specifying a callback reporting the progress of the slow hash, at
+    /// the granularity described in [`ProgressCallback`]
+    pub progress: Option<ProgressCallback<'h>>,
+    /// Specifying a token which can be used to cancel the slow hash,
+    /// between the steps described in [`CancellationToken`]
+    pub cancellation: Option<&'h CancellationToken>,
+    /// Specifying that the export key is derived and returned in
+    /// [`ClientLoginFinishResult::export_key`], which it is not by default
+    pub export_key: bool,
+    /// Specifying the application salt which was passed in
+    /// [`ClientRegistrationFinishParameters::slow_hash_salt`]
+    pub slow_hash_salt: Option<&'h [u8]>,
 }
 
 impl<'h, CS: CipherSuite> Default for ClientLoginFinishParameters<'h, CS> {
@@ -806,6 +2249,10 @@ impl<'h, CS: CipherSuite> Default for ClientLoginFinishParameters<'h, CS> {
             context: None,
             identifiers: None,
             slow_hash: None,
+            progress: None,
+            cancellation: None,
+            export_key: false,
+            slow_hash_salt: None,
         }
     }
 }
@@ -821,7 +2268,92 @@ impl<'h, CS: CipherSuite> ClientLoginFinishParameters<'h, CS> {
             context,
             identifiers,
             slow_hash,
+            progress: None,
+            cancellation: None,
+            export_key: false,
+            slow_hash_salt: None,
+        }
+    }
+}
+
+/// The session key established by a successful login, which is zeroized on
+/// drop and compared in constant time.
+///
+/// It deliberately implements neither `Clone` nor `Deref`, and its `Debug`
+/// output only shows its length, so that the key does not get copied or
+/// logged by accident. Its bytes are accessed with
+/// [expose_secret](Self::expose_secret).
+#[derive(Zeroize)]
+#[zeroize(drop)]
+pub struct SessionKey(Vec<u8>);
+
+impl SessionKey {
+    // The key exchange outputs a session key of the hash output size, which
+    // is only expanded again when the ciphersuite asks for another length
+    fn derive<CS: CipherSuite>(mut session_key: Vec<u8>) -> Result<Self, ProtocolError> {
+        if CS::SESSION_KEY_LEN != <CS::Hash as Digest>::OutputSize::USIZE {
+            let mut okm = vec![0u8; CS::SESSION_KEY_LEN];
+            Hkdf::<CS::Hash>::from_prk(&session_key)
+                .map_err(|_| InternalError::HkdfError)?
+                .expand_multi_info(&[CS::CONTEXT, STR_SESSION_KEY], &mut okm)
+                .map_err(|_| InternalError::HkdfError)?;
+            session_key.zeroize();
+            session_key = okm;
         }
+        Ok(Self(session_key))
+    }
+
+    /// The bytes of the session key
+    pub fn expose_secret(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq for SessionKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+impl Eq for SessionKey {}
+
+impl core::fmt::Debug for SessionKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("SessionKey")
+            .field(&Redacted(self.0.len()))
+            .finish()
+    }
+}
+
+/// The export key of a registration, output by both client registration and
+/// client login, which is zeroized on drop and compared in constant time.
+///
+/// Like [SessionKey], it implements neither `Clone` nor `Deref`, and its
+/// bytes are accessed with [expose_secret](Self::expose_secret).
+#[derive(Zeroize)]
+#[zeroize(drop)]
+pub struct ExportKey(Vec<u8>);
+
+impl ExportKey {
+    /// The bytes of the export key
+    pub fn expose_secret(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq for ExportKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+impl Eq for ExportKey {}
+
+impl core::fmt::Debug for ExportKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("ExportKey")
+            .field(&Redacted(self.0.len()))
+            .finish()
     }
 }
 
@@ -829,12 +2361,23 @@ impl<'h, CS: CipherSuite> ClientLoginFinishParameters<'h, CS> {
 pub struct ClientLoginFinishResult<CS: CipherSuite> {
     /// The message to send to the server to complete the protocol
     pub message: CredentialFinalization<CS>,
-    /// The session key
-    pub session_key: Vec<u8>,
-    /// The client-side export key
-    pub export_key: GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
+    /// The session key, of [`CipherSuite::SESSION_KEY_LEN`] bytes
+    pub session_key: SessionKey,
+    /// The client-side export key, of [`CipherSuite::EXPORT_KEY_LEN`] bytes,
+    /// if requested with [`ClientLoginFinishParameters::export_key`]
+    pub export_key: Option<ExportKey>,
     /// The server's static public key
     pub server_s_pk: PublicKey<CS::KeGroup>,
+    /// The identifier of the server key, of [`CipherSuite::KEY_ID_LEN`]
+    /// bytes, as authenticated by the server, e.g. to look up a pinned key
+    pub key_id: Vec<u8>,
+    /// The hash of the full handshake transcript, which is the same for the
+    /// client and the server. It can bind external artifacts, such as audit
+    /// records or tokens, to this specific handshake
+    pub transcript_hash: GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
+    /// The replacement of the registration, sealed with another slow hash,
+    /// see [`ClientLogin::finish_with_upgrade`]
+    pub upgrade: Option<RegistrationUpgrade<CS>>,
     /// Instance of the ClientLogin, only used in tests for checking zeroize
     #[cfg(test)]
     pub state: ClientLogin<CS>,
@@ -846,62 +2389,185 @@ pub struct ClientLoginFinishResult<CS: CipherSuite> {
     pub client_mac_key: GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
 }
 
-// Cannot be derived because it would require for CS to be Clone.
-impl<CS: CipherSuite> Clone for ClientLoginFinishResult<CS> {
-    fn clone(&self) -> Self {
-        Self {
-            message: self.message.clone(),
-            session_key: self.session_key.clone(),
-            export_key: self.export_key.clone(),
-            server_s_pk: self.server_s_pk.clone(),
-            #[cfg(test)]
-            state: self.state.clone(),
-            #[cfg(test)]
-            handshake_secret: self.handshake_secret.clone(),
-            #[cfg(test)]
-            client_mac_key: self.client_mac_key.clone(),
-        }
+impl<CS: CipherSuite> ClientLoginFinishResult<CS> {
+    /// Derives the pseudonym of the client for the service named by
+    /// `service_identifier` from the export key.
+    ///
+    /// The pseudonym is the same on every login with the same registration,
+    /// so that a service can recognize the client without learning any
+    /// global identifier, and pseudonyms for different services cannot be
+    /// linked to each other. Registering again yields a new export key, and
+    /// thus new pseudonyms.
+    pub fn pseudonym(
+        &self,
+        service_identifier: &[u8],
+    ) -> Result<GenericArray<u8, <CS::Hash as Digest>::OutputSize>, ProtocolError> {
+        derive_pseudonym::<CS>(
+            self.export_key
+                .as_ref()
+                .ok_or(InternalError::ExportKeyNotRequestedError)?
+                .expose_secret(),
+            service_identifier,
+        )
+    }
+}
+
+impl<CS: CipherSuite> core::fmt::Debug for ClientLoginFinishResult<CS>
+where
+    CredentialFinalization<CS>: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ClientLoginFinishResult")
+            .field("message", &self.message)
+            .field("session_key", &self.session_key)
+            .field("export_key", &self.export_key)
+            .field("server_s_pk", &self.server_s_pk)
+            .field("key_id", &self.key_id)
+            .field("transcript_hash", &self.transcript_hash)
+            .field("upgrade", &self.upgrade)
+            .finish()
+    }
+}
+
+/// A registration sealed with the slow hash given to
+/// [`ClientLogin::finish_with_upgrade`], replacing one sealed with a previous
+/// slow hash
+pub struct RegistrationUpgrade<CS: CipherSuite> {
+    /// The message to send to the server over the authenticated session,
+    /// which the server stores in place of the previous registration with
+    /// [`ServerRegistration::finish`]
+    pub message: RegistrationUpload<CS>,
+    /// The export key of the new registration, of
+    /// [`CipherSuite::EXPORT_KEY_LEN`] bytes, which replaces the one of the
+    /// previous registration once the server has stored the upgrade. It is
+    /// only derived if requested with
+    /// [`ClientLoginFinishParameters::export_key`].
+    pub export_key: Option<ExportKey>,
+}
+
+impl<CS: CipherSuite> core::fmt::Debug for RegistrationUpgrade<CS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RegistrationUpgrade")
+            .field("message", &self.message)
+            .field("export_key", &self.export_key)
+            .finish()
+    }
+}
+
+impl<CS: CipherSuite> RegistrationUpgrade<CS> {
+    fn seal<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        randomized_pwd_hasher: Hkdf<CS::Hash>,
+        server_s_pk: &PublicKey<CS::KeGroup>,
+        identifiers: Option<Identifiers>,
+        envelope_mode: EnvelopeMode,
+        client_static_keypair: Option<KeyPair<CS::KeGroup>>,
+        export_key: bool,
+    ) -> Result<Self, ProtocolError> {
+        let mut masking_key = GenericArray::<u8, <CS::Hash as Digest>::OutputSize>::default();
+        randomized_pwd_hasher
+            .expand_multi_info(
+                &[CS::CONTEXT, CS::ENVELOPE_LABELS.masking_key],
+                &mut masking_key,
+            )
+            .map_err(|_| InternalError::HkdfError)?;
+
+        let result = Envelope::<CS>::seal(
+            rng,
+            randomized_pwd_hasher,
+            &server_s_pk.to_arr(),
+            identifiers,
+            envelope_mode,
+            client_static_keypair.map_or(ClientStaticKey::Default, ClientStaticKey::Stored),
+            export_key,
+        )?;
+
+        Ok(Self {
+            message: RegistrationUpload {
+                envelope: result.0,
+                masking_key,
+                client_s_pk: result.1,
+            },
+            export_key: result.2.map(ExportKey),
+        })
     }
 }
 
 /// Contains the fields that are returned by a server login finish
 pub struct ServerLoginFinishResult<CS: CipherSuite> {
-    /// The session key between client and server
-    pub session_key: Vec<u8>,
+    /// The session key between client and server, of
+    /// [`CipherSuite::SESSION_KEY_LEN`] bytes
+    pub session_key: SessionKey,
+    /// The hash of the full handshake transcript, see
+    /// [`ClientLoginFinishResult::transcript_hash`]
+    pub transcript_hash: GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
     _cs: PhantomData<CS>,
     /// Instance of the ClientRegistration, only used in tests for checking zeroize
     #[cfg(test)]
     pub state: ServerLogin<CS>,
 }
 
-// Cannot be derived because it would require for CS to be Clone.
-impl<CS: CipherSuite> Clone for ServerLoginFinishResult<CS> {
-    fn clone(&self) -> Self {
-        Self {
-            session_key: self.session_key.clone(),
-            _cs: PhantomData,
-            #[cfg(test)]
-            state: self.state.clone(),
-        }
+impl<CS: CipherSuite> core::fmt::Debug for ServerLoginFinishResult<CS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ServerLoginFinishResult")
+            .field("session_key", &self.session_key)
+            .field("transcript_hash", &self.transcript_hash)
+            .finish()
     }
 }
 
-/// Optional parameters for server login start
-#[derive(Clone)]
-pub enum ServerLoginStartParameters {
+/// Optional parameters for server login start, which can be combined freely
+#[derive(Clone, Default)]
+pub struct ServerLoginStartParameters {
     /// Specifying a context field that the client must agree on
-    WithContext(Vec<u8>),
+    pub context: Option<Vec<u8>>,
     /// Specifying a user identifier and server identifier that will be matched against the client
-    WithIdentifiers(Identifiers),
-    /// Specifying a context field that the client must agree on,
-    /// along with a user identifier and and server identifier that will be matched against the client
-    /// (in that order)
-    WithContextAndIdentifiers(Vec<u8>, Identifiers),
+    pub identifiers: Option<Identifiers>,
+    /// Specifying a puzzle issued to the client and the client's solution,
+    /// which is checked before any other work
+    pub puzzle: Option<(Puzzle, u64)>,
+    /// Specifying the identifier of the server key, of
+    /// [`CipherSuite::KEY_ID_LEN`] bytes, instead of the one derived by
+    /// [`ServerSetup::key_id`]
+    pub key_id: Option<Vec<u8>>,
+    /// Specifying a nonce of [`RETRANSMISSION_NONCE_LEN`] bytes from which,
+    /// along with the server setup, the credential identifier and the
+    /// credential request, the randomness of the response is derived instead
+    /// of being drawn from the RNG. A retransmitted credential request then
+    /// yields the same response and state, for transports which may deliver
+    /// a message more than once.
+    ///
+    /// The server must draw a fresh random nonce for each login attempt, and
+    /// only reuse it for the retransmissions of that attempt, e.g. by keeping
+    /// it with the exchange state of the transport until the attempt times
+    /// out. A nonce reused across attempts rebuilds the state of an earlier
+    /// login, which then accepts a replay of its recorded credential
+    /// finalization.
+    pub retransmission_nonce: Option<Vec<u8>>,
+    /// Specifying the identifiers of the slow hashes in use along with their
+    /// weights, e.g. the number of records sealed with each, from which the
+    /// slow hash identifier of an unregistered client is drawn, see
+    /// [`ServerLoginStartResult::slow_hash_id`]
+    ///
+    /// While registrations migrate to a new slow hash, this keeps the
+    /// identifier sent to the client from telling registered clients apart
+    /// from unregistered ones. The identifier of a credential identifier is
+    /// the same on every login, like the one of a record. Unregistered
+    /// clients get the default identifier 0 when this is empty.
+    pub dummy_slow_hash_ids: Vec<(u8, u32)>,
 }
 
-impl Default for ServerLoginStartParameters {
-    fn default() -> Self {
-        Self::WithContext(Vec::new())
+impl ServerLoginStartParameters {
+    /// Create a new [`ServerLoginStartParameters`]
+    pub fn new(context: Option<Vec<u8>>, identifiers: Option<Identifiers>) -> Self {
+        Self {
+            context,
+            identifiers,
+            puzzle: None,
+            key_id: None,
+            retransmission_nonce: None,
+            dummy_slow_hash_ids: Vec::new(),
+        }
     }
 }
 
@@ -911,6 +2577,11 @@ pub struct ServerLoginStartResult<CS: CipherSuite> {
     pub message: CredentialResponse<CS>,
     /// The state that the server must keep in order to finish the protocl
     pub state: ServerLogin<CS>,
+    /// The identifier of the slow hash of the record, see
+    /// [`ServerRegistration::slow_hash_id`], which the server sends to the
+    /// client along with the message. For an unregistered client, it is drawn
+    /// from [`ServerLoginStartParameters::dummy_slow_hash_ids`].
+    pub slow_hash_id: u8,
     /// Handshake secret, only used in tests
     #[cfg(test)]
     pub handshake_secret: Vec<u8>,
@@ -928,6 +2599,7 @@ impl<CS: CipherSuite> Clone for ServerLoginStartResult<CS> {
         Self {
             message: self.message.clone(),
             state: self.state.clone(),
+            slow_hash_id: self.slow_hash_id,
             #[cfg(test)]
             handshake_secret: self.handshake_secret.clone(),
             #[cfg(test)]
@@ -938,6 +2610,38 @@ impl<CS: CipherSuite> Clone for ServerLoginStartResult<CS> {
     }
 }
 
+impl<CS: CipherSuite> ServerLoginStartResult<CS> {
+    /// The server's nonce sent in the message. Like the other handshake
+    /// fingerprints below, it is public and can be logged for monitoring.
+    pub fn server_nonce(&self) -> &[u8] {
+        self.message.server_nonce()
+    }
+
+    /// The serialized ephemeral public key of the server sent in the message
+    pub fn server_e_pk(&self) -> Vec<u8> {
+        self.message.server_e_pk()
+    }
+
+    /// The MAC authenticating the server sent in the message
+    pub fn server_mac(&self) -> &[u8] {
+        self.message.server_mac()
+    }
+}
+
+impl<CS: CipherSuite> core::fmt::Debug for ServerLoginStartResult<CS>
+where
+    CredentialResponse<CS>: core::fmt::Debug,
+    ServerLogin<CS>: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ServerLoginStartResult")
+            .field("message", &self.message)
+            .field("state", &self.state)
+            .field("slow_hash_id", &self.slow_hash_id)
+            .finish()
+    }
+}
+
 ////////////////////////////////////////////////
 // Helper functions and Trait Implementations //
 // ========================================== //
@@ -945,11 +2649,42 @@ impl<CS: CipherSuite> Clone for ServerLoginStartResult<CS> {
 
 // Helper functions
 
+// Applies `f` to the items of `items` in parallel, in batches of at most
+// `batch_size` items, and passes the outputs to `sink` in order. `prepare`
+// computes the state shared by the items of each batch.
+#[cfg(feature = "rayon")]
+fn par_batched<T: Send, P: Sync, U: Send>(
+    items: impl IntoIterator<Item = T>,
+    batch_size: usize,
+    mut prepare: impl FnMut(&[T]) -> P,
+    f: impl Fn(&P, T) -> U + Sync,
+    mut sink: impl FnMut(U),
+) {
+    use rayon_::prelude::*;
+
+    let mut items = items.into_iter();
+    loop {
+        let batch: Vec<T> = items.by_ref().take(batch_size.max(1)).collect();
+        if batch.is_empty() {
+            break;
+        }
+        let prepared = prepare(&batch);
+        let outputs: Vec<U> = batch
+            .into_par_iter()
+            .map(|item| f(&prepared, item))
+            .collect();
+        outputs.into_iter().for_each(&mut sink);
+    }
+}
+
 #[allow(clippy::type_complexity)]
 fn get_password_derived_key<CS: CipherSuite>(
-    oprf_client: voprf::NonVerifiableClient<CS::OprfGroup, CS::Hash>,
+    oprf_client: &voprf::NonVerifiableClient<CS::OprfGroup, CS::Hash>,
     evaluation_element: voprf::EvaluationElement<CS::OprfGroup, CS::Hash>,
     slow_hash: Option<&CS::SlowHash>,
+    slow_hash_salt: Option<&[u8]>,
+    progress: Option<ProgressCallback<'_>>,
+    cancellation: Option<&CancellationToken>,
 ) -> Result<
     (
         GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
@@ -957,35 +2692,285 @@ fn get_password_derived_key<CS: CipherSuite>(
     ),
     ProtocolError,
 > {
-    let oprf_output = oprf_client.finalize(evaluation_element, None)?;
+    let oprf_output = Zeroizing::new(oprf_client.finalize(evaluation_element, None)?.to_vec());
+
+    let default_slow_hash;
+    let slow_hash = match slow_hash {
+        Some(slow_hash) => slow_hash,
+        None => {
+            default_slow_hash = CS::SlowHash::default();
+            &default_slow_hash
+        }
+    };
+
+    // An application salt is hashed along with the OPRF output, so that the
+    // input of the slow hash keeps the length of the hash output
+    let slow_hash_input = match slow_hash_salt {
+        Some(salt) => Zeroizing::new(
+            CS::Hash::new()
+                .chain(CS::CONTEXT)
+                .chain(STR_SLOW_HASH_SALT)
+                .chain(serialize(salt, 2)?)
+                .chain(&oprf_output[..])
+                .finalize()
+                .to_vec(),
+        ),
+        None => oprf_output.clone(),
+    };
 
-    let hardened_output = if let Some(slow_hash) = slow_hash {
-        slow_hash.hash(oprf_output.clone())
-    } else {
-        CS::SlowHash::default().hash(oprf_output.clone())
+    let output_len = <CS::SlowHash as SlowHash<CS::Hash>>::OUTPUT_LEN;
+    let hardened_output = Zeroizing::new(
+        match (cancellation, progress) {
+            (Some(token), progress) => {
+                slow_hash.hash_with_cancellation(&slow_hash_input, output_len, progress, token)
+            }
+            (None, Some(progress)) => {
+                slow_hash.hash_with_progress(&slow_hash_input, output_len, progress)
+            }
+            (None, None) => slow_hash.hash(&slow_hash_input, output_len),
+        }
+        .map_err(ProtocolError::from)?,
+    );
+    if hardened_output.len() != output_len {
+        return Err(InternalError::SlowHashError.into());
     }
-    .map_err(ProtocolError::from)?;
 
-    Ok(Hkdf::<CS::Hash>::extract(
-        None,
-        &[oprf_output.to_vec(), hardened_output].concat(),
-    ))
+    let ikm = Zeroizing::new([&oprf_output[..], &hardened_output[..]].concat());
+    Ok(Hkdf::<CS::Hash>::extract(None, &ikm))
 }
 
-fn oprf_key_from_seed<G: Group, D: Hash>(
-    oprf_seed: &GenericArray<u8, D::OutputSize>,
+fn oprf_key_from_seed<CS: CipherSuite>(
+    oprf_seed: &GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
     credential_identifier: &[u8],
 ) -> Result<Vec<u8>, ProtocolError> {
-    let mut ikm = vec![0u8; G::ScalarLen::USIZE];
-    Hkdf::<D>::from_prk(oprf_seed)
+    let mut ikm = vec![0u8; <CS::OprfGroup as Group>::ScalarLen::USIZE];
+    Hkdf::<CS::Hash>::from_prk(oprf_seed)
         .map_err(|_| InternalError::HkdfError)?
-        .expand(&[credential_identifier, STR_OPRF_KEY].concat(), &mut ikm)
+        .expand_multi_info(
+            &[credential_identifier, CS::CONTEXT, STR_OPRF_KEY],
+            &mut ikm,
+        )
         .map_err(|_| InternalError::HkdfError)?;
-    Ok(G::scalar_as_bytes(G::hash_to_scalar::<D, _, _>(
-        Some(&ikm[..]),
-        GenericArray::from(*STR_OPAQUE_DERIVE_KEY_PAIR),
-    )?)
-    .to_vec())
+    Ok(
+        CS::OprfGroup::scalar_as_bytes(CS::OprfGroup::hash_to_scalar::<CS::Hash, _, _>(
+            Some(&ikm[..]),
+            GenericArray::from(*STR_OPAQUE_DERIVE_KEY_PAIR),
+        )?)
+        .to_vec(),
+    )
+}
+
+// Commits to an OPRF key by multiplying the base point with it
+fn oprf_public_key_from_key<CS: CipherSuite>(
+    oprf_key: &[u8],
+) -> Result<GenericArray<u8, <CS::OprfGroup as Group>::ElemLen>, ProtocolError> {
+    let scalar = CS::OprfGroup::from_scalar_slice(GenericArray::from_slice(oprf_key))?;
+    Ok((CS::OprfGroup::base_point() * &scalar).to_arr())
+}
+
+#[cfg(feature = "sealed-records")]
+fn record_cipher(kek: &[u8]) -> Result<XChaCha20Poly1305, ProtocolError> {
+    let kek = check_slice_size(kek, RECORD_SEALING_KEY_LEN, "record_sealing_key")?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(kek)))
+}
+
+// The data authenticated along with a sealed record: its header, and the
+// credential identifier it is stored under
+#[cfg(feature = "sealed-records")]
+fn sealed_record_aad(
+    header: &[u8],
+    credential_identifier: &[u8],
+) -> Result<Vec<u8>, ProtocolError> {
+    Ok([header, &serialize(credential_identifier, 2)?].concat())
+}
+
+// Identifies the ciphersuite of a serialized server login state. The state
+// only consists of outputs of the hash, so hashing the description of the
+// ciphersuite with it also tells apart hash functions of the same length.
+fn suite_tag<CS: CipherSuite>() -> Result<[u8; SUITE_TAG_LEN], ProtocolError> {
+    let digest = CS::Hash::new()
+        .chain(STR_SERVER_LOGIN_STATE)
+        .chain(serialize(CS::CONTEXT, 2)?)
+        .chain(i2osp(<CS::OprfGroup as Group>::ElemLen::USIZE, 2)?)
+        .chain(i2osp(<CS::KeGroup as KeGroup>::PkLen::USIZE, 2)?)
+        .chain(i2osp(CS::SESSION_KEY_LEN, 2)?)
+        .finalize();
+    let mut tag = [0u8; SUITE_TAG_LEN];
+    tag.copy_from_slice(&digest[..SUITE_TAG_LEN]);
+    Ok(tag)
+}
+
+// The seed of the randomness of a retransmission-safe login start, which is
+// bound to the credential request and to the nonce the server drew for the
+// login attempt, so that a replayed credential request gets a fresh state
+fn retransmission_seed<CS: CipherSuite>(
+    oprf_seed: &GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
+    credential_identifier: &[u8],
+    credential_request: &CredentialRequest<CS>,
+    retransmission_nonce: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, ProtocolError> {
+    let mut seed = Zeroizing::new(vec![0u8; <CS::Hash as Digest>::OutputSize::USIZE]);
+    Hkdf::<CS::Hash>::from_prk(oprf_seed)
+        .map_err(|_| InternalError::HkdfError)?
+        .expand_multi_info(
+            &[
+                &serialize(credential_identifier, 2)?,
+                &credential_request.to_vec(),
+                retransmission_nonce,
+                CS::CONTEXT,
+                STR_RETRANSMISSION_SEED,
+            ],
+            &mut seed,
+        )
+        .map_err(|_| InternalError::HkdfError)?;
+    Ok(seed)
+}
+
+// Draws the slow hash identifier of an unregistered credential identifier
+// from `weighted_ids`, deterministically so that it does not change from one
+// login to the next
+fn dummy_slow_hash_id<CS: CipherSuite>(
+    oprf_seed: &GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
+    credential_identifier: &[u8],
+    weighted_ids: &[(u8, u32)],
+) -> Result<u8, ProtocolError> {
+    let total: u64 = weighted_ids
+        .iter()
+        .map(|&(_, weight)| u64::from(weight))
+        .sum();
+    if total == 0 {
+        return Ok(0);
+    }
+
+    let mut draw = [0u8; 8];
+    Hkdf::<CS::Hash>::from_prk(oprf_seed)
+        .map_err(|_| InternalError::HkdfError)?
+        .expand_multi_info(
+            &[
+                &serialize(credential_identifier, 2)?,
+                CS::CONTEXT,
+                STR_DUMMY_SLOW_HASH_ID,
+            ],
+            &mut draw,
+        )
+        .map_err(|_| InternalError::HkdfError)?;
+    // The bias of the reduction is at most the total weight over 2^64
+    let mut draw = u64::from_be_bytes(draw) % total;
+    for &(slow_hash_id, weight) in weighted_ids {
+        if draw < u64::from(weight) {
+            return Ok(slow_hash_id);
+        }
+        draw -= u64::from(weight);
+    }
+    unreachable!("the draw is less than the total weight")
+}
+
+fn derive_pseudonym<CS: CipherSuite>(
+    export_key: &[u8],
+    service_identifier: &[u8],
+) -> Result<GenericArray<u8, <CS::Hash as Digest>::OutputSize>, ProtocolError> {
+    let mut pseudonym = GenericArray::default();
+    Hkdf::<CS::Hash>::new(None, export_key)
+        .expand_multi_info(
+            &[
+                &serialize(service_identifier, 2)?,
+                CS::CONTEXT,
+                STR_PSEUDONYM,
+            ],
+            &mut pseudonym,
+        )
+        .map_err(|_| InternalError::HkdfError)?;
+    Ok(pseudonym)
+}
+
+fn key_id_from_public_key<CS: CipherSuite>(public_key: &PublicKey<CS::KeGroup>) -> Vec<u8> {
+    CS::Hash::new()
+        .chain(CS::CONTEXT)
+        .chain(STR_KEY_ID)
+        .chain(public_key.to_arr())
+        .finalize()[..CS::KEY_ID_LEN]
+        .to_vec()
+}
+
+fn derive_tenant_keypair<CS: CipherSuite>(
+    oprf_seed: &GenericArray<u8, <CS::Hash as Digest>::OutputSize>,
+    tenant_id: &[u8],
+    label: &[u8],
+) -> Result<KeyPair<CS::KeGroup>, ProtocolError> {
+    let mut keypair_seed = Zeroizing::new(vec![0u8; <CS::KeGroup as KeGroup>::SkLen::USIZE]);
+    Hkdf::<CS::Hash>::from_prk(oprf_seed)
+        .map_err(|_| InternalError::HkdfError)?
+        .expand_multi_info(
+            &[&serialize(tenant_id, 2)?, CS::CONTEXT, label],
+            &mut keypair_seed,
+        )
+        .map_err(|_| InternalError::HkdfError)?;
+    KeyPair::from_private_key_slice(&CS::OprfGroup::scalar_as_bytes(
+        CS::OprfGroup::hash_to_scalar::<CS::Hash, _, _>(
+            Some(&keypair_seed[..]),
+            GenericArray::from(*STR_OPAQUE_DERIVE_KEY_PAIR),
+        )?,
+    ))
+}
+
+// Expands a seed into a stream of bytes with HKDF, one block of hash output
+// per counter value, so that the key generation of any group derives the
+// same keypair from the same seed
+struct SeedRng<CS: CipherSuite> {
+    hkdf: Hkdf<CS::Hash>,
+    label: &'static [u8],
+    counter: u64,
+}
+
+impl<CS: CipherSuite> SeedRng<CS> {
+    fn new(seed: &[u8], label: &'static [u8]) -> Self {
+        Self {
+            hkdf: Hkdf::new(None, seed),
+            label,
+            counter: 0,
+        }
+    }
+}
+
+impl<CS: CipherSuite> RngCore for SeedRng<CS> {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(<CS::Hash as Digest>::OutputSize::USIZE) {
+            self.counter += 1;
+            // Cannot fail, since a chunk is at most one block of output
+            let _ = self.hkdf.expand_multi_info(
+                &[CS::CONTEXT, self.label, &self.counter.to_be_bytes()],
+                chunk,
+            );
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl<CS: CipherSuite> CryptoRng for SeedRng<CS> {}
+
+// Prefixes the context of a login with the constant context of the
+// ciphersuite, before it is bound to the key exchange transcript
+fn transcript_context<CS: CipherSuite>(context: Vec<u8>) -> Vec<u8> {
+    if CS::CONTEXT.is_empty() {
+        return context;
+    }
+    [CS::CONTEXT, &context].concat()
 }
 
 fn mask_response<CS: CipherSuite>(
@@ -994,22 +2979,59 @@ fn mask_response<CS: CipherSuite>(
     server_s_pk: &PublicKey<CS::KeGroup>,
     envelope: &Envelope<CS>,
 ) -> Result<Vec<u8>, ProtocolError> {
-    let mut xor_pad = vec![0u8; <CS::KeGroup as KeGroup>::PkLen::USIZE + Envelope::<CS>::len()];
+    let mut xor_pad = vec![0u8; <CS::KeGroup as KeGroup>::PkLen::USIZE + envelope.serialized_len()];
     Hkdf::<CS::Hash>::from_prk(masking_key)
         .map_err(|_| InternalError::HkdfError)?
-        .expand(
-            &[masking_nonce, STR_CREDENTIAL_RESPONSE_PAD].concat(),
+        .expand_multi_info(
+            &[
+                masking_nonce,
+                CS::CONTEXT,
+                CS::ENVELOPE_LABELS.credential_response_pad,
+            ],
             &mut xor_pad,
         )
         .map_err(|_| InternalError::HkdfError)?;
 
-    let plaintext = [&server_s_pk.to_arr()[..], &envelope.serialize()].concat();
-
-    Ok(xor_pad
-        .iter()
+    // The pad is XORed with the plaintext in place
+    let mut plaintext = Vec::with_capacity(xor_pad.len());
+    plaintext.extend_from_slice(&server_s_pk[..]);
+    envelope.serialize_into(&mut plaintext);
+    xor_pad
+        .iter_mut()
         .zip(plaintext.iter())
-        .map(|(&x1, &x2)| x1 ^ x2)
-        .collect())
+        .for_each(|(x1, &x2)| *x1 ^= x2);
+
+    Ok(xor_pad)
+}
+
+// Unmasks the server's static public key and the envelope of a credential
+// response, with the masking key derived from the randomized password
+fn unmask<CS: CipherSuite>(
+    credential_response: &CredentialResponse<CS>,
+    randomized_pwd_hasher: &Hkdf<CS::Hash>,
+) -> Result<(PublicKey<CS::KeGroup>, Envelope<CS>), ProtocolError> {
+    let mut masking_key = vec![0u8; <CS::Hash as Digest>::OutputSize::USIZE];
+    randomized_pwd_hasher
+        .expand_multi_info(
+            &[CS::CONTEXT, CS::ENVELOPE_LABELS.masking_key],
+            &mut masking_key,
+        )
+        .map_err(|_| InternalError::HkdfError)?;
+
+    unmask_response::<CS>(
+        &masking_key,
+        &credential_response.masking_nonce,
+        &credential_response.masked_response,
+    )
+    // A wrong password yields a wrong masking key, and hence an invalid
+    // server public key, which must not be distinguishable from any other
+    // login failure
+    .map_err(|e| match e {
+        ProtocolError::SerializationError | ProtocolError::InvalidServerStaticKeyError => {
+            ProtocolError::InvalidLoginError
+        }
+        err => err,
+    })
 }
 
 fn unmask_response<CS: CipherSuite>(
@@ -1017,30 +3039,44 @@ fn unmask_response<CS: CipherSuite>(
     masking_nonce: &[u8],
     masked_response: &[u8],
 ) -> Result<(PublicKey<CS::KeGroup>, Envelope<CS>), ProtocolError> {
-    let mut xor_pad = vec![0u8; <CS::KeGroup as KeGroup>::PkLen::USIZE + Envelope::<CS>::len()];
+    let mut plaintext = vec![0u8; masked_response.len()];
     Hkdf::<CS::Hash>::from_prk(masking_key)
         .map_err(|_| InternalError::HkdfError)?
-        .expand(
-            &[masking_nonce, STR_CREDENTIAL_RESPONSE_PAD].concat(),
-            &mut xor_pad,
+        .expand_multi_info(
+            &[
+                masking_nonce,
+                CS::CONTEXT,
+                CS::ENVELOPE_LABELS.credential_response_pad,
+            ],
+            &mut plaintext,
         )
         .map_err(|_| InternalError::HkdfError)?;
-    let plaintext: Vec<u8> = xor_pad
-        .iter()
+    plaintext
+        .iter_mut()
         .zip(masked_response.iter())
-        .map(|(&x1, &x2)| x1 ^ x2)
-        .collect();
+        .for_each(|(x1, &x2)| *x1 ^= x2);
     let key_len = <CS::KeGroup as KeGroup>::PkLen::USIZE;
     let unchecked_server_s_pk = PublicKey::from_bytes(&plaintext[..key_len])?;
     let envelope = Envelope::deserialize(&plaintext[key_len..])?;
 
     // Ensure that public key is valid
     let server_s_pk = KeyPair::<CS::KeGroup>::check_public_key(unchecked_server_s_pk)
-        .map_err(|_| ProtocolError::SerializationError)?;
+        .map_err(|e| unless_external(e, ProtocolError::InvalidServerStaticKeyError))?;
 
     Ok((server_s_pk, envelope))
 }
 
+// The OPRF input when the credential identifier is bound into it, which
+// length-prefixes the identifier so that it cannot run into the password
+fn bind_credential_identifier(
+    credential_identifier: &[u8],
+    password: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, ProtocolError> {
+    Ok(Zeroizing::new(
+        [&serialize(credential_identifier, 2)?[..], password].concat(),
+    ))
+}
+
 pub(crate) fn bytestrings_from_identifiers(
     ids: &Option<Identifiers>,
     client_s_pk: &[u8],
@@ -1059,39 +3095,57 @@ pub(crate) fn bytestrings_from_identifiers(
 }
 
 /// Internal function for computing the blind result by calling the
-/// voprf library. Note that for tests, we use the deterministic blinding
-/// in order to be able to set the blinding factor directly from the passed-in
-/// rng.
+/// voprf library. The blinding factor is sampled from the passed-in rng, see
+/// `random_nonzero_blind`, which lets tests set it.
+///
+/// When [`CipherSuite::HASH_TO_CURVE`] is set, the password is hashed to
+/// `OprfGroup` with it instead, and only the blinding is left to voprf.
 fn blind<CS: CipherSuite, R: RngCore + CryptoRng>(
     rng: &mut R,
     password: &[u8],
-) -> Result<
-    voprf::NonVerifiableClientBlindResult<CS::OprfGroup, CS::Hash>,
-    voprf::errors::InternalError,
-> {
-    #[cfg(not(test))]
-    let result = voprf::NonVerifiableClient::blind(password.to_vec(), rng)?;
+) -> Result<voprf::NonVerifiableClientBlindResult<CS::OprfGroup, CS::Hash>, InternalError> {
+    // The blind is always sampled here rather than by voprf, which panics if
+    // the rng fails
+    blind_with::<CS>(random_nonzero_blind::<CS, _>(rng)?, password)
+}
 
-    #[cfg(test)]
-    let result = {
-        let mut blind_bytes = vec![0u8; <CS::OprfGroup as Group>::ScalarLen::USIZE];
-        let blind = loop {
-            rng.fill_bytes(&mut blind_bytes);
-            let scalar = <CS::OprfGroup as Group>::from_scalar_slice_unchecked(
-                &GenericArray::clone_from_slice(&blind_bytes),
-            )?;
-            match scalar
-                .ct_eq(&<CS::OprfGroup as Group>::scalar_zero())
-                .into()
-            {
-                false => break scalar,
-                true => (),
-            }
-        };
-        voprf::NonVerifiableClient::deterministic_blind_unchecked(password.to_vec(), blind)?
-    };
+fn blind_with<CS: CipherSuite>(
+    blind: <CS::OprfGroup as Group>::Scalar,
+    password: &[u8],
+) -> Result<voprf::NonVerifiableClientBlindResult<CS::OprfGroup, CS::Hash>, InternalError> {
+    if let Some(hash_to_curve) = CS::HASH_TO_CURVE {
+        let message =
+            voprf::BlindedElement::from_value_unchecked(hash_to_curve(password)? * &blind);
+        // The state only holds the password and the blind, so that it
+        // unblinds any evaluation of the element computed above
+        let mut result =
+            voprf::NonVerifiableClient::deterministic_blind_unchecked(password.to_vec(), blind)?;
+        result.message = message;
+        return Ok(result);
+    }
+
+    Ok(voprf::NonVerifiableClient::deterministic_blind_unchecked(
+        password.to_vec(),
+        blind,
+    )?)
+}
 
-    Ok(result)
+// Samples a uniform non-zero blind by rejection, only accepting random bytes
+// which encode a canonical non-zero scalar. Tests set the blind by feeding its
+// encoding to the rng.
+fn random_nonzero_blind<CS: CipherSuite, R: RngCore + CryptoRng>(
+    rng: &mut R,
+) -> Result<<CS::OprfGroup as Group>::Scalar, InternalError> {
+    let mut blind_bytes = Zeroizing::new(vec![0u8; <CS::OprfGroup as Group>::ScalarLen::USIZE]);
+    loop {
+        fill_random(rng, &mut blind_bytes)?;
+        if let Ok(scalar) = CS::OprfGroup::from_scalar_slice(GenericArray::from_slice(&blind_bytes))
+        {
+            if !bool::from(scalar.ct_eq(&<CS::OprfGroup as Group>::scalar_zero())) {
+                return Ok(scalar);
+            }
+        }
+    }
 }
 
 // Zeroize on drop implementations
@@ -1116,6 +3170,8 @@ impl<CS: CipherSuite> Zeroize for ServerRegistration<CS> {
         self.0.envelope.zeroize();
         self.0.masking_key.zeroize();
         self.0.client_s_pk.zeroize();
+        self.1.zeroize();
+        self.2.zeroize();
     }
 }
 
@@ -1140,6 +3196,20 @@ impl<CS: CipherSuite> Drop for ClientLogin<CS> {
     }
 }
 
+// This can't be derived because of the use of a phantom parameter
+impl<CS: CipherSuite> Zeroize for ClientLoginPrecomputation<CS> {
+    fn zeroize(&mut self) {
+        self.blind.zeroize();
+        self.ke1_state.zeroize();
+    }
+}
+
+impl<CS: CipherSuite> Drop for ClientLoginPrecomputation<CS> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 // This can't be derived because of the use of a phantom parameter
 impl<CS: CipherSuite> Zeroize for ServerLogin<CS> {
     fn zeroize(&mut self) {
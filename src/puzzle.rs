@@ -0,0 +1,281 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Client puzzles, which a server can demand before running login start, as
+//! a mitigation against denial of service on public login endpoints
+//!
+//! The server sends a [Puzzle] to the client, which solves it for its
+//! [CredentialRequest] with [Puzzle::solve] and sends the solution along with
+//! the request. The server then passes the puzzle and the solution in
+//! [`ServerLoginStartParameters::puzzle`](crate::ServerLoginStartParameters),
+//! and [`ServerLogin::start`](crate::ServerLogin::start) fails with
+//! [`ProtocolError::InvalidPuzzleSolutionError`] before doing any expensive
+//! work if the solution is wrong.
+//!
+//! A solution is the counter for which the hash of the puzzle, the credential
+//! request and the counter starts with [Puzzle::difficulty] zero bits, so
+//! that solving takes `2^difficulty` hashes on average while verifying takes
+//! one. Since a solution is bound to the credential request, it cannot be
+//! reused for another login attempt, but the server is responsible for only
+//! accepting puzzles it issued recently, and each of them once.
+//!
+//! ```
+//! use opaque_ke::{puzzle::Puzzle, ClientLogin, ServerLoginStartParameters};
+//! # use opaque_ke::{
+//! #   errors::ProtocolError,
+//! #   ClientRegistration, ClientRegistrationFinishParameters, ServerLogin, ServerRegistration,
+//! #   ServerSetup,
+//! # };
+//! # use opaque_ke::CipherSuite;
+//! # struct Default;
+//! # impl CipherSuite for Default {
+//! #     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+//! #     type Hash = sha2::Sha512;
+//! #     type SlowHash = opaque_ke::slow_hash::NoOpHash;
+//! # }
+//! # use rand::rngs::OsRng;
+//! # let mut rng = OsRng;
+//! # let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+//! // Server: issue a puzzle
+//! let puzzle = Puzzle::new(&mut rng, 8)?;
+//!
+//! // Client: solve it for the credential request
+//! let client_login_start_result = ClientLogin::<Default>::start(&mut rng, b"password")?;
+//! let solution = puzzle.solve(&client_login_start_result.message)?;
+//!
+//! // Server: check the solution while starting the login
+//! let server_login_start_result = ServerLogin::start(
+//!     &mut rng,
+//!     &server_setup,
+//!     None,
+//!     client_login_start_result.message,
+//!     b"alice@example.com",
+//!     ServerLoginStartParameters {
+//!         puzzle: Some((puzzle, solution)),
+//!         ..ServerLoginStartParameters::default()
+//!     },
+//! )?;
+//! # Ok::<(), ProtocolError>(())
+//! ```
+
+use crate::{
+    ciphersuite::CipherSuite,
+    errors::{
+        utils::{check_slice_size, fill_random},
+        InternalError, ProtocolError,
+    },
+    CredentialRequest,
+};
+use alloc::vec::Vec;
+use digest::Digest;
+use rand::{CryptoRng, RngCore};
+
+/// The length of the random challenge of a [Puzzle]
+pub const CHALLENGE_LEN: usize = 32;
+
+/// The largest difficulty of a [Puzzle], so that a malicious server cannot
+/// make clients search for a solution indefinitely
+pub const MAX_DIFFICULTY: u8 = 32;
+
+const STR_PUZZLE: &[u8; 6] = b"Puzzle";
+
+/// A puzzle issued by the server, to be solved by the client for its
+/// credential request
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Puzzle {
+    challenge: [u8; CHALLENGE_LEN],
+    difficulty: u8,
+}
+
+impl Puzzle {
+    /// Creates a puzzle with a random challenge, whose solutions require
+    /// `difficulty` leading zero bits. Each additional bit doubles the work
+    /// of the client, so that values beyond 24 are impractical, and values
+    /// beyond [MAX_DIFFICULTY] are rejected.
+    pub fn new<R: RngCore + CryptoRng>(rng: &mut R, difficulty: u8) -> Result<Self, InternalError> {
+        check_difficulty(difficulty)?;
+        let mut challenge = [0u8; CHALLENGE_LEN];
+        fill_random(rng, &mut challenge)?;
+        Ok(Self {
+            challenge,
+            difficulty,
+        })
+    }
+
+    /// The number of leading zero bits required from solutions
+    pub fn difficulty(&self) -> u8 {
+        self.difficulty
+    }
+
+    /// Serialization into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(CHALLENGE_LEN + 1);
+        output.extend_from_slice(&self.challenge);
+        output.push(self.difficulty);
+        output
+    }
+
+    /// Deserialization from bytes, rejecting a difficulty beyond
+    /// [MAX_DIFFICULTY]
+    pub fn deserialize(input: &[u8]) -> Result<Self, ProtocolError> {
+        let input = check_slice_size(input, CHALLENGE_LEN + 1, "puzzle_bytes")?;
+        let difficulty = input[CHALLENGE_LEN];
+        check_difficulty(difficulty)?;
+        let mut challenge = [0u8; CHALLENGE_LEN];
+        challenge.copy_from_slice(&input[..CHALLENGE_LEN]);
+        Ok(Self {
+            challenge,
+            difficulty,
+        })
+    }
+
+    /// Finds a solution to the puzzle for `credential_request`, which takes
+    /// `2^difficulty` hashes on average
+    pub fn solve<CS: CipherSuite>(
+        &self,
+        credential_request: &CredentialRequest<CS>,
+    ) -> Result<u64, ProtocolError> {
+        let hasher = self.hasher(credential_request)?;
+        (0..=u64::MAX)
+            .find(|&solution| self.is_solution(hasher.clone(), solution))
+            .ok_or(ProtocolError::InvalidPuzzleSolutionError)
+    }
+
+    /// Checks that `solution` solves the puzzle for `credential_request`
+    pub fn verify<CS: CipherSuite>(
+        &self,
+        credential_request: &CredentialRequest<CS>,
+        solution: u64,
+    ) -> Result<(), ProtocolError> {
+        if self.is_solution(self.hasher(credential_request)?, solution) {
+            Ok(())
+        } else {
+            Err(ProtocolError::InvalidPuzzleSolutionError)
+        }
+    }
+
+    fn hasher<CS: CipherSuite>(
+        &self,
+        credential_request: &CredentialRequest<CS>,
+    ) -> Result<CS::Hash, ProtocolError> {
+        Ok(CS::Hash::new()
+            .chain(CS::CONTEXT)
+            .chain(STR_PUZZLE)
+            .chain(self.challenge)
+            .chain([self.difficulty])
+            .chain(credential_request.to_vec()))
+    }
+
+    fn is_solution<H: Digest>(&self, hasher: H, solution: u64) -> bool {
+        let hash = hasher.chain(solution.to_be_bytes()).finalize();
+        let difficulty = usize::from(self.difficulty);
+        let (full_bytes, remaining_bits) = (difficulty / 8, difficulty % 8);
+
+        hash[..full_bytes].iter().all(|&byte| byte == 0)
+            && (remaining_bits == 0 || hash[full_bytes] >> (8 - remaining_bits) == 0)
+    }
+}
+
+fn check_difficulty(difficulty: u8) -> Result<(), InternalError> {
+    if difficulty > MAX_DIFFICULTY {
+        return Err(InternalError::PuzzleDifficultyError);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientLogin, ServerLogin, ServerLoginStartParameters, ServerSetup};
+    use curve25519_dalek::ristretto::RistrettoPoint;
+    use rand::rngs::OsRng;
+
+    struct Default;
+
+    impl CipherSuite for Default {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = crate::key_exchange::tripledh::TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = crate::slow_hash::NoOpHash;
+    }
+
+    #[test]
+    fn test_puzzle() -> Result<(), ProtocolError> {
+        let mut rng = OsRng;
+        let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+        let puzzle = Puzzle::new(&mut rng, 12)?;
+        assert_eq!(Puzzle::deserialize(&puzzle.serialize())?, puzzle);
+
+        let credential_request = ClientLogin::<Default>::start(&mut rng, b"password")?.message;
+        let other_request = ClientLogin::<Default>::start(&mut rng, b"password")?.message;
+        let solution = puzzle.solve(&credential_request)?;
+        puzzle.verify(&credential_request, solution)?;
+
+        // Solutions depend on both the puzzle and the credential request
+        let solutions = |puzzle: &Puzzle, credential_request| {
+            (0..1024)
+                .filter(|&candidate| puzzle.verify(credential_request, candidate).is_ok())
+                .collect::<Vec<_>>()
+        };
+        let easy = Puzzle::new(&mut rng, 4)?;
+        assert_ne!(
+            solutions(&easy, &credential_request),
+            solutions(&easy, &other_request)
+        );
+        assert_ne!(
+            solutions(&easy, &credential_request),
+            solutions(&Puzzle::new(&mut rng, 4)?, &credential_request)
+        );
+
+        // The solution is checked by ServerLogin::start
+        let start = |solution| {
+            ServerLogin::start(
+                &mut OsRng,
+                &server_setup,
+                None,
+                credential_request.clone(),
+                b"alice",
+                ServerLoginStartParameters {
+                    puzzle: Some((puzzle.clone(), solution)),
+                    ..ServerLoginStartParameters::default()
+                },
+            )
+        };
+        assert!(start(solution).is_ok());
+        let wrong_solution = (0..)
+            .find(|&candidate| puzzle.verify(&credential_request, candidate).is_err())
+            .unwrap();
+        assert!(matches!(
+            start(wrong_solution),
+            Err(ProtocolError::InvalidPuzzleSolutionError)
+        ));
+
+        assert_eq!(Puzzle::new(&mut rng, 0)?.solve(&credential_request)?, 0);
+        assert!(matches!(
+            Puzzle::deserialize(&puzzle.serialize()[1..]),
+            Err(ProtocolError::LibraryError(_))
+        ));
+
+        // Puzzles too hard to solve are rejected on both sides
+        assert!(Puzzle::new(&mut rng, MAX_DIFFICULTY).is_ok());
+        assert!(matches!(
+            Puzzle::new(&mut rng, MAX_DIFFICULTY + 1),
+            Err(InternalError::PuzzleDifficultyError)
+        ));
+        let mut hostile = puzzle.serialize();
+        hostile[CHALLENGE_LEN] = u8::MAX;
+        assert!(matches!(
+            Puzzle::deserialize(&hostile),
+            Err(ProtocolError::LibraryError(
+                InternalError::PuzzleDifficultyError
+            ))
+        ));
+
+        Ok(())
+    }
+}
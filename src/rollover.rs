@@ -0,0 +1,255 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Announcements of the next server key, delivered inside a successful login
+//! so that clients pinning the server key can update their pin ahead of the
+//! actual rotation
+//!
+//! The server seals a [KeyRollover] under the session key of a login, and the
+//! client opens it with its own session key, which authenticates the
+//! announcement as coming from the server it just logged in to. The next
+//! public key and its identifier are encrypted with a pad and authenticated
+//! with HMAC, both under keys expanded from the session key with HKDF.
+//!
+//! ```
+//! use opaque_ke::rollover::KeyRollover;
+//! # use opaque_ke::{
+//! #   errors::ProtocolError,
+//! #   ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+//! #   ClientRegistrationFinishParameters, ServerLogin, ServerLoginStartParameters,
+//! #   ServerRegistration, ServerSetup,
+//! # };
+//! # use opaque_ke::CipherSuite;
+//! # struct Default;
+//! # impl CipherSuite for Default {
+//! #     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+//! #     type Hash = sha2::Sha512;
+//! #     type SlowHash = opaque_ke::slow_hash::NoOpHash;
+//! # }
+//! # use rand::rngs::OsRng;
+//! # let mut rng = OsRng;
+//! # let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+//! # let client_registration_start_result = ClientRegistration::<Default>::start(&mut rng, b"password")?;
+//! # let server_registration_start_result = ServerRegistration::<Default>::start(&server_setup, client_registration_start_result.message, b"alice@example.com")?;
+//! # let client_registration_finish_result = client_registration_start_result.state.finish(&mut rng, server_registration_start_result.message, ClientRegistrationFinishParameters::default())?;
+//! # let password_file = ServerRegistration::<Default>::finish(client_registration_finish_result.message);
+//! # let client_login_start_result = ClientLogin::<Default>::start(&mut rng, b"password")?;
+//! # let server_login_start_result = ServerLogin::start(&mut rng, &server_setup, Some(password_file), client_login_start_result.message, b"alice@example.com", ServerLoginStartParameters::default())?;
+//! # let client_login_finish_result = client_login_start_result.state.finish(server_login_start_result.message, ClientLoginFinishParameters::default())?;
+//! # let server_login_finish_result = server_login_start_result.state.finish(client_login_finish_result.message)?;
+//! // Server: announce the next setup after a successful login
+//! let next_server_setup = ServerSetup::<Default>::new(&mut rng)?;
+//! let announcement = KeyRollover::from_server_setup(&next_server_setup)
+//!     .seal(server_login_finish_result.session_key.expose_secret())?;
+//!
+//! // Client: open the announcement and pin the next key
+//! let rollover =
+//!     KeyRollover::<Default>::open(client_login_finish_result.session_key.expose_secret(), &announcement)?;
+//! assert_eq!(rollover.public_key(), next_server_setup.keypair().public());
+//! # Ok::<(), ProtocolError>(())
+//! ```
+
+use crate::{
+    ciphersuite::CipherSuite,
+    errors::{utils::check_slice_size, InternalError, ProtocolError},
+    key_exchange::group::KeGroup,
+    keypair::{PublicKey, SecretKey},
+    ServerSetup,
+};
+use alloc::vec;
+use alloc::vec::Vec;
+use digest::Digest;
+use generic_array::typenum::Unsigned;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac, NewMac};
+use zeroize::Zeroizing;
+
+const STR_ROLLOVER_PAD: &[u8; 11] = b"RolloverPad";
+const STR_ROLLOVER_MAC: &[u8; 11] = b"RolloverMac";
+
+/// The public key and key identifier of the next server setup
+pub struct KeyRollover<CS: CipherSuite> {
+    public_key: PublicKey<CS::KeGroup>,
+    key_id: Vec<u8>,
+}
+
+impl_clone_for!(struct KeyRollover<CS: CipherSuite>, [public_key, key_id]);
+impl_debug_eq_hash_for!(struct KeyRollover<CS: CipherSuite>, [public_key, key_id]);
+
+impl<CS: CipherSuite> KeyRollover<CS> {
+    /// Announces `public_key` under `key_id`, which must be of
+    /// [`CipherSuite::KEY_ID_LEN`] bytes
+    pub fn new(public_key: PublicKey<CS::KeGroup>, key_id: &[u8]) -> Result<Self, ProtocolError> {
+        let key_id = check_slice_size(key_id, CS::KEY_ID_LEN, "key_id")?;
+        Ok(Self {
+            public_key,
+            key_id: key_id.to_vec(),
+        })
+    }
+
+    /// Announces the public key of `server_setup`, under the identifier
+    /// returned by [`ServerSetup::key_id`]
+    pub fn from_server_setup<S: SecretKey<CS::KeGroup>>(server_setup: &ServerSetup<CS, S>) -> Self {
+        Self {
+            public_key: server_setup.keypair().public().clone(),
+            key_id: server_setup.key_id(),
+        }
+    }
+
+    /// The announced public key
+    pub fn public_key(&self) -> &PublicKey<CS::KeGroup> {
+        &self.public_key
+    }
+
+    /// The identifier of the announced public key
+    pub fn key_id(&self) -> &[u8] {
+        &self.key_id
+    }
+
+    /// Encrypts and authenticates the announcement under the session key of
+    /// a successful login
+    pub fn seal(&self, session_key: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        let hkdf = Hkdf::<CS::Hash>::new(None, session_key);
+
+        let mut output = pad::<CS>(&hkdf)?.to_vec();
+        output
+            .iter_mut()
+            .zip(self.public_key.iter().chain(self.key_id.iter()))
+            .for_each(|(x1, &x2)| *x1 ^= x2);
+
+        let tag = mac::<CS>(&hkdf, &output)?.finalize().into_bytes();
+        output.extend_from_slice(&tag);
+        Ok(output)
+    }
+
+    /// Authenticates and decrypts an announcement sealed by
+    /// [`seal`](Self::seal) under the same session key.
+    ///
+    /// Fails with [`InternalError::SealOpenHmacError`] if the session key is
+    /// not the one of the server, or if the announcement was modified.
+    pub fn open(session_key: &[u8], sealed: &[u8]) -> Result<Self, ProtocolError> {
+        let key_len = <CS::KeGroup as KeGroup>::PkLen::USIZE;
+        let tag_len = <CS::Hash as Digest>::OutputSize::USIZE;
+        let sealed = check_slice_size(sealed, key_len + CS::KEY_ID_LEN + tag_len, "key_rollover")?;
+        let (ciphertext, tag) = sealed.split_at(key_len + CS::KEY_ID_LEN);
+
+        let hkdf = Hkdf::<CS::Hash>::new(None, session_key);
+        mac::<CS>(&hkdf, ciphertext)?
+            .verify(tag)
+            .map_err(|_| InternalError::SealOpenHmacError)?;
+
+        let mut plaintext = pad::<CS>(&hkdf)?;
+        plaintext
+            .iter_mut()
+            .zip(ciphertext.iter())
+            .for_each(|(x1, &x2)| *x1 ^= x2);
+
+        Ok(Self {
+            public_key: PublicKey::deserialize(&plaintext[..key_len])?,
+            key_id: plaintext[key_len..].to_vec(),
+        })
+    }
+}
+
+fn pad<CS: CipherSuite>(hkdf: &Hkdf<CS::Hash>) -> Result<Zeroizing<Vec<u8>>, ProtocolError> {
+    let mut pad = Zeroizing::new(vec![
+        0u8;
+        <CS::KeGroup as KeGroup>::PkLen::USIZE + CS::KEY_ID_LEN
+    ]);
+    hkdf.expand_multi_info(&[CS::CONTEXT, STR_ROLLOVER_PAD], &mut pad)
+        .map_err(|_| InternalError::HkdfError)?;
+    Ok(pad)
+}
+
+fn mac<CS: CipherSuite>(
+    hkdf: &Hkdf<CS::Hash>,
+    ciphertext: &[u8],
+) -> Result<Hmac<CS::Hash>, ProtocolError> {
+    let mut mac_key = Zeroizing::new(vec![0u8; <CS::Hash as Digest>::OutputSize::USIZE]);
+    hkdf.expand_multi_info(&[CS::CONTEXT, STR_ROLLOVER_MAC], &mut mac_key)
+        .map_err(|_| InternalError::HkdfError)?;
+    let mut mac =
+        Hmac::<CS::Hash>::new_from_slice(&mac_key).map_err(|_| InternalError::HmacError)?;
+    mac.update(ciphertext);
+    Ok(mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::ristretto::RistrettoPoint;
+    use rand::{rngs::OsRng, RngCore};
+
+    struct Default;
+
+    impl CipherSuite for Default {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = crate::key_exchange::tripledh::TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = crate::slow_hash::NoOpHash;
+        const KEY_ID_LEN: usize = 4;
+    }
+
+    #[test]
+    fn test_seal_open() -> Result<(), ProtocolError> {
+        let mut rng = OsRng;
+        let mut session_key = [0u8; 64];
+        rng.fill_bytes(&mut session_key);
+        let next_server_setup = ServerSetup::<Default>::new(&mut rng)?;
+
+        let rollover = KeyRollover::from_server_setup(&next_server_setup);
+        assert_eq!(rollover.key_id(), &next_server_setup.key_id()[..]);
+        let sealed = rollover.seal(&session_key)?;
+        assert_eq!(
+            KeyRollover::<Default>::open(&session_key, &sealed)?,
+            rollover
+        );
+
+        let rollover = KeyRollover::<Default>::new(rollover.public_key().clone(), b"next")?;
+        let sealed = rollover.seal(&session_key)?;
+        assert_eq!(
+            KeyRollover::<Default>::open(&session_key, &sealed)?,
+            rollover
+        );
+        assert!(matches!(
+            KeyRollover::<Default>::new(rollover.public_key().clone(), b"key"),
+            Err(ProtocolError::LibraryError(InternalError::SizeError {
+                name: "key_id",
+                ..
+            }))
+        ));
+
+        // Only the holder of the same session key can open the announcement,
+        // and any modification is detected
+        assert!(matches!(
+            KeyRollover::<Default>::open(&[0u8; 64], &sealed),
+            Err(ProtocolError::LibraryError(
+                InternalError::SealOpenHmacError
+            ))
+        ));
+        for index in [0, 32, sealed.len() - 1].iter().cloned() {
+            let mut modified = sealed.clone();
+            modified[index] ^= 1;
+            assert!(matches!(
+                KeyRollover::<Default>::open(&session_key, &modified),
+                Err(ProtocolError::LibraryError(
+                    InternalError::SealOpenHmacError
+                ))
+            ));
+        }
+        assert!(matches!(
+            KeyRollover::<Default>::open(&session_key, &sealed[1..]),
+            Err(ProtocolError::LibraryError(InternalError::SizeError {
+                name: "key_rollover",
+                ..
+            }))
+        ));
+
+        Ok(())
+    }
+}
@@ -43,27 +43,55 @@ pub(crate) fn serialize(input: &[u8], max_bytes: usize) -> Result<Vec<u8>, Proto
     Ok([&i2osp(input.len(), max_bytes)?, input].concat())
 }
 
+// Appends I2OSP(len, max_bytes) to `output` without allocating, for length
+// prefixes of values which are then serialized in place
+pub(crate) fn serialize_len_into(
+    len: usize,
+    max_bytes: usize,
+    output: &mut Vec<u8>,
+) -> Result<(), ProtocolError> {
+    let sizeof_usize = core::mem::size_of::<usize>();
+    if max_bytes > sizeof_usize
+        || (sizeof_usize as u32 - len.leading_zeros() / 8) > max_bytes as u32
+    {
+        return Err(ProtocolError::SerializationError);
+    }
+
+    output.extend_from_slice(&len.to_be_bytes()[sizeof_usize - max_bytes..]);
+    Ok(())
+}
+
 // Tokenizes an input of the format I2OSP(len(input), max_bytes) || input, outputting
-// (input, remainder)
-pub(crate) fn tokenize(
-    input: &[u8],
-    size_bytes: usize,
-) -> Result<(Vec<u8>, Vec<u8>), ProtocolError> {
+// (input, remainder). Both outputs borrow from the input, so that a large remainder
+// is never copied before it has been validated
+pub(crate) fn tokenize(input: &[u8], size_bytes: usize) -> Result<(&[u8], &[u8]), ProtocolError> {
     if size_bytes > core::mem::size_of::<usize>() || input.len() < size_bytes {
         return Err(ProtocolError::SerializationError);
     }
 
     let size = os2ip(&input[..size_bytes])?;
-    if size_bytes + size > input.len() {
+    if size > input.len() - size_bytes {
         return Err(ProtocolError::SerializationError);
     }
 
     Ok((
-        input[size_bytes..size_bytes + size].to_vec(),
-        input[size_bytes + size..].to_vec(),
+        &input[size_bytes..size_bytes + size],
+        &input[size_bytes + size..],
     ))
 }
 
+// Displays a byte string as lowercase hex without allocating
+pub(crate) struct Hex<'a>(pub(crate) &'a [u8]);
+
+impl core::fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests;
 
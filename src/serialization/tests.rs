@@ -12,7 +12,7 @@ use crate::{
         tripledh::{NonceLen, TripleDH},
     },
     keypair::KeyPair,
-    serialization::{i2osp, os2ip, serialize},
+    serialization::{serialize, tokenize},
     *,
 };
 #[cfg(test)]
@@ -20,8 +20,11 @@ use alloc::vec;
 #[cfg(test)]
 use alloc::vec::Vec;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::serialization::{i2osp, os2ip};
 use curve25519_dalek::ristretto::RistrettoPoint;
 use generic_array::typenum::Unsigned;
+#[cfg(not(target_arch = "wasm32"))]
 use proptest::{collection::vec, prelude::*};
 use rand::{rngs::OsRng, RngCore};
 use voprf::group::Group;
@@ -157,6 +160,14 @@ fn registration_response_roundtrip() -> Result<(), ProtocolError> {
         _ => false,
     });
 
+    // Assert that an identity or non-canonical server public key is rejected
+    for bad_pk in [identity.to_arr().to_vec(), vec![0xff; 32]].iter() {
+        assert!(matches!(
+            RegistrationResponse::<Default>::deserialize(&[&beta_bytes[..], bad_pk].concat()),
+            Err(ProtocolError::InvalidServerStaticKeyError)
+        ));
+    }
+
     Ok(())
 }
 
@@ -179,8 +190,10 @@ fn registration_upload_roundtrip() -> Result<(), ProtocolError> {
     let (envelope, _, _) = Envelope::<Default>::seal_raw(
         randomized_pwd_hasher,
         &nonce,
+        Vec::new(),
         &pubkey_bytes,
         InnerEnvelopeMode::Internal,
+        false,
     )
     .unwrap();
     let envelope_bytes = envelope.serialize();
@@ -194,6 +207,17 @@ fn registration_upload_roundtrip() -> Result<(), ProtocolError> {
     let r3_bytes = r3.serialize()?;
     assert_eq!(input, r3_bytes);
 
+    // Assert that an identity or non-canonical client public key is rejected
+    let identity = RistrettoPoint::identity();
+    for bad_pk in [identity.to_arr().to_vec(), vec![0xff; 32]].iter() {
+        assert!(matches!(
+            RegistrationUpload::<Default>::deserialize(
+                &[bad_pk, &masking_key[..], &envelope_bytes[..]].concat()
+            ),
+            Err(ProtocolError::InvalidClientStaticKeyError)
+        ));
+    }
+
     Ok(())
 }
 
@@ -302,6 +326,186 @@ fn credential_finalization_roundtrip() -> Result<(), ProtocolError> {
     Ok(())
 }
 
+#[test]
+fn framed_message_dispatch() -> Result<(), ProtocolError> {
+    use crate::framing::{frame_len, Message, MessageType};
+
+    let mut rng = OsRng;
+    let registration_request = ClientRegistration::<Default>::start(&mut rng, b"password")?.message;
+    let credential_request = ClientLogin::<Default>::start(&mut rng, b"password")?.message;
+
+    let stream = [
+        Message::from(registration_request.clone()).serialize()?,
+        Message::from(credential_request.clone()).serialize()?,
+    ]
+    .concat();
+
+    assert_eq!(frame_len(&stream[..2])?, None);
+    let first_len = frame_len(&stream)?.unwrap();
+    let (first, second) = stream.split_at(first_len);
+    assert_eq!(frame_len(second)?, Some(second.len()));
+
+    match Message::<Default>::deserialize(first)? {
+        Message::RegistrationRequest(message) => {
+            assert_eq!(message.serialize()?, registration_request.serialize()?)
+        }
+        other => panic!("unexpected message {:?}", other),
+    }
+    let message = Message::<Default>::deserialize(second)?;
+    assert_eq!(message.message_type(), MessageType::CredentialRequest);
+    match message {
+        Message::CredentialRequest(message) => {
+            assert_eq!(message.serialize()?, credential_request.serialize()?)
+        }
+        other => panic!("unexpected message {:?}", other),
+    }
+
+    // Unknown type byte, and trailing bytes after a frame
+    let mut unknown = first.to_vec();
+    unknown[0] = 0xff;
+    assert!(frame_len(&unknown).is_err());
+    assert!(Message::<Default>::deserialize(&unknown).is_err());
+    assert!(Message::<Default>::deserialize(&stream).is_err());
+    Ok(())
+}
+
+#[test]
+fn framed_message_peek() -> Result<(), ProtocolError> {
+    use crate::framing::{peek_message_type, Message, MessageType};
+
+    let mut rng = OsRng;
+    let credential_request = ClientLogin::<Default>::start(&mut rng, b"password")?.message;
+    let frame = Message::from(credential_request).serialize()?;
+    assert_eq!(peek_message_type(&frame)?, MessageType::CredentialRequest);
+
+    // Truncated frames, trailing bytes and unknown type bytes
+    assert!(peek_message_type(&frame[..2]).is_err());
+    assert!(peek_message_type(&frame[..frame.len() - 1]).is_err());
+    assert!(peek_message_type(&[&frame[..], &[0]].concat()).is_err());
+    let mut unknown = frame;
+    unknown[0] = 0xff;
+    assert!(peek_message_type(&unknown).is_err());
+    Ok(())
+}
+
+#[test]
+fn fragmented_message_reassembly() -> Result<(), ProtocolError> {
+    use crate::framing::{Message, MessageType, Reassembler, FRAGMENT_HEADER_LEN, HEADER_LEN};
+
+    let mut rng = OsRng;
+    let credential_request = ClientLogin::<Default>::start(&mut rng, b"password")?.message;
+    let message = Message::from(credential_request.clone());
+    let frame = message.serialize()?;
+    assert_eq!(
+        frame.len(),
+        MessageType::CredentialRequest.max_frame_len::<Default>()
+    );
+    assert_eq!(
+        Message::<Default>::max_frame_len(),
+        HEADER_LEN + CredentialResponse::<Default>::max_serialized_len()
+    );
+
+    // Fragments of 20 bytes carry 18 bytes of the frame each
+    let fragments = message.fragment(20)?;
+    assert_eq!(
+        fragments.len(),
+        (frame.len() + 17) / (20 - FRAGMENT_HEADER_LEN)
+    );
+    assert!(fragments.iter().all(|fragment| fragment.len() <= 20));
+
+    // In order, reversed, and with repeated fragments
+    let mut reassembler = Reassembler::new(Message::<Default>::max_frame_len());
+    for order in [
+        fragments.iter().collect::<Vec<_>>(),
+        fragments.iter().rev().collect(),
+        fragments
+            .iter()
+            .flat_map(|fragment| vec![fragment, fragment])
+            .collect(),
+    ]
+    .iter()
+    {
+        let mut reassembled = None;
+        for fragment in order.iter() {
+            if let Some(frame) = reassembler.push(fragment)? {
+                assert!(reassembled.is_none());
+                reassembled = Some(frame);
+            }
+        }
+        match Message::<Default>::deserialize(&reassembled.unwrap())? {
+            Message::CredentialRequest(message) => {
+                assert_eq!(message.serialize()?, credential_request.serialize()?)
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    // Fragments which are malformed, inconsistent or over the size limit are
+    // rejected, and discard the partial frame
+    assert!(reassembler.push(&[]).is_err());
+    assert!(reassembler.push(&[2, 2, 0]).is_err());
+    assert!(reassembler.push(&fragments[0])?.is_none());
+    assert!(reassembler.push(&[1, 2, 0]).is_err());
+    assert!(reassembler.push(&fragments[1])?.is_none());
+    let mut small = Reassembler::new(frame.len() - 1);
+    assert!(fragments
+        .iter()
+        .map(|fragment| small.push(fragment))
+        .any(|result| result.is_err()));
+    let mut truncated = fragments.clone();
+    truncated.last_mut().unwrap().pop();
+    assert!(truncated
+        .iter()
+        .map(|fragment| reassembler.push(fragment))
+        .any(|result| result.is_err()));
+
+    assert!(message.fragment(FRAGMENT_HEADER_LEN).is_err());
+    assert!(Message::from(credential_request).fragment(3).is_ok());
+    assert!(crate::framing::fragment(&[0u8; 256], 3).is_err());
+    Ok(())
+}
+
+#[test]
+fn credential_response_display() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let pt = random_ristretto_point();
+    let mut masking_nonce = [0u8; 32];
+    rng.fill_bytes(&mut masking_nonce);
+    let mut masked_response =
+        vec![0u8; <RistrettoPoint as Group>::ElemLen::USIZE + Envelope::<Default>::len()];
+    rng.fill_bytes(&mut masked_response);
+    let server_e_kp = KeyPair::<<Default as CipherSuite>::OprfGroup>::generate_random(&mut rng)?;
+    let mut mac = [0u8; MAC_SIZE];
+    rng.fill_bytes(&mut mac);
+    let mut server_nonce = [0u8; NonceLen::USIZE];
+    rng.fill_bytes(&mut server_nonce);
+    let ke2m: Vec<u8> = [&server_nonce[..], server_e_kp.public(), &mac[..]].concat();
+
+    let input = [
+        &pt.to_arr()[..],
+        &masking_nonce[..],
+        &masked_response[..],
+        &ke2m[..],
+    ]
+    .concat();
+    let l2 = CredentialResponse::<Default>::deserialize(&input)?;
+    assert_eq!(l2.to_hex()?, hex::encode(&input[..]));
+
+    let displayed = l2.to_string();
+    assert!(displayed.starts_with(&format!("CredentialResponse ({} bytes) {{", input.len())));
+    assert!(displayed.contains(&format!(
+        "masking_nonce (32 bytes): {}",
+        hex::encode(masking_nonce)
+    )));
+    assert!(displayed.contains(&format!(
+        "ke2_message ({} bytes): {}",
+        ke2m.len(),
+        hex::encode(&ke2m[..])
+    )));
+
+    Ok(())
+}
+
 #[test]
 fn client_login_roundtrip() -> Result<(), ProtocolError> {
     let pw = b"hunter2";
@@ -344,6 +548,17 @@ fn ke1_message_roundtrip() -> Result<(), ProtocolError> {
     let reg_bytes = reg.to_bytes();
     assert_eq!(reg_bytes, ke1m);
 
+    // Assert that an identity or non-canonical ephemeral key is rejected
+    let identity = RistrettoPoint::identity();
+    for bad_pk in [identity.to_arr().to_vec(), vec![0xff; 32]].iter() {
+        assert!(matches!(
+            <TripleDH as KeyExchange<sha2::Sha512, RistrettoPoint>>::KE1Message::from_bytes::<
+                Default,
+            >(&[&client_nonce[..], bad_pk].concat()),
+            Err(ProtocolError::InvalidClientEphemeralKeyError)
+        ));
+    }
+
     Ok(())
 }
 
@@ -365,6 +580,17 @@ fn ke2_message_roundtrip() -> Result<(), ProtocolError> {
     let reg_bytes = reg.to_bytes();
     assert_eq!(reg_bytes, ke2m);
 
+    // Assert that an identity or non-canonical ephemeral key is rejected
+    let identity = RistrettoPoint::identity();
+    for bad_pk in [identity.to_arr().to_vec(), vec![0xff; 32]].iter() {
+        assert!(matches!(
+            <TripleDH as KeyExchange<sha2::Sha512, RistrettoPoint>>::KE2Message::from_bytes::<
+                Default,
+            >(&[&server_nonce[..], bad_pk, &mac[..]].concat()),
+            Err(ProtocolError::InvalidServerEphemeralKeyError)
+        ));
+    }
+
     Ok(())
 }
 
@@ -385,6 +611,190 @@ fn ke3_message_roundtrip() -> Result<(), ProtocolError> {
     Ok(())
 }
 
+#[test]
+fn key_exchange_submessages() -> Result<(), ProtocolError> {
+    use crate::key_exchange::tripledh::{Ke1Message, Ke2Message, Ke3Message};
+
+    let mut rng = OsRng;
+    let alpha_bytes = random_ristretto_point().to_arr().to_vec();
+    let client_e_kp = KeyPair::<<Default as CipherSuite>::KeGroup>::generate_random(&mut rng)?;
+    let mut client_nonce = vec![0u8; NonceLen::USIZE];
+    rng.fill_bytes(&mut client_nonce);
+    let ke1m: Vec<u8> = [&client_nonce[..], &client_e_kp.public()[..]].concat();
+
+    let credential_request =
+        CredentialRequest::<Default>::deserialize(&[&alpha_bytes[..], &ke1m[..]].concat())?;
+    let ke1_message = credential_request.ke1_message();
+    assert_eq!(ke1_message.client_nonce().as_slice(), &client_nonce[..]);
+    assert_eq!(ke1_message.client_e_pk(), client_e_kp.public());
+    assert_eq!(ke1_message.serialize(), ke1m);
+    assert_eq!(&Ke1Message::deserialize(&ke1m)?, ke1_message);
+
+    let beta_bytes = random_ristretto_point().to_arr().to_vec();
+    let mut masking_nonce = vec![0u8; 32];
+    rng.fill_bytes(&mut masking_nonce);
+    let mut masked_response =
+        vec![0u8; <RistrettoPoint as Group>::ElemLen::USIZE + Envelope::<Default>::len()];
+    rng.fill_bytes(&mut masked_response);
+    let server_e_kp = KeyPair::<<Default as CipherSuite>::KeGroup>::generate_random(&mut rng)?;
+    let mut server_nonce = vec![0u8; NonceLen::USIZE];
+    rng.fill_bytes(&mut server_nonce);
+    let mut mac = [0u8; MAC_SIZE];
+    rng.fill_bytes(&mut mac);
+    let ke2m: Vec<u8> = [&server_nonce[..], &server_e_kp.public()[..], &mac[..]].concat();
+
+    let credential_response = CredentialResponse::<Default>::deserialize(
+        &[&beta_bytes[..], &masking_nonce, &masked_response, &ke2m].concat(),
+    )?;
+    assert_eq!(credential_response.masking_nonce(), &masking_nonce[..]);
+    assert_eq!(credential_response.masked_response(), &masked_response[..]);
+    let ke2_message = credential_response.ke2_message();
+    assert_eq!(ke2_message.server_nonce().as_slice(), &server_nonce[..]);
+    assert_eq!(ke2_message.server_e_pk(), server_e_kp.public());
+    assert_eq!(ke2_message.mac().as_slice(), &mac[..]);
+    assert_eq!(ke2_message.serialize(), ke2m);
+    assert_eq!(&Ke2Message::deserialize(&ke2m)?, ke2_message);
+
+    let credential_finalization = CredentialFinalization::<Default>::deserialize(&mac)?;
+    let ke3_message = credential_finalization.ke3_message();
+    assert_eq!(ke3_message.mac().as_slice(), &mac[..]);
+    assert_eq!(&Ke3Message::deserialize(&mac)?, ke3_message);
+
+    Ok(())
+}
+
+// Checks that `bytes` is canonical: it is reproduced by re-encoding, and none
+// of the encodings differing from it by one bit decodes to another value
+fn assert_canonical<T>(
+    bytes: &[u8],
+    deserialize: fn(&[u8]) -> Result<T, ProtocolError>,
+    canonical_bytes: fn(&T) -> Result<Vec<u8>, ProtocolError>,
+) -> Result<(), ProtocolError> {
+    assert_eq!(canonical_bytes(&deserialize(bytes)?)?, bytes);
+    for bit in 0..bytes.len() * 8 {
+        let mut modified = bytes.to_vec();
+        modified[bit / 8] ^= 1 << (bit % 8);
+        if let Ok(value) = deserialize(&modified) {
+            assert_eq!(canonical_bytes(&value)?, modified);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn canonical_serialization() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<Default>::start(&mut rng, b"password")?;
+    let registration_request = client_registration_start_result.message.canonical_bytes()?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let registration_response = server_registration_start_result.message.canonical_bytes()?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let registration_upload = client_registration_finish_result
+        .message
+        .canonical_bytes()?;
+    let password_file =
+        ServerRegistration::finish(client_registration_finish_result.message.clone());
+    let password_file_with_identity = ServerRegistration::finish_with_client_identity(
+        client_registration_finish_result.message,
+        b"alice",
+    );
+
+    let client_login_start_result = ClientLogin::<Default>::start(&mut rng, b"password")?;
+    let credential_request = client_login_start_result.message.canonical_bytes()?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(password_file.clone()),
+        client_login_start_result.message,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    )?;
+    let credential_response = server_login_start_result.message.canonical_bytes()?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters::default(),
+    )?;
+    let credential_finalization = client_login_finish_result.message.canonical_bytes()?;
+
+    assert_canonical(
+        &registration_request,
+        RegistrationRequest::<Default>::deserialize,
+        RegistrationRequest::canonical_bytes,
+    )?;
+    assert_canonical(
+        &registration_response,
+        RegistrationResponse::<Default>::deserialize,
+        RegistrationResponse::canonical_bytes,
+    )?;
+    assert_canonical(
+        &registration_upload,
+        RegistrationUpload::<Default>::deserialize,
+        RegistrationUpload::canonical_bytes,
+    )?;
+    assert_canonical(
+        &credential_request,
+        CredentialRequest::<Default>::deserialize,
+        CredentialRequest::canonical_bytes,
+    )?;
+    assert_canonical(
+        &credential_response,
+        CredentialResponse::<Default>::deserialize,
+        CredentialResponse::canonical_bytes,
+    )?;
+    assert_canonical(
+        &credential_finalization,
+        CredentialFinalization::<Default>::deserialize,
+        CredentialFinalization::canonical_bytes,
+    )?;
+    for record in [password_file, password_file_with_identity].iter() {
+        assert_eq!(record.canonical_bytes()?, record.serialize()?);
+        assert_canonical(
+            &record.canonical_bytes()?,
+            ServerRegistration::<Default>::deserialize,
+            ServerRegistration::canonical_bytes,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn oversized_input_rejected() -> Result<(), ProtocolError> {
+    let hostile = vec![0u8; 1 << 20];
+
+    assert!(matches!(
+        Envelope::<Default>::deserialize(&hostile),
+        Err(ProtocolError::LibraryError(InternalError::SizeError {
+            name: "envelope_bytes",
+            ..
+        }))
+    ));
+    assert!(matches!(
+        RegistrationUpload::<Default>::deserialize(&hostile),
+        Err(ProtocolError::LibraryError(InternalError::SizeError {
+            name: "registration_upload_bytes",
+            ..
+        }))
+    ));
+    assert!(ClientRegistration::<Default>::deserialize(&hostile).is_err());
+    assert!(ClientLogin::<Default>::deserialize(&hostile).is_err());
+
+    // A length prefix pointing past the end of the input
+    assert!(tokenize(&[0xff, 0xff, 0x00], 2).is_err());
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 proptest! {
 
 #[test]
@@ -0,0 +1,370 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Ed25519 signatures over registration records, by which the server detects
+//! records that were tampered with or corrupted in the credential database
+//!
+//! Requires the `signed-records` feature.
+//!
+//! [sign] appends to the
+//! [canonical bytes](ServerRegistration::canonical_bytes) of a record a
+//! signature over them and over the credential identifier the record is
+//! stored under, so that records cannot be swapped between users either.
+//! [verify] checks the signature when the record is loaded back. The signing
+//! key is independent of the [ServerSetup](crate::ServerSetup), so that the
+//! database can be audited by services which only hold the [VerifyingKey].
+//!
+//! ```
+//! use opaque_ke::signed::{self, SigningKey};
+//! # use opaque_ke::{
+//! #   errors::ProtocolError,
+//! #   ClientRegistration, ClientRegistrationFinishParameters, ServerRegistration, ServerSetup,
+//! # };
+//! # use opaque_ke::CipherSuite;
+//! # struct Default;
+//! # impl CipherSuite for Default {
+//! #     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+//! #     type Hash = sha2::Sha512;
+//! #     type SlowHash = opaque_ke::slow_hash::NoOpHash;
+//! # }
+//! # use rand::rngs::OsRng;
+//! # let mut rng = OsRng;
+//! # let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+//! # let client_registration_start_result = ClientRegistration::<Default>::start(&mut rng, b"password")?;
+//! # let server_registration_start_result = ServerRegistration::<Default>::start(&server_setup, client_registration_start_result.message, b"alice@example.com")?;
+//! # let client_registration_finish_result = client_registration_start_result.state.finish(&mut rng, server_registration_start_result.message, ClientRegistrationFinishParameters::default())?;
+//! let signing_key = SigningKey::generate(&mut rng)?;
+//!
+//! // When the record is created
+//! let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+//! let stored = signed::sign(&signing_key, b"alice@example.com", &password_file)?;
+//!
+//! // When the record is loaded
+//! let loaded = signed::verify::<Default>(&signing_key.verifying_key(), b"alice@example.com", &stored)?;
+//! assert_eq!(loaded, password_file);
+//! # Ok::<(), ProtocolError>(())
+//! ```
+
+use crate::{
+    ciphersuite::CipherSuite,
+    errors::{
+        utils::{check_slice_size, fill_random},
+        InternalError, ProtocolError,
+    },
+    impls::Redacted,
+    serialization::serialize,
+    ServerRegistration,
+};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use ed25519_dalek::{ExpandedSecretKey, PublicKey, SecretKey, Signature};
+use rand::{CryptoRng, RngCore};
+use zeroize::{Zeroize, Zeroizing};
+
+/// The length of a [SigningKey]
+pub const SIGNING_KEY_LEN: usize = 32;
+/// The length of a [VerifyingKey]
+pub const VERIFYING_KEY_LEN: usize = 32;
+/// The length of the signature appended to a signed record
+pub const SIGNATURE_LEN: usize = 64;
+
+const STR_SIGNED_RECORD: &[u8; 19] = b"OPAQUE-SignedRecord";
+
+/// An Ed25519 private key, for signing registration records
+#[derive(Clone)]
+pub struct SigningKey {
+    seed: [u8; SIGNING_KEY_LEN],
+    verifying_key: VerifyingKey,
+}
+
+impl SigningKey {
+    /// Generates a random signing key
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Result<Self, InternalError> {
+        let mut seed = Zeroizing::new([0u8; SIGNING_KEY_LEN]);
+        fill_random(rng, &mut seed[..])?;
+        Self::from_seed(&seed[..])
+    }
+
+    /// Deserialization from the 32-byte seed of RFC 8032
+    pub fn from_bytes(input: &[u8]) -> Result<Self, ProtocolError> {
+        let input = check_slice_size(input, SIGNING_KEY_LEN, "signing_key")?;
+        Ok(Self::from_seed(input)?)
+    }
+
+    /// Serialization into the 32-byte seed of RFC 8032
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(self.seed.to_vec())
+    }
+
+    /// The public key matching this signing key
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.verifying_key
+    }
+
+    fn from_seed(seed: &[u8]) -> Result<Self, InternalError> {
+        let secret_key = Self::secret_key(seed)?;
+        let mut key = Self {
+            seed: [0u8; SIGNING_KEY_LEN],
+            verifying_key: VerifyingKey(PublicKey::from(&secret_key)),
+        };
+        key.seed.copy_from_slice(seed);
+        Ok(key)
+    }
+
+    // Both keys zeroize themselves on drop
+    fn secret_key(seed: &[u8]) -> Result<SecretKey, InternalError> {
+        SecretKey::from_bytes(seed).map_err(|_| InternalError::InvalidKeypairError)
+    }
+
+    // Signs `message` as specified by RFC 8032
+    fn sign(&self, message: &[u8]) -> Result<[u8; SIGNATURE_LEN], InternalError> {
+        let expanded_secret_key = ExpandedSecretKey::from(&Self::secret_key(&self.seed)?);
+        Ok(expanded_secret_key
+            .sign(message, &self.verifying_key.0)
+            .to_bytes())
+    }
+}
+
+impl core::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("SigningKey")
+            .field(&Redacted(SIGNING_KEY_LEN))
+            .finish()
+    }
+}
+
+impl Drop for SigningKey {
+    fn drop(&mut self) {
+        self.seed.zeroize();
+    }
+}
+
+/// An Ed25519 public key, for verifying signed registration records
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VerifyingKey(PublicKey);
+
+impl VerifyingKey {
+    /// Deserialization from the 32-byte encoding of RFC 8032, rejecting
+    /// invalid encodings and points of small order
+    pub fn from_bytes(input: &[u8]) -> Result<Self, ProtocolError> {
+        let input = check_slice_size(input, VERIFYING_KEY_LEN, "verifying_key")?;
+        // No signature would verify under such a key, so it is rejected
+        // before it can be stored
+        let compressed = CompressedEdwardsY::from_slice(input);
+        match compressed.decompress() {
+            Some(point) if !point.is_small_order() && point.compress() == compressed => Ok(Self(
+                PublicKey::from_bytes(input).map_err(|_| InternalError::PointError)?,
+            )),
+            _ => Err(InternalError::PointError.into()),
+        }
+    }
+
+    /// Serialization into the 32-byte encoding of RFC 8032
+    pub fn to_bytes(&self) -> [u8; VERIFYING_KEY_LEN] {
+        self.0.to_bytes()
+    }
+
+    // Verifies `signature` over `message` with the strict verification of
+    // ed25519-dalek, which rejects small-order and non-canonical values
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        Signature::try_from(signature)
+            .and_then(|signature| self.0.verify_strict(message, &signature))
+            .is_ok()
+    }
+}
+
+// The message covered by the signature of a record
+fn signed_message<CS: CipherSuite>(
+    credential_identifier: &[u8],
+    record: &[u8],
+) -> Result<Vec<u8>, ProtocolError> {
+    Ok([
+        &STR_SIGNED_RECORD[..],
+        &serialize(CS::CONTEXT, 2)?,
+        &serialize(credential_identifier, 2)?,
+        record,
+    ]
+    .concat())
+}
+
+/// Signs `record`, stored under `credential_identifier`, returning its
+/// canonical bytes followed by a [SIGNATURE_LEN]-byte signature
+pub fn sign<CS: CipherSuite>(
+    signing_key: &SigningKey,
+    credential_identifier: &[u8],
+    record: &ServerRegistration<CS>,
+) -> Result<Vec<u8>, ProtocolError> {
+    let mut output = record.canonical_bytes()?;
+    let signature = signing_key.sign(&signed_message::<CS>(credential_identifier, &output)?)?;
+    output.extend_from_slice(&signature);
+    Ok(output)
+}
+
+/// Verifies a record signed by [sign] under the same credential identifier,
+/// and deserializes it.
+///
+/// Fails with [`InternalError::RecordSignatureError`] if the signature does
+/// not match the record, the credential identifier or the verifying key.
+pub fn verify<CS: CipherSuite>(
+    verifying_key: &VerifyingKey,
+    credential_identifier: &[u8],
+    input: &[u8],
+) -> Result<ServerRegistration<CS>, ProtocolError> {
+    let record_len = input
+        .len()
+        .checked_sub(SIGNATURE_LEN)
+        .ok_or(InternalError::RecordSignatureError)?;
+    let (record, signature) = input.split_at(record_len);
+    if !verifying_key.verify(
+        &signed_message::<CS>(credential_identifier, record)?,
+        signature,
+    ) {
+        return Err(InternalError::RecordSignatureError.into());
+    }
+    ServerRegistration::deserialize(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ClientRegistration, ClientRegistrationFinishParameters, RegistrationUpload, ServerSetup,
+    };
+    use curve25519_dalek::ristretto::RistrettoPoint;
+    use rand::rngs::OsRng;
+
+    struct Default;
+
+    impl CipherSuite for Default {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = crate::key_exchange::tripledh::TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = crate::slow_hash::NoOpHash;
+    }
+
+    fn register(
+        server_setup: &ServerSetup<Default>,
+    ) -> Result<RegistrationUpload<Default>, ProtocolError> {
+        let mut rng = OsRng;
+        let client_registration_start_result =
+            ClientRegistration::<Default>::start(&mut rng, b"password")?;
+        let server_registration_start_result = ServerRegistration::start(
+            server_setup,
+            client_registration_start_result.message,
+            b"alice",
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters::default(),
+        )?;
+        Ok(client_registration_finish_result.message)
+    }
+
+    // Test vectors 1 and 2 of RFC 8032, section 7.1
+    #[test]
+    fn test_rfc8032_vectors() -> Result<(), ProtocolError> {
+        for (seed, public_key, message, signature) in [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+        ]
+        .iter()
+        {
+            let signing_key = SigningKey::from_bytes(&hex::decode(seed).unwrap())?;
+            let verifying_key = signing_key.verifying_key();
+            assert_eq!(hex::encode(verifying_key.to_bytes()), *public_key);
+            let message = hex::decode(message).unwrap();
+            assert_eq!(hex::encode(&signing_key.sign(&message)?[..]), *signature);
+            assert!(verifying_key.verify(&message, &hex::decode(signature).unwrap()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_verify() -> Result<(), ProtocolError> {
+        let mut rng = OsRng;
+        let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+        let signing_key = SigningKey::generate(&mut rng)?;
+        let verifying_key = signing_key.verifying_key();
+        assert_eq!(
+            VerifyingKey::from_bytes(&verifying_key.to_bytes())?,
+            verifying_key
+        );
+
+        for record in [
+            ServerRegistration::finish(register(&server_setup)?),
+            ServerRegistration::finish_with_client_identity(register(&server_setup)?, b"alice"),
+        ]
+        .iter()
+        {
+            let signed = sign(&signing_key, b"alice", record)?;
+            assert_eq!(signed.len(), record.serialized_len() + SIGNATURE_LEN);
+            assert_eq!(
+                &verify::<Default>(&verifying_key, b"alice", &signed)?,
+                record
+            );
+
+            // Another key or credential identifier, and any modification, are
+            // detected
+            let other_key = SigningKey::generate(&mut rng)?.verifying_key();
+            for (verifying_key, credential_identifier) in
+                [(&other_key, &b"alice"[..]), (&verifying_key, &b"bob"[..])].iter()
+            {
+                assert!(matches!(
+                    verify::<Default>(verifying_key, credential_identifier, &signed),
+                    Err(ProtocolError::LibraryError(
+                        InternalError::RecordSignatureError
+                    ))
+                ));
+            }
+            for index in [0, record.serialized_len(), signed.len() - 1]
+                .iter()
+                .cloned()
+            {
+                let mut modified = signed.clone();
+                modified[index] ^= 1;
+                assert!(matches!(
+                    verify::<Default>(&verifying_key, b"alice", &modified),
+                    Err(ProtocolError::LibraryError(
+                        InternalError::RecordSignatureError
+                    ))
+                ));
+            }
+            assert!(
+                verify::<Default>(&verifying_key, b"alice", &signed[..SIGNATURE_LEN - 1]).is_err()
+            );
+        }
+
+        // Signatures with a small-order commitment or a non-canonical scalar
+        // are rejected
+        let message = b"message";
+        let signature = signing_key.sign(message)?;
+        assert!(verifying_key.verify(message, &signature));
+        let mut small_order = signature;
+        small_order[..32].copy_from_slice(&[0u8; 32]);
+        assert!(!verifying_key.verify(message, &small_order));
+        let mut non_canonical = signature;
+        non_canonical[32..].copy_from_slice(&[0xff; 32]);
+        assert!(!verifying_key.verify(message, &non_canonical));
+
+        assert!(VerifyingKey::from_bytes(&[0u8; VERIFYING_KEY_LEN]).is_err());
+        assert!(SigningKey::from_bytes(&[0u8; SIGNING_KEY_LEN - 1]).is_err());
+        Ok(())
+    }
+}
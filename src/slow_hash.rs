@@ -7,42 +7,341 @@
 
 use crate::{errors::InternalError, hash::Hash};
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 use digest::Digest;
-#[cfg(feature = "slow-hash")]
 use generic_array::typenum::Unsigned;
-use generic_array::GenericArray;
+#[cfg(feature = "slow-hash")]
+use zeroize::Zeroizing;
+
+/// Callback receiving the number of completed steps and the total number of
+/// steps of a slow hash computation
+///
+/// The granularity of the steps depends on the slow hash. The Argon2
+/// implementations of this module hash in a single call to the `argon2`
+/// crate, which does not report the completion of passes or lanes, and so
+/// only report the start `(0, 1)` and the completion `(1, 1)`.
+/// `IteratedArgon2` runs each pass as its own call and reports every one.
+pub type ProgressCallback<'a> = &'a dyn Fn(u32, u32);
+
+/// A token which can be shared with another thread in order to cancel a slow
+/// hash computation
+///
+/// The token is only observed between the steps of the slow hash, see
+/// [`SlowHash::hash_with_cancellation`]. The Argon2 implementations of this
+/// module hash in a single call to the `argon2` crate, which cannot be
+/// interrupted: a cancelled token keeps them from starting and discards
+/// their output, but a computation which has started runs to completion.
+/// `IteratedArgon2` observes the token between its passes, so that it stops
+/// at most one pass after cancellation.
+#[derive(Debug, Default)]
+pub struct CancellationToken(AtomicBool);
+
+impl CancellationToken {
+    /// Create a new token which has not been cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation of the computations observing this token
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether cancellation has been requested
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns [`InternalError::SlowHashCancelledError`] if cancellation has
+    /// been requested
+    pub fn check(&self) -> Result<(), InternalError> {
+        if self.is_cancelled() {
+            return Err(InternalError::SlowHashCancelledError);
+        }
+        Ok(())
+    }
+}
 
 /// Used for the slow hashing function in OPAQUE
+///
+/// The input is the full OPRF output, and the output has the length
+/// [`SlowHash::OUTPUT_LEN`], which need not match the output size of `D`.
+/// Implementations can report their own errors with
+/// [`InternalError::external`], which the protocol functions return as is.
 pub trait SlowHash<D: Hash>: Default {
-    /// Computes the slow hashing function
-    fn hash(
+    /// The length of the output of the slow hashing function. Defaults to the
+    /// output size of `D`.
+    const OUTPUT_LEN: usize = <D as Digest>::OutputSize::USIZE;
+
+    /// Computes the slow hashing function of `input` into `output_len` bytes
+    fn hash(&self, input: &[u8], output_len: usize) -> Result<Vec<u8>, InternalError>;
+
+    /// Computes the slow hashing function, reporting progress through
+    /// `progress`. The default implementation only reports the start and the
+    /// completion of [`SlowHash::hash`] as a single step.
+    fn hash_with_progress(
+        &self,
+        input: &[u8],
+        output_len: usize,
+        progress: ProgressCallback<'_>,
+    ) -> Result<Vec<u8>, InternalError> {
+        progress(0, 1);
+        let output = self.hash(input, output_len)?;
+        progress(1, 1);
+        Ok(output)
+    }
+
+    /// Computes the slow hashing function, aborting with
+    /// [`InternalError::SlowHashCancelledError`] once `token` is cancelled.
+    /// The default implementation can only observe `token` before and after
+    /// running [`SlowHash::hash`], so implementations which iterate over
+    /// several passes should override it to check `token` between passes.
+    fn hash_with_cancellation(
         &self,
-        input: GenericArray<u8, <D as Digest>::OutputSize>,
-    ) -> Result<Vec<u8>, InternalError>;
+        input: &[u8],
+        output_len: usize,
+        progress: Option<ProgressCallback<'_>>,
+        token: &CancellationToken,
+    ) -> Result<Vec<u8>, InternalError> {
+        token.check()?;
+        let output = match progress {
+            Some(progress) => self.hash_with_progress(input, output_len, progress),
+            None => self.hash(input, output_len),
+        }?;
+        token.check()?;
+        Ok(output)
+    }
 }
 
-/// A no-op hash which simply returns its input
+/// A no-op hash which simply returns its input, and thus fails with
+/// [`InternalError::SlowHashError`] if asked for an output of another length
 #[derive(Default)]
 pub struct NoOpHash;
 
 impl<D: Hash> SlowHash<D> for NoOpHash {
-    fn hash(
-        &self,
-        input: GenericArray<u8, <D as Digest>::OutputSize>,
-    ) -> Result<Vec<u8>, InternalError> {
+    fn hash(&self, input: &[u8], output_len: usize) -> Result<Vec<u8>, InternalError> {
+        if input.len() != output_len {
+            return Err(InternalError::SlowHashError);
+        }
         Ok(input.to_vec())
     }
 }
 
+// Only the start and the completion are reported by the default
+// `hash_with_progress`, and cancellation is only observed before and after
+// hashing by the default `hash_with_cancellation`, since `argon2` does not
+// expose its passes
 #[cfg(feature = "slow-hash")]
 impl<D: Hash> SlowHash<D> for argon2::Argon2<'_> {
-    fn hash(
+    fn hash(&self, input: &[u8], output_len: usize) -> Result<Vec<u8>, InternalError> {
+        let mut output = alloc::vec![0u8; output_len];
+        self.hash_password_into(input, &[0; argon2::MIN_SALT_LEN], &mut output)
+            .map_err(|_| InternalError::SlowHashError)?;
+        Ok(output)
+    }
+}
+
+/// Argon2id keyed with an application-supplied secret (a pepper) and
+/// associated data, which are both mixed into the hash in addition to the
+/// password. The pepper is typically held by the client rather than stored
+/// with the password file, so it must be supplied again on every login.
+///
+/// Use it as the [`SlowHash`] of a ciphersuite and pass the keyed instance
+/// through the `slow_hash` field of the finish parameters. Its [`Default`]
+/// value has no pepper and no associated data, and then matches
+/// `argon2::Argon2::default()`.
+#[cfg(feature = "slow-hash")]
+#[derive(Clone, Default)]
+pub struct Argon2WithSecret {
+    secret: Zeroizing<Vec<u8>>,
+    params: argon2::Params,
+}
+
+#[cfg(feature = "slow-hash")]
+impl Argon2WithSecret {
+    /// Creates an instance hashing with Argon2id under `params`, keyed with
+    /// `secret` and binding `associated_data`. Fails with
+    /// [`InternalError::SlowHashError`] if either input exceeds the lengths
+    /// supported by Argon2.
+    pub fn new(
+        secret: &[u8],
+        associated_data: &[u8],
+        params: argon2::Params,
+    ) -> Result<Self, InternalError> {
+        let mut builder = argon2::ParamsBuilder::new();
+        builder
+            .m_cost(params.m_cost())
+            .and_then(|builder| builder.t_cost(params.t_cost()))
+            .and_then(|builder| builder.p_cost(params.p_cost()))
+            .and_then(|builder| builder.data(associated_data))
+            .map_err(|_| InternalError::SlowHashError)?;
+        let slow_hash = Self {
+            secret: Zeroizing::new(secret.to_vec()),
+            params: builder.params().map_err(|_| InternalError::SlowHashError)?,
+        };
+        slow_hash.argon2()?;
+        Ok(slow_hash)
+    }
+
+    fn argon2(&self) -> Result<argon2::Argon2<'_>, InternalError> {
+        argon2::Argon2::new_with_secret(
+            &self.secret,
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            self.params.clone(),
+        )
+        .map_err(|_| InternalError::SlowHashError)
+    }
+}
+
+#[cfg(feature = "slow-hash")]
+impl core::fmt::Debug for Argon2WithSecret {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Argon2WithSecret")
+            .field("secret", &crate::impls::Redacted(self.secret.len()))
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+#[cfg(feature = "slow-hash")]
+impl<D: Hash> SlowHash<D> for Argon2WithSecret {
+    fn hash(&self, input: &[u8], output_len: usize) -> Result<Vec<u8>, InternalError> {
+        SlowHash::<D>::hash(&self.argon2()?, input, output_len)
+    }
+}
+
+/// Argon2id run as a chain of single-pass hashes, each hashing the output of
+/// the previous one, so that the completion of every pass can be reported
+/// through [`SlowHash::hash_with_progress`] and cancellation observed
+/// between passes by [`SlowHash::hash_with_cancellation`]
+///
+/// The number of passes of the chain is the `t_cost` of its parameters,
+/// while each pass uses their memory and parallelism costs. The output
+/// differs from that of `argon2::Argon2` with the same parameters, so a
+/// registration must be hashed with the same slow hash on login.
+#[cfg(feature = "slow-hash")]
+#[derive(Clone, Debug, Default)]
+pub struct IteratedArgon2 {
+    params: argon2::Params,
+}
+
+#[cfg(feature = "slow-hash")]
+impl IteratedArgon2 {
+    /// Creates an instance running `params.t_cost()` passes of Argon2id
+    /// with the memory and parallelism costs of `params`
+    pub fn new(params: argon2::Params) -> Self {
+        Self { params }
+    }
+
+    fn hash_passes(
         &self,
-        input: GenericArray<u8, <D as Digest>::OutputSize>,
+        input: &[u8],
+        output_len: usize,
+        progress: Option<ProgressCallback<'_>>,
+        token: Option<&CancellationToken>,
     ) -> Result<Vec<u8>, InternalError> {
-        let mut output = alloc::vec![0u8; <D as Digest>::OutputSize::USIZE];
-        self.hash_password_into(&input, &[0; argon2::MIN_SALT_LEN], &mut output)
+        let params = argon2::Params::new(self.params.m_cost(), 1, self.params.p_cost(), None)
             .map_err(|_| InternalError::SlowHashError)?;
-        Ok(output)
+        let argon2 =
+            argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+        let passes = self.params.t_cost();
+
+        let mut output = Zeroizing::new(input.to_vec());
+        for pass in 0..passes {
+            if let Some(token) = token {
+                token.check()?;
+            }
+            if let Some(progress) = progress {
+                progress(pass, passes);
+            }
+            let mut next = Zeroizing::new(alloc::vec![0u8; output_len]);
+            argon2
+                .hash_password_into(&output, &[0; argon2::MIN_SALT_LEN], &mut next)
+                .map_err(|_| InternalError::SlowHashError)?;
+            output = next;
+        }
+        if let Some(token) = token {
+            token.check()?;
+        }
+        if let Some(progress) = progress {
+            progress(passes, passes);
+        }
+        Ok(output.to_vec())
+    }
+}
+
+#[cfg(feature = "slow-hash")]
+impl<D: Hash> SlowHash<D> for IteratedArgon2 {
+    fn hash(&self, input: &[u8], output_len: usize) -> Result<Vec<u8>, InternalError> {
+        self.hash_passes(input, output_len, None, None)
+    }
+
+    fn hash_with_progress(
+        &self,
+        input: &[u8],
+        output_len: usize,
+        progress: ProgressCallback<'_>,
+    ) -> Result<Vec<u8>, InternalError> {
+        self.hash_passes(input, output_len, Some(progress), None)
+    }
+
+    fn hash_with_cancellation(
+        &self,
+        input: &[u8],
+        output_len: usize,
+        progress: Option<ProgressCallback<'_>>,
+        token: &CancellationToken,
+    ) -> Result<Vec<u8>, InternalError> {
+        self.hash_passes(input, output_len, progress, Some(token))
+    }
+}
+
+/// Benchmarks Argon2id on this device and returns the parameters with which
+/// hashing a password takes at least `target`, e.g. 250 ms, so that clients
+/// can tune the slow hash to their hardware. The memory cost, in KiB, is
+/// doubled up to `max_m_cost` before the number of passes is increased.
+///
+/// The returned parameters can be used with `argon2::Argon2::new` or with
+/// [`Argon2WithSecret::new`]. Since logging in requires the parameters used
+/// on registration, they must be persisted along with the registration.
+#[cfg(all(feature = "slow-hash", feature = "std"))]
+pub fn calibrate_argon2(
+    target: std::time::Duration,
+    max_m_cost: u32,
+) -> Result<argon2::Params, InternalError> {
+    use core::convert::TryFrom;
+
+    const INITIAL_M_COST: u32 = 1024;
+
+    let mut m_cost = INITIAL_M_COST.min(max_m_cost);
+    let mut t_cost = 1;
+    loop {
+        let params = argon2::Params::new(m_cost, t_cost, 1, None)
+            .map_err(|_| InternalError::SlowHashError)?;
+        let argon2 = argon2::Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            params.clone(),
+        );
+        let mut output = [0u8; 64];
+        let start = std::time::Instant::now();
+        argon2
+            .hash_password_into(&[0; 64], &[0; argon2::MIN_SALT_LEN], &mut output)
+            .map_err(|_| InternalError::SlowHashError)?;
+        let elapsed = start.elapsed();
+
+        if elapsed >= target {
+            return Ok(params);
+        }
+        if m_cost < max_m_cost {
+            m_cost = m_cost.saturating_mul(2).min(max_m_cost);
+        } else {
+            // The running time is about linear in the number of passes
+            let estimate = target.as_nanos() * u128::from(t_cost) / elapsed.as_nanos().max(1);
+            t_cost = t_cost
+                .saturating_add(1)
+                .max(u32::try_from(estimate).unwrap_or(u32::MAX));
+        }
     }
 }
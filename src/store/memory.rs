@@ -0,0 +1,139 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! An in-memory [CredentialStore], for examples, tests and getting started
+//!
+//! Requires the `memory-store` feature.
+//!
+//! ```
+//! use opaque_ke::store::{self, memory::MemoryStore};
+//! # use opaque_ke::{
+//! #   errors::ProtocolError,
+//! #   ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+//! #   ClientRegistrationFinishParameters, ServerLoginStartParameters, ServerRegistration,
+//! #   ServerSetup,
+//! # };
+//! # use opaque_ke::CipherSuite;
+//! # struct Default;
+//! # impl CipherSuite for Default {
+//! #     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+//! #     type Hash = sha2::Sha512;
+//! #     type SlowHash = opaque_ke::slow_hash::NoOpHash;
+//! # }
+//! # use rand::rngs::OsRng;
+//! # let mut rng = OsRng;
+//! # let server_setup = ServerSetup::<Default>::new(&mut rng).unwrap();
+//! # let client_registration_start_result = ClientRegistration::<Default>::start(&mut rng, b"password")?;
+//! # let server_registration_start_result = ServerRegistration::<Default>::start(&server_setup, client_registration_start_result.message, b"alice@example.com")?;
+//! # let client_registration_finish_result = client_registration_start_result.state.finish(&mut rng, server_registration_start_result.message, ClientRegistrationFinishParameters::default())?;
+//! # let client_login_start_result = ClientLogin::<Default>::start(&mut rng, b"password")?;
+//! # fn block_on<F: core::future::Future>(future: F) -> F::Output {
+//! #     struct NoopWaker;
+//! #     impl std::task::Wake for NoopWaker { fn wake(self: std::sync::Arc<Self>) {} }
+//! #     let waker = std::task::Waker::from(std::sync::Arc::new(NoopWaker));
+//! #     match Box::pin(future).as_mut().poll(&mut std::task::Context::from_waker(&waker)) {
+//! #         std::task::Poll::Ready(output) => output,
+//! #         std::task::Poll::Pending => unreachable!(),
+//! #     }
+//! # }
+//! let store = MemoryStore::<Default>::new();
+//!
+//! // Inside the async handlers of the server
+//! block_on(store::finish_registration(
+//!     &store,
+//!     b"alice@example.com",
+//!     client_registration_finish_result.message,
+//! ))?;
+//! let server_login_start_result = block_on(store::start_login(
+//!     &store,
+//!     &mut rng,
+//!     &server_setup,
+//!     client_login_start_result.message,
+//!     b"alice@example.com",
+//!     ServerLoginStartParameters::default(),
+//! ))?;
+//! # let client_login_finish_result = client_login_start_result.state.finish(server_login_start_result.message, ClientLoginFinishParameters::default()).map_err(ProtocolError::into_custom)?;
+//! # server_login_start_result.state.finish(client_login_finish_result.message).map_err(ProtocolError::into_custom)?;
+//! # Ok::<(), ProtocolError<core::convert::Infallible>>(())
+//! ```
+
+use super::{CredentialStore, StoreFuture};
+use crate::{ciphersuite::CipherSuite, ServerRegistration};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// A [CredentialStore] keeping the records in a [HashMap] behind a [Mutex]
+pub struct MemoryStore<CS: CipherSuite> {
+    records: Mutex<HashMap<Vec<u8>, ServerRegistration<CS>>>,
+}
+
+impl<CS: CipherSuite> MemoryStore<CS> {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self {
+            records: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the number of stored records
+    pub fn len(&self) -> usize {
+        self.records().len()
+    }
+
+    /// Returns `true` if no record is stored
+    pub fn is_empty(&self) -> bool {
+        self.records().is_empty()
+    }
+
+    // The map is never left half-updated, so a panic while the lock was held
+    // does not invalidate it
+    fn records(&self) -> MutexGuard<'_, HashMap<Vec<u8>, ServerRegistration<CS>>> {
+        self.records.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<CS: CipherSuite> Default for MemoryStore<CS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<CS: CipherSuite> CredentialStore<CS> for MemoryStore<CS>
+where
+    ServerRegistration<CS>: Send,
+{
+    type Error = Infallible;
+
+    fn get<'a>(
+        &'a self,
+        credential_identifier: &'a [u8],
+    ) -> StoreFuture<'a, Option<ServerRegistration<CS>>, Self::Error> {
+        Box::pin(async move { Ok(self.records().get(credential_identifier).cloned()) })
+    }
+
+    fn put<'a>(
+        &'a self,
+        credential_identifier: &'a [u8],
+        record: ServerRegistration<CS>,
+    ) -> StoreFuture<'a, (), Self::Error> {
+        Box::pin(async move {
+            self.records()
+                .insert(credential_identifier.to_vec(), record);
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, credential_identifier: &'a [u8]) -> StoreFuture<'a, (), Self::Error> {
+        Box::pin(async move {
+            self.records().remove(credential_identifier);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Asynchronous storage of registration records, along with the server flows
+//! which use it
+//!
+//! A server implements [CredentialStore] over its database, and then calls
+//! [finish_registration] and [start_login] instead of fetching and storing
+//! the records itself. The methods of [CredentialStore] return boxed futures,
+//! so that the trait can be implemented without any macro and used as a
+//! trait object.
+//!
+//! Errors of the store are returned as
+//! [`InternalError::Custom`](crate::errors::InternalError::Custom), so that
+//! they are never mistaken for a missing record.
+//!
+//! The [memory] module provides an in-memory store behind the `memory-store`
+//! feature, for running a complete server before plugging in a database.
+
+use crate::{
+    ciphersuite::CipherSuite, errors::ProtocolError, keypair::SecretKey, CredentialRequest,
+    RegistrationUpload, ServerLogin, ServerLoginStartParameters, ServerLoginStartResult,
+    ServerRegistration, ServerSetup,
+};
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use rand::{CryptoRng, RngCore};
+
+#[cfg(feature = "memory-store")]
+pub mod memory;
+
+/// The future returned by the methods of [CredentialStore]
+pub type StoreFuture<'a, T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'a>>;
+
+/// Storage for the registration records of a server, keyed by credential
+/// identifier
+pub trait CredentialStore<CS: CipherSuite> {
+    /// The error returned when the underlying storage fails
+    type Error;
+
+    /// Fetches the record stored for `credential_identifier`, if any
+    fn get<'a>(
+        &'a self,
+        credential_identifier: &'a [u8],
+    ) -> StoreFuture<'a, Option<ServerRegistration<CS>>, Self::Error>;
+
+    /// Stores `record` for `credential_identifier`, replacing any previous
+    /// record
+    fn put<'a>(
+        &'a self,
+        credential_identifier: &'a [u8],
+        record: ServerRegistration<CS>,
+    ) -> StoreFuture<'a, (), Self::Error>;
+
+    /// Removes the record stored for `credential_identifier`, if any
+    fn delete<'a>(&'a self, credential_identifier: &'a [u8]) -> StoreFuture<'a, (), Self::Error>;
+}
+
+/// Completes a registration by storing the record built from `message` for
+/// `credential_identifier`
+pub async fn finish_registration<CS: CipherSuite, S: CredentialStore<CS>>(
+    store: &S,
+    credential_identifier: &[u8],
+    message: RegistrationUpload<CS>,
+) -> Result<(), ProtocolError<S::Error>> {
+    store
+        .put(credential_identifier, ServerRegistration::finish(message))
+        .await
+        .map_err(custom)
+}
+
+/// Fetches the record for `credential_identifier` and runs
+/// [`ServerLogin::start`] with it.
+///
+/// Without a stored record, login starts with a fake record instead, which
+/// goes through the same computations and yields a response of the same
+/// shape, so that clients cannot tell whether the credential identifier is
+/// registered.
+///
+/// The errors of the secret key of `server_setup` are converted into those of
+/// the store with [From]. With the default
+/// [PrivateKey](crate::keypair::PrivateKey), whose errors are
+/// [Infallible](core::convert::Infallible), the store error must implement
+/// `From<Infallible>`.
+pub async fn start_login<CS, S, K, R>(
+    store: &S,
+    rng: &mut R,
+    server_setup: &ServerSetup<CS, K>,
+    credential_request: CredentialRequest<CS>,
+    credential_identifier: &[u8],
+    params: ServerLoginStartParameters,
+) -> Result<ServerLoginStartResult<CS>, ProtocolError<S::Error>>
+where
+    CS: CipherSuite,
+    S: CredentialStore<CS>,
+    S::Error: From<K::Error>,
+    K: SecretKey<CS::KeGroup>,
+    R: RngCore + CryptoRng + ?Sized,
+{
+    let password_file = store.get(credential_identifier).await.map_err(custom)?;
+
+    ServerLogin::start(
+        rng,
+        server_setup,
+        password_file,
+        credential_request,
+        credential_identifier,
+        params,
+    )
+    .map_err(|e| e.map_custom(From::from))
+}
+
+fn custom<E>(error: E) -> ProtocolError<E> {
+    ProtocolError::LibraryError(crate::errors::InternalError::Custom(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        errors::InternalError, ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+        ClientRegistrationFinishParameters,
+    };
+    use alloc::vec::Vec;
+    use curve25519_dalek::ristretto::RistrettoPoint;
+    use rand::rngs::OsRng;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    struct Default;
+
+    impl CipherSuite for Default {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = crate::key_exchange::tripledh::TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = crate::slow_hash::NoOpHash;
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Unavailable;
+
+    impl From<core::convert::Infallible> for Unavailable {
+        fn from(never: core::convert::Infallible) -> Self {
+            match never {}
+        }
+    }
+
+    #[derive(Default)]
+    struct TestStore {
+        records: Mutex<HashMap<Vec<u8>, ServerRegistration<Default>>>,
+        unavailable: bool,
+    }
+
+    impl CredentialStore<Default> for TestStore {
+        type Error = Unavailable;
+
+        fn get<'a>(
+            &'a self,
+            credential_identifier: &'a [u8],
+        ) -> StoreFuture<'a, Option<ServerRegistration<Default>>, Self::Error> {
+            Box::pin(async move {
+                if self.unavailable {
+                    return Err(Unavailable);
+                }
+                Ok(self
+                    .records
+                    .lock()
+                    .unwrap()
+                    .get(credential_identifier)
+                    .cloned())
+            })
+        }
+
+        fn put<'a>(
+            &'a self,
+            credential_identifier: &'a [u8],
+            record: ServerRegistration<Default>,
+        ) -> StoreFuture<'a, (), Self::Error> {
+            Box::pin(async move {
+                self.records
+                    .lock()
+                    .unwrap()
+                    .insert(credential_identifier.to_vec(), record);
+                Ok(())
+            })
+        }
+
+        fn delete<'a>(
+            &'a self,
+            credential_identifier: &'a [u8],
+        ) -> StoreFuture<'a, (), Self::Error> {
+            Box::pin(async move {
+                self.records.lock().unwrap().remove(credential_identifier);
+                Ok(())
+            })
+        }
+    }
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    fn login(
+        store: &TestStore,
+        server_setup: &ServerSetup<Default>,
+        password: &[u8],
+    ) -> Result<(), ProtocolError<Unavailable>> {
+        let mut rng = OsRng;
+        let client_login_start_result = ClientLogin::<Default>::start(&mut rng, password)
+            .map_err(ProtocolError::into_custom)?;
+        let server_login_start_result = block_on(start_login(
+            store,
+            &mut rng,
+            server_setup,
+            client_login_start_result.message,
+            b"alice",
+            ServerLoginStartParameters::default(),
+        ))?;
+        let client_login_finish_result = client_login_start_result
+            .state
+            .finish(
+                server_login_start_result.message,
+                ClientLoginFinishParameters::default(),
+            )
+            .map_err(ProtocolError::into_custom)?;
+        server_login_start_result
+            .state
+            .finish(client_login_finish_result.message)
+            .map_err(ProtocolError::into_custom)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_credential_store() -> Result<(), ProtocolError<Unavailable>> {
+        let mut rng = OsRng;
+        let server_setup = ServerSetup::<Default>::new(&mut rng)
+            .map_err(|e| ProtocolError::LibraryError(e.into_custom()))?;
+        let store = TestStore::default();
+
+        // An unknown client gets a response, which fails to log in
+        assert!(matches!(
+            login(&store, &server_setup, b"password"),
+            Err(ProtocolError::InvalidLoginError)
+        ));
+
+        let client_registration_start_result =
+            ClientRegistration::<Default>::start(&mut rng, b"password")
+                .map_err(ProtocolError::into_custom)?;
+        let server_registration_start_result = ServerRegistration::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"alice",
+        )
+        .map_err(ProtocolError::into_custom)?;
+        let client_registration_finish_result = client_registration_start_result
+            .state
+            .finish(
+                &mut rng,
+                server_registration_start_result.message,
+                ClientRegistrationFinishParameters::default(),
+            )
+            .map_err(ProtocolError::into_custom)?;
+        block_on(finish_registration(
+            &store,
+            b"alice",
+            client_registration_finish_result.message,
+        ))?;
+
+        login(&store, &server_setup, b"password")?;
+        assert!(matches!(
+            login(&store, &server_setup, b"wrong password"),
+            Err(ProtocolError::InvalidLoginError)
+        ));
+
+        block_on(store.delete(b"alice")).map_err(custom)?;
+        assert!(matches!(
+            login(&store, &server_setup, b"password"),
+            Err(ProtocolError::InvalidLoginError)
+        ));
+
+        // A failing store is reported as such, rather than as a missing record
+        let unavailable = TestStore {
+            unavailable: true,
+            ..TestStore::default()
+        };
+        assert!(matches!(
+            login(&unavailable, &server_setup, b"password"),
+            Err(ProtocolError::LibraryError(InternalError::Custom(
+                Unavailable
+            )))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,510 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! A loopback harness running registration and login between an in-process
+//! client and server, for smoke-testing storage and transport glue
+//!
+//! Requires the `testing` feature.
+//!
+//! Every message is wrapped in a [frame](crate::framing) and sent through a
+//! [Transport], and the server keeps its password files in a
+//! [PasswordFileStore]. Either can be replaced by the integrator's own
+//! implementation, while [channel_pair] and `HashMap` provide in-memory
+//! defaults:
+//!
+//! ```
+//! use opaque_ke::test_utils::{channel_pair, run_loopback};
+//! # use opaque_ke::CipherSuite;
+//! # struct Default;
+//! # impl CipherSuite for Default {
+//! #     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+//! #     type Hash = sha2::Sha512;
+//! #     type SlowHash = opaque_ke::slow_hash::NoOpHash;
+//! # }
+//! use std::collections::HashMap;
+//!
+//! let (mut client, mut server) = channel_pair();
+//! let mut store = HashMap::new();
+//! let result = run_loopback::<Default, _, _>(
+//!     b"password",
+//!     b"alice@example.com",
+//!     &mut client,
+//!     &mut server,
+//!     &mut store,
+//! )?;
+//! assert_eq!(result.client_session_key, result.server_session_key);
+//! assert!(store.contains_key(&b"alice@example.com"[..]));
+//! # Ok::<(), opaque_ke::errors::ProtocolError>(())
+//! ```
+//!
+//! The module also provides [account_existence_oracle], which checks that
+//! the login handler of a deployment answers alike for registered and
+//! unregistered accounts.
+
+use crate::{
+    ciphersuite::CipherSuite,
+    errors::{InternalError, ProtocolError},
+    framing::Message,
+    ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+    ClientRegistrationFinishParameters, ExportKey, ServerLogin, ServerLoginStartParameters,
+    ServerRegistration, ServerSetup, SessionKey,
+};
+use alloc::vec::Vec;
+use rand::rngs::OsRng;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Instant;
+
+/// One end of a connection carrying frames between the client and the
+/// server
+pub trait Transport {
+    /// Sends one frame to the other end
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), ProtocolError>;
+
+    /// Receives the next frame from the other end
+    fn recv(&mut self) -> Result<Vec<u8>, ProtocolError>;
+}
+
+/// Storage for the serialized password files of registered clients, keyed by
+/// credential identifier
+pub trait PasswordFileStore {
+    /// Stores the password file for `credential_identifier`, replacing any
+    /// previous one
+    fn store(&mut self, credential_identifier: &[u8], password_file: Vec<u8>);
+
+    /// Loads the password file for `credential_identifier`, if any
+    fn load(&self, credential_identifier: &[u8]) -> Option<Vec<u8>>;
+}
+
+impl PasswordFileStore for HashMap<Vec<u8>, Vec<u8>> {
+    fn store(&mut self, credential_identifier: &[u8], password_file: Vec<u8>) {
+        self.insert(credential_identifier.to_vec(), password_file);
+    }
+
+    fn load(&self, credential_identifier: &[u8]) -> Option<Vec<u8>> {
+        self.get(credential_identifier).cloned()
+    }
+}
+
+/// A [Transport] over an in-memory channel, created with [channel_pair]
+#[derive(Debug)]
+pub struct ChannelTransport {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl Transport for ChannelTransport {
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), ProtocolError> {
+        self.sender
+            .send(frame)
+            .map_err(|_| ProtocolError::SerializationError)
+    }
+
+    fn recv(&mut self) -> Result<Vec<u8>, ProtocolError> {
+        self.receiver
+            .recv()
+            .map_err(|_| ProtocolError::SerializationError)
+    }
+}
+
+/// Returns the client and server ends of an in-memory connection
+pub fn channel_pair() -> (ChannelTransport, ChannelTransport) {
+    let (client_sender, server_receiver) = channel();
+    let (server_sender, client_receiver) = channel();
+
+    (
+        ChannelTransport {
+            sender: client_sender,
+            receiver: client_receiver,
+        },
+        ChannelTransport {
+            sender: server_sender,
+            receiver: server_receiver,
+        },
+    )
+}
+
+/// The keys obtained by both parties in [run_loopback]
+#[derive(Debug)]
+pub struct LoopbackResult {
+    /// The export key obtained by the client during registration, which
+    /// matched the one obtained during login
+    pub export_key: ExportKey,
+    /// The session key obtained by the client
+    pub client_session_key: SessionKey,
+    /// The session key obtained by the server
+    pub server_session_key: SessionKey,
+}
+
+/// Registers `password` for `credential_identifier`, then logs in with it,
+/// sending every message through the given transports and keeping the
+/// password file in `store` between registration and login.
+///
+/// Fails with [`ProtocolError::SerializationError`] if a transport fails or
+/// delivers an unexpected frame, and with
+/// [`ProtocolError::InvalidLoginError`] if the password file could not be
+/// loaded back or the export keys of registration and login differ.
+pub fn run_loopback<CS: CipherSuite, T: Transport, S: PasswordFileStore>(
+    password: &[u8],
+    credential_identifier: &[u8],
+    client_transport: &mut T,
+    server_transport: &mut T,
+    store: &mut S,
+) -> Result<LoopbackResult, ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<CS>::new(&mut rng)?;
+
+    // Registration
+    let client_registration_start_result = ClientRegistration::<CS>::start(&mut rng, password)?;
+    send(
+        client_transport,
+        Message::RegistrationRequest(client_registration_start_result.message),
+    )?;
+
+    let registration_request = match recv::<CS, _>(server_transport)? {
+        Message::RegistrationRequest(message) => message,
+        _ => return Err(ProtocolError::SerializationError),
+    };
+    let server_registration_start_result = ServerRegistration::<CS>::start(
+        &server_setup,
+        registration_request,
+        credential_identifier,
+    )?;
+    send(
+        server_transport,
+        Message::RegistrationResponse(server_registration_start_result.message),
+    )?;
+
+    let registration_response = match recv::<CS, _>(client_transport)? {
+        Message::RegistrationResponse(message) => message,
+        _ => return Err(ProtocolError::SerializationError),
+    };
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        registration_response,
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+    send(
+        client_transport,
+        Message::RegistrationUpload(client_registration_finish_result.message),
+    )?;
+
+    let registration_upload = match recv::<CS, _>(server_transport)? {
+        Message::RegistrationUpload(message) => message,
+        _ => return Err(ProtocolError::SerializationError),
+    };
+    store.store(
+        credential_identifier,
+        ServerRegistration::finish(registration_upload).serialize()?,
+    );
+
+    // Login
+    let client_login_start_result = ClientLogin::<CS>::start(&mut rng, password)?;
+    send(
+        client_transport,
+        Message::CredentialRequest(client_login_start_result.message),
+    )?;
+
+    let credential_request = match recv::<CS, _>(server_transport)? {
+        Message::CredentialRequest(message) => message,
+        _ => return Err(ProtocolError::SerializationError),
+    };
+    let password_file = store
+        .load(credential_identifier)
+        .ok_or(ProtocolError::InvalidLoginError)?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(ServerRegistration::<CS>::deserialize(&password_file)?),
+        credential_request,
+        credential_identifier,
+        ServerLoginStartParameters::default(),
+    )?;
+    send(
+        server_transport,
+        Message::CredentialResponse(server_login_start_result.message),
+    )?;
+
+    let credential_response = match recv::<CS, _>(client_transport)? {
+        Message::CredentialResponse(message) => message,
+        _ => return Err(ProtocolError::SerializationError),
+    };
+    let client_login_finish_result = client_login_start_result.state.finish(
+        credential_response,
+        ClientLoginFinishParameters {
+            export_key: true,
+            ..ClientLoginFinishParameters::default()
+        },
+    )?;
+    send(
+        client_transport,
+        Message::CredentialFinalization(client_login_finish_result.message),
+    )?;
+
+    let credential_finalization = match recv::<CS, _>(server_transport)? {
+        Message::CredentialFinalization(message) => message,
+        _ => return Err(ProtocolError::SerializationError),
+    };
+    let server_login_finish_result = server_login_start_result
+        .state
+        .finish(credential_finalization)?;
+
+    if client_registration_finish_result.export_key != client_login_finish_result.export_key {
+        return Err(ProtocolError::InvalidLoginError);
+    }
+
+    Ok(LoopbackResult {
+        export_key: client_login_finish_result
+            .export_key
+            .ok_or(InternalError::ExportKeyNotRequestedError)?,
+        client_session_key: client_login_finish_result.session_key,
+        server_session_key: server_login_finish_result.session_key,
+    })
+}
+
+/// The Welch t-statistic above which [OracleReport::leaks] reports a timing
+/// difference, as used by dudect
+pub const ORACLE_T_THRESHOLD: f64 = 4.5;
+
+/// The comparison of the credential responses for a registered and an
+/// unregistered account, produced by [account_existence_oracle]
+#[derive(Clone, Debug, PartialEq)]
+pub struct OracleReport {
+    /// The number of logins run against each account
+    pub samples: usize,
+    /// The mean time taken by the handler for the registered account
+    pub registered_mean_nanos: f64,
+    /// The mean time taken by the handler for the unregistered account
+    pub unregistered_mean_nanos: f64,
+    /// Welch's t-statistic of the difference between the two timings
+    pub t_statistic: f64,
+    /// The number of responses which were not a credential response of the
+    /// length of the first one
+    pub shape_mismatches: usize,
+}
+
+impl OracleReport {
+    /// Returns `true` if the responses differ in shape, or if their timings
+    /// differ beyond [ORACLE_T_THRESHOLD]
+    pub fn leaks(&self) -> bool {
+        self.shape_mismatches > 0 || self.t_statistic.abs() > ORACLE_T_THRESHOLD
+    }
+}
+
+/// Runs `samples` logins against each of `registered` and `unregistered`,
+/// two credential identifiers of which only the first has a password file in
+/// the deployment, and compares the responses of `login_start`.
+///
+/// `login_start` stands for the login handler of the deployment: it receives
+/// a credential identifier and the frame of a credential request, and
+/// returns the frame of its response, after loading the password file from
+/// its store and calling [`ServerLogin::start`]. The logins alternate between
+/// the two accounts, so that a drift in the load of the machine affects
+/// both alike, and each response must be a [`Message::CredentialResponse`]
+/// of the same length.
+///
+/// Timings are only meaningful with enough samples, on a quiet machine and
+/// in a release build.
+pub fn account_existence_oracle<CS: CipherSuite, F>(
+    registered: &[u8],
+    unregistered: &[u8],
+    samples: usize,
+    mut login_start: F,
+) -> Result<OracleReport, ProtocolError>
+where
+    F: FnMut(&[u8], Vec<u8>) -> Result<Vec<u8>, ProtocolError>,
+{
+    let mut rng = OsRng;
+    let mut timings = [Vec::with_capacity(samples), Vec::with_capacity(samples)];
+    let mut response_len = None;
+    let mut shape_mismatches = 0;
+
+    for _ in 0..samples {
+        for (credential_identifier, timings) in [registered, unregistered].iter().zip(&mut timings)
+        {
+            let frame = Message::CredentialRequest(
+                ClientLogin::<CS>::start(&mut rng, b"password")?.message,
+            )
+            .serialize()?;
+
+            let start = Instant::now();
+            let response = login_start(*credential_identifier, frame)?;
+            timings.push(start.elapsed().as_nanos() as f64);
+
+            let is_credential_response = matches!(
+                Message::<CS>::deserialize(&response),
+                Ok(Message::CredentialResponse(_))
+            );
+            if !is_credential_response
+                || *response_len.get_or_insert(response.len()) != response.len()
+            {
+                shape_mismatches += 1;
+            }
+        }
+    }
+
+    let [registered_timings, unregistered_timings] = timings;
+    let (registered_mean, registered_variance) = mean_and_variance(&registered_timings);
+    let (unregistered_mean, unregistered_variance) = mean_and_variance(&unregistered_timings);
+    let standard_error = ((registered_variance + unregistered_variance) / samples as f64).sqrt();
+    let t_statistic = if standard_error > 0.0 {
+        (registered_mean - unregistered_mean) / standard_error
+    } else {
+        0.0
+    };
+
+    Ok(OracleReport {
+        samples,
+        registered_mean_nanos: registered_mean,
+        unregistered_mean_nanos: unregistered_mean,
+        t_statistic,
+        shape_mismatches,
+    })
+}
+
+// The mean and the unbiased sample variance
+fn mean_and_variance(values: &[f64]) -> (f64, f64) {
+    if values.len() < 2 {
+        return (values.iter().sum::<f64>(), 0.0);
+    }
+    let len = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / len;
+    let variance = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / (len - 1.0);
+    (mean, variance)
+}
+
+fn send<CS: CipherSuite, T: Transport>(
+    transport: &mut T,
+    message: Message<CS>,
+) -> Result<(), ProtocolError> {
+    transport.send(message.serialize()?)
+}
+
+fn recv<CS: CipherSuite, T: Transport>(transport: &mut T) -> Result<Message<CS>, ProtocolError> {
+    Message::deserialize(&transport.recv()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::ristretto::RistrettoPoint;
+
+    struct Default;
+
+    impl CipherSuite for Default {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = crate::key_exchange::tripledh::TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = crate::slow_hash::NoOpHash;
+    }
+
+    struct ForgetfulStore;
+
+    impl PasswordFileStore for ForgetfulStore {
+        fn store(&mut self, _: &[u8], _: Vec<u8>) {}
+
+        fn load(&self, _: &[u8]) -> Option<Vec<u8>> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_loopback() -> Result<(), ProtocolError> {
+        let (mut client, mut server) = channel_pair();
+        let mut store = HashMap::new();
+        let result = run_loopback::<Default, _, _>(
+            b"password",
+            b"credentialIdentifier",
+            &mut client,
+            &mut server,
+            &mut store,
+        )?;
+        assert_eq!(result.client_session_key, result.server_session_key);
+
+        let (mut client, mut server) = channel_pair();
+        assert!(matches!(
+            run_loopback::<Default, _, _>(
+                b"password",
+                b"credentialIdentifier",
+                &mut client,
+                &mut server,
+                &mut ForgetfulStore,
+            ),
+            Err(ProtocolError::InvalidLoginError)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_existence_oracle() -> Result<(), ProtocolError> {
+        let mut rng = OsRng;
+        let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+        let client_registration_start_result =
+            ClientRegistration::<Default>::start(&mut rng, b"password")?;
+        let server_registration_start_result = ServerRegistration::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"alice",
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters::default(),
+        )?;
+        let mut store = HashMap::new();
+        store.store(
+            b"alice",
+            ServerRegistration::finish(client_registration_finish_result.message).serialize()?,
+        );
+
+        let handler = |credential_identifier: &[u8], frame: Vec<u8>| {
+            let credential_request = match Message::<Default>::deserialize(&frame)? {
+                Message::CredentialRequest(message) => message,
+                _ => return Err(ProtocolError::SerializationError),
+            };
+            let password_file = store
+                .load(credential_identifier)
+                .map(|password_file| ServerRegistration::deserialize(&password_file))
+                .transpose()?;
+            let server_login_start_result = ServerLogin::start(
+                &mut OsRng,
+                &server_setup,
+                password_file,
+                credential_request,
+                credential_identifier,
+                ServerLoginStartParameters::default(),
+            )?;
+            Message::CredentialResponse(server_login_start_result.message).serialize()
+        };
+
+        let report = account_existence_oracle::<Default, _>(b"alice", b"bob", 16, &handler)?;
+        assert_eq!(report.samples, 16);
+        assert_eq!(report.shape_mismatches, 0);
+
+        // A handler rejecting unknown accounts is caught by the shape of its
+        // responses
+        let report = account_existence_oracle::<Default, _>(b"alice", b"bob", 4, |id, frame| {
+            if store.load(id).is_none() {
+                return Ok(Vec::new());
+            }
+            handler(id, frame)
+        })?;
+        assert_eq!(report.shape_mismatches, 4);
+        assert!(report.leaks());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! [proptest](https://docs.rs/proptest) strategies and helpers for crates
+//! which integrate with this one and want to property-test their own layers
+//! (transport, storage, FFI, ...) against it
+//!
+//! Requires the `testing` feature.
+//!
+//! ```
+//! use opaque_ke::testing::{assert_roundtrip, protocol_run_strategy};
+//! # use opaque_ke::{CipherSuite, RegistrationUpload};
+//! # struct Default;
+//! # impl CipherSuite for Default {
+//! #     type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+//! #     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+//! #     type Hash = sha2::Sha512;
+//! #     type SlowHash = opaque_ke::slow_hash::NoOpHash;
+//! # }
+//! use proptest::prelude::*;
+//!
+//! proptest! {
+//!     #![proptest_config(ProptestConfig::with_cases(8))]
+//!     fn upload_survives_storage(run in protocol_run_strategy::<Default>()) {
+//!         assert_roundtrip(
+//!             &run.registration_upload,
+//!             RegistrationUpload::serialize,
+//!             |bytes| RegistrationUpload::<Default>::deserialize(bytes),
+//!         )?;
+//!     }
+//! }
+//! # upload_survives_storage();
+//! ```
+
+use crate::{
+    ciphersuite::CipherSuite,
+    errors::{InternalError, ProtocolError},
+    ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+    ClientRegistrationFinishParameters, CredentialFinalization, CredentialRequest,
+    CredentialResponse, ExportKey, Identifiers, RegistrationRequest, RegistrationResponse,
+    RegistrationUpload, ServerLogin, ServerLoginStartParameters, ServerRegistration, ServerSetup,
+    SessionKey,
+};
+use alloc::format;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug};
+use proptest::{collection::vec, prelude::*, test_runner::TestCaseError};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// Strategy for a seeded CSPRNG, so that failing cases replay
+/// deterministically. Does not shrink, as a different seed yields unrelated
+/// output.
+pub fn rng_strategy() -> BoxedStrategy<StdRng> {
+    any::<[u8; 32]>()
+        .prop_map(StdRng::from_seed)
+        .no_shrink()
+        .boxed()
+}
+
+/// Strategy for arbitrary passwords, including the empty password and
+/// non-UTF-8 bytes
+pub fn password_strategy() -> BoxedStrategy<Vec<u8>> {
+    vec(any::<u8>(), 0..128).boxed()
+}
+
+/// Strategy for arbitrary credential identifiers
+pub fn credential_identifier_strategy() -> BoxedStrategy<Vec<u8>> {
+    vec(any::<u8>(), 0..64).boxed()
+}
+
+/// Strategy for every shape of [`Identifiers`], including none at all
+pub fn identifiers_strategy() -> BoxedStrategy<Option<Identifiers>> {
+    let id = || vec(any::<u8>(), 0..64);
+
+    prop_oneof![
+        Just(None),
+        id().prop_map(|id_u| Some(Identifiers::ClientIdentifier(id_u))),
+        id().prop_map(|id_s| Some(Identifiers::ServerIdentifier(id_s))),
+        (id(), id())
+            .prop_map(|(id_u, id_s)| Some(Identifiers::ClientAndServerIdentifiers(id_u, id_s))),
+    ]
+    .boxed()
+}
+
+/// Every message of a successful registration followed by a successful
+/// login, along with the inputs which produced them
+pub struct ProtocolRun<CS: CipherSuite> {
+    /// The password used for registration and login
+    pub password: Vec<u8>,
+    /// The credential identifier the server used
+    pub credential_identifier: Vec<u8>,
+    /// The identifiers both parties agreed on
+    pub identifiers: Option<Identifiers>,
+    /// The first registration message
+    pub registration_request: RegistrationRequest<CS>,
+    /// The second registration message
+    pub registration_response: RegistrationResponse<CS>,
+    /// The third registration message, i.e. the password file
+    pub registration_upload: RegistrationUpload<CS>,
+    /// The first login message
+    pub credential_request: CredentialRequest<CS>,
+    /// The second login message
+    pub credential_response: CredentialResponse<CS>,
+    /// The third login message
+    pub credential_finalization: CredentialFinalization<CS>,
+    /// The export key, identical on registration and login
+    pub export_key: ExportKey,
+    /// The session key both parties agreed on
+    pub session_key: SessionKey,
+}
+
+impl<CS: CipherSuite> ProtocolRun<CS> {
+    /// Runs registration followed by login with the given inputs
+    pub fn new(
+        rng: &mut StdRng,
+        password: Vec<u8>,
+        credential_identifier: Vec<u8>,
+        identifiers: Option<Identifiers>,
+    ) -> Result<Self, ProtocolError> {
+        let server_setup = ServerSetup::<CS>::new(rng)?;
+
+        let client_registration_start_result = ClientRegistration::<CS>::start(rng, &password)?;
+        let registration_request = client_registration_start_result.message.clone();
+        let server_registration_start_result = ServerRegistration::<CS>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            &credential_identifier,
+        )?;
+        let registration_response = server_registration_start_result.message.clone();
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters::new(identifiers.clone(), None),
+        )?;
+        let registration_upload = client_registration_finish_result.message.clone();
+        let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+        let client_login_start_result = ClientLogin::<CS>::start(rng, &password)?;
+        let credential_request = client_login_start_result.message.clone();
+        let server_login_start_result = ServerLogin::start(
+            rng,
+            &server_setup,
+            Some(password_file),
+            client_login_start_result.message,
+            &credential_identifier,
+            ServerLoginStartParameters::new(None, identifiers.clone()),
+        )?;
+        let credential_response = server_login_start_result.message.clone();
+        let client_login_finish_result = client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters {
+                export_key: true,
+                ..ClientLoginFinishParameters::new(None, identifiers.clone(), None)
+            },
+        )?;
+        let credential_finalization = client_login_finish_result.message.clone();
+        server_login_start_result
+            .state
+            .finish(client_login_finish_result.message)?;
+
+        Ok(Self {
+            password,
+            credential_identifier,
+            identifiers,
+            registration_request,
+            registration_response,
+            registration_upload,
+            credential_request,
+            credential_response,
+            credential_finalization,
+            export_key: client_login_finish_result
+                .export_key
+                .ok_or(InternalError::ExportKeyNotRequestedError)?,
+            session_key: client_login_finish_result.session_key,
+        })
+    }
+}
+
+impl<CS: CipherSuite> Debug for ProtocolRun<CS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProtocolRun")
+            .field("password", &self.password)
+            .field("credential_identifier", &self.credential_identifier)
+            .field("identifiers", &self.identifiers)
+            .field(
+                "registration_request",
+                &format_args!("{}", self.registration_request),
+            )
+            .field(
+                "registration_response",
+                &format_args!("{}", self.registration_response),
+            )
+            // The upload holds the masking key, so it has no `Display`
+            .field(
+                "registration_upload",
+                &format_args!("RegistrationUpload {{ .. }}"),
+            )
+            .field(
+                "credential_request",
+                &format_args!("{}", self.credential_request),
+            )
+            .field(
+                "credential_response",
+                &format_args!("{}", self.credential_response),
+            )
+            .field(
+                "credential_finalization",
+                &format_args!("{}", self.credential_finalization),
+            )
+            .field("export_key", &self.export_key)
+            .field("session_key", &self.session_key)
+            .finish()
+    }
+}
+
+/// Strategy for a complete [`ProtocolRun`] over arbitrary passwords,
+/// credential identifiers and identifiers
+pub fn protocol_run_strategy<CS: CipherSuite>() -> BoxedStrategy<ProtocolRun<CS>> {
+    (
+        rng_strategy(),
+        password_strategy(),
+        credential_identifier_strategy(),
+        identifiers_strategy(),
+    )
+        .prop_map(|(mut rng, password, credential_identifier, identifiers)| {
+            ProtocolRun::new(&mut rng, password, credential_identifier, identifiers)
+                .expect("a run with matching passwords and identifiers succeeds")
+        })
+        .boxed()
+}
+
+/// Checks that `serialize` → `deserialize` → `serialize` reproduces the same
+/// bytes for `value`, failing the current proptest case otherwise
+pub fn assert_roundtrip<T, E: Debug>(
+    value: &T,
+    serialize: impl Fn(&T) -> Result<Vec<u8>, E>,
+    deserialize: impl Fn(&[u8]) -> Result<T, E>,
+) -> Result<(), TestCaseError> {
+    let bytes = serialize(value).map_err(|e| TestCaseError::fail(format!("{:?}", e)))?;
+    let deserialized = deserialize(&bytes).map_err(|e| TestCaseError::fail(format!("{:?}", e)))?;
+    let reserialized =
+        serialize(&deserialized).map_err(|e| TestCaseError::fail(format!("{:?}", e)))?;
+    prop_assert_eq!(bytes, reserialized);
+    Ok(())
+}
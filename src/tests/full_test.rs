@@ -29,6 +29,36 @@ impl CipherSuite for RistrettoSha5123dhNoSlowHash {
     type SlowHash = NoOpHash;
 }
 
+struct RistrettoLegacyHashToCurve;
+impl CipherSuite for RistrettoLegacyHashToCurve {
+    type OprfGroup = RistrettoPoint;
+    type KeGroup = RistrettoPoint;
+    type KeyExchange = TripleDH;
+    type Hash = sha2::Sha512;
+    type SlowHash = NoOpHash;
+
+    const HASH_TO_CURVE: Option<ciphersuite::HashToCurve<RistrettoPoint>> =
+        Some(legacy_hash_to_curve);
+}
+
+#[cfg(feature = "aead-envelope")]
+struct RistrettoSha5123dhAeadEnvelope;
+#[cfg(feature = "aead-envelope")]
+impl CipherSuite for RistrettoSha5123dhAeadEnvelope {
+    type OprfGroup = RistrettoPoint;
+    type KeGroup = RistrettoPoint;
+    type KeyExchange = TripleDH;
+    type Hash = sha2::Sha512;
+    type SlowHash = NoOpHash;
+
+    const ENVELOPE_MODE: EnvelopeMode = EnvelopeMode::Aead;
+}
+
+// The encoding used before hash-to-curve was standardized
+fn legacy_hash_to_curve(password: &[u8]) -> Result<RistrettoPoint, voprf::errors::InternalError> {
+    Ok(RistrettoPoint::hash_from_bytes::<sha2::Sha512>(password))
+}
+
 pub struct TestVectorParameters {
     pub client_s_pk: Vec<u8>,
     pub client_s_sk: Vec<u8>,
@@ -98,7 +128,7 @@ static TEST_VECTOR: &str = r#"
     "credential_finalization": "e13284ada3e78eed48047934115ce7e6c2cdff0c3012e9ba2d423759c4000ddf11ecd186dd7f0740ee3413ff0d253e2437eced56f3717e45c071b170d12db1dd",
     "client_registration_state": "0028544ce97b02dff0201282a44cf73171a62a76e2a113d40dce8950f31bf433940370617373776f72640020f05048bb39f3f5a3a414f50254c425b36f842162a630bf73456df453351cb33d",
     "client_login_state": "0028544ce97b02dff0201282a44cf73171a62a76e2a113d40dce8950f31bf433940370617373776f72640060f05048bb39f3f5a3a414f50254c425b36f842162a630bf73456df453351cb33d652a39daf155cc9b5a005b67951f19c2ccdf4667cf7bcd39f941a87565ed4c2958a16b672e100b18069d0716715a9a8d9a643954bb24c0887e46d542eab9e4170040c1a4db9d650ce1700e05fbd472d30c13e0a4c6926b114e7ca11e2e9f397c5005652a39daf155cc9b5a005b67951f19c2ccdf4667cf7bcd39f941a87565ed4c29",
-    "server_login_state": "2a009e5881454a2b42fb8c039762f78828c5b4ba7008d2e57b16ffdc937ee846b56461f9cda751b2a1c2b03793d72d5ca8c482adf8009880779323e64e12eb1d4c0cf45ec665be918cb9d655f9eca974494f0f4c0f6e714c6ddcca37b547c122cf7419984e123fa4c7981212e5171b01bfd6f8ac88e7964c8da4a88b5df4f2c85da5318465cef76fbddd389ff36be66c693cfc6feecbcf43bf16a22c97de8430e824b2812449934d13fb666b24de78a007f1fc06064304b0abfae3fc5caba7f6",
+    "server_login_state": "01d2cfe1c92a009e5881454a2b42fb8c039762f78828c5b4ba7008d2e57b16ffdc937ee846b56461f9cda751b2a1c2b03793d72d5ca8c482adf8009880779323e64e12eb1d4c0cf45ec665be918cb9d655f9eca974494f0f4c0f6e714c6ddcca37b547c122cf7419984e123fa4c7981212e5171b01bfd6f8ac88e7964c8da4a88b5df4f2c85da5318465cef76fbddd389ff36be66c693cfc6feecbcf43bf16a22c97de8430e824b2812449934d13fb666b24de78a007f1fc06064304b0abfae3fc5caba7f6",
     "password_file": "08a51d9973140af4f911f235d4910e9536503157bfaffefaeaa11f69d723cc54d35d9ae50d6a0a7ab38614e571a81821cfbfec36ed9fd46e397e173252d02ff623287035e190153e9fb88509da1c225765bb200ed59249cbfd6201656d1672db2f21529b9fb27c8c12770b765dc36750c4a51c5ccaf2f83d0182504a85a22c0b19e07582aea6c5e782b15ff18f6188203f54ea62dfb1efb77d641f030b86062c9f0d1bc3c39b7f824fe81df456c702ea4fa084eba803fea7e5a80d2284c2ff15",
     "export_key": "ea8d1f871a3c8ad5d2a7a2d647e020105a33f8b8534055c56ab4bae2b8467d22806968159f918d9c31098602790fcad3e5969f1d8ff0b90b48c26b4132877ed4",
     "session_key": "5da5318465cef76fbddd389ff36be66c693cfc6feecbcf43bf16a22c97de8430e824b2812449934d13fb666b24de78a007f1fc06064304b0abfae3fc5caba7f6"
@@ -345,13 +375,16 @@ fn generate_parameters<CS: CipherSuite>() -> Result<TestVectorParameters, Protoc
         .finish(
             &mut finish_registration_rng,
             server_registration_start_result.message,
-            ClientRegistrationFinishParameters::new(
-                Some(Identifiers::ClientAndServerIdentifiers(
-                    id_u.to_vec(),
-                    id_s.to_vec(),
-                )),
-                None,
-            ),
+            ClientRegistrationFinishParameters {
+                export_key: true,
+                ..ClientRegistrationFinishParameters::new(
+                    Some(Identifiers::ClientAndServerIdentifiers(
+                        id_u.to_vec(),
+                        id_s.to_vec(),
+                    )),
+                    None,
+                )
+            },
         )
         .unwrap();
     let registration_upload_bytes = client_registration_finish_result.message.serialize()?;
@@ -388,9 +421,12 @@ fn generate_parameters<CS: CipherSuite>() -> Result<TestVectorParameters, Protoc
         Some(password_file),
         client_login_start_result.message,
         credential_identifier,
-        ServerLoginStartParameters::WithContextAndIdentifiers(
-            context.to_vec(),
-            Identifiers::ClientAndServerIdentifiers(id_u.to_vec(), id_s.to_vec()),
+        ServerLoginStartParameters::new(
+            Some(context.to_vec()),
+            Some(Identifiers::ClientAndServerIdentifiers(
+                id_u.to_vec(),
+                id_s.to_vec(),
+            )),
         ),
     )
     .unwrap();
@@ -401,14 +437,17 @@ fn generate_parameters<CS: CipherSuite>() -> Result<TestVectorParameters, Protoc
         .state
         .finish(
             server_login_start_result.message,
-            ClientLoginFinishParameters::new(
-                Some(context.to_vec()),
-                Some(Identifiers::ClientAndServerIdentifiers(
-                    id_u.to_vec(),
-                    id_s.to_vec(),
-                )),
-                None,
-            ),
+            ClientLoginFinishParameters {
+                export_key: true,
+                ..ClientLoginFinishParameters::new(
+                    Some(context.to_vec()),
+                    Some(Identifiers::ClientAndServerIdentifiers(
+                        id_u.to_vec(),
+                        id_s.to_vec(),
+                    )),
+                    None,
+                )
+            },
         )
         .unwrap();
     let credential_finalization_bytes = client_login_finish_result.message.serialize()?;
@@ -444,8 +483,16 @@ fn generate_parameters<CS: CipherSuite>() -> Result<TestVectorParameters, Protoc
         client_registration_state,
         client_login_state,
         server_login_state,
-        session_key: client_login_finish_result.session_key,
-        export_key: client_registration_finish_result.export_key.to_vec(),
+        session_key: client_login_finish_result
+            .session_key
+            .expose_secret()
+            .to_vec(),
+        export_key: client_registration_finish_result
+            .export_key
+            .as_ref()
+            .unwrap()
+            .expose_secret()
+            .to_vec(),
     })
 }
 
@@ -552,13 +599,16 @@ fn test_registration_upload() -> Result<(), ProtocolError> {
     .finish(
         &mut finish_registration_rng,
         RegistrationResponse::deserialize(&parameters.registration_response[..])?,
-        ClientRegistrationFinishParameters::new(
-            Some(Identifiers::ClientAndServerIdentifiers(
-                parameters.id_u,
-                parameters.id_s,
-            )),
-            None,
-        ),
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::new(
+                Some(Identifiers::ClientAndServerIdentifiers(
+                    parameters.id_u,
+                    parameters.id_s,
+                )),
+                None,
+            )
+        },
     )?;
 
     assert_eq!(
@@ -566,8 +616,8 @@ fn test_registration_upload() -> Result<(), ProtocolError> {
         hex::encode(result.message.serialize()?)
     );
     assert_eq!(
-        hex::encode(parameters.export_key),
-        hex::encode(result.export_key.to_vec())
+        hex::encode(&parameters.export_key),
+        hex::encode(result.export_key.as_ref().unwrap().expose_secret().to_vec())
     );
 
     Ok(())
@@ -647,9 +697,12 @@ fn test_credential_response() -> Result<(), ProtocolError> {
             &parameters.credential_request[..],
         )?,
         &parameters.credential_identifier,
-        ServerLoginStartParameters::WithContextAndIdentifiers(
-            parameters.context,
-            Identifiers::ClientAndServerIdentifiers(parameters.id_u, parameters.id_s),
+        ServerLoginStartParameters::new(
+            Some(parameters.context),
+            Some(Identifiers::ClientAndServerIdentifiers(
+                parameters.id_u,
+                parameters.id_s,
+            )),
         ),
     )?;
     assert_eq!(
@@ -674,14 +727,17 @@ fn test_credential_finalization() -> Result<(), ProtocolError> {
         CredentialResponse::<RistrettoSha5123dhNoSlowHash>::deserialize(
             &parameters.credential_response[..],
         )?,
-        ClientLoginFinishParameters::new(
-            Some(parameters.context),
-            Some(Identifiers::ClientAndServerIdentifiers(
-                parameters.id_u,
-                parameters.id_s,
-            )),
-            None,
-        ),
+        ClientLoginFinishParameters {
+            export_key: true,
+            ..ClientLoginFinishParameters::new(
+                Some(parameters.context),
+                Some(Identifiers::ClientAndServerIdentifiers(
+                    parameters.id_u,
+                    parameters.id_s,
+                )),
+                None,
+            )
+        },
     )?;
 
     assert_eq!(
@@ -690,7 +746,7 @@ fn test_credential_finalization() -> Result<(), ProtocolError> {
     );
     assert_eq!(
         hex::encode(&parameters.session_key),
-        hex::encode(&client_login_finish_result.session_key)
+        hex::encode(client_login_finish_result.session_key.expose_secret())
     );
     assert_eq!(
         hex::encode(&parameters.credential_finalization),
@@ -698,7 +754,13 @@ fn test_credential_finalization() -> Result<(), ProtocolError> {
     );
     assert_eq!(
         hex::encode(&parameters.export_key),
-        hex::encode(client_login_finish_result.export_key)
+        hex::encode(
+            client_login_finish_result
+                .export_key
+                .as_ref()
+                .unwrap()
+                .expose_secret()
+        )
     );
 
     Ok(())
@@ -716,41 +778,121 @@ fn test_server_login_finish() -> Result<(), ProtocolError> {
     )?)?;
 
     assert_eq!(
-        hex::encode(parameters.session_key),
-        hex::encode(&server_login_result.session_key)
+        hex::encode(&parameters.session_key),
+        hex::encode(server_login_result.session_key.expose_secret())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_max_serialized_len() -> Result<(), ProtocolError> {
+    type CS = RistrettoSha5123dhNoSlowHash;
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<CS>::new(&mut rng)?;
+
+    // The default mode of the ciphersuite sets the envelope length
+    let envelope_len = 32 + 64;
+    assert_eq!(RegistrationRequest::<CS>::max_serialized_len(), 32);
+    assert_eq!(RegistrationResponse::<CS>::max_serialized_len(), 64);
+    assert_eq!(
+        RegistrationUpload::<CS>::max_serialized_len(),
+        32 + 64 + envelope_len
+    );
+    assert_eq!(CredentialRequest::<CS>::max_serialized_len(), 96);
+    assert_eq!(
+        CredentialResponse::<CS>::max_serialized_len(),
+        32 + 32 + 32 + envelope_len + 32 + 32 + 64
+    );
+    assert_eq!(CredentialFinalization::<CS>::max_serialized_len(), 64);
+
+    let client_registration_start_result = ClientRegistration::<CS>::start(&mut rng, b"password")?;
+    let registration_request = client_registration_start_result.message.serialize()?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let registration_response = server_registration_start_result.message.serialize()?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let registration_upload = client_registration_finish_result.message.serialize()?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let client_login_start_result = ClientLogin::<CS>::start(&mut rng, b"password")?;
+    let credential_request = client_login_start_result.message.serialize()?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(password_file),
+        client_login_start_result.message,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    )?;
+    let credential_response = server_login_start_result.message.serialize()?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters::default(),
+    )?;
+    let credential_finalization = client_login_finish_result.message.serialize()?;
+
+    assert_eq!(
+        registration_request.len(),
+        RegistrationRequest::<CS>::max_serialized_len()
+    );
+    assert_eq!(
+        registration_response.len(),
+        RegistrationResponse::<CS>::max_serialized_len()
+    );
+    assert_eq!(
+        credential_request.len(),
+        CredentialRequest::<CS>::max_serialized_len()
+    );
+    assert_eq!(
+        credential_finalization.len(),
+        CredentialFinalization::<CS>::max_serialized_len()
+    );
+    assert_eq!(
+        registration_upload.len(),
+        RegistrationUpload::<CS>::max_serialized_len()
+    );
+    assert_eq!(
+        credential_response.len(),
+        CredentialResponse::<CS>::max_serialized_len()
     );
 
     Ok(())
 }
 
-fn test_complete_flow(
+fn test_complete_flow<CS: CipherSuite>(
     registration_password: &[u8],
     login_password: &[u8],
 ) -> Result<(), ProtocolError> {
     let credential_identifier = b"credentialIdentifier";
     let mut client_rng = OsRng;
     let mut server_rng = OsRng;
-    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+    let server_setup = ServerSetup::<CS>::new(&mut server_rng)?;
     let client_registration_start_result =
-        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &mut client_rng,
-            registration_password,
-        )?;
-    let server_registration_start_result =
-        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &server_setup,
-            client_registration_start_result.message,
-            credential_identifier,
-        )?;
+        ClientRegistration::<CS>::start(&mut client_rng, registration_password)?;
+    let server_registration_start_result = ServerRegistration::<CS>::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
+    )?;
     let client_registration_finish_result = client_registration_start_result.state.finish(
         &mut client_rng,
         server_registration_start_result.message,
-        ClientRegistrationFinishParameters::default(),
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::default()
+        },
     )?;
     let p_file = ServerRegistration::finish(client_registration_finish_result.message);
-    let client_login_start_result =
-        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, login_password)?;
-    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
+    let client_login_start_result = ClientLogin::<CS>::start(&mut client_rng, login_password)?;
+    let server_login_start_result = ServerLogin::<CS>::start(
         &mut server_rng,
         &server_setup,
         Some(p_file),
@@ -761,7 +903,10 @@ fn test_complete_flow(
 
     let client_login_result = client_login_start_result.state.finish(
         server_login_start_result.message,
-        ClientLoginFinishParameters::default(),
+        ClientLoginFinishParameters {
+            export_key: true,
+            ..ClientLoginFinishParameters::default()
+        },
     );
 
     if hex::encode(registration_password) == hex::encode(login_password) {
@@ -771,12 +916,28 @@ fn test_complete_flow(
             .finish(client_login_finish_result.message)?;
 
         assert_eq!(
-            hex::encode(&server_login_finish_result.session_key),
-            hex::encode(&client_login_finish_result.session_key)
+            hex::encode(server_login_finish_result.session_key.expose_secret()),
+            hex::encode(client_login_finish_result.session_key.expose_secret())
+        );
+        assert_eq!(
+            hex::encode(
+                client_registration_finish_result
+                    .export_key
+                    .as_ref()
+                    .unwrap()
+                    .expose_secret()
+            ),
+            hex::encode(
+                client_login_finish_result
+                    .export_key
+                    .as_ref()
+                    .unwrap()
+                    .expose_secret()
+            )
         );
         assert_eq!(
-            hex::encode(client_registration_finish_result.export_key),
-            hex::encode(client_login_finish_result.export_key)
+            server_login_finish_result.transcript_hash,
+            client_login_finish_result.transcript_hash
         );
     } else {
         assert!(match client_login_result {
@@ -790,238 +951,408 @@ fn test_complete_flow(
 
 #[test]
 fn test_complete_flow_success() -> Result<(), ProtocolError> {
-    test_complete_flow(b"good password", b"good password")
+    test_complete_flow::<RistrettoSha5123dhNoSlowHash>(b"good password", b"good password")
 }
 
 #[test]
 fn test_complete_flow_fail() -> Result<(), ProtocolError> {
-    test_complete_flow(b"good password", b"bad password")
+    test_complete_flow::<RistrettoSha5123dhNoSlowHash>(b"good password", b"bad password")
 }
 
-// Zeroize tests
-
 #[test]
-fn test_zeroize_client_registration_start() -> Result<(), ProtocolError> {
-    let mut client_rng = OsRng;
-    let client_registration_start_result =
-        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &mut client_rng,
-            STR_PASSWORD.as_bytes(),
-        )?;
-
-    let mut state = client_registration_start_result.state;
-    Zeroize::zeroize(&mut state);
-    for bytes in state.to_vec() {
-        assert!(bytes.iter().all(|&x| x == 0));
+fn test_complete_flow_sha512_trunc256() -> Result<(), ProtocolError> {
+    struct RistrettoSha512Trunc2563dhNoSlowHash;
+    impl CipherSuite for RistrettoSha512Trunc2563dhNoSlowHash {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512Trunc256;
+        type SlowHash = NoOpHash;
     }
 
-    Ok(())
+    test_complete_flow::<RistrettoSha512Trunc2563dhNoSlowHash>(b"good password", b"good password")?;
+    test_complete_flow::<RistrettoSha512Trunc2563dhNoSlowHash>(b"good password", b"bad password")
 }
 
+#[cfg(feature = "kmac")]
 #[test]
-fn test_zeroize_client_registration_finish() -> Result<(), ProtocolError> {
-    let mut client_rng = OsRng;
-    let mut server_rng = OsRng;
-    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
-    let client_registration_start_result =
-        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &mut client_rng,
-            STR_PASSWORD.as_bytes(),
-        )?;
-    let server_registration_start_result =
-        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &server_setup,
-            client_registration_start_result.message,
-            STR_CREDENTIAL_IDENTIFIER.as_bytes(),
-        )?;
-    let client_registration_finish_result = client_registration_start_result.state.finish(
-        &mut client_rng,
-        server_registration_start_result.message,
-        ClientRegistrationFinishParameters::default(),
-    )?;
-
-    let mut state = client_registration_finish_result.state;
-    Zeroize::zeroize(&mut state);
-    for bytes in state.to_vec() {
-        assert!(bytes.iter().all(|&x| x == 0));
+fn test_complete_flow_kmac() -> Result<(), ProtocolError> {
+    struct RistrettoSha3512KmacNoSlowHash;
+    impl CipherSuite for RistrettoSha3512KmacNoSlowHash {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = crate::key_exchange::tripledh::TripleDHKmac;
+        type Hash = sha3::Sha3_512;
+        type SlowHash = NoOpHash;
     }
 
-    Ok(())
+    test_complete_flow::<RistrettoSha3512KmacNoSlowHash>(b"good password", b"good password")?;
+    test_complete_flow::<RistrettoSha3512KmacNoSlowHash>(b"good password", b"bad password")
 }
 
-#[test]
-fn test_zeroize_server_registration_finish() -> Result<(), ProtocolError> {
-    let mut client_rng = OsRng;
-    let mut server_rng = OsRng;
-    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
-    let client_registration_start_result =
-        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &mut client_rng,
-            STR_PASSWORD.as_bytes(),
-        )?;
-    let server_registration_start_result =
-        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &server_setup,
-            client_registration_start_result.message,
-            STR_CREDENTIAL_IDENTIFIER.as_bytes(),
-        )?;
+// Runs a login over serialized messages and a serialized password file, so
+// that the lengths of both groups are exercised
+fn test_serialized_flow<CS: CipherSuite>() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup =
+        ServerSetup::<CS>::deserialize(&ServerSetup::<CS>::new(&mut rng)?.serialize()?)?;
+    let client_registration_start_result = ClientRegistration::<CS>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::<CS>::start(
+        &server_setup,
+        RegistrationRequest::deserialize(&client_registration_start_result.message.serialize()?)?,
+        b"credentialIdentifier",
+    )?;
     let client_registration_finish_result = client_registration_start_result.state.finish(
-        &mut client_rng,
-        server_registration_start_result.message,
-        ClientRegistrationFinishParameters::default(),
+        &mut rng,
+        RegistrationResponse::deserialize(&server_registration_start_result.message.serialize()?)?,
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+    let password_file = ServerRegistration::<CS>::deserialize(
+        &ServerRegistration::<CS>::finish(RegistrationUpload::deserialize(
+            &client_registration_finish_result.message.serialize()?,
+        )?)
+        .serialize()?,
     )?;
-    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
 
-    let mut state = p_file;
-    Zeroize::zeroize(&mut state);
-    for bytes in state.serialize() {
-        assert!(bytes.iter().all(|&x| x == 0));
+    for (password, success) in [(&b"password"[..], true), (&b"wrong"[..], false)]
+        .iter()
+        .cloned()
+    {
+        let client_login_start_result = ClientLogin::<CS>::start(&mut rng, password)?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &server_setup,
+            Some(password_file.clone()),
+            CredentialRequest::deserialize(&client_login_start_result.message.serialize()?)?,
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
+        )?;
+        let client_login_finish_result = client_login_start_result.state.finish(
+            CredentialResponse::deserialize(&server_login_start_result.message.serialize()?)?,
+            ClientLoginFinishParameters {
+                export_key: true,
+                ..ClientLoginFinishParameters::default()
+            },
+        );
+        if !success {
+            assert!(matches!(
+                client_login_finish_result,
+                Err(ProtocolError::InvalidLoginError)
+            ));
+            continue;
+        }
+        let client_login_finish_result = client_login_finish_result?;
+        let server_login_finish_result =
+            server_login_start_result
+                .state
+                .finish(CredentialFinalization::deserialize(
+                    &client_login_finish_result.message.serialize()?,
+                )?)?;
+        assert_eq!(
+            client_login_finish_result.session_key,
+            server_login_finish_result.session_key
+        );
+        assert_eq!(
+            client_login_finish_result.export_key,
+            client_registration_finish_result.export_key
+        );
     }
 
     Ok(())
 }
 
 #[test]
-fn test_zeroize_client_login_start() -> Result<(), ProtocolError> {
-    let mut client_rng = OsRng;
-    let client_login_start_result = ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(
-        &mut client_rng,
-        STR_PASSWORD.as_bytes(),
-    )?;
+fn test_mixed_groups() -> Result<(), ProtocolError> {
+    use curve25519_dalek::montgomery::MontgomeryPoint;
 
-    let mut state = client_login_start_result.state;
-    Zeroize::zeroize(&mut state);
-    for bytes in state.to_vec() {
-        assert!(bytes.iter().all(|&x| x == 0));
+    struct RistrettoX25519Sha5123dh;
+    impl CipherSuite for RistrettoX25519Sha5123dh {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = MontgomeryPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = NoOpHash;
+    }
+    test_serialized_flow::<RistrettoX25519Sha5123dh>()?;
+
+    #[cfg(feature = "p256")]
+    {
+        struct RistrettoP256Sha5123dh;
+        impl CipherSuite for RistrettoP256Sha5123dh {
+            type OprfGroup = RistrettoPoint;
+            type KeGroup = p256_::ProjectivePoint;
+            type KeyExchange = TripleDH;
+            type Hash = sha2::Sha512;
+            type SlowHash = NoOpHash;
+        }
+        test_serialized_flow::<RistrettoP256Sha5123dh>()?;
+
+        struct RistrettoP256UncompressedSha5123dh;
+        impl CipherSuite for RistrettoP256UncompressedSha5123dh {
+            type OprfGroup = RistrettoPoint;
+            type KeGroup = crate::key_exchange::group::p256::P256Uncompressed;
+            type KeyExchange = TripleDH;
+            type Hash = sha2::Sha512;
+            type SlowHash = NoOpHash;
+        }
+        test_serialized_flow::<RistrettoP256UncompressedSha5123dh>()?;
+
+        struct P256RistrettoSha2563dh;
+        impl CipherSuite for P256RistrettoSha2563dh {
+            type OprfGroup = p256_::ProjectivePoint;
+            type KeGroup = RistrettoPoint;
+            type KeyExchange = TripleDH;
+            type Hash = sha2::Sha256;
+            type SlowHash = NoOpHash;
+        }
+        test_serialized_flow::<P256RistrettoSha2563dh>()?;
+
+        struct P256X25519Sha3843dh;
+        impl CipherSuite for P256X25519Sha3843dh {
+            type OprfGroup = p256_::ProjectivePoint;
+            type KeGroup = MontgomeryPoint;
+            type KeyExchange = TripleDH;
+            type Hash = sha2::Sha384;
+            type SlowHash = NoOpHash;
+        }
+        test_serialized_flow::<P256X25519Sha3843dh>()?;
     }
 
     Ok(())
 }
 
 #[test]
-fn test_zeroize_server_login_start() -> Result<(), ProtocolError> {
-    let mut client_rng = OsRng;
-    let mut server_rng = OsRng;
-    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+fn test_export_key_len() -> Result<(), ProtocolError> {
+    struct RistrettoSha5123dhShortExportKey;
+    impl CipherSuite for RistrettoSha5123dhShortExportKey {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = NoOpHash;
+        const EXPORT_KEY_LEN: usize = 32;
+    }
+
+    let credential_identifier = b"credentialIdentifier";
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhShortExportKey>::new(&mut rng)?;
     let client_registration_start_result =
-        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &mut client_rng,
-            STR_PASSWORD.as_bytes(),
-        )?;
-    let server_registration_start_result =
-        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &server_setup,
-            client_registration_start_result.message,
-            STR_CREDENTIAL_IDENTIFIER.as_bytes(),
-        )?;
+        ClientRegistration::<RistrettoSha5123dhShortExportKey>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
+    )?;
     let client_registration_finish_result = client_registration_start_result.state.finish(
-        &mut client_rng,
+        &mut rng,
         server_registration_start_result.message,
-        ClientRegistrationFinishParameters::default(),
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::default()
+        },
     )?;
+    assert_eq!(
+        client_registration_finish_result
+            .export_key
+            .as_ref()
+            .unwrap()
+            .expose_secret()
+            .len(),
+        32
+    );
     let p_file = ServerRegistration::finish(client_registration_finish_result.message);
-    let client_login_start_result = ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(
-        &mut client_rng,
-        STR_PASSWORD.as_bytes(),
-    )?;
-    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
-        &mut server_rng,
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhShortExportKey>::start(&mut rng, b"password")?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
         &server_setup,
         Some(p_file),
         client_login_start_result.message,
-        STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        credential_identifier,
         ServerLoginStartParameters::default(),
     )?;
-
-    let mut state = server_login_start_result.state;
-    Zeroize::zeroize(&mut state);
-    for bytes in state.serialize() {
-        assert!(bytes.iter().all(|&x| x == 0));
-    }
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters {
+            export_key: true,
+            ..ClientLoginFinishParameters::default()
+        },
+    )?;
+    assert_eq!(
+        client_login_finish_result.export_key,
+        client_registration_finish_result.export_key
+    );
 
     Ok(())
 }
 
 #[test]
-fn test_zeroize_client_login_finish() -> Result<(), ProtocolError> {
-    let mut client_rng = OsRng;
-    let mut server_rng = OsRng;
-    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+fn test_session_key_len() -> Result<(), ProtocolError> {
+    struct RistrettoSha5123dhShortSessionKey;
+    impl CipherSuite for RistrettoSha5123dhShortSessionKey {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = NoOpHash;
+        const SESSION_KEY_LEN: usize = 32;
+    }
+
+    let credential_identifier = b"credentialIdentifier";
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhShortSessionKey>::new(&mut rng)?;
     let client_registration_start_result =
-        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &mut client_rng,
-            STR_PASSWORD.as_bytes(),
-        )?;
-    let server_registration_start_result =
-        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &server_setup,
-            client_registration_start_result.message,
-            STR_CREDENTIAL_IDENTIFIER.as_bytes(),
-        )?;
+        ClientRegistration::<RistrettoSha5123dhShortSessionKey>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
+    )?;
     let client_registration_finish_result = client_registration_start_result.state.finish(
-        &mut client_rng,
+        &mut rng,
         server_registration_start_result.message,
         ClientRegistrationFinishParameters::default(),
     )?;
     let p_file = ServerRegistration::finish(client_registration_finish_result.message);
-    let client_login_start_result = ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(
-        &mut client_rng,
-        STR_PASSWORD.as_bytes(),
-    )?;
-    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
-        &mut server_rng,
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhShortSessionKey>::start(&mut rng, b"password")?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
         &server_setup,
         Some(p_file),
         client_login_start_result.message,
-        STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        credential_identifier,
         ServerLoginStartParameters::default(),
     )?;
     let client_login_finish_result = client_login_start_result.state.finish(
         server_login_start_result.message,
         ClientLoginFinishParameters::default(),
     )?;
+    let server_login_finish_result = server_login_start_result
+        .state
+        .finish(client_login_finish_result.message.clone())?;
 
-    let mut state = client_login_finish_result.state;
-    Zeroize::zeroize(&mut state);
-    for bytes in state.to_vec() {
-        assert!(bytes.iter().all(|&x| x == 0));
-    }
+    assert_eq!(
+        client_login_finish_result.session_key.expose_secret().len(),
+        32
+    );
+    assert_eq!(
+        client_login_finish_result.session_key,
+        server_login_finish_result.session_key
+    );
+    assert_eq!(
+        format!("{:?}", client_login_finish_result.session_key),
+        "SessionKey([REDACTED; 32])"
+    );
 
     Ok(())
 }
 
 #[test]
-fn test_zeroize_server_login_finish() -> Result<(), ProtocolError> {
-    let mut client_rng = OsRng;
-    let mut server_rng = OsRng;
-    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
-    let client_registration_start_result =
-        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &mut client_rng,
-            STR_PASSWORD.as_bytes(),
-        )?;
-    let server_registration_start_result =
-        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &server_setup,
-            client_registration_start_result.message,
-            STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+fn test_ciphersuite_context() -> Result<(), ProtocolError> {
+    struct Staging;
+    impl CipherSuite for Staging {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = NoOpHash;
+        const CONTEXT: &'static [u8] = b"staging";
+    }
+    struct Production;
+    impl CipherSuite for Production {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = NoOpHash;
+        const CONTEXT: &'static [u8] = b"production";
+    }
+
+    // Logs in with the server setup and password file of another
+    // ciphersuite, which share the same serialization
+    fn login<CS: CipherSuite>(
+        server_setup: &[u8],
+        password_file: &[u8],
+    ) -> Result<ClientLoginFinishResult<CS>, ProtocolError> {
+        let mut rng = OsRng;
+        let client_login_start_result = ClientLogin::<CS>::start(&mut rng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &ServerSetup::<CS>::deserialize(server_setup)?,
+            Some(ServerRegistration::<CS>::deserialize(password_file)?),
+            client_login_start_result.message,
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
         )?;
+        client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters::default(),
+        )
+    }
+
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<Staging>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<Staging>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
     let client_registration_finish_result = client_registration_start_result.state.finish(
-        &mut client_rng,
+        &mut rng,
         server_registration_start_result.message,
         ClientRegistrationFinishParameters::default(),
     )?;
     let p_file = ServerRegistration::finish(client_registration_finish_result.message);
-    let client_login_start_result = ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(
-        &mut client_rng,
-        STR_PASSWORD.as_bytes(),
+
+    let server_setup_bytes = server_setup.serialize()?;
+    let p_file_bytes = p_file.serialize()?;
+    login::<Staging>(&server_setup_bytes, &p_file_bytes)?;
+    assert!(matches!(
+        login::<Production>(&server_setup_bytes, &p_file_bytes),
+        Err(ProtocolError::InvalidLoginError)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_dyn_rng() -> Result<(), ProtocolError> {
+    trait DynRng: rand::RngCore + rand::CryptoRng {}
+    impl<R: rand::RngCore + rand::CryptoRng> DynRng for R {}
+
+    let credential_identifier = b"credentialIdentifier";
+    let rng: &mut dyn DynRng = &mut OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
     )?;
-    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
-        &mut server_rng,
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(rng, b"password")?;
+    let server_login_start_result = ServerLogin::start(
+        rng,
         &server_setup,
         Some(p_file),
         client_login_start_result.message,
-        STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        credential_identifier,
         ServerLoginStartParameters::default(),
     )?;
     let client_login_finish_result = client_login_start_result.state.finish(
@@ -1031,138 +1362,4060 @@ fn test_zeroize_server_login_finish() -> Result<(), ProtocolError> {
     let server_login_finish_result = server_login_start_result
         .state
         .finish(client_login_finish_result.message)?;
-
-    let mut state = server_login_finish_result.state;
-    Zeroize::zeroize(&mut state);
-    for bytes in state.serialize() {
-        assert!(bytes.iter().all(|&x| x == 0));
-    }
+    assert_eq!(
+        client_login_finish_result.session_key,
+        server_login_finish_result.session_key
+    );
 
     Ok(())
 }
 
 #[test]
-fn test_scalar_always_nonzero() -> Result<(), ProtocolError> {
-    // Start out with a bunch of zeros to force resampling of scalar
-    let mut client_registration_rng = CycleRng::new([vec![0u8; 128], vec![1u8; 128]].concat());
+fn test_login_state_copies() -> Result<(), ProtocolError> {
+    let credential_identifier = b"credentialIdentifier";
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
     let client_registration_start_result =
-        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &mut client_registration_rng,
-            STR_PASSWORD.as_bytes(),
-        )?;
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
 
-    assert_ne!(
-        RistrettoPoint::identity(),
-        client_registration_start_result
-            .message
-            .get_blinded_element_for_testing()
-            .value(),
-    );
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(p_file),
+        client_login_start_result.message,
+        credential_identifier,
+        ServerLoginStartParameters::default(),
+    )?;
 
-    // Start out with a bunch of zeros to force resampling of scalar
-    let mut client_login_rng = CycleRng::new([vec![0u8; 128], vec![1u8; 128]].concat());
-    let client_login_start_result = ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(
-        &mut client_login_rng,
-        STR_PASSWORD.as_bytes(),
+    // Once the client state is finished, its copies are rejected
+    let client_copy = client_login_start_result.state.clone();
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message.clone(),
+        ClientLoginFinishParameters::default(),
     )?;
+    assert!(matches!(
+        client_copy.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters::default(),
+        ),
+        Err(ProtocolError::ReplayDetectedError)
+    ));
 
-    assert_ne!(
-        RistrettoPoint::identity(),
-        client_login_start_result
-            .message
-            .get_blinded_element_for_testing()
-            .value(),
+    // So are the copies of the server state, but not a copy taken through
+    // storage, which is why storage layers must enforce single use
+    let stored = server_login_start_result.state.serialize()?;
+    let server_clone = server_login_start_result.state.clone();
+    let server_copy = ServerLogin::<RistrettoSha5123dhNoSlowHash>::deserialize(&stored)?;
+    let server_login_finish_result = server_login_start_result
+        .state
+        .finish(client_login_finish_result.message.clone())?;
+    assert!(matches!(
+        server_clone.finish(client_login_finish_result.message.clone()),
+        Err(ProtocolError::ReplayDetectedError)
+    ));
+    let server_copy_result = server_copy.finish(client_login_finish_result.message)?;
+    assert_eq!(
+        server_login_finish_result.session_key,
+        server_copy_result.session_key
+    );
+    assert_eq!(
+        server_login_finish_result.session_key,
+        client_login_finish_result.session_key
     );
 
     Ok(())
 }
 
 #[test]
-fn test_reflected_value_error_registration() -> Result<(), ProtocolError> {
-    let credential_identifier = b"credentialIdentifier";
-    let password = b"password";
-    let mut client_rng = OsRng;
-    let mut server_rng = OsRng;
-    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
-    let client_registration_start_result =
-        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, password)?;
-    let alpha = client_registration_start_result
-        .message
-        .get_blinded_element_for_testing()
-        .value();
-    let server_registration_start_result =
-        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &server_setup,
-            client_registration_start_result.message,
-            credential_identifier,
-        )?;
-
-    let reflected_registration_response = server_registration_start_result
-        .message
-        .set_evaluation_element_for_testing(alpha);
+fn test_server_login_state_portability() -> Result<(), ProtocolError> {
+    // The same primitives, deployed under another context
+    struct OtherContext;
+    impl CipherSuite for OtherContext {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = NoOpHash;
+        const CONTEXT: &'static [u8] = b"other";
+    }
 
-    let client_registration_finish_result = client_registration_start_result.state.finish(
-        &mut client_rng,
-        reflected_registration_response,
-        ClientRegistrationFinishParameters::default(),
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        None,
+        client_login_start_result.message,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    )?;
+    let state = server_login_start_result.state.serialize()?;
+    assert_eq!(state[0], SERVER_LOGIN_STATE_VERSION);
+    assert_eq!(
+        ServerLogin::<RistrettoSha5123dhNoSlowHash>::deserialize(&state)?,
+        server_login_start_result.state
     );
 
-    assert!(match client_registration_finish_result {
-        Err(ProtocolError::ReflectedValueError) => true,
-        _ => false,
-    });
+    assert!(matches!(
+        ServerLogin::<OtherContext>::deserialize(&state),
+        Err(ProtocolError::SerializationError)
+    ));
+    let mut future_version = state.clone();
+    future_version[0] = SERVER_LOGIN_STATE_VERSION + 1;
+    assert!(matches!(
+        ServerLogin::<RistrettoSha5123dhNoSlowHash>::deserialize(&future_version),
+        Err(ProtocolError::VersionMismatchError)
+    ));
+    assert!(matches!(
+        ServerLogin::<RistrettoSha5123dhNoSlowHash>::deserialize(&state[..4]),
+        Err(ProtocolError::LibraryError(InternalError::SizeError {
+            name: "server_login",
+            ..
+        }))
+    ));
 
     Ok(())
 }
 
+// `CS: CipherSuite` alone must be enough for the messages and states to
+// implement these traits
+fn assert_implied_bounds<CS: CipherSuite>() {
+    fn clone_debug_eq<T: Clone + core::fmt::Debug + Eq>() {}
+    fn clone_debug<T: Clone + core::fmt::Debug>() {}
+
+    clone_debug_eq::<RegistrationRequest<CS>>();
+    clone_debug_eq::<RegistrationResponse<CS>>();
+    clone_debug_eq::<RegistrationUpload<CS>>();
+    clone_debug_eq::<CredentialRequest<CS>>();
+    clone_debug_eq::<CredentialResponse<CS>>();
+    clone_debug_eq::<CredentialFinalization<CS>>();
+    clone_debug_eq::<ServerSetup<CS>>();
+    clone_debug_eq::<ServerRegistration<CS>>();
+    clone_debug_eq::<ServerLogin<CS>>();
+    clone_debug::<ClientRegistration<CS>>();
+    clone_debug::<ClientLogin<CS>>();
+}
+
 #[test]
-fn test_reflected_value_error_login() -> Result<(), ProtocolError> {
-    let credential_identifier = b"credentialIdentifier";
-    let password = b"password";
-    let mut client_rng = OsRng;
-    let mut server_rng = OsRng;
-    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+fn test_retransmission_safe_login_start() -> Result<(), ProtocolError> {
+    use rand::RngCore;
+
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
     let client_registration_start_result =
-        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, password)?;
-    let server_registration_start_result =
-        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
-            &server_setup,
-            client_registration_start_result.message,
-            credential_identifier,
-        )?;
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
     let client_registration_finish_result = client_registration_start_result.state.finish(
-        &mut client_rng,
+        &mut rng,
         server_registration_start_result.message,
         ClientRegistrationFinishParameters::default(),
     )?;
-    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
-    let client_login_start_result =
-        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, password)?;
-    let alpha = client_login_start_result
-        .message
-        .get_blinded_element_for_testing()
-        .value();
-    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
-        &mut server_rng,
-        &server_setup,
-        Some(p_file),
-        client_login_start_result.message,
-        credential_identifier,
-        ServerLoginStartParameters::default(),
-    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
 
-    let reflected_credential_response = server_login_start_result
-        .message
-        .set_evaluation_element_for_testing(alpha);
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let start = |password_file: Option<ServerRegistration<_>>,
+                 credential_request,
+                 retransmission_nonce: Option<&[u8]>| {
+        let server_login_start_result = ServerLogin::start(
+            &mut OsRng,
+            &server_setup,
+            password_file,
+            credential_request,
+            b"credentialIdentifier",
+            ServerLoginStartParameters {
+                retransmission_nonce: retransmission_nonce.map(|nonce| nonce.to_vec()),
+                ..ServerLoginStartParameters::default()
+            },
+        )?;
+        Ok::<_, ProtocolError>((
+            server_login_start_result.message.serialize()?,
+            server_login_start_result.state.serialize()?,
+        ))
+    };
 
-    let client_login_result = client_login_start_result.state.finish(
-        reflected_credential_response,
-        ClientLoginFinishParameters::default(),
-    );
+    let mut nonce = [0u8; RETRANSMISSION_NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+    let mut other_nonce = [0u8; RETRANSMISSION_NONCE_LEN];
+    rng.fill_bytes(&mut other_nonce);
 
-    assert!(match client_login_result {
-        Err(ProtocolError::ReflectedValueError) => true,
-        _ => false,
+    // A retransmitted credential request gets the same response and state,
+    // also when the response is faked for an unregistered user
+    for password_file in [Some(password_file.clone()), None].iter().cloned() {
+        let first = start(
+            password_file.clone(),
+            client_login_start_result.message.clone(),
+            Some(&nonce),
+        )?;
+        let retransmitted = start(
+            password_file.clone(),
+            client_login_start_result.message.clone(),
+            Some(&nonce),
+        )?;
+        assert_eq!(first, retransmitted);
+        assert_ne!(
+            start(
+                password_file.clone(),
+                client_login_start_result.message.clone(),
+                None,
+            )?,
+            first
+        );
+        assert_ne!(
+            start(
+                password_file.clone(),
+                client_login_start_result.message.clone(),
+                Some(&other_nonce),
+            )?,
+            first
+        );
+        let other_credential_request =
+            ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?.message;
+        assert_ne!(
+            start(password_file, other_credential_request, Some(&nonce))?.0,
+            first.0
+        );
+    }
+
+    assert!(matches!(
+        start(
+            Some(password_file.clone()),
+            client_login_start_result.message.clone(),
+            Some(&nonce[1..]),
+        ),
+        Err(ProtocolError::LibraryError(InternalError::SizeError { .. }))
+    ));
+
+    // Any of the responses completes the login
+    let (credential_response, server_login) = start(
+        Some(password_file.clone()),
+        client_login_start_result.message.clone(),
+        Some(&nonce),
+    )?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        CredentialResponse::deserialize(&credential_response)?,
+        ClientLoginFinishParameters::default(),
+    )?;
+    let server_login_finish_result =
+        ServerLogin::<RistrettoSha5123dhNoSlowHash>::deserialize(&server_login)?
+            .finish(client_login_finish_result.message.clone())?;
+    assert_eq!(
+        client_login_finish_result.session_key,
+        server_login_finish_result.session_key
+    );
+
+    // A replay of the recorded credential request starts a new attempt,
+    // with a new nonce, whose state rejects the recorded finalization
+    let (_, replayed_server_login) = start(
+        Some(password_file),
+        client_login_start_result.message,
+        Some(&other_nonce),
+    )?;
+    assert!(matches!(
+        ServerLogin::<RistrettoSha5123dhNoSlowHash>::deserialize(&replayed_server_login)?
+            .finish(client_login_finish_result.message),
+        Err(ProtocolError::InvalidLoginError)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_implied_bounds() {
+    assert_implied_bounds::<RistrettoSha5123dhNoSlowHash>();
+}
+
+#[test]
+fn test_io_error_conversion() {
+    use std::io::ErrorKind;
+
+    let io_error: std::io::Error =
+        ProtocolError::<core::convert::Infallible>::InvalidLoginError.into();
+    assert_eq!(io_error.kind(), ErrorKind::PermissionDenied);
+    assert!(matches!(
+        io_error
+            .into_inner()
+            .unwrap()
+            .downcast_ref::<ProtocolError>(),
+        Some(ProtocolError::InvalidLoginError)
+    ));
+
+    let io_error: std::io::Error =
+        CredentialFinalization::<RistrettoSha5123dhNoSlowHash>::deserialize(&[0u8; 3])
+            .unwrap_err()
+            .into();
+    assert_eq!(io_error.kind(), ErrorKind::InvalidData);
+
+    let cancelled: ProtocolError = InternalError::SlowHashCancelledError.into();
+    assert_eq!(cancelled.io_error_kind(), ErrorKind::Interrupted);
+
+    for (error, kind) in [
+        (ProtocolError::VersionMismatchError, ErrorKind::InvalidData),
+        (
+            ProtocolError::ReplayDetectedError,
+            ErrorKind::PermissionDenied,
+        ),
+    ]
+    .iter()
+    {
+        assert_eq!(error.io_error_kind(), *kind);
+        assert_eq!(error.clone().into_custom::<()>().io_error_kind(), *kind);
+    }
+}
+
+// Zeroize tests
+
+#[test]
+fn test_zeroize_client_registration_start() -> Result<(), ProtocolError> {
+    let mut client_rng = OsRng;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &mut client_rng,
+            STR_PASSWORD.as_bytes(),
+        )?;
+
+    let mut state = client_registration_start_result.state;
+    Zeroize::zeroize(&mut state);
+    for bytes in state.to_vec() {
+        assert!(bytes.iter().all(|&x| x == 0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_zeroize_client_registration_finish() -> Result<(), ProtocolError> {
+    let mut client_rng = OsRng;
+    let mut server_rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &mut client_rng,
+            STR_PASSWORD.as_bytes(),
+        )?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut client_rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+
+    let mut state = client_registration_finish_result.state;
+    Zeroize::zeroize(&mut state);
+    for bytes in state.to_vec() {
+        assert!(bytes.iter().all(|&x| x == 0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_zeroize_server_registration_finish() -> Result<(), ProtocolError> {
+    let mut client_rng = OsRng;
+    let mut server_rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &mut client_rng,
+            STR_PASSWORD.as_bytes(),
+        )?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut client_rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let mut state = p_file;
+    Zeroize::zeroize(&mut state);
+    for bytes in state.serialize() {
+        assert!(bytes.iter().all(|&x| x == 0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_zeroize_client_login_start() -> Result<(), ProtocolError> {
+    let mut client_rng = OsRng;
+    let client_login_start_result = ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut client_rng,
+        STR_PASSWORD.as_bytes(),
+    )?;
+
+    let mut state = client_login_start_result.state;
+    Zeroize::zeroize(&mut state);
+    for bytes in state.to_vec() {
+        assert!(bytes.iter().all(|&x| x == 0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_zeroize_server_login_start() -> Result<(), ProtocolError> {
+    let mut client_rng = OsRng;
+    let mut server_rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &mut client_rng,
+            STR_PASSWORD.as_bytes(),
+        )?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut client_rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+    let client_login_start_result = ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut client_rng,
+        STR_PASSWORD.as_bytes(),
+    )?;
+    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut server_rng,
+        &server_setup,
+        Some(p_file),
+        client_login_start_result.message,
+        STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        ServerLoginStartParameters::default(),
+    )?;
+
+    let mut state = server_login_start_result.state;
+    Zeroize::zeroize(&mut state);
+    let bytes = state.serialize()?;
+    // Past the version and the ciphersuite tag, which the state does not hold
+    let (header, ke2_state) = bytes.split_at(5);
+    assert_eq!(header[0], SERVER_LOGIN_STATE_VERSION);
+    assert!(ke2_state.iter().all(|&x| x == 0));
+
+    Ok(())
+}
+
+#[test]
+fn test_zeroize_client_login_finish() -> Result<(), ProtocolError> {
+    let mut client_rng = OsRng;
+    let mut server_rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &mut client_rng,
+            STR_PASSWORD.as_bytes(),
+        )?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut client_rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+    let client_login_start_result = ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut client_rng,
+        STR_PASSWORD.as_bytes(),
+    )?;
+    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut server_rng,
+        &server_setup,
+        Some(p_file),
+        client_login_start_result.message,
+        STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        ServerLoginStartParameters::default(),
+    )?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters::default(),
+    )?;
+
+    let mut state = client_login_finish_result.state;
+    Zeroize::zeroize(&mut state);
+    for bytes in state.to_vec() {
+        assert!(bytes.iter().all(|&x| x == 0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_zeroize_server_login_finish() -> Result<(), ProtocolError> {
+    let mut client_rng = OsRng;
+    let mut server_rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &mut client_rng,
+            STR_PASSWORD.as_bytes(),
+        )?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut client_rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+    let client_login_start_result = ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut client_rng,
+        STR_PASSWORD.as_bytes(),
+    )?;
+    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut server_rng,
+        &server_setup,
+        Some(p_file),
+        client_login_start_result.message,
+        STR_CREDENTIAL_IDENTIFIER.as_bytes(),
+        ServerLoginStartParameters::default(),
+    )?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters::default(),
+    )?;
+    let server_login_finish_result = server_login_start_result
+        .state
+        .finish(client_login_finish_result.message)?;
+
+    let mut state = server_login_finish_result.state;
+    Zeroize::zeroize(&mut state);
+    let bytes = state.serialize()?;
+    // Past the version and the ciphersuite tag, which the state does not hold
+    let (header, ke2_state) = bytes.split_at(5);
+    assert_eq!(header[0], SERVER_LOGIN_STATE_VERSION);
+    assert!(ke2_state.iter().all(|&x| x == 0));
+
+    Ok(())
+}
+
+#[test]
+fn test_scalar_always_nonzero() -> Result<(), ProtocolError> {
+    // Start out with a bunch of zeros to force resampling of scalar
+    let mut client_registration_rng = CycleRng::new([vec![0u8; 128], vec![1u8; 128]].concat());
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &mut client_registration_rng,
+            STR_PASSWORD.as_bytes(),
+        )?;
+
+    assert_ne!(
+        RistrettoPoint::identity(),
+        client_registration_start_result
+            .message
+            .get_blinded_element_for_testing()
+            .value(),
+    );
+
+    // Start out with a bunch of zeros to force resampling of scalar
+    let mut client_login_rng = CycleRng::new([vec![0u8; 128], vec![1u8; 128]].concat());
+    let client_login_start_result = ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut client_login_rng,
+        STR_PASSWORD.as_bytes(),
+    )?;
+
+    assert_ne!(
+        RistrettoPoint::identity(),
+        client_login_start_result
+            .message
+            .get_blinded_element_for_testing()
+            .value(),
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_blind_rejection_sampling() -> Result<(), ProtocolError> {
+    let blind = vec![1u8; 32];
+    let expected = ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut CycleRng::new(blind.clone()),
+        STR_PASSWORD.as_bytes(),
+    )?
+    .message
+    .serialize()?;
+
+    // Bytes which do not encode a canonical scalar, or encode zero, are
+    // rejected rather than reduced, so that the blind is uniform
+    let mut rng = CycleRng::new([vec![0xffu8; 32], vec![0u8; 32], blind].concat());
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &mut rng,
+            STR_PASSWORD.as_bytes(),
+        )?;
+    assert_eq!(
+        client_registration_start_result.message.serialize()?,
+        expected
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_reflected_value_error_registration() -> Result<(), ProtocolError> {
+    let credential_identifier = b"credentialIdentifier";
+    let password = b"password";
+    let mut client_rng = OsRng;
+    let mut server_rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, password)?;
+    let alpha = client_registration_start_result
+        .message
+        .get_blinded_element_for_testing()
+        .value();
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            credential_identifier,
+        )?;
+
+    let reflected_registration_response = server_registration_start_result
+        .message
+        .set_evaluation_element_for_testing(alpha);
+
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut client_rng,
+        reflected_registration_response,
+        ClientRegistrationFinishParameters::default(),
+    );
+
+    assert!(match client_registration_finish_result {
+        Err(ProtocolError::ReflectedValueError) => true,
+        _ => false,
+    });
+
+    Ok(())
+}
+
+#[test]
+fn test_reflected_value_error_login() -> Result<(), ProtocolError> {
+    let credential_identifier = b"credentialIdentifier";
+    let password = b"password";
+    let mut client_rng = OsRng;
+    let mut server_rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, password)?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            credential_identifier,
+        )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut client_rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, password)?;
+    let alpha = client_login_start_result
+        .message
+        .get_blinded_element_for_testing()
+        .value();
+    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut server_rng,
+        &server_setup,
+        Some(p_file),
+        client_login_start_result.message,
+        credential_identifier,
+        ServerLoginStartParameters::default(),
+    )?;
+
+    let reflected_credential_response = server_login_start_result
+        .message
+        .set_evaluation_element_for_testing(alpha);
+
+    let client_login_result = client_login_start_result.state.finish(
+        reflected_credential_response,
+        ClientLoginFinishParameters::default(),
+    );
+
+    assert!(match client_login_result {
+        Err(ProtocolError::ReflectedValueError) => true,
+        _ => false,
+    });
+    Ok(())
+}
+
+#[test]
+fn test_reflected_ephemeral_key_login() -> Result<(), ProtocolError> {
+    let password = b"password";
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+    let credential_request_bytes = client_login_start_result.message.serialize()?;
+    let client_e_pk = &credential_request_bytes[credential_request_bytes.len() - 32..];
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(p_file),
+        client_login_start_result.message,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    )?;
+
+    // Replace the server's ephemeral public key, which precedes the 64-byte
+    // MAC at the end of the message, with the client's own
+    let mut credential_response_bytes = server_login_start_result.message.serialize()?;
+    let server_e_pk_end = credential_response_bytes.len() - 64;
+    credential_response_bytes[server_e_pk_end - 32..server_e_pk_end].copy_from_slice(client_e_pk);
+    let reflected_credential_response =
+        CredentialResponse::<RistrettoSha5123dhNoSlowHash>::deserialize(
+            &credential_response_bytes,
+        )?;
+
+    let client_login_result = client_login_start_result.state.finish(
+        reflected_credential_response,
+        ClientLoginFinishParameters::default(),
+    );
+
+    assert!(matches!(
+        client_login_result,
+        Err(ProtocolError::ReflectedValueError)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_degenerate_diffie_hellman_login() -> Result<(), ProtocolError> {
+    use crate::key_exchange::group::KeGroup;
+    use crate::keypair::{KeyPair, PrivateKey, PublicKey, SecretKey};
+
+    // A server key whose Diffie-Hellman output is always the identity
+    #[derive(Clone, Zeroize)]
+    struct DegenerateKey(PrivateKey<RistrettoPoint>);
+
+    impl SecretKey<RistrettoPoint> for DegenerateKey {
+        type Error = core::convert::Infallible;
+
+        fn diffie_hellman(
+            &self,
+            _: PublicKey<RistrettoPoint>,
+        ) -> Result<Vec<u8>, InternalError<Self::Error>> {
+            Ok(RistrettoPoint::identity().to_arr().to_vec())
+        }
+
+        fn public_key(&self) -> Result<PublicKey<RistrettoPoint>, InternalError<Self::Error>> {
+            self.0.public_key()
+        }
+
+        fn serialize(&self) -> Vec<u8> {
+            self.0.serialize()
+        }
+
+        fn deserialize(input: &[u8]) -> Result<Self, InternalError<Self::Error>> {
+            PrivateKey::deserialize(input).map(Self)
+        }
+    }
+
+    let password = b"password";
+    let mut rng = OsRng;
+    let sk = PrivateKey::from_arr(RistrettoPoint::random_sk(&mut rng)?);
+    let keypair = KeyPair::from_private_key(DegenerateKey(sk))?;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash, DegenerateKey>::new_with_key(
+        &mut rng, keypair,
+    )?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(p_file),
+        client_login_start_result.message,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    );
+
+    assert!(matches!(
+        server_login_start_result,
+        Err(ProtocolError::DegenerateDiffieHellmanError)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_password_preprocessing() -> Result<(), ProtocolError> {
+    use hmac::{Hmac, Mac, NewMac};
+
+    let credential_identifier = b"credentialIdentifier";
+    let password = b"password";
+    let pepper = |password: &[u8]| {
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"client pepper").unwrap();
+        mac.update(password);
+        mac.finalize().into_bytes().to_vec()
+    };
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start_with_preprocessing(
+            &mut rng, password, pepper,
+        )?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    // Logging in with the same preprocessing, or with its output directly,
+    // succeeds, while the raw password fails
+    for (login, succeeds) in [
+        (
+            ClientLogin::<RistrettoSha5123dhNoSlowHash>::start_with_preprocessing(
+                &mut rng, password, pepper,
+            )?,
+            true,
+        ),
+        (ClientLogin::start(&mut rng, &pepper(password))?, true),
+        (ClientLogin::start(&mut rng, password)?, false),
+    ]
+    .iter()
+    .cloned()
+    {
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &server_setup,
+            Some(p_file.clone()),
+            login.message,
+            credential_identifier,
+            ServerLoginStartParameters::default(),
+        )?;
+        let client_login_result = login.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters {
+                export_key: true,
+                ..ClientLoginFinishParameters::default()
+            },
+        );
+
+        if succeeds {
+            assert_eq!(
+                client_login_result?.export_key,
+                client_registration_finish_result.export_key
+            );
+        } else {
+            assert!(matches!(
+                client_login_result,
+                Err(ProtocolError::InvalidLoginError)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_bind_credential_identifier() -> Result<(), ProtocolError> {
+    struct BoundIdentifier;
+    impl CipherSuite for BoundIdentifier {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = NoOpHash;
+        const BIND_CREDENTIAL_IDENTIFIER: bool = true;
+    }
+
+    let credential_identifier = b"credentialIdentifier";
+    let password = b"password";
+    let mut rng = OsRng;
+
+    // Starting without the identifier is rejected
+    assert!(matches!(
+        ClientRegistration::<BoundIdentifier>::start(&mut rng, password),
+        Err(ProtocolError::LibraryError(
+            InternalError::MissingCredentialIdentifierError
+        ))
+    ));
+    assert!(matches!(
+        ClientLogin::<BoundIdentifier>::start(&mut rng, password),
+        Err(ProtocolError::LibraryError(
+            InternalError::MissingCredentialIdentifierError
+        ))
+    ));
+
+    let server_setup = ServerSetup::<BoundIdentifier>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<BoundIdentifier>::start_with_identifier(
+            &mut rng,
+            password,
+            credential_identifier,
+        )?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    // Only the identifier used during registration logs in
+    for (login_identifier, succeeds) in [
+        (&credential_identifier[..], true),
+        (&b"otherIdentifier"[..], false),
+    ]
+    .iter()
+    .cloned()
+    {
+        let client_login_start_result = ClientLogin::<BoundIdentifier>::start_with_identifier(
+            &mut rng,
+            password,
+            login_identifier,
+        )?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &server_setup,
+            Some(p_file.clone()),
+            client_login_start_result.message,
+            credential_identifier,
+            ServerLoginStartParameters::default(),
+        )?;
+        let client_login_result = client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters {
+                export_key: true,
+                ..ClientLoginFinishParameters::default()
+            },
+        );
+
+        if succeeds {
+            let client_login_finish_result = client_login_result?;
+            assert_eq!(
+                client_login_finish_result.export_key,
+                client_registration_finish_result.export_key
+            );
+            server_login_start_result
+                .state
+                .finish(client_login_finish_result.message)?;
+        } else {
+            assert!(matches!(
+                client_login_result,
+                Err(ProtocolError::InvalidLoginError)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_client_identity_in_record() -> Result<(), ProtocolError> {
+    let credential_identifier = b"credentialIdentifier";
+    let client_identity = b"alice@example.com";
+    let password = b"password";
+    let mut rng = OsRng;
+
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::new(
+            Some(Identifiers::ClientIdentifier(client_identity.to_vec())),
+            None,
+        ),
+    )?;
+    let upload = client_registration_finish_result.message;
+    assert_eq!(
+        ServerRegistration::finish(upload.clone()).client_identity(),
+        None
+    );
+
+    // The client identity survives serialization, whatever its length
+    for len in 0..128 {
+        let record =
+            ServerRegistration::finish_with_client_identity(upload.clone(), &vec![b'a'; len]);
+        let record_bytes = record.serialize()?;
+        assert_ne!(record_bytes, upload.serialize()?);
+        assert_eq!(
+            ServerRegistration::<RistrettoSha5123dhNoSlowHash>::deserialize(&record_bytes)?,
+            record
+        );
+    }
+
+    let record_bytes =
+        ServerRegistration::finish_with_client_identity(upload, client_identity).serialize()?;
+    let record = ServerRegistration::<RistrettoSha5123dhNoSlowHash>::deserialize(&record_bytes)?;
+    assert_eq!(record.client_identity(), Some(&client_identity[..]));
+
+    // The server supplies the stored identity at login
+    let identifiers = record
+        .client_identity()
+        .map(|id_u| Identifiers::ClientIdentifier(id_u.to_vec()));
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(record),
+        client_login_start_result.message,
+        credential_identifier,
+        ServerLoginStartParameters::new(None, identifiers.clone()),
+    )?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters::new(None, identifiers, None),
+    )?;
+    let server_login_finish_result = server_login_start_result
+        .state
+        .finish(client_login_finish_result.message)?;
+    assert_eq!(
+        server_login_finish_result.session_key,
+        client_login_finish_result.session_key
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_server_registration_accessors() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let mut register = |password: &[u8]| -> Result<_, ProtocolError> {
+        let client_registration_start_result =
+            ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+        let server_registration_start_result = ServerRegistration::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"credentialIdentifier",
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters::default(),
+        )?;
+        Ok(client_registration_finish_result.message)
+    };
+
+    let upload = register(b"password")?;
+    let record = ServerRegistration::finish(upload.clone());
+    assert_eq!(record.client_s_pk(), &upload.client_s_pk);
+    assert_eq!(record.masking_key(), &upload.masking_key[..]);
+    assert_eq!(record.envelope_mode()?, EnvelopeMode::Internal);
+
+    // The masking key only depends on the password and the OPRF key, while the
+    // client's static key is fresh for every registration
+    let same_password_record = ServerRegistration::finish(register(b"password")?);
+    assert_ne!(record.client_s_pk(), same_password_record.client_s_pk());
+    assert_eq!(record.masking_key(), same_password_record.masking_key());
+    let other_password_record = ServerRegistration::finish(register(b"other password")?);
+    assert_ne!(record.masking_key(), other_password_record.masking_key());
+
+    Ok(())
+}
+
+#[test]
+fn test_pseudonym() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let mut register = || -> Result<_, ProtocolError> {
+        let client_registration_start_result =
+            ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+        let server_registration_start_result = ServerRegistration::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"credentialIdentifier",
+        )?;
+        client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters {
+                export_key: true,
+                ..ClientRegistrationFinishParameters::default()
+            },
+        )
+    };
+    let client_registration_finish_result = register()?;
+    let password_file =
+        ServerRegistration::finish(client_registration_finish_result.message.clone());
+
+    let mut rng = OsRng;
+    let mut login = || -> Result<_, ProtocolError> {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &server_setup,
+            Some(password_file.clone()),
+            client_login_start_result.message,
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
+        )?;
+        client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters {
+                export_key: true,
+                ..ClientLoginFinishParameters::default()
+            },
+        )
+    };
+
+    // Stable across logins, and already known at registration
+    let pseudonym = login()?.pseudonym(b"service A")?;
+    assert_eq!(login()?.pseudonym(b"service A")?, pseudonym);
+    assert_eq!(
+        client_registration_finish_result.pseudonym(b"service A")?,
+        pseudonym
+    );
+
+    // Unlinkable between services and registrations
+    assert_ne!(login()?.pseudonym(b"service B")?, pseudonym);
+    assert_ne!(login()?.pseudonym(b"service ")?, pseudonym);
+    assert_ne!(login()?.pseudonym(b"")?, pseudonym);
+    assert_ne!(
+        login()?.export_key.as_ref().unwrap().expose_secret(),
+        &pseudonym[..]
+    );
+    assert_ne!(register()?.pseudonym(b"service A")?, pseudonym);
+
+    Ok(())
+}
+
+#[test]
+fn test_storage_key() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let storage_key = server_setup.storage_key(b"alice")?;
+
+    // Stable for a server setup, including after serialization
+    assert_eq!(server_setup.storage_key(b"alice")?, storage_key);
+    let restored =
+        ServerSetup::<RistrettoSha5123dhNoSlowHash>::deserialize(&server_setup.serialize()?)?;
+    assert_eq!(restored.storage_key(b"alice")?, storage_key);
+
+    assert_ne!(server_setup.storage_key(b"bob")?, storage_key);
+    assert_ne!(server_setup.storage_key(b"")?, storage_key);
+    assert_ne!(
+        ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?.storage_key(b"alice")?,
+        storage_key
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_tenant_server_setup() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+
+    // Tenants are derived deterministically, and share the keypair unless
+    // asked for their own
+    let tenant_a = server_setup.tenant(b"tenant A")?;
+    assert_eq!(server_setup.tenant(b"tenant A")?, tenant_a);
+    assert_ne!(tenant_a, server_setup);
+    assert_eq!(tenant_a.keypair(), server_setup.keypair());
+    let tenant_a_with_keypair = server_setup.tenant_with_keypair(b"tenant A")?;
+    assert_eq!(
+        server_setup.tenant_with_keypair(b"tenant A")?,
+        tenant_a_with_keypair
+    );
+    assert_ne!(
+        tenant_a_with_keypair.keypair().public(),
+        server_setup.keypair().public()
+    );
+    assert_ne!(
+        server_setup
+            .tenant_with_keypair(b"tenant B")?
+            .keypair()
+            .public(),
+        tenant_a_with_keypair.keypair().public()
+    );
+
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &tenant_a,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    // The record only works with the setup of its tenant
+    for (setup, success) in [
+        (server_setup.tenant(b"tenant A")?, true),
+        (server_setup.tenant(b"tenant B")?, false),
+        (server_setup.clone(), false),
+    ]
+    .iter()
+    {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            setup,
+            Some(password_file.clone()),
+            client_login_start_result.message,
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
+        )?;
+        let client_login_finish_result = client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters::default(),
+        );
+        assert_eq!(client_login_finish_result.is_ok(), *success);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_key_id() -> Result<(), ProtocolError> {
+    struct KeyIdentified;
+    impl CipherSuite for KeyIdentified {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = NoOpHash;
+        const KEY_ID_LEN: usize = 8;
+    }
+
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<KeyIdentified>::new(&mut rng)?;
+    assert_eq!(server_setup.key_id().len(), 8);
+    assert_ne!(
+        ServerSetup::<KeyIdentified>::new(&mut rng)?.key_id(),
+        server_setup.key_id()
+    );
+
+    let client_registration_start_result =
+        ClientRegistration::<KeyIdentified>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let mut login = |key_id: Option<Vec<u8>>, tamper: bool| -> Result<_, ProtocolError> {
+        let client_login_start_result = ClientLogin::<KeyIdentified>::start(&mut rng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &server_setup,
+            Some(password_file.clone()),
+            client_login_start_result.message,
+            b"credentialIdentifier",
+            ServerLoginStartParameters {
+                key_id,
+                ..ServerLoginStartParameters::default()
+            },
+        )?;
+        let mut credential_response_bytes = server_login_start_result.message.serialize()?;
+        let key_id_start = 32 + 32 + server_login_start_result.message.masked_response().len();
+        if tamper {
+            credential_response_bytes[key_id_start] ^= 1;
+        }
+        let credential_response =
+            CredentialResponse::<KeyIdentified>::deserialize(&credential_response_bytes)?;
+        assert_eq!(
+            credential_response.key_id(),
+            &credential_response_bytes[key_id_start..key_id_start + 8]
+        );
+        let client_login_finish_result = client_login_start_result
+            .state
+            .finish(credential_response, ClientLoginFinishParameters::default())?;
+        Ok(client_login_finish_result.key_id)
+    };
+
+    // The key identifier defaults to the one of the server setup, and is
+    // covered by the server's MAC
+    assert_eq!(login(None, false)?, server_setup.key_id());
+    assert_eq!(login(Some(b"key 0002".to_vec()), false)?, b"key 0002");
+    assert!(matches!(
+        login(None, true),
+        Err(ProtocolError::InvalidLoginError)
+    ));
+    assert!(matches!(
+        login(Some(b"key 2".to_vec()), false),
+        Err(ProtocolError::LibraryError(InternalError::SizeError {
+            name: "key_id",
+            ..
+        }))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_server_registration_stream() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let mut records = Vec::new();
+    for i in 0..4u8 {
+        let client_registration_start_result =
+            ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, &[i])?;
+        let server_registration_start_result = ServerRegistration::start(
+            &server_setup,
+            client_registration_start_result.message,
+            &[i],
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters::default(),
+        )?;
+        let upload = client_registration_finish_result.message;
+        records.push(if i % 2 == 0 {
+            ServerRegistration::finish(upload)
+        } else {
+            ServerRegistration::finish_with_client_identity(upload, &[i])
+        });
+    }
+
+    for record in &records {
+        let mut output = vec![0xff];
+        record.serialize_into(&mut output)?;
+        assert_eq!(output[1..], record.serialize()?[..]);
+        assert_eq!(output.len() - 1, record.serialized_len());
+    }
+
+    let mut stream = Vec::new();
+    ServerRegistration::serialize_stream(&records, &mut stream)?;
+    let deserialized =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::deserialize_stream(&stream)
+            .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(deserialized, records);
+    assert_eq!(
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::deserialize_stream(&[]).count(),
+        0
+    );
+
+    // A malformed record does not affect the following ones
+    let mut malformed = stream.clone();
+    malformed[2] ^= 0x01;
+    let mut results =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::deserialize_stream(&malformed);
+    assert!(matches!(
+        results.next(),
+        Some(Err(ProtocolError::InvalidClientStaticKeyError))
+    ));
+    assert_eq!(results.filter(Result::is_ok).count(), records.len() - 1);
+
+    // A truncated stream ends the iteration
+    let mut results = ServerRegistration::<RistrettoSha5123dhNoSlowHash>::deserialize_stream(
+        &stream[..stream.len() - 1],
+    );
+    assert_eq!(
+        results.by_ref().take(records.len() - 1).count(),
+        records.len() - 1
+    );
+    assert!(matches!(
+        results.next(),
+        Some(Err(ProtocolError::SerializationError))
+    ));
+    assert!(results.next().is_none());
+
+    Ok(())
+}
+#[test]
+fn test_slow_hash_progress() -> Result<(), ProtocolError> {
+    use core::cell::RefCell;
+
+    let credential_identifier = b"credentialIdentifier";
+    let password = b"password";
+    let mut client_rng = OsRng;
+    let mut server_rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, password)?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            credential_identifier,
+        )?;
+
+    let steps = RefCell::new(Vec::new());
+    let progress = |completed, total| steps.borrow_mut().push((completed, total));
+
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut client_rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters {
+            progress: Some(&progress),
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+    assert_eq!(steps.replace(Vec::new()), vec![(0, 1), (1, 1)]);
+
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, password)?;
+    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut server_rng,
+        &server_setup,
+        Some(p_file),
+        client_login_start_result.message,
+        credential_identifier,
+        ServerLoginStartParameters::default(),
+    )?;
+    client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters {
+            progress: Some(&progress),
+            ..ClientLoginFinishParameters::default()
+        },
+    )?;
+    assert_eq!(steps.into_inner(), vec![(0, 1), (1, 1)]);
+
+    Ok(())
+}
+
+#[test]
+fn test_slow_hash_cancellation() -> Result<(), ProtocolError> {
+    use crate::slow_hash::CancellationToken;
+
+    let credential_identifier = b"credentialIdentifier";
+    let password = b"password";
+    let mut client_rng = OsRng;
+    let mut server_rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut server_rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, password)?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            credential_identifier,
+        )?;
+
+    let token = CancellationToken::new();
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut client_rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters {
+            cancellation: Some(&token),
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut client_rng, password)?;
+    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut server_rng,
+        &server_setup,
+        Some(p_file),
+        client_login_start_result.message,
+        credential_identifier,
+        ServerLoginStartParameters::default(),
+    )?;
+
+    token.cancel();
+    let client_login_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters {
+            cancellation: Some(&token),
+            ..ClientLoginFinishParameters::default()
+        },
+    );
+
+    assert!(matches!(
+        client_login_result,
+        Err(ProtocolError::LibraryError(
+            InternalError::SlowHashCancelledError
+        ))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_login_with_slow_hash_upgrade() -> Result<(), ProtocolError> {
+    use crate::slow_hash::SlowHash;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use digest::Digest;
+
+    // Counts its computations, to check that logins only compute the slow
+    // hashes they need
+    #[derive(Default)]
+    struct Rounds(u8, AtomicUsize);
+    impl SlowHash<sha2::Sha512> for Rounds {
+        fn hash(&self, input: &[u8], output_len: usize) -> Result<Vec<u8>, InternalError> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            let mut output = (0..self.0).fold(input.to_vec(), |output, _| {
+                sha2::Sha512::digest(&output).to_vec()
+            });
+            output.truncate(output_len);
+            Ok(output)
+        }
+    }
+
+    struct RistrettoSha5123dhRounds;
+    impl CipherSuite for RistrettoSha5123dhRounds {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = Rounds;
+    }
+
+    let credential_identifier = b"credentialIdentifier";
+    let password = b"password";
+    // The slow hashes of identifiers 1 and 2, the latter being the current one
+    let slow_hashes = &[
+        Rounds(1, AtomicUsize::new(0)),
+        Rounds(2, AtomicUsize::new(0)),
+    ];
+    let slow_hash = move |slow_hash_id: u8| &slow_hashes[usize::from(slow_hash_id) - 1];
+    let calls = move |slow_hash_id| slow_hash(slow_hash_id).1.load(Ordering::SeqCst);
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhRounds>::new(&mut rng)?;
+
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhRounds>::start(&mut rng, password)?;
+    let server_registration_start_result = ServerRegistration::<RistrettoSha5123dhRounds>::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters {
+            slow_hash: Some(slow_hash(1)),
+            export_key: true,
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+    let mut p_file =
+        ServerRegistration::finish(client_registration_finish_result.message).with_slow_hash_id(1);
+
+    // The identifier is stored with the record, in a versioned format
+    let p_file_bytes = p_file.serialize()?;
+    assert_eq!(ServerRegistration::deserialize(&p_file_bytes)?, p_file);
+    assert_eq!(p_file_bytes[..2], [SERVER_REGISTRATION_VERSION, 1]);
+    assert_eq!(
+        p_file_bytes.len(),
+        4 + RegistrationUpload::<RistrettoSha5123dhRounds>::max_serialized_len()
+    );
+    let mut default_id = p_file_bytes.clone();
+    default_id[1] = 0;
+    assert!(matches!(
+        ServerRegistration::<RistrettoSha5123dhRounds>::deserialize(&default_id),
+        Err(ProtocolError::SerializationError)
+    ));
+    let mut unknown_version = p_file_bytes.clone();
+    unknown_version[0] = SERVER_REGISTRATION_VERSION + 1;
+    assert!(ServerRegistration::<RistrettoSha5123dhRounds>::deserialize(&unknown_version).is_err());
+
+    // Unregistered clients get identifiers drawn from the weights, which
+    // stay the same from one login to the next
+    let dummy_slow_hash_id = |credential_identifier: &[u8], dummy_slow_hash_ids| {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhRounds>::start(&mut OsRng, password)?;
+        Ok::<_, ProtocolError>(
+            ServerLogin::start(
+                &mut OsRng,
+                &server_setup,
+                None,
+                client_login_start_result.message,
+                credential_identifier,
+                ServerLoginStartParameters {
+                    dummy_slow_hash_ids,
+                    ..ServerLoginStartParameters::default()
+                },
+            )?
+            .slow_hash_id,
+        )
+    };
+    assert_eq!(dummy_slow_hash_id(b"mallory", Vec::new())?, 0);
+    let mut drawn = [0usize; 3];
+    for i in 0..64u8 {
+        let id = dummy_slow_hash_id(&[i], vec![(1, 1), (2, 1), (3, 0)])?;
+        assert_eq!(dummy_slow_hash_id(&[i], vec![(1, 1), (2, 1), (3, 0)])?, id);
+        drawn[usize::from(id) - 1] += 1;
+    }
+    assert!(drawn[0] > 0 && drawn[1] > 0 && drawn[2] == 0);
+
+    let login = |p_file: &ServerRegistration<RistrettoSha5123dhRounds>, password: &[u8]| {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhRounds>::start(&mut OsRng, password)?;
+        let server_login_start_result = ServerLogin::start(
+            &mut OsRng,
+            &server_setup,
+            Some(p_file.clone()),
+            client_login_start_result.message,
+            credential_identifier,
+            ServerLoginStartParameters::default(),
+        )?;
+        // The server sends the identifier along with the credential response
+        assert_eq!(
+            server_login_start_result.slow_hash_id,
+            p_file.slow_hash_id()
+        );
+        let params = ClientLoginFinishParameters {
+            slow_hash: Some(slow_hash(server_login_start_result.slow_hash_id)),
+            export_key: true,
+            ..ClientLoginFinishParameters::default()
+        };
+        let client_login_finish_result = if p_file.slow_hash_id() == 2 {
+            client_login_start_result
+                .state
+                .finish(server_login_start_result.message, params)?
+        } else {
+            client_login_start_result.state.finish_with_upgrade(
+                &mut OsRng,
+                server_login_start_result.message,
+                params,
+                slow_hash(2),
+            )?
+        };
+        let server_login_finish_result = server_login_start_result
+            .state
+            .finish(client_login_finish_result.message.clone())?;
+        assert_eq!(
+            client_login_finish_result.session_key,
+            server_login_finish_result.session_key
+        );
+        Ok::<_, ProtocolError>(client_login_finish_result)
+    };
+
+    // A wrong password only costs the slow hash of the record
+    let (old_calls, new_calls) = (calls(1), calls(2));
+    assert!(matches!(
+        login(&p_file, b"wrong password"),
+        Err(ProtocolError::InvalidLoginError)
+    ));
+    assert_eq!((calls(1), calls(2)), (old_calls + 1, new_calls));
+
+    let client_login_finish_result = login(&p_file, password)?;
+    assert_eq!((calls(1), calls(2)), (old_calls + 2, new_calls + 1));
+    assert_eq!(
+        client_login_finish_result.export_key,
+        client_registration_finish_result.export_key
+    );
+
+    // Once the upgrade is stored, the current slow hash opens the
+    // registration, under a new export key
+    let upgrade = client_login_finish_result.upgrade.unwrap();
+    assert_ne!(
+        upgrade.export_key,
+        client_registration_finish_result.export_key
+    );
+    p_file = ServerRegistration::finish(upgrade.message).with_slow_hash_id(2);
+    let client_login_finish_result = login(&p_file, password)?;
+    assert_eq!((calls(1), calls(2)), (old_calls + 2, new_calls + 2));
+    assert_eq!(client_login_finish_result.export_key, upgrade.export_key);
+    assert!(client_login_finish_result.upgrade.is_none());
+
+    Ok(())
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_registration_batch() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+
+    let mut client_states = Vec::new();
+    let mut requests = Vec::new();
+    // Batches hold repeated credential identifiers, whose OPRF key is only
+    // derived once
+    for i in 0..10u8 {
+        let client_registration_start_result =
+            ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, &[i])?;
+        client_states.push(client_registration_start_result.state);
+        requests.push((client_registration_start_result.message, vec![i % 4]));
+    }
+
+    let mut expected = Vec::new();
+    for (message, credential_identifier) in requests.iter() {
+        expected.push(
+            ServerRegistration::start(&server_setup, message.clone(), credential_identifier)?
+                .message
+                .serialize(),
+        );
+    }
+
+    let mut responses = Vec::new();
+    ServerRegistration::start_batch(&server_setup, requests, 3, |result| {
+        responses.push(result.map(|result| result.message))
     });
+    assert_eq!(responses.len(), expected.len());
+
+    let mut uploads = Vec::new();
+    for ((client_state, response), expected) in client_states
+        .into_iter()
+        .zip(responses.into_iter())
+        .zip(expected.into_iter())
+    {
+        let response = response?;
+        assert_eq!(response.serialize(), expected);
+        let client_registration_finish_result = client_state.finish(
+            &mut rng,
+            response,
+            ClientRegistrationFinishParameters::default(),
+        )?;
+        uploads.push(
+            ServerRegistration::finish(client_registration_finish_result.message).serialize()?,
+        );
+    }
+
+    let mut records = Vec::new();
+    ServerRegistration::<RistrettoSha5123dhNoSlowHash>::deserialize_batch(
+        uploads.iter(),
+        4,
+        |result| records.push(result),
+    );
+    assert_eq!(records.len(), uploads.len());
+    for (record, upload) in records.into_iter().zip(uploads.iter()) {
+        assert_eq!(&record?.serialize()?, upload);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_login_finish_batch() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let mut session_keys = Vec::new();
+    let mut logins = Vec::new();
+    for i in 0..10 {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &server_setup,
+            Some(password_file.clone()),
+            client_login_start_result.message,
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
+        )?;
+        let client_login_finish_result = client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters::default(),
+        )?;
+
+        // Corrupt the MAC of one of the logins
+        let mut message = client_login_finish_result.message.serialize()?;
+        if i == 4 {
+            message[0] ^= 1;
+        }
+        session_keys.push(client_login_finish_result.session_key);
+        logins.push((
+            server_login_start_result.state,
+            CredentialFinalization::deserialize(&message)?,
+        ));
+    }
+
+    let mut results = Vec::new();
+    ServerLogin::finish_many(logins, 3, |result| results.push(result));
+    assert_eq!(results.len(), session_keys.len());
+    for (i, (result, session_key)) in results.into_iter().zip(session_keys).enumerate() {
+        if i == 4 {
+            assert!(matches!(result, Err(ProtocolError::InvalidLoginError)));
+        } else {
+            assert_eq!(result?.session_key, session_key);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_debug_redaction() -> Result<(), ProtocolError> {
+    let credential_identifier = b"credentialIdentifier";
+    let password = b"password";
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message.clone(),
+            credential_identifier,
+        )?;
+    let client_registration_finish_result = client_registration_start_result.state.clone().finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message.clone());
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut rng,
+        &server_setup,
+        Some(p_file.clone()),
+        client_login_start_result.message.clone(),
+        credential_identifier,
+        ServerLoginStartParameters::default(),
+    )?;
+    let client_login_finish_result = client_login_start_result.state.clone().finish(
+        server_login_start_result.message.clone(),
+        ClientLoginFinishParameters {
+            export_key: true,
+            ..ClientLoginFinishParameters::default()
+        },
+    )?;
+    let server_login_finish_result = server_login_start_result
+        .state
+        .clone()
+        .finish(client_login_finish_result.message.clone())?;
+
+    let secrets = [
+        format!("{:?}", &server_setup.keypair().private()[..]),
+        format!(
+            "{:?}",
+            client_registration_finish_result
+                .export_key
+                .as_ref()
+                .unwrap()
+                .expose_secret()
+        ),
+        format!(
+            "{:?}",
+            client_login_finish_result.session_key.expose_secret()
+        ),
+        format!(
+            "{:?}",
+            client_login_finish_result
+                .export_key
+                .as_ref()
+                .unwrap()
+                .expose_secret()
+        ),
+        format!(
+            "{:?}",
+            server_login_finish_result.session_key.expose_secret()
+        ),
+        format!("{:?}", password),
+    ];
+    let outputs = [
+        format!("{:?}", server_setup),
+        format!("{:?}", client_registration_start_result),
+        format!("{:?}", client_registration_finish_result),
+        format!("{:?}", p_file),
+        format!("{:?}", client_login_start_result),
+        format!("{:?}", server_login_start_result),
+        format!("{:?}", client_login_finish_result),
+        format!("{:?}", server_login_finish_result),
+    ];
+    for output in outputs.iter() {
+        assert!(output.contains("[REDACTED; "));
+        for secret in secrets.iter() {
+            assert!(!output.contains(secret.as_str()));
+        }
+    }
+    assert!(outputs[7].contains("[REDACTED; 64]"));
+
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_protocol_observer() -> Result<(), ProtocolError> {
+    use crate::observer::{clear_observer, set_observer, ProtocolEvent, ProtocolObserver};
+    use std::sync::Mutex;
+
+    struct Recorder(Mutex<Vec<ProtocolEvent>>);
+
+    impl ProtocolObserver for Recorder {
+        fn observe(&self, event: ProtocolEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl Recorder {
+        fn count(&self, event: ProtocolEvent) -> usize {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|e| **e == event)
+                .count()
+        }
+    }
+
+    lazy_static::lazy_static! {
+        static ref RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+    }
+
+    // Other tests run concurrently and report events too, so only check that
+    // the counts increase
+    set_observer(&*RECORDER).unwrap();
+    assert!(set_observer(&*RECORDER).is_err());
+
+    let failed_deserialization = ProtocolEvent::DeserializationFailed("CredentialFinalization");
+    let before = RECORDER.count(failed_deserialization);
+    assert!(
+        CredentialFinalization::<RistrettoSha5123dhNoSlowHash>::deserialize(&[0u8; 3]).is_err()
+    );
+    assert!(RECORDER.count(failed_deserialization) > before);
+
+    let credential_identifier = b"credentialIdentifier";
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            credential_identifier,
+        )?;
+    let before = RECORDER.count(ProtocolEvent::ClientRegistrationFinished);
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    assert!(RECORDER.count(ProtocolEvent::ClientRegistrationFinished) > before);
+
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"incorrect")?;
+    let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut rng,
+        &server_setup,
+        Some(p_file),
+        client_login_start_result.message,
+        credential_identifier,
+        ServerLoginStartParameters::default(),
+    )?;
+    let before = RECORDER.count(ProtocolEvent::ClientLoginMacFailed);
+    assert!(client_login_start_result
+        .state
+        .finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters::default(),
+        )
+        .is_err());
+    assert!(RECORDER.count(ProtocolEvent::ClientLoginMacFailed) > before);
+
+    // Other tests no longer report to the recorder once it is removed
+    assert!(clear_observer().is_some());
+    assert!(clear_observer().is_none());
+
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_audit_sink() -> Result<(), ProtocolError> {
+    use crate::audit::{
+        clear_audit_sink, set_audit_sink, AuditEvent, AuditSink, LoginFailureReason,
+    };
+    use std::sync::Mutex;
+
+    #[derive(Debug, PartialEq)]
+    enum Recorded {
+        RegistrationCompleted(Vec<u8>),
+        DummyRecordServed(Vec<u8>),
+        LoginStarted(Vec<u8>),
+        LoginSucceeded,
+        LoginFailed(LoginFailureReason),
+    }
+
+    struct Recorder(Mutex<Vec<Recorded>>);
+
+    impl AuditSink for Recorder {
+        fn record(&self, event: &AuditEvent<'_>) {
+            self.0.lock().unwrap().push(match *event {
+                AuditEvent::RegistrationCompleted { client_s_pk } => {
+                    Recorded::RegistrationCompleted(client_s_pk.to_vec())
+                }
+                AuditEvent::DummyRecordServed {
+                    credential_identifier,
+                } => Recorded::DummyRecordServed(credential_identifier.to_vec()),
+                AuditEvent::LoginStarted {
+                    credential_identifier,
+                } => Recorded::LoginStarted(credential_identifier.to_vec()),
+                AuditEvent::LoginSucceeded => Recorded::LoginSucceeded,
+                AuditEvent::LoginFailed { reason } => Recorded::LoginFailed(reason),
+            });
+        }
+    }
+
+    impl Recorder {
+        fn count(&self, recorded: &Recorded) -> usize {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|r| *r == recorded)
+                .count()
+        }
+    }
+
+    lazy_static::lazy_static! {
+        static ref RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+    }
+
+    // Other tests run concurrently and record events too, so only check that
+    // the counts increase
+    set_audit_sink(&*RECORDER).unwrap();
+    assert!(set_audit_sink(&*RECORDER).is_err());
+
+    let credential_identifier = b"auditedCredentialIdentifier";
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            credential_identifier,
+        )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let client_s_pk = client_registration_finish_result
+        .message
+        .client_s_pk
+        .to_arr()
+        .to_vec();
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+    assert_eq!(
+        RECORDER.count(&Recorded::RegistrationCompleted(client_s_pk)),
+        1
+    );
+
+    for password_file in [Some(p_file), None].iter().cloned() {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+        let server_login_start_result = ServerLogin::<RistrettoSha5123dhNoSlowHash>::start(
+            &mut rng,
+            &server_setup,
+            password_file,
+            client_login_start_result.message,
+            credential_identifier,
+            ServerLoginStartParameters::default(),
+        )?;
+
+        let failed = Recorded::LoginFailed(LoginFailureReason::BadMac);
+        let before = RECORDER.count(&failed);
+        let forged = CredentialFinalization::deserialize(&[0u8; 64])?;
+        assert!(server_login_start_result.state.finish(forged).is_err());
+        assert!(RECORDER.count(&failed) > before);
+    }
+    assert_eq!(
+        RECORDER.count(&Recorded::LoginStarted(credential_identifier.to_vec())),
+        1
+    );
+    assert_eq!(
+        RECORDER.count(&Recorded::DummyRecordServed(credential_identifier.to_vec())),
+        1
+    );
+
+    assert!(clear_audit_sink().is_some());
+    assert!(clear_audit_sink().is_none());
+
+    Ok(())
+}
+
+#[cfg(feature = "erased")]
+#[test]
+fn test_erased_api() -> Result<(), ProtocolError> {
+    use crate::erased::Suite;
+
+    let mut rng = OsRng;
+    let suite = Suite::from_id(Suite::Ristretto255Sha512.id())?;
+    assert_eq!(suite, Suite::Ristretto255Sha512);
+    assert!(Suite::from_id(0).is_err());
+
+    let server_setup = suite.new_server_setup(&mut rng)?;
+    let server_setup = suite.deserialize_server_setup(&server_setup.serialize()?)?;
+    assert_eq!(server_setup.suite(), suite);
+
+    let client_registration_start_result =
+        suite.client_registration_start(&mut rng, b"password")?;
+    let registration_response =
+        server_setup.registration_start(&client_registration_start_result.message, b"id")?;
+    let client_registration = suite
+        .deserialize_client_registration(&client_registration_start_result.state.serialize()?)?;
+    let client_registration_finish_result =
+        client_registration.finish_with_export_key(&mut rng, &registration_response)?;
+    let password_file =
+        server_setup.registration_finish(&client_registration_finish_result.message)?;
+
+    for (password, success) in [(&b"password"[..], true), (&b"wrong"[..], false)]
+        .iter()
+        .cloned()
+    {
+        let client_login_start_result = suite.client_login_start(&mut rng, password)?;
+        let server_login_start_result = server_setup.login_start(
+            &mut rng,
+            Some(&password_file),
+            &client_login_start_result.message,
+            b"id",
+        )?;
+        let client_login =
+            suite.deserialize_client_login(&client_login_start_result.state.serialize()?)?;
+        let client_login_finish_result =
+            client_login.finish_with_export_key(&server_login_start_result.message);
+        if !success {
+            assert!(matches!(
+                client_login_finish_result,
+                Err(ProtocolError::InvalidLoginError)
+            ));
+            continue;
+        }
+        let client_login_finish_result = client_login_finish_result?;
+        assert_eq!(
+            client_login_finish_result.export_key,
+            client_registration_finish_result.export_key
+        );
+
+        let server_login =
+            suite.deserialize_server_login(&server_login_start_result.state.serialize()?)?;
+        let session_key = server_login.finish(&client_login_finish_result.message)?;
+        assert_eq!(session_key, client_login_finish_result.session_key);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "aead-envelope")]
+#[test]
+fn test_aead_envelope() -> Result<(), ProtocolError> {
+    let credential_identifier = b"credentialIdentifier";
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhAeadEnvelope>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhAeadEnvelope>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+
+    // The envelope carries the encrypted private key and its tag
+    let upload_bytes = client_registration_finish_result.message.serialize()?;
+    assert_eq!(upload_bytes.len(), 32 + 64 + (32 + 32 + 16 + 64));
+    let p_file = ServerRegistration::finish(RegistrationUpload::deserialize(&upload_bytes)?);
+
+    for (password, success) in [(&b"password"[..], true), (&b"wrong"[..], false)]
+        .iter()
+        .cloned()
+    {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhAeadEnvelope>::start(&mut rng, password)?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &server_setup,
+            Some(p_file.clone()),
+            client_login_start_result.message,
+            credential_identifier,
+            ServerLoginStartParameters::default(),
+        )?;
+        let client_login_finish_result = client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters {
+                export_key: true,
+                ..ClientLoginFinishParameters::default()
+            },
+        );
+        if !success {
+            assert!(matches!(
+                client_login_finish_result,
+                Err(ProtocolError::InvalidLoginError)
+            ));
+            continue;
+        }
+        let client_login_finish_result = client_login_finish_result?;
+        assert_eq!(
+            client_login_finish_result.export_key,
+            client_registration_finish_result.export_key
+        );
+        let server_login_finish_result = server_login_start_result
+            .state
+            .finish(client_login_finish_result.message)?;
+        assert_eq!(
+            server_login_finish_result.session_key,
+            client_login_finish_result.session_key
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "aead-envelope")]
+#[test]
+fn test_runtime_envelope_mode() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhAeadEnvelope>::new(&mut rng)?;
+
+    for envelope_mode in [None, Some(EnvelopeMode::Internal), Some(EnvelopeMode::Aead)]
+        .iter()
+        .cloned()
+    {
+        let client_registration_start_result =
+            ClientRegistration::<RistrettoSha5123dhAeadEnvelope>::start(&mut rng, b"password")?;
+        let server_registration_start_result = ServerRegistration::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"credentialIdentifier",
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters {
+                export_key: true,
+                envelope_mode,
+                ..ClientRegistrationFinishParameters::default()
+            },
+        )?;
+
+        // Every envelope has the length of the AEAD envelope, which carries
+        // the encrypted private key and its tag
+        let password_file_bytes =
+            ServerRegistration::finish(client_registration_finish_result.message).serialize()?;
+        assert_eq!(password_file_bytes.len(), 32 + 64 + (32 + 32 + 16 + 64));
+        let password_file = ServerRegistration::<RistrettoSha5123dhAeadEnvelope>::deserialize(
+            &password_file_bytes,
+        )?;
+
+        // Login picks up the mode from the envelope
+        for (password, success) in [(&b"password"[..], true), (&b"wrong"[..], false)]
+            .iter()
+            .cloned()
+        {
+            let client_login_start_result =
+                ClientLogin::<RistrettoSha5123dhAeadEnvelope>::start(&mut rng, password)?;
+            let server_login_start_result = ServerLogin::start(
+                &mut rng,
+                &server_setup,
+                Some(password_file.clone()),
+                client_login_start_result.message,
+                b"credentialIdentifier",
+                ServerLoginStartParameters::default(),
+            )?;
+            // Which cannot be told apart from that of an unregistered client
+            let credential_response_bytes = server_login_start_result.message.serialize()?;
+            let fake_credential_response = ServerLogin::start(
+                &mut rng,
+                &server_setup,
+                None,
+                ClientLogin::<RistrettoSha5123dhAeadEnvelope>::start(&mut rng, password)?.message,
+                b"credentialIdentifier",
+                ServerLoginStartParameters::default(),
+            )?
+            .message
+            .serialize()?;
+            assert_eq!(
+                credential_response_bytes.len(),
+                fake_credential_response.len()
+            );
+            let credential_response = CredentialResponse::deserialize(&credential_response_bytes)?;
+            let client_login_finish_result = client_login_start_result.state.finish(
+                credential_response,
+                ClientLoginFinishParameters {
+                    export_key: true,
+                    ..ClientLoginFinishParameters::default()
+                },
+            );
+            if !success {
+                assert!(matches!(
+                    client_login_finish_result,
+                    Err(ProtocolError::InvalidLoginError)
+                ));
+                continue;
+            }
+            let client_login_finish_result = client_login_finish_result?;
+            assert_eq!(
+                client_login_finish_result.export_key,
+                client_registration_finish_result.export_key
+            );
+            let server_login_finish_result = server_login_start_result
+                .state
+                .finish(client_login_finish_result.message)?;
+            assert_eq!(
+                server_login_finish_result.session_key,
+                client_login_finish_result.session_key
+            );
+        }
+    }
+
+    // With the internal mode as the default, the longer AEAD envelope would
+    // give the registration away
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    assert!(matches!(
+        client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters {
+                envelope_mode: Some(EnvelopeMode::Aead),
+                ..ClientRegistrationFinishParameters::default()
+            },
+        ),
+        Err(ProtocolError::LibraryError(
+            InternalError::IncompatibleEnvelopeModeError
+        ))
+    ));
+
+    Ok(())
+}
+
+#[cfg(feature = "aead-envelope")]
+#[test]
+fn test_client_seed() -> Result<(), ProtocolError> {
+    let seed = b"correct horse battery staple";
+    let keypair = ClientRegistration::<RistrettoSha5123dhAeadEnvelope>::keypair_from_seed(seed)?;
+    assert_eq!(
+        ClientRegistration::<RistrettoSha5123dhAeadEnvelope>::keypair_from_seed(seed)?,
+        keypair
+    );
+    assert_ne!(
+        ClientRegistration::<RistrettoSha5123dhAeadEnvelope>::keypair_from_seed(b"other seed")?,
+        keypair
+    );
+
+    let mut rng = OsRng;
+    let register = |rng: &mut OsRng, envelope_mode| {
+        let server_setup = ServerSetup::<RistrettoSha5123dhAeadEnvelope>::new(rng)?;
+        let client_registration_start_result =
+            ClientRegistration::<RistrettoSha5123dhAeadEnvelope>::start(rng, b"password")?;
+        let server_registration_start_result = ServerRegistration::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"credentialIdentifier",
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters {
+                envelope_mode: Some(envelope_mode),
+                client_seed: Some(&seed[..]),
+                ..ClientRegistrationFinishParameters::default()
+            },
+        )?;
+        Ok::<_, ProtocolError>((
+            server_setup,
+            ServerRegistration::finish(client_registration_finish_result.message),
+        ))
+    };
+
+    // Registrations with different servers share the keypair of the seed
+    let (server_setup, password_file) = register(&mut rng, EnvelopeMode::Aead)?;
+    assert_eq!(password_file.client_s_pk(), keypair.public());
+    assert_eq!(
+        register(&mut rng, EnvelopeMode::Aead)?.1.client_s_pk(),
+        keypair.public()
+    );
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhAeadEnvelope>::start(&mut rng, b"password")?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(password_file),
+        client_login_start_result.message,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    )?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters::default(),
+    )?;
+    let server_login_finish_result = server_login_start_result
+        .state
+        .finish(client_login_finish_result.message)?;
+    assert_eq!(
+        server_login_finish_result.session_key,
+        client_login_finish_result.session_key
+    );
+
+    // The internal mode derives the keypair from the password instead
+    assert!(matches!(
+        register(&mut rng, EnvelopeMode::Internal),
+        Err(ProtocolError::LibraryError(
+            InternalError::IncompatibleEnvelopeModeError
+        ))
+    ));
+
+    Ok(())
+}
+
+#[cfg(feature = "sealed-records")]
+#[test]
+fn test_sealed_registration_record() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let old_kek = [1u8; RECORD_SEALING_KEY_LEN];
+    let new_kek = [2u8; RECORD_SEALING_KEY_LEN];
+    let sealed = password_file.seal(&mut rng, b"credentialIdentifier", b"2021-01", &old_kek)?;
+    assert_eq!(
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::sealed_key_id(&sealed)?,
+        b"2021-01"
+    );
+    assert_eq!(
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::unseal(
+            &sealed,
+            b"credentialIdentifier",
+            &old_kek
+        )?,
+        password_file
+    );
+    // The record itself is not stored in the clear, and every sealing uses
+    // a fresh nonce
+    let record = password_file.serialize()?;
+    assert!(!sealed
+        .windows(record.len())
+        .any(|window| window == &record[..]));
+    assert_ne!(
+        password_file.seal(&mut rng, b"credentialIdentifier", b"2021-01", &old_kek)?,
+        sealed
+    );
+
+    // Rotation: unseal under the key named in the record, reseal under the
+    // new one
+    let resealed = ServerRegistration::<RistrettoSha5123dhNoSlowHash>::unseal(
+        &sealed,
+        b"credentialIdentifier",
+        &old_kek,
+    )?
+    .seal(&mut rng, b"credentialIdentifier", b"2021-02", &new_kek)?;
+    assert_eq!(
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::sealed_key_id(&resealed)?,
+        b"2021-02"
+    );
+    assert_eq!(
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::unseal(
+            &resealed,
+            b"credentialIdentifier",
+            &new_kek
+        )?,
+        password_file
+    );
+
+    // A wrong key or credential identifier, or any modification, including
+    // of the key identifier, is detected
+    assert!(matches!(
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::unseal(
+            &sealed,
+            b"credentialIdentifier",
+            &new_kek
+        ),
+        Err(ProtocolError::LibraryError(
+            InternalError::RecordDecryptionError
+        ))
+    ));
+    assert!(matches!(
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::unseal(&sealed, b"other", &old_kek),
+        Err(ProtocolError::LibraryError(
+            InternalError::RecordDecryptionError
+        ))
+    ));
+    for index in [2, 9, 40, sealed.len() - 1].iter().cloned() {
+        let mut modified = sealed.clone();
+        modified[index] ^= 1;
+        assert!(matches!(
+            ServerRegistration::<RistrettoSha5123dhNoSlowHash>::unseal(
+                &modified,
+                b"credentialIdentifier",
+                &old_kek
+            ),
+            Err(ProtocolError::LibraryError(
+                InternalError::RecordDecryptionError
+            ))
+        ));
+    }
+    assert!(matches!(
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::unseal(
+            &sealed[..40],
+            b"credentialIdentifier",
+            &old_kek
+        ),
+        Err(ProtocolError::LibraryError(
+            InternalError::RecordDecryptionError
+        ))
+    ));
+    assert!(matches!(
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::unseal(
+            &sealed,
+            b"credentialIdentifier",
+            &old_kek[1..]
+        ),
+        Err(ProtocolError::LibraryError(InternalError::SizeError {
+            name: "record_sealing_key",
+            ..
+        }))
+    ));
+
+    Ok(())
+}
+
+#[cfg(feature = "sealed-records")]
+#[test]
+fn test_crypto_shredding() -> Result<(), ProtocolError> {
+    use rand::RngCore;
+
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let mut salt = [0u8; ERASURE_SALT_LEN];
+    rng.fill_bytes(&mut salt);
+    let wrapped =
+        server_setup.wrap_record(&mut rng, b"credentialIdentifier", &salt, &password_file)?;
+    assert_eq!(
+        server_setup.unwrap_record(b"credentialIdentifier", &salt, &wrapped)?,
+        password_file
+    );
+    // The setup survives a serialization roundtrip, as it would a restart
+    assert_eq!(
+        ServerSetup::<RistrettoSha5123dhNoSlowHash>::deserialize(&server_setup.serialize()?)?
+            .unwrap_record(b"credentialIdentifier", &salt, &wrapped)?,
+        password_file
+    );
+
+    // Without the original salt, or under another identifier or setup, the
+    // record cannot be recovered
+    let mut other_salt = [0u8; ERASURE_SALT_LEN];
+    rng.fill_bytes(&mut other_salt);
+    let other_server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    for result in [
+        server_setup.unwrap_record(b"credentialIdentifier", &other_salt, &wrapped),
+        server_setup.unwrap_record(b"otherIdentifier", &salt, &wrapped),
+        other_server_setup.unwrap_record(b"credentialIdentifier", &salt, &wrapped),
+    ]
+    .iter()
+    {
+        assert!(matches!(
+            result,
+            Err(ProtocolError::LibraryError(
+                InternalError::RecordDecryptionError
+            ))
+        ));
+    }
+    assert!(matches!(
+        server_setup.unwrap_record(b"credentialIdentifier", &salt[1..], &wrapped),
+        Err(ProtocolError::LibraryError(InternalError::SizeError {
+            name: "erasure_salt",
+            ..
+        }))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_export_key_opt_in() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+
+    // The export key is not derived unless requested
+    assert!(client_registration_finish_result.export_key.is_none());
+    assert!(matches!(
+        client_registration_finish_result.pseudonym(b"service"),
+        Err(ProtocolError::LibraryError(
+            InternalError::ExportKeyNotRequestedError
+        ))
+    ));
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let mut login = |export_key| {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut OsRng,
+            &server_setup,
+            Some(password_file.clone()),
+            client_login_start_result.message,
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
+        )?;
+        client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters {
+                export_key,
+                ..ClientLoginFinishParameters::default()
+            },
+        )
+    };
+    let client_login_finish_result = login(false)?;
+    assert!(client_login_finish_result.export_key.is_none());
+    assert!(matches!(
+        client_login_finish_result.pseudonym(b"service"),
+        Err(ProtocolError::LibraryError(
+            InternalError::ExportKeyNotRequestedError
+        ))
+    ));
+
+    // Requesting it on login alone gives the key of the registration
+    let client_login_finish_result = login(true)?;
+    assert_eq!(
+        client_login_finish_result
+            .export_key
+            .as_ref()
+            .map(|export_key| export_key.expose_secret().len()),
+        Some(64)
+    );
+    assert_eq!(
+        client_login_finish_result.pseudonym(b"service")?,
+        login(true)?.pseudonym(b"service")?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_handshake_fingerprint() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(password_file),
+        client_login_start_result.message,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    )?;
+
+    // The fingerprint is the trailing `KE2` part of the serialized message
+    let fingerprint = [
+        server_login_start_result.server_nonce(),
+        &server_login_start_result.server_e_pk(),
+        server_login_start_result.server_mac(),
+    ]
+    .concat();
+    assert_eq!(fingerprint.len(), 32 + 32 + 64);
+    assert!(server_login_start_result
+        .message
+        .serialize()?
+        .ends_with(&fingerprint));
+
+    // It matches the values the client receives
+    let message = CredentialResponse::<RistrettoSha5123dhNoSlowHash>::deserialize(
+        &server_login_start_result.message.serialize()?,
+    )?;
+    assert_eq!(
+        message.server_e_pk(),
+        server_login_start_result.server_e_pk()
+    );
+    assert_eq!(message.server_mac(), server_login_start_result.server_mac());
+
+    Ok(())
+}
+
+#[test]
+fn test_hash_to_curve_override() -> Result<(), ProtocolError> {
+    // A blind of one leaves the hashed password unchanged
+    let mut one = vec![0u8; 32];
+    one[0] = 1;
+
+    let legacy_request = ClientRegistration::<RistrettoLegacyHashToCurve>::start(
+        &mut CycleRng::new(one.clone()),
+        b"password",
+    )?
+    .message;
+    assert_eq!(
+        legacy_request.get_blinded_element_for_testing().value(),
+        RistrettoPoint::hash_from_bytes::<sha2::Sha512>(b"password")
+    );
+    let default_request = ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+        &mut CycleRng::new(one),
+        b"password",
+    )?
+    .message;
+    assert_ne!(
+        default_request.get_blinded_element_for_testing().value(),
+        legacy_request.get_blinded_element_for_testing().value()
+    );
+
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let legacy_server_setup =
+        ServerSetup::<RistrettoLegacyHashToCurve>::deserialize(&server_setup.serialize()?)?;
+
+    fn register<CS: CipherSuite>(
+        server_setup: &ServerSetup<CS>,
+    ) -> Result<ServerRegistration<CS>, ProtocolError> {
+        let client_registration_start_result =
+            ClientRegistration::<CS>::start(&mut OsRng, b"password")?;
+        let server_registration_start_result = ServerRegistration::start(
+            server_setup,
+            client_registration_start_result.message,
+            b"credentialIdentifier",
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            &mut OsRng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters::default(),
+        )?;
+        Ok(ServerRegistration::finish(
+            client_registration_finish_result.message,
+        ))
+    }
+
+    fn login<CS: CipherSuite>(
+        server_setup: &ServerSetup<CS>,
+        password_file: ServerRegistration<CS>,
+    ) -> Result<(), ProtocolError> {
+        let client_login_start_result = ClientLogin::<CS>::start(&mut OsRng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut OsRng,
+            server_setup,
+            Some(password_file),
+            client_login_start_result.message,
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
+        )?;
+        let client_login_finish_result = client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters::default(),
+        )?;
+        let server_login_finish_result = server_login_start_result
+            .state
+            .finish(client_login_finish_result.message)?;
+        assert_eq!(
+            client_login_finish_result.session_key.expose_secret(),
+            server_login_finish_result.session_key.expose_secret()
+        );
+        Ok(())
+    }
+
+    // Each encoding logs in against its own password files
+    login(&server_setup, register(&server_setup)?)?;
+    login(&legacy_server_setup, register(&legacy_server_setup)?)?;
+
+    // But a password file registered with one encoding rejects the other
+    let password_file = register(&server_setup)?;
+    let legacy_password_file =
+        ServerRegistration::<RistrettoLegacyHashToCurve>::deserialize(&password_file.serialize()?)?;
+    assert!(matches!(
+        login(&legacy_server_setup, legacy_password_file),
+        Err(ProtocolError::InvalidLoginError)
+    ));
+
+    Ok(())
+}
+
+#[cfg(feature = "slow-hash")]
+#[test]
+fn test_argon2_with_secret() -> Result<(), ProtocolError> {
+    use crate::slow_hash::Argon2WithSecret;
+
+    struct RistrettoSha512Argon2WithSecret;
+    impl CipherSuite for RistrettoSha512Argon2WithSecret {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = Argon2WithSecret;
+    }
+
+    let params = argon2::Params::new(256, 1, 1, None).unwrap();
+    let peppered = Argon2WithSecret::new(b"pepper", b"associated data", params.clone())?;
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha512Argon2WithSecret>::new(&mut rng)?;
+
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha512Argon2WithSecret>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters {
+            slow_hash: Some(&peppered),
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let mut login = |slow_hash: &Argon2WithSecret| {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha512Argon2WithSecret>::start(&mut rng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut OsRng,
+            &server_setup,
+            Some(password_file.clone()),
+            client_login_start_result.message,
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
+        )?;
+        client_login_start_result
+            .state
+            .finish(
+                server_login_start_result.message,
+                ClientLoginFinishParameters {
+                    slow_hash: Some(slow_hash),
+                    ..ClientLoginFinishParameters::default()
+                },
+            )
+            .map(|_| ())
+    };
+
+    // Both the pepper and the associated data are needed to log in
+    login(&peppered)?;
+    for slow_hash in [
+        Argon2WithSecret::new(b"other pepper", b"associated data", params.clone())?,
+        Argon2WithSecret::new(b"pepper", b"other associated data", params.clone())?,
+        Argon2WithSecret::new(b"", b"", params)?,
+    ]
+    .iter()
+    {
+        assert!(matches!(
+            login(slow_hash),
+            Err(ProtocolError::InvalidLoginError)
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(all(feature = "slow-hash", feature = "std"))]
+#[test]
+fn test_calibrate_argon2() -> Result<(), ProtocolError> {
+    use crate::slow_hash::{calibrate_argon2, Argon2WithSecret};
+    use std::time::Duration;
+
+    // Any parameters meet an empty target
+    let params = calibrate_argon2(Duration::from_secs(0), 1 << 16)?;
+    assert_eq!((params.m_cost(), params.t_cost()), (1024, 1));
+
+    // Once the memory cost is capped, the number of passes increases
+    let params = calibrate_argon2(Duration::from_millis(20), 64)?;
+    assert_eq!(params.m_cost(), 64);
+    assert!(params.t_cost() > 1);
+    Argon2WithSecret::new(b"pepper", b"", params)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "slow-hash")]
+#[test]
+fn test_iterated_argon2_progress() -> Result<(), ProtocolError> {
+    use crate::slow_hash::{IteratedArgon2, SlowHash};
+    use core::cell::RefCell;
+
+    let iterated = IteratedArgon2::new(argon2::Params::new(64, 3, 1, None).unwrap());
+    let steps = RefCell::new(Vec::new());
+    let output = SlowHash::<sha2::Sha512>::hash_with_progress(
+        &iterated,
+        b"input",
+        64,
+        &|completed, total| steps.borrow_mut().push((completed, total)),
+    )?;
+    assert_eq!(steps.into_inner(), vec![(0, 3), (1, 3), (2, 3), (3, 3)]);
+    assert_eq!(
+        SlowHash::<sha2::Sha512>::hash(&iterated, b"input", 64)?,
+        output
+    );
+
+    // Each pass hashes the output of the previous one
+    let single_pass = IteratedArgon2::new(argon2::Params::new(64, 1, 1, None).unwrap());
+    let chained = (0..3).try_fold(b"input".to_vec(), |input, _| {
+        SlowHash::<sha2::Sha512>::hash(&single_pass, &input, 64)
+    })?;
+    assert_eq!(chained, output);
+
+    Ok(())
+}
+
+#[cfg(feature = "slow-hash")]
+#[test]
+fn test_iterated_argon2_cancellation() -> Result<(), ProtocolError> {
+    use crate::slow_hash::{CancellationToken, IteratedArgon2, SlowHash};
+    use core::cell::Cell;
+
+    // Cancelling from the progress callback stops the hash before its next
+    // pass
+    let iterated = IteratedArgon2::new(argon2::Params::new(64, 3, 1, None).unwrap());
+    let token = CancellationToken::new();
+    let passes = Cell::new(0);
+    let progress = |completed: u32, _: u32| {
+        passes.set(completed);
+        if completed == 1 {
+            token.cancel();
+        }
+    };
+    assert!(matches!(
+        SlowHash::<sha2::Sha512>::hash_with_cancellation(
+            &iterated,
+            b"input",
+            64,
+            Some(&progress),
+            &token
+        ),
+        Err(InternalError::SlowHashCancelledError)
+    ));
+    assert_eq!(passes.get(), 1);
+
+    let token = CancellationToken::new();
+    assert_eq!(
+        SlowHash::<sha2::Sha512>::hash_with_cancellation(&iterated, b"input", 64, None, &token)?,
+        SlowHash::<sha2::Sha512>::hash(&iterated, b"input", 64)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_slow_hash_output_len() -> Result<(), ProtocolError> {
+    use crate::slow_hash::SlowHash;
+    use digest::Digest;
+
+    // A KSF whose output is shorter than the output of the suite hash
+    #[derive(Default)]
+    struct Sha256Ksf;
+    impl SlowHash<sha2::Sha512> for Sha256Ksf {
+        const OUTPUT_LEN: usize = 32;
+
+        fn hash(&self, input: &[u8], output_len: usize) -> Result<Vec<u8>, InternalError> {
+            assert_eq!((input.len(), output_len), (64, 32));
+            Ok(sha2::Sha256::digest(input).to_vec())
+        }
+    }
+
+    struct RistrettoSha5123dhSha256Ksf;
+    impl CipherSuite for RistrettoSha5123dhSha256Ksf {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = Sha256Ksf;
+    }
+
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhSha256Ksf>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhSha256Ksf>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhSha256Ksf>::start(&mut rng, b"password")?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(password_file),
+        client_login_start_result.message,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    )?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters::default(),
+    )?;
+    server_login_start_result
+        .state
+        .finish(client_login_finish_result.message)?;
+
+    // The no-op hash cannot change the length of its input
+    assert!(matches!(
+        SlowHash::<sha2::Sha512>::hash(&NoOpHash, &[0; 64], 32),
+        Err(InternalError::SlowHashError)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_request_validation() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let registration_request =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?
+            .message
+            .serialize()?;
+    let credential_request =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?
+            .message
+            .serialize()?;
+
+    RegistrationRequest::<RistrettoSha5123dhNoSlowHash>::validate(&registration_request)?;
+    CredentialRequest::<RistrettoSha5123dhNoSlowHash>::validate(&credential_request)?;
+
+    // Wrong lengths
+    for input in [&registration_request[1..], &credential_request[..]].iter() {
+        assert!(matches!(
+            RegistrationRequest::<RistrettoSha5123dhNoSlowHash>::validate(input),
+            Err(ProtocolError::LibraryError(InternalError::SizeError { .. }))
+        ));
+    }
+    for input in [&credential_request[1..], &registration_request[..]].iter() {
+        assert!(matches!(
+            CredentialRequest::<RistrettoSha5123dhNoSlowHash>::validate(input),
+            Err(ProtocolError::LibraryError(InternalError::SizeError { .. }))
+        ));
+    }
+
+    // Identity and invalid blinded elements
+    let identity = RistrettoPoint::identity().compress().to_bytes();
+    assert!(matches!(
+        RegistrationRequest::<RistrettoSha5123dhNoSlowHash>::validate(&identity),
+        Err(ProtocolError::IdentityGroupElementError)
+    ));
+    assert!(RegistrationRequest::<RistrettoSha5123dhNoSlowHash>::validate(&[0xff; 32]).is_err());
+    let mut input = credential_request.clone();
+    input[..32].copy_from_slice(&identity);
+    assert!(matches!(
+        CredentialRequest::<RistrettoSha5123dhNoSlowHash>::validate(&input),
+        Err(ProtocolError::IdentityGroupElementError)
+    ));
+
+    // Invalid ephemeral public key
+    let mut input = credential_request;
+    let len = input.len();
+    input[len - 32..].copy_from_slice(&[0xff; 32]);
+    assert!(CredentialRequest::<RistrettoSha5123dhNoSlowHash>::validate(&input).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_borrowed_message_views() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let ke1 = client_login_start_result.message.serialize()?;
+    let ke1_view = CredentialRequestRef::<RistrettoSha5123dhNoSlowHash>::parse(&ke1)?;
+    assert_eq!(
+        [ke1_view.blinded_element(), ke1_view.ke1_message()].concat(),
+        ke1
+    );
+    assert_eq!(
+        ke1_view.ke1_message(),
+        &client_login_start_result.message.ke1_message().serialize()[..]
+    );
+
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(password_file),
+        ke1_view.to_owned()?,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    )?;
+    let ke2 = server_login_start_result.message.serialize()?;
+    let ke2_view = CredentialResponseRef::<RistrettoSha5123dhNoSlowHash>::parse(&ke2)?;
+    assert_eq!(
+        ke2_view.masking_nonce(),
+        server_login_start_result.message.masking_nonce()
+    );
+    assert_eq!(
+        ke2_view.masked_response(),
+        server_login_start_result.message.masked_response()
+    );
+    assert_eq!(
+        ke2_view.ke2_message(),
+        [
+            server_login_start_result.server_nonce(),
+            &server_login_start_result.server_e_pk(),
+            server_login_start_result.server_mac(),
+        ]
+        .concat()
+    );
+    assert_eq!(ke2_view.as_bytes().as_ptr(), ke2.as_ptr());
+
+    let client_login_finish_result = client_login_start_result
+        .state
+        .finish(ke2_view.to_owned()?, ClientLoginFinishParameters::default())?;
+    let ke3 = client_login_finish_result.message.serialize()?;
+    let ke3_view = CredentialFinalizationRef::<RistrettoSha5123dhNoSlowHash>::parse(&ke3)?;
+    server_login_start_result
+        .state
+        .finish(ke3_view.to_owned()?)?;
+
+    // The views perform the checks of the owned messages
+    assert!(CredentialRequestRef::<RistrettoSha5123dhNoSlowHash>::parse(&ke1[1..]).is_err());
+    assert!(CredentialResponseRef::<RistrettoSha5123dhNoSlowHash>::parse(&ke2[1..]).is_err());
+    assert!(CredentialFinalizationRef::<RistrettoSha5123dhNoSlowHash>::parse(&ke3[1..]).is_err());
+    let mut invalid_ke2 = ke2.clone();
+    invalid_ke2[..32].copy_from_slice(&RistrettoPoint::identity().compress().to_bytes());
+    assert!(matches!(
+        CredentialResponseRef::<RistrettoSha5123dhNoSlowHash>::parse(&invalid_ke2),
+        Err(ProtocolError::IdentityGroupElementError)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_serialize_to_slice() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let message = client_login_start_result.message;
+
+    let mut buffer = [0u8; 128];
+    let len = message.serialize_to_slice(&mut buffer)?;
+    assert_eq!(
+        len,
+        CredentialRequest::<RistrettoSha5123dhNoSlowHash>::max_serialized_len()
+    );
+    assert_eq!(&buffer[..len], &message.serialize()?[..]);
+
+    assert!(matches!(
+        message.serialize_to_slice(&mut buffer[..len - 1]),
+        Err(ProtocolError::LibraryError(InternalError::SizeError { .. }))
+    ));
+
+    // The response is written field by field, and reads back unchanged
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhNoSlowHash>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"credentialIdentifier",
+        )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+    let response = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(password_file),
+        message,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    )?
+    .message;
+    let mut buffer = [0u8; 512];
+    let len = response.serialize_to_slice(&mut buffer)?;
+    assert_eq!(
+        len,
+        CredentialResponse::<RistrettoSha5123dhNoSlowHash>::max_serialized_len()
+    );
+    assert_eq!(
+        CredentialResponse::<RistrettoSha5123dhNoSlowHash>::deserialize(&buffer[..len])?,
+        response
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_external_slow_hash_error() -> Result<(), ProtocolError> {
+    use crate::slow_hash::SlowHash;
+
+    #[derive(Debug, PartialEq)]
+    struct OutOfMemory(usize);
+    impl core::fmt::Display for OutOfMemory {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "could not allocate {} KiB", self.0)
+        }
+    }
+
+    #[derive(Default)]
+    struct FailingHash;
+    impl SlowHash<sha2::Sha512> for FailingHash {
+        fn hash(&self, _: &[u8], _: usize) -> Result<Vec<u8>, InternalError> {
+            Err(InternalError::external(OutOfMemory(1 << 20)))
+        }
+    }
+
+    struct RistrettoSha5123dhFailingHash;
+    impl CipherSuite for RistrettoSha5123dhFailingHash {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = FailingHash;
+    }
+
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhFailingHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhFailingHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let error = match client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    ) {
+        Err(ProtocolError::LibraryError(InternalError::External(error))) => error,
+        _ => panic!("expected the error of the slow hash"),
+    };
+    assert_eq!(error.downcast_ref(), Some(&OutOfMemory(1 << 20)));
+    assert!(error.downcast_ref::<&str>().is_none());
+    assert_eq!(error.to_string(), "could not allocate 1048576 KiB");
+    assert_eq!(error.clone(), error);
+
+    Ok(())
+}
+
+#[test]
+fn test_failing_rng() -> Result<(), ProtocolError> {
+    struct FailingRng;
+    impl rand::RngCore for FailingRng {
+        fn next_u32(&mut self) -> u32 {
+            panic!("the library must only draw randomness through try_fill_bytes")
+        }
+        fn next_u64(&mut self) -> u64 {
+            panic!("the library must only draw randomness through try_fill_bytes")
+        }
+        fn fill_bytes(&mut self, _: &mut [u8]) {
+            panic!("the library must only draw randomness through try_fill_bytes")
+        }
+        fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), rand::Error> {
+            Err(core::num::NonZeroU32::new(rand::Error::CUSTOM_START)
+                .unwrap()
+                .into())
+        }
+    }
+    impl rand::CryptoRng for FailingRng {}
+
+    fn is_rng_error<T, E>(result: Result<T, ProtocolError<E>>) -> bool {
+        matches!(
+            result,
+            Err(ProtocolError::LibraryError(InternalError::RngError))
+        )
+    }
+
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut OsRng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut OsRng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut OsRng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    assert!(is_rng_error(
+        ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut FailingRng)
+            .map_err(ProtocolError::from)
+    ));
+    assert!(is_rng_error(ClientRegistration::<
+        RistrettoSha5123dhNoSlowHash,
+    >::start(&mut FailingRng, b"password")));
+    assert!(is_rng_error(
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut FailingRng, b"password")
+    ));
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut OsRng, b"password")?;
+    for password_file in [Some(password_file), None].iter() {
+        assert!(is_rng_error(ServerLogin::start(
+            &mut FailingRng,
+            &server_setup,
+            password_file.clone(),
+            client_login_start_result.message.clone(),
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
+        )));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_client_login_precomputation() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    // Precomputing draws the same randomness as starting directly
+    let seed = [42u8; 32].to_vec();
+    let precomputation =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::precompute(&mut CycleRng::new(seed.clone()))?;
+    let client_login_start_result = precomputation.start(b"password")?;
+    let expected =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut CycleRng::new(seed), b"password")?;
+    assert_eq!(client_login_start_result.message, expected.message);
+    assert_eq!(client_login_start_result.state, expected.state);
+
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(password_file),
+        client_login_start_result.message,
+        b"credentialIdentifier",
+        ServerLoginStartParameters::default(),
+    )?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters::default(),
+    )?;
+    let server_login_finish_result = server_login_start_result
+        .state
+        .finish(client_login_finish_result.message)?;
+    assert_eq!(
+        client_login_finish_result.session_key,
+        server_login_finish_result.session_key
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_ephemeral_key_pool() -> Result<(), ProtocolError> {
+    use crate::key_pool::EphemeralKeyPool;
+
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let pool = EphemeralKeyPool::new(2);
+    assert!(pool.is_empty());
+    assert_eq!(pool.refill(&mut rng)?, 2);
+    assert_eq!(pool.refill(&mut rng)?, 0);
+    assert_eq!(pool.len(), 2);
+
+    let login = |params: ServerLoginStartParameters| -> Result<(), ProtocolError> {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut OsRng, b"password")?;
+        let server_login_start_result = ServerLogin::start_with_key_pool(
+            &mut OsRng,
+            &pool,
+            &server_setup,
+            Some(password_file.clone()),
+            client_login_start_result.message,
+            b"credentialIdentifier",
+            params,
+        )?;
+        let client_login_finish_result = client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters::default(),
+        )?;
+        let server_login_finish_result = server_login_start_result
+            .state
+            .finish(client_login_finish_result.message)?;
+        assert_eq!(
+            client_login_finish_result.session_key,
+            server_login_finish_result.session_key
+        );
+        Ok(())
+    };
+
+    // Retransmission-safe responses derive their ephemeral key pair instead
+    login(ServerLoginStartParameters {
+        retransmission_nonce: Some(vec![0u8; RETRANSMISSION_NONCE_LEN]),
+        ..ServerLoginStartParameters::default()
+    })?;
+    assert_eq!(pool.len(), 2);
+
+    login(ServerLoginStartParameters::default())?;
+    assert_eq!(pool.len(), 1);
+    login(ServerLoginStartParameters::default())?;
+    assert!(pool.is_empty());
+
+    // A dry pool falls back to generating the key pair
+    login(ServerLoginStartParameters::default())?;
+    assert!(pool.is_empty());
+
+    Ok(())
+}
+
+#[cfg(feature = "p256")]
+#[test]
+fn test_p256_uncompressed_encoding() -> Result<(), ProtocolError> {
+    use crate::key_exchange::group::{p256::P256Uncompressed, KeGroup};
+    use generic_array::GenericArray;
+
+    let sk = P256Uncompressed::random_sk(&mut OsRng)?;
+    let uncompressed = P256Uncompressed::public_key(&sk).to_arr();
+    let compressed = p256_::ProjectivePoint::public_key(&sk).to_arr();
+    assert_eq!(uncompressed.len(), 65);
+    assert_eq!(uncompressed[0], 0x04);
+    assert_eq!(uncompressed[1..33], compressed[1..]);
+    assert!(P256Uncompressed::from_pk_slice(&uncompressed).is_ok());
+
+    // Compressed encodings padded to the length, and points off the curve,
+    // are rejected
+    let mut padded = GenericArray::default();
+    padded[..33].copy_from_slice(&compressed);
+    assert!(P256Uncompressed::from_pk_slice(&padded).is_err());
+    let mut off_curve = uncompressed;
+    off_curve[64] ^= 1;
+    assert!(P256Uncompressed::from_pk_slice(&off_curve).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_suite_params() -> Result<(), ProtocolError> {
+    use crate::ciphersuite::SuiteParams;
+
+    let params = SuiteParams::of::<RistrettoSha5123dhNoSlowHash>();
+    assert!(params.oprf_group.ends_with("RistrettoPoint"));
+    assert!(params.ke_group.ends_with("RistrettoPoint"));
+    assert!(params.hash.ends_with("Sha512"));
+    assert_eq!(params.oprf_element_len, 32);
+    assert_eq!(params.ke_public_key_len, 32);
+    assert_eq!(params.hash_len, 64);
+    assert_eq!(params.nonce_len, 32);
+    assert_eq!(params.session_key_len, 64);
+    assert_eq!(params.key_id_len, 0);
+
+    let mut rng = OsRng;
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    assert_eq!(
+        client_login_start_result.message.serialize()?.len(),
+        params.credential_request_len
+    );
+    assert_eq!(params.credential_request_len, 96);
+    assert_eq!(params.credential_finalization_len, 64);
+    Ok(())
+}
+
+#[test]
+fn test_slow_hash_salt() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let password = b"password";
+
+    let register = |rng: &mut OsRng, salt: Option<&[u8]>| {
+        let client_registration_start_result =
+            ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(rng, password)?;
+        let server_registration_start_result = ServerRegistration::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"alice",
+        )?;
+        client_registration_start_result.state.finish(
+            rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters {
+                slow_hash_salt: salt,
+                export_key: true,
+                ..ClientRegistrationFinishParameters::default()
+            },
+        )
+    };
+    let login = |rng: &mut OsRng, password_file, salt: Option<&[u8]>| {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(rng, password)?;
+        let server_login_start_result = ServerLogin::start(
+            rng,
+            &server_setup,
+            Some(password_file),
+            client_login_start_result.message,
+            b"alice",
+            ServerLoginStartParameters::default(),
+        )?;
+        client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters {
+                slow_hash_salt: salt,
+                export_key: true,
+                ..ClientLoginFinishParameters::default()
+            },
+        )
+    };
+
+    let salted = register(&mut rng, Some(b"application A"))?;
+    let unsalted = register(&mut rng, None)?;
+    assert_ne!(salted.export_key, unsalted.export_key);
+
+    let password_file = ServerRegistration::finish(salted.message);
+    let client_login_finish_result =
+        login(&mut rng, password_file.clone(), Some(b"application A"))?;
+    assert_eq!(client_login_finish_result.export_key, salted.export_key);
+
+    // Another salt, or no salt, does not open the envelope
+    for salt in [Some(&b"application B"[..]), Some(&b""[..]), None].iter() {
+        assert!(matches!(
+            login(&mut rng, password_file.clone(), *salt),
+            Err(ProtocolError::InvalidLoginError)
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_oprf_public_key() -> Result<(), ProtocolError> {
+    use voprf::group::Group as OprfGroup;
+
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let oprf_public_key = server_setup.oprf_public_key(b"alice")?;
+
+    // The commitment is to the key evaluating the logins of the identifier
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        None,
+        client_login_start_result.message,
+        b"alice",
+        ServerLoginStartParameters::default(),
+    )?;
+    let oprf_key =
+        <RistrettoPoint as OprfGroup>::from_scalar_slice(&server_login_start_result.oprf_key)?;
+    assert_eq!(
+        OprfGroup::to_arr(&(<RistrettoPoint as OprfGroup>::base_point() * &oprf_key)),
+        oprf_public_key
+    );
+
+    let restored =
+        ServerSetup::<RistrettoSha5123dhNoSlowHash>::deserialize(&server_setup.serialize()?)?;
+    assert_eq!(restored.oprf_public_key(b"alice")?, oprf_public_key);
+    assert_ne!(server_setup.oprf_public_key(b"bob")?, oprf_public_key);
+    assert_ne!(
+        server_setup.tenant(b"tenant")?.oprf_public_key(b"alice")?,
+        oprf_public_key
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "envelope-inspect")]
+#[test]
+fn test_inspect_envelope() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"alice",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let login_start = |rng: &mut OsRng, password: &[u8]| {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(rng, password)?;
+        let server_login_start_result = ServerLogin::start(
+            rng,
+            &server_setup,
+            Some(password_file.clone()),
+            client_login_start_result.message.clone(),
+            b"alice",
+            ServerLoginStartParameters::default(),
+        )?;
+        Ok::<_, ProtocolError>((client_login_start_result, server_login_start_result))
+    };
+
+    let (client_login_start_result, server_login_start_result) =
+        login_start(&mut rng, b"password")?;
+    let info = client_login_start_result.state.inspect_envelope(
+        &server_login_start_result.message,
+        &ClientLoginFinishParameters::default(),
+    )?;
+    assert_eq!(info.mode, EnvelopeMode::Internal);
+    assert_eq!(info.nonce.len(), 32);
+    assert!(!info.stores_private_key);
+    assert_eq!(info.server_s_pk, server_setup.keypair().public().to_vec());
+    assert_eq!(info.client_s_pk, password_file.client_s_pk().to_vec());
+    assert!(info.authenticated);
+
+    // Other identifiers are reported rather than failing
+    let info = client_login_start_result.state.inspect_envelope(
+        &server_login_start_result.message,
+        &ClientLoginFinishParameters::new(
+            None,
+            Some(Identifiers::ClientIdentifier(b"mallory".to_vec())),
+            None,
+        ),
+    )?;
+    assert!(!info.authenticated);
+
+    // The state can still be finished
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters::default(),
+    )?;
+    server_login_start_result
+        .state
+        .finish(client_login_finish_result.message)?;
+
+    // A wrong password yields a garbled server public key, which is either
+    // invalid or fails the MAC
+    let (client_login_start_result, server_login_start_result) =
+        login_start(&mut rng, b"wrong password")?;
+    match client_login_start_result.state.inspect_envelope(
+        &server_login_start_result.message,
+        &ClientLoginFinishParameters::default(),
+    ) {
+        Ok(info) => assert!(!info.authenticated),
+        Err(e) => assert_eq!(e, ProtocolError::InvalidLoginError),
+    }
+
+    Ok(())
+}
+
+#[cfg(all(feature = "envelope-inspect", feature = "aead-envelope"))]
+#[test]
+fn test_inspect_aead_envelope() -> Result<(), ProtocolError> {
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhAeadEnvelope>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhAeadEnvelope>::start(&mut rng, b"password")?;
+    let server_registration_start_result =
+        ServerRegistration::<RistrettoSha5123dhAeadEnvelope>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"alice",
+        )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    for (password, success) in [(&b"password"[..], true), (&b"wrong password"[..], false)]
+        .iter()
+        .cloned()
+    {
+        let client_login_start_result =
+            ClientLogin::<RistrettoSha5123dhAeadEnvelope>::start(&mut rng, password)?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &server_setup,
+            Some(password_file.clone()),
+            client_login_start_result.message,
+            b"alice",
+            ServerLoginStartParameters::default(),
+        )?;
+        let info = client_login_start_result.state.inspect_envelope(
+            &server_login_start_result.message,
+            &ClientLoginFinishParameters::default(),
+        );
+        if success {
+            let info = info?;
+            assert_eq!(info.mode, EnvelopeMode::Aead);
+            assert!(info.stores_private_key);
+            assert_eq!(info.client_s_pk, password_file.client_s_pk().to_vec());
+            assert!(info.authenticated);
+            continue;
+        }
+
+        // As in the internal mode, a wrong password is reported rather than
+        // failing, unless it garbles the server public key
+        match info {
+            Ok(info) => {
+                assert!(!info.authenticated);
+                assert!(info.client_s_pk.is_empty());
+            }
+            Err(e) => assert_eq!(e, ProtocolError::InvalidLoginError),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_key_exchange_labels() -> Result<(), ProtocolError> {
+    struct Diverging;
+    impl CipherSuite for Diverging {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = NoOpHash;
+        const KEY_EXCHANGE_LABELS: ciphersuite::KeyExchangeLabels =
+            ciphersuite::KeyExchangeLabels {
+                transcript_preamble: b"ExampleOPAQUE-v1",
+                prefix: b"Example-",
+                ..ciphersuite::KeyExchangeLabels::SPECIFICATION
+            };
+    }
+
+    // Logs in with a client of ciphersuite `C` and a server of ciphersuite
+    // `S`, which share the same serialization
+    fn login<C: CipherSuite, S: CipherSuite>(
+        server_setup: &[u8],
+        password_file: &[u8],
+    ) -> Result<(), ProtocolError> {
+        let mut rng = OsRng;
+        let client_login_start_result = ClientLogin::<C>::start(&mut rng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &ServerSetup::<S>::deserialize(server_setup)?,
+            Some(ServerRegistration::<S>::deserialize(password_file)?),
+            CredentialRequest::deserialize(&client_login_start_result.message.serialize()?)?,
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
+        )?;
+        let client_login_finish_result = client_login_start_result.state.finish(
+            CredentialResponse::deserialize(&server_login_start_result.message.serialize()?)?,
+            ClientLoginFinishParameters::default(),
+        )?;
+        let server_login_finish_result =
+            server_login_start_result
+                .state
+                .finish(CredentialFinalization::deserialize(
+                    &client_login_finish_result.message.serialize()?,
+                )?)?;
+        assert_eq!(
+            client_login_finish_result.session_key,
+            server_login_finish_result.session_key
+        );
+        Ok(())
+    }
+
+    assert_eq!(
+        RistrettoSha5123dhNoSlowHash::KEY_EXCHANGE_LABELS,
+        ciphersuite::KeyExchangeLabels::default()
+    );
+
+    // The labels only affect the key exchange, so that a password file can
+    // be shared by both ciphersuites
+    let mut rng = OsRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, b"password")?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        b"credentialIdentifier",
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::default(),
+    )?;
+    let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let server_setup_bytes = server_setup.serialize()?;
+    let p_file_bytes = p_file.serialize()?;
+    login::<RistrettoSha5123dhNoSlowHash, RistrettoSha5123dhNoSlowHash>(
+        &server_setup_bytes,
+        &p_file_bytes,
+    )?;
+    login::<Diverging, Diverging>(&server_setup_bytes, &p_file_bytes)?;
+
+    // Peers with different labels cannot log in with each other
+    assert!(matches!(
+        login::<Diverging, RistrettoSha5123dhNoSlowHash>(&server_setup_bytes, &p_file_bytes),
+        Err(ProtocolError::InvalidLoginError)
+    ));
+    assert!(matches!(
+        login::<RistrettoSha5123dhNoSlowHash, Diverging>(&server_setup_bytes, &p_file_bytes),
+        Err(ProtocolError::InvalidLoginError)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_envelope_labels() -> Result<(), ProtocolError> {
+    struct Diverging;
+    impl CipherSuite for Diverging {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = NoOpHash;
+        const ENVELOPE_LABELS: ciphersuite::EnvelopeLabels = ciphersuite::EnvelopeLabels {
+            auth_key: b"ExampleAuthKey",
+            masking_key: b"ExampleMaskingKey",
+            ..ciphersuite::EnvelopeLabels::SPECIFICATION
+        };
+    }
+
+    // Registers with ciphersuite `R`, then logs in with ciphersuite `L`,
+    // which shares the same serialization
+    fn register_then_login<R: CipherSuite, L: CipherSuite>() -> Result<(), ProtocolError> {
+        let mut rng = OsRng;
+        let server_setup = ServerSetup::<R>::new(&mut rng)?;
+        let client_registration_start_result =
+            ClientRegistration::<R>::start(&mut rng, b"password")?;
+        let server_registration_start_result = ServerRegistration::<R>::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"credentialIdentifier",
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters::default(),
+        )?;
+        let p_file = ServerRegistration::finish(client_registration_finish_result.message);
+        let server_setup = ServerSetup::<L>::deserialize(&server_setup.serialize()?)?;
+        let p_file = ServerRegistration::<L>::deserialize(&p_file.serialize()?)?;
+
+        let client_login_start_result = ClientLogin::<L>::start(&mut rng, b"password")?;
+        let server_login_start_result = ServerLogin::start(
+            &mut rng,
+            &server_setup,
+            Some(p_file),
+            client_login_start_result.message,
+            b"credentialIdentifier",
+            ServerLoginStartParameters::default(),
+        )?;
+        let client_login_finish_result = client_login_start_result.state.finish(
+            server_login_start_result.message,
+            ClientLoginFinishParameters::default(),
+        )?;
+        server_login_start_result
+            .state
+            .finish(client_login_finish_result.message)?;
+        Ok(())
+    }
+
+    assert_eq!(
+        RistrettoSha5123dhNoSlowHash::ENVELOPE_LABELS,
+        ciphersuite::EnvelopeLabels::default()
+    );
+
+    register_then_login::<Diverging, Diverging>()?;
+
+    // Unlike the key exchange labels, the envelope labels are bound to the
+    // registration records
+    assert!(matches!(
+        register_then_login::<RistrettoSha5123dhNoSlowHash, Diverging>(),
+        Err(ProtocolError::InvalidLoginError)
+    ));
+    assert!(matches!(
+        register_then_login::<Diverging, RistrettoSha5123dhNoSlowHash>(),
+        Err(ProtocolError::InvalidLoginError)
+    ));
+
     Ok(())
 }
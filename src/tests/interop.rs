@@ -0,0 +1,350 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Replays the recorded transcripts of `tests/transcripts` through the client
+//! and server state machines, behind the `interop-tests` feature
+//!
+//! A transcript is a JSON object holding hex-encoded fields, named as in the
+//! test vectors of the specification: the `suite`, the inputs of both
+//! parties, the randomness they drew, and every message they exchanged along
+//! with the resulting keys. Each party is run with the randomness of the
+//! transcript and fed the messages of the transcript rather than those of the
+//! other party, so that every message is compared byte for byte and every
+//! recorded message is accepted on its own.
+//!
+//! Transcripts of other implementations are added by dropping them in the
+//! directory, as described in its README. The test vectors of the
+//! specification are checked by `test_opaque_vectors` and are not duplicated
+//! there. Transcripts of this implementation are recorded with
+//! `cargo test --features interop-tests -- --ignored record_transcript`, and
+//! committed along with each release which changes the wire format.
+
+use crate::{
+    ciphersuite::CipherSuite, errors::*, key_exchange::tripledh::TripleDH, slow_hash::NoOpHash,
+    tests::mock_rng::CycleRng, *,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use json::JsonValue;
+use std::fs;
+use std::path::Path;
+
+const TRANSCRIPTS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/transcripts");
+
+const RISTRETTO255_SHA512: &str = "ristretto255, SHA512";
+const P256_SHA256: &str = "P256_XMD:SHA-256_SSWU_RO_, SHA256";
+
+struct Ristretto255Sha512;
+impl CipherSuite for Ristretto255Sha512 {
+    type OprfGroup = curve25519_dalek::ristretto::RistrettoPoint;
+    type KeGroup = curve25519_dalek::ristretto::RistrettoPoint;
+    type KeyExchange = TripleDH;
+    type Hash = sha2::Sha512;
+    type SlowHash = NoOpHash;
+}
+
+struct Transcript {
+    name: String,
+    values: JsonValue,
+}
+
+impl Transcript {
+    fn field(&self, key: &str) -> Vec<u8> {
+        self.optional_field(key)
+            .unwrap_or_else(|| panic!("{}: missing field {}", self.name, key))
+    }
+
+    fn optional_field(&self, key: &str) -> Option<Vec<u8>> {
+        self.values[key].as_str().map(|value| {
+            hex::decode(value).unwrap_or_else(|_| panic!("{}: field {} is not hex", self.name, key))
+        })
+    }
+
+    fn identifiers(&self) -> Option<Identifiers> {
+        match (
+            self.optional_field("client_identity"),
+            self.optional_field("server_identity"),
+        ) {
+            (None, None) => None,
+            (Some(client), None) => Some(Identifiers::ClientIdentifier(client)),
+            (None, Some(server)) => Some(Identifiers::ServerIdentifier(server)),
+            (Some(client), Some(server)) => {
+                Some(Identifiers::ClientAndServerIdentifiers(client, server))
+            }
+        }
+    }
+
+    fn check(&self, key: &str, actual: &[u8]) {
+        assert_eq!(
+            hex::encode(actual),
+            hex::encode(self.field(key)),
+            "{}: {} differs",
+            self.name,
+            key
+        );
+    }
+
+    fn rng(&self, keys: &[&str]) -> CycleRng {
+        CycleRng::new(keys.iter().flat_map(|key| self.field(key)).collect())
+    }
+}
+
+fn replay<CS: CipherSuite>(transcript: &Transcript) -> Result<(), ProtocolError> {
+    let password = transcript.field("password");
+    let credential_identifier = transcript.field("credential_identifier");
+    let context = transcript.field("context");
+    let identifiers = transcript.identifiers();
+
+    // The fake key pair is only used for unregistered clients, so the
+    // server's key pair stands in for it
+    let server_private_key = transcript.field("server_private_key");
+    let server_setup = ServerSetup::<CS>::deserialize(
+        &[
+            &transcript.field("oprf_seed")[..],
+            &server_private_key[..],
+            &server_private_key[..],
+        ]
+        .concat(),
+    )?;
+
+    // Registration
+    let client_registration_start_result =
+        ClientRegistration::<CS>::start(&mut transcript.rng(&["blind_registration"]), &password)?;
+    transcript.check(
+        "registration_request",
+        &client_registration_start_result.message.serialize()?,
+    );
+
+    let server_registration_start_result = ServerRegistration::<CS>::start(
+        &server_setup,
+        RegistrationRequest::deserialize(&transcript.field("registration_request"))?,
+        &credential_identifier,
+    )?;
+    transcript.check(
+        "registration_response",
+        &server_registration_start_result.message.serialize()?,
+    );
+
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut transcript.rng(&["envelope_nonce"]),
+        RegistrationResponse::deserialize(&transcript.field("registration_response"))?,
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::new(identifiers.clone(), None)
+        },
+    )?;
+    transcript.check(
+        "registration_upload",
+        &client_registration_finish_result.message.serialize()?,
+    );
+
+    let password_file = ServerRegistration::finish(RegistrationUpload::deserialize(
+        &transcript.field("registration_upload"),
+    )?);
+
+    // Login
+    let client_login_start_result = ClientLogin::<CS>::start(
+        &mut transcript.rng(&["blind_login", "client_private_keyshare", "client_nonce"]),
+        &password,
+    )?;
+    transcript.check("KE1", &client_login_start_result.message.serialize()?);
+
+    let server_login_start_result = ServerLogin::start(
+        &mut transcript.rng(&["masking_nonce", "server_private_keyshare", "server_nonce"]),
+        &server_setup,
+        Some(password_file),
+        CredentialRequest::deserialize(&transcript.field("KE1"))?,
+        &credential_identifier,
+        ServerLoginStartParameters::new(Some(context.clone()), identifiers.clone()),
+    )?;
+    transcript.check("KE2", &server_login_start_result.message.serialize()?);
+
+    let client_login_finish_result = client_login_start_result.state.finish(
+        CredentialResponse::deserialize(&transcript.field("KE2"))?,
+        ClientLoginFinishParameters {
+            export_key: true,
+            ..ClientLoginFinishParameters::new(Some(context), identifiers, None)
+        },
+    )?;
+    transcript.check("KE3", &client_login_finish_result.message.serialize()?);
+    transcript.check(
+        "session_key",
+        client_login_finish_result.session_key.expose_secret(),
+    );
+    transcript.check(
+        "export_key",
+        client_login_finish_result
+            .export_key
+            .as_ref()
+            .unwrap()
+            .expose_secret(),
+    );
+
+    let server_login_finish_result =
+        server_login_start_result
+            .state
+            .finish(CredentialFinalization::deserialize(
+                &transcript.field("KE3"),
+            )?)?;
+    transcript.check(
+        "session_key",
+        server_login_finish_result.session_key.expose_secret(),
+    );
+
+    Ok(())
+}
+
+#[test]
+fn replay_transcripts() {
+    let mut paths: Vec<_> = fs::read_dir(TRANSCRIPTS_DIR)
+        .expect("Could not read the transcripts directory")
+        .map(|entry| entry.expect("Could not read a transcript").path())
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+    assert!(
+        !paths.is_empty(),
+        "No transcript to replay in {}, record one with the record_transcript test",
+        TRANSCRIPTS_DIR
+    );
+
+    for path in paths {
+        let transcript = read_transcript(&path);
+        let result = match transcript.values["suite"].as_str() {
+            Some(RISTRETTO255_SHA512) => replay::<Ristretto255Sha512>(&transcript),
+            #[cfg(feature = "p256")]
+            Some(P256_SHA256) => replay::<crate::ciphersuite::P256Sha256>(&transcript),
+            #[cfg(not(feature = "p256"))]
+            Some(P256_SHA256) => continue,
+            suite => panic!("{}: unknown suite {:?}", transcript.name, suite),
+        };
+        if let Err(e) = result {
+            panic!("{}: replay failed with {:?}", transcript.name, e);
+        }
+    }
+}
+
+fn read_transcript(path: &Path) -> Transcript {
+    let name = path.display().to_string();
+    let contents =
+        fs::read_to_string(path).unwrap_or_else(|_| panic!("{}: could not be read", name));
+    let values = json::parse(&contents).unwrap_or_else(|_| panic!("{}: invalid JSON", name));
+    Transcript { name, values }
+}
+
+// Records a transcript of this implementation with fixed inputs, named after
+// the version of the crate
+#[test]
+#[ignore]
+fn record_transcript() -> Result<(), ProtocolError> {
+    let mut values = json::object! {
+        source: format!("opaque-ke {}", env!("CARGO_PKG_VERSION")),
+        suite: RISTRETTO255_SHA512,
+        context: hex::encode(b"opaque-ke interop"),
+        client_identity: hex::encode(b"alice"),
+        server_identity: hex::encode(b"bob"),
+        credential_identifier: hex::encode(b"alice@example.com"),
+        password: hex::encode(b"CorrectHorseBatteryStaple"),
+        oprf_seed: hex::encode([0x01u8; 64]),
+        server_private_key: hex::encode([0x02u8; 32]),
+        blind_registration: hex::encode([0x03u8; 32]),
+        envelope_nonce: hex::encode([0x04u8; 32]),
+        blind_login: hex::encode([0x05u8; 32]),
+        client_private_keyshare: hex::encode([0x06u8; 32]),
+        client_nonce: hex::encode([0x07u8; 32]),
+        masking_nonce: hex::encode([0x08u8; 32]),
+        server_private_keyshare: hex::encode([0x09u8; 32]),
+        server_nonce: hex::encode([0x0au8; 32]),
+    };
+    let transcript = Transcript {
+        name: String::from("recorded"),
+        values: values.clone(),
+    };
+    let context = transcript.field("context");
+    let identifiers = transcript.identifiers();
+    let credential_identifier = transcript.field("credential_identifier");
+    let password = transcript.field("password");
+    let server_private_key = transcript.field("server_private_key");
+    let server_setup = ServerSetup::<Ristretto255Sha512>::deserialize(
+        &[
+            &transcript.field("oprf_seed")[..],
+            &server_private_key[..],
+            &server_private_key[..],
+        ]
+        .concat(),
+    )?;
+
+    let client_registration_start_result = ClientRegistration::<Ristretto255Sha512>::start(
+        &mut transcript.rng(&["blind_registration"]),
+        &password,
+    )?;
+    let registration_request = client_registration_start_result.message.serialize()?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message.clone(),
+        &credential_identifier,
+    )?;
+    let registration_response = server_registration_start_result.message.serialize()?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut transcript.rng(&["envelope_nonce"]),
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters::new(identifiers.clone(), None),
+    )?;
+    let registration_upload = client_registration_finish_result.message.serialize()?;
+
+    let client_login_start_result = ClientLogin::<Ristretto255Sha512>::start(
+        &mut transcript.rng(&["blind_login", "client_private_keyshare", "client_nonce"]),
+        &password,
+    )?;
+    let ke1 = client_login_start_result.message.serialize()?;
+    let server_login_start_result = ServerLogin::start(
+        &mut transcript.rng(&["masking_nonce", "server_private_keyshare", "server_nonce"]),
+        &server_setup,
+        Some(ServerRegistration::finish(
+            client_registration_finish_result.message,
+        )),
+        client_login_start_result.message.clone(),
+        &credential_identifier,
+        ServerLoginStartParameters::new(Some(context.clone()), identifiers.clone()),
+    )?;
+    let ke2 = server_login_start_result.message.serialize()?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters {
+            export_key: true,
+            ..ClientLoginFinishParameters::new(Some(context), identifiers, None)
+        },
+    )?;
+
+    values["registration_request"] = hex::encode(registration_request).into();
+    values["registration_response"] = hex::encode(registration_response).into();
+    values["registration_upload"] = hex::encode(registration_upload).into();
+    values["KE1"] = hex::encode(ke1).into();
+    values["KE2"] = hex::encode(ke2).into();
+    values["KE3"] = hex::encode(client_login_finish_result.message.serialize()?).into();
+    values["export_key"] = hex::encode(
+        client_login_finish_result
+            .export_key
+            .as_ref()
+            .unwrap()
+            .expose_secret(),
+    )
+    .into();
+    values["session_key"] =
+        hex::encode(client_login_finish_result.session_key.expose_secret()).into();
+
+    let path = Path::new(TRANSCRIPTS_DIR).join(format!(
+        "opaque-ke-{}-ristretto255.json",
+        env!("CARGO_PKG_VERSION")
+    ));
+    fs::write(&path, values.pretty(2) + "\n").expect("Could not write the transcript");
+    replay::<Ristretto255Sha512>(&read_transcript(&path))
+}
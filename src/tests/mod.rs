@@ -4,7 +4,11 @@
 // LICENSE file in the root directory of this source tree.
 
 mod full_test;
+#[cfg(feature = "interop-tests")]
+mod interop;
 pub mod mock_rng;
 mod opaque_vectors;
 mod parser;
 mod test_opaque_vectors;
+#[cfg(all(target_arch = "wasm32", feature = "std"))]
+mod wasm_test;
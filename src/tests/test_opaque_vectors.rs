@@ -210,23 +210,16 @@ fn tests() -> Result<(), ProtocolError> {
             panic!("Parsing error");
         }
 
-        struct P256Sha256NoSlowHash;
-        impl CipherSuite for P256Sha256NoSlowHash {
-            type OprfGroup = p256_::ProjectivePoint;
-            type KeGroup = p256_::ProjectivePoint;
-            type KeyExchange = TripleDH;
-            type Hash = sha2::Sha256;
-            type SlowHash = NoOpHash;
-        }
-
-        test_registration_request::<P256Sha256NoSlowHash>(&p256_real_tvs)?;
-        test_registration_response::<P256Sha256NoSlowHash>(&p256_real_tvs)?;
-        test_registration_upload::<P256Sha256NoSlowHash>(&p256_real_tvs)?;
-        test_ke1::<P256Sha256NoSlowHash>(&p256_real_tvs)?;
-        test_ke2::<P256Sha256NoSlowHash>(&p256_real_tvs)?;
-        test_ke3::<P256Sha256NoSlowHash>(&p256_real_tvs)?;
-        test_server_login_finish::<P256Sha256NoSlowHash>(&p256_real_tvs)?;
-        test_fake_vectors::<P256Sha256NoSlowHash>(&p256_fake_tvs)?;
+        use crate::ciphersuite::P256Sha256;
+
+        test_registration_request::<P256Sha256>(&p256_real_tvs)?;
+        test_registration_response::<P256Sha256>(&p256_real_tvs)?;
+        test_registration_upload::<P256Sha256>(&p256_real_tvs)?;
+        test_ke1::<P256Sha256>(&p256_real_tvs)?;
+        test_ke2::<P256Sha256>(&p256_real_tvs)?;
+        test_ke3::<P256Sha256>(&p256_real_tvs)?;
+        test_server_login_finish::<P256Sha256>(&p256_real_tvs)?;
+        test_fake_vectors::<P256Sha256>(&p256_fake_tvs)?;
     }
 
     Ok(())
@@ -289,8 +282,14 @@ fn test_registration_upload<CS: CipherSuite>(
             &mut finish_registration_rng,
             RegistrationResponse::deserialize(&parameters.registration_response[..]).unwrap(),
             match parse_identifiers(&parameters.client_identity, &parameters.server_identity) {
-                None => ClientRegistrationFinishParameters::default(),
-                Some(ids) => ClientRegistrationFinishParameters::new(Some(ids), None),
+                None => ClientRegistrationFinishParameters {
+                    export_key: true,
+                    ..ClientRegistrationFinishParameters::default()
+                },
+                Some(ids) => ClientRegistrationFinishParameters {
+                    export_key: true,
+                    ..ClientRegistrationFinishParameters::new(Some(ids), None)
+                },
             },
         )?;
         assert_eq!(
@@ -307,7 +306,7 @@ fn test_registration_upload<CS: CipherSuite>(
         );
         assert_eq!(
             hex::encode(&parameters.export_key),
-            hex::encode(result.export_key.to_vec())
+            hex::encode(result.export_key.as_ref().unwrap().expose_secret().to_vec())
         );
     }
 
@@ -368,13 +367,10 @@ fn test_ke2<CS: CipherSuite>(tvs: &[OpaqueTestVectorParameters]) -> Result<(), P
             Some(record),
             CredentialRequest::<CS>::deserialize(&parameters.KE1[..]).unwrap(),
             &parameters.credential_identifier,
-            match parse_identifiers(&parameters.client_identity, &parameters.server_identity) {
-                None => ServerLoginStartParameters::WithContext(parameters.context.to_vec()),
-                Some(ids) => ServerLoginStartParameters::WithContextAndIdentifiers(
-                    parameters.context.to_vec(),
-                    ids,
-                ),
-            },
+            ServerLoginStartParameters::new(
+                Some(parameters.context.to_vec()),
+                parse_identifiers(&parameters.client_identity, &parameters.server_identity),
+            ),
         )?;
         assert_eq!(
             hex::encode(&parameters.handshake_secret),
@@ -411,20 +407,24 @@ fn test_ke3<CS: CipherSuite>(tvs: &[OpaqueTestVectorParameters]) -> Result<(), P
         let client_login_finish_result = client_login_start_result.state.finish(
             CredentialResponse::<CS>::deserialize(&parameters.KE2[..])?,
             match parse_identifiers(&parameters.client_identity, &parameters.server_identity) {
-                None => {
-                    ClientLoginFinishParameters::new(Some(parameters.context.clone()), None, None)
-                }
-                Some(ids) => ClientLoginFinishParameters::new(
-                    Some(parameters.context.clone()),
-                    Some(ids),
-                    None,
-                ),
+                None => ClientLoginFinishParameters {
+                    export_key: true,
+                    ..ClientLoginFinishParameters::new(Some(parameters.context.clone()), None, None)
+                },
+                Some(ids) => ClientLoginFinishParameters {
+                    export_key: true,
+                    ..ClientLoginFinishParameters::new(
+                        Some(parameters.context.clone()),
+                        Some(ids),
+                        None,
+                    )
+                },
             },
         )?;
 
         assert_eq!(
             hex::encode(&parameters.session_key),
-            hex::encode(&client_login_finish_result.session_key)
+            hex::encode(client_login_finish_result.session_key.expose_secret())
         );
         assert_eq!(
             hex::encode(&parameters.handshake_secret),
@@ -440,7 +440,13 @@ fn test_ke3<CS: CipherSuite>(tvs: &[OpaqueTestVectorParameters]) -> Result<(), P
         );
         assert_eq!(
             hex::encode(&parameters.export_key),
-            hex::encode(client_login_finish_result.export_key)
+            hex::encode(
+                client_login_finish_result
+                    .export_key
+                    .as_ref()
+                    .unwrap()
+                    .expose_secret()
+            )
         );
     }
     Ok(())
@@ -477,13 +483,10 @@ fn test_server_login_finish<CS: CipherSuite>(
             Some(record),
             CredentialRequest::<CS>::deserialize(&parameters.KE1[..]).unwrap(),
             &parameters.credential_identifier,
-            match parse_identifiers(&parameters.client_identity, &parameters.server_identity) {
-                None => ServerLoginStartParameters::WithContext(parameters.context.to_vec()),
-                Some(ids) => ServerLoginStartParameters::WithContextAndIdentifiers(
-                    parameters.context.to_vec(),
-                    ids,
-                ),
-            },
+            ServerLoginStartParameters::new(
+                Some(parameters.context.to_vec()),
+                parse_identifiers(&parameters.client_identity, &parameters.server_identity),
+            ),
         )?;
 
         let server_login_result = server_login_start_result
@@ -492,7 +495,7 @@ fn test_server_login_finish<CS: CipherSuite>(
 
         assert_eq!(
             hex::encode(&parameters.session_key),
-            hex::encode(&server_login_result.session_key)
+            hex::encode(server_login_result.session_key.expose_secret())
         );
     }
     Ok(())
@@ -526,13 +529,10 @@ fn test_fake_vectors<CS: CipherSuite>(
             None,
             CredentialRequest::<CS>::deserialize(&parameters.KE1[..]).unwrap(),
             &parameters.credential_identifier,
-            match parse_identifiers(&parameters.client_identity, &parameters.server_identity) {
-                None => ServerLoginStartParameters::WithContext(parameters.context.to_vec()),
-                Some(ids) => ServerLoginStartParameters::WithContextAndIdentifiers(
-                    parameters.context.to_vec(),
-                    ids,
-                ),
-            },
+            ServerLoginStartParameters::new(
+                Some(parameters.context.to_vec()),
+                parse_identifiers(&parameters.client_identity, &parameters.server_identity),
+            ),
         )?;
         assert_eq!(
             hex::encode(&parameters.KE2),
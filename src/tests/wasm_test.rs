@@ -0,0 +1,76 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+use crate::{
+    ciphersuite::CipherSuite, errors::*, key_exchange::tripledh::TripleDH, opaque::*,
+    slow_hash::NoOpHash, wasm::WebCryptoRng,
+};
+use curve25519_dalek::ristretto::RistrettoPoint;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+struct RistrettoSha5123dhNoSlowHash;
+impl CipherSuite for RistrettoSha5123dhNoSlowHash {
+    type OprfGroup = RistrettoPoint;
+    type KeGroup = RistrettoPoint;
+    type KeyExchange = TripleDH;
+    type Hash = sha2::Sha512;
+    type SlowHash = NoOpHash;
+}
+
+#[wasm_bindgen_test]
+fn test_client_flows_with_web_crypto_rng() -> Result<(), ProtocolError> {
+    let password = b"password";
+    let credential_identifier = b"credentialIdentifier";
+    let mut rng = WebCryptoRng;
+    let server_setup = ServerSetup::<RistrettoSha5123dhNoSlowHash>::new(&mut rng)?;
+
+    let client_registration_start_result =
+        ClientRegistration::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+    let server_registration_start_result = ServerRegistration::start(
+        &server_setup,
+        client_registration_start_result.message,
+        credential_identifier,
+    )?;
+    let client_registration_finish_result = client_registration_start_result.state.finish(
+        &mut rng,
+        server_registration_start_result.message,
+        ClientRegistrationFinishParameters {
+            export_key: true,
+            ..ClientRegistrationFinishParameters::default()
+        },
+    )?;
+    let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+    let client_login_start_result =
+        ClientLogin::<RistrettoSha5123dhNoSlowHash>::start(&mut rng, password)?;
+    let server_login_start_result = ServerLogin::start(
+        &mut rng,
+        &server_setup,
+        Some(password_file),
+        client_login_start_result.message,
+        credential_identifier,
+        ServerLoginStartParameters::default(),
+    )?;
+    let client_login_finish_result = client_login_start_result.state.finish(
+        server_login_start_result.message,
+        ClientLoginFinishParameters {
+            export_key: true,
+            ..ClientLoginFinishParameters::default()
+        },
+    )?;
+    let server_login_finish_result = server_login_start_result
+        .state
+        .finish(client_login_finish_result.message)?;
+
+    assert_eq!(
+        client_login_finish_result.session_key,
+        server_login_finish_result.session_key
+    );
+    assert_eq!(
+        client_registration_finish_result.export_key,
+        client_login_finish_result.export_key
+    );
+    Ok(())
+}
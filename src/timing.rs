@@ -0,0 +1,205 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Timing instrumentation of the two paths of
+//! [`ServerLogin::start`](crate::ServerLogin::start), for checking on given
+//! hardware that a login for an unregistered client, which is answered with a
+//! dummy record, takes as long as one for a registered client
+//!
+//! Requires the `bench` feature. [measure_login_start] runs both paths in
+//! turn on the same credential request and the same randomness, drawn from a
+//! seeded RNG, so that the record is the only input which differs and a run
+//! can be reproduced. Each call is measured in nanoseconds, and in cycles of
+//! the time-stamp counter on x86-64.
+
+use crate::{
+    ciphersuite::CipherSuite, errors::ProtocolError, ClientLogin, CredentialRequest, ServerLogin,
+    ServerLoginStartParameters, ServerRegistration, ServerSetup,
+};
+use alloc::vec::Vec;
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use std::time::Instant;
+
+/// The cost of one call to `ServerLogin::start`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Measurement {
+    /// The elapsed wall-clock time
+    pub nanos: u64,
+    /// The elapsed cycles of the time-stamp counter, on x86-64 only
+    pub cycles: Option<u64>,
+}
+
+/// The measurements of both paths of `ServerLogin::start`, in the order they
+/// were taken, as returned by [measure_login_start]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LoginStartTimings {
+    /// The measurements with the password file of a registered client
+    pub record_present: Vec<Measurement>,
+    /// The measurements without a password file, answered with a dummy
+    /// record
+    pub record_absent: Vec<Measurement>,
+}
+
+impl LoginStartTimings {
+    /// The median time of the record-present and the record-absent paths,
+    /// in nanoseconds
+    pub fn median_nanos(&self) -> (u64, u64) {
+        (
+            median(self.record_present.iter().map(|m| m.nanos)),
+            median(self.record_absent.iter().map(|m| m.nanos)),
+        )
+    }
+
+    /// The median cycle count of the record-present and the record-absent
+    /// paths, if cycles were measured
+    pub fn median_cycles(&self) -> Option<(u64, u64)> {
+        let present: Option<Vec<u64>> = self.record_present.iter().map(|m| m.cycles).collect();
+        let absent: Option<Vec<u64>> = self.record_absent.iter().map(|m| m.cycles).collect();
+        Some((median(present?), median(absent?)))
+    }
+}
+
+/// Measures `samples` calls to `ServerLogin::start` with `password_file`, and
+/// as many without it, for `credential_identifier` under `server_setup`.
+///
+/// The calls alternate between both paths, so that a drift in the load of
+/// the machine affects both alike. The credential requests and the
+/// randomness of the server are derived from `seed`.
+pub fn measure_login_start<CS: CipherSuite>(
+    server_setup: &ServerSetup<CS>,
+    password_file: &ServerRegistration<CS>,
+    credential_identifier: &[u8],
+    samples: usize,
+    seed: [u8; 32],
+) -> Result<LoginStartTimings, ProtocolError> {
+    let mut seed_rng = StdRng::from_seed(seed);
+    let mut timings = LoginStartTimings {
+        record_present: Vec::with_capacity(samples),
+        record_absent: Vec::with_capacity(samples),
+    };
+
+    for _ in 0..samples {
+        let mut sample_seed = [0u8; 32];
+        seed_rng.fill_bytes(&mut sample_seed);
+        let credential_request =
+            ClientLogin::<CS>::start(&mut StdRng::from_seed(sample_seed), b"password")?.message;
+        seed_rng.fill_bytes(&mut sample_seed);
+
+        timings.record_present.push(measure(
+            server_setup,
+            Some(password_file.clone()),
+            credential_request.clone(),
+            credential_identifier,
+            sample_seed,
+        )?);
+        timings.record_absent.push(measure(
+            server_setup,
+            None,
+            credential_request,
+            credential_identifier,
+            sample_seed,
+        )?);
+    }
+
+    Ok(timings)
+}
+
+fn measure<CS: CipherSuite>(
+    server_setup: &ServerSetup<CS>,
+    password_file: Option<ServerRegistration<CS>>,
+    credential_request: CredentialRequest<CS>,
+    credential_identifier: &[u8],
+    seed: [u8; 32],
+) -> Result<Measurement, ProtocolError> {
+    let mut rng = StdRng::from_seed(seed);
+
+    let start = Instant::now();
+    let start_cycles = cycles();
+    let result = ServerLogin::start(
+        &mut rng,
+        server_setup,
+        password_file,
+        credential_request,
+        credential_identifier,
+        ServerLoginStartParameters::default(),
+    );
+    let end_cycles = cycles();
+    let nanos = start.elapsed().as_nanos() as u64;
+    result?;
+
+    Ok(Measurement {
+        nanos,
+        cycles: start_cycles
+            .zip(end_cycles)
+            .map(|(start, end)| end.wrapping_sub(start)),
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+#[allow(unsafe_code)]
+fn cycles() -> Option<u64> {
+    // Reading the time-stamp counter has no preconditions
+    Some(unsafe { core::arch::x86_64::_rdtsc() })
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn cycles() -> Option<u64> {
+    None
+}
+
+fn median(values: impl IntoIterator<Item = u64>) -> u64 {
+    let mut values: Vec<u64> = values.into_iter().collect();
+    values.sort_unstable();
+    values.get(values.len() / 2).cloned().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientRegistration, ClientRegistrationFinishParameters};
+    use curve25519_dalek::ristretto::RistrettoPoint;
+    use rand::rngs::OsRng;
+
+    struct Default;
+
+    impl CipherSuite for Default {
+        type OprfGroup = RistrettoPoint;
+        type KeGroup = RistrettoPoint;
+        type KeyExchange = crate::key_exchange::tripledh::TripleDH;
+        type Hash = sha2::Sha512;
+        type SlowHash = crate::slow_hash::NoOpHash;
+    }
+
+    #[test]
+    fn test_measure_login_start() -> Result<(), ProtocolError> {
+        let mut rng = OsRng;
+        let server_setup = ServerSetup::<Default>::new(&mut rng)?;
+        let client_registration_start_result =
+            ClientRegistration::<Default>::start(&mut rng, b"password")?;
+        let server_registration_start_result = ServerRegistration::start(
+            &server_setup,
+            client_registration_start_result.message,
+            b"alice",
+        )?;
+        let client_registration_finish_result = client_registration_start_result.state.finish(
+            &mut rng,
+            server_registration_start_result.message,
+            ClientRegistrationFinishParameters::default(),
+        )?;
+        let password_file = ServerRegistration::finish(client_registration_finish_result.message);
+
+        let timings = measure_login_start(&server_setup, &password_file, b"alice", 8, [7u8; 32])?;
+        assert_eq!(timings.record_present.len(), 8);
+        assert_eq!(timings.record_absent.len(), 8);
+        let (present, absent) = timings.median_nanos();
+        assert!(present > 0 && absent > 0);
+        assert_eq!(
+            timings.median_cycles().is_some(),
+            cfg!(target_arch = "x86_64")
+        );
+
+        Ok(())
+    }
+}
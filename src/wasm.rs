@@ -0,0 +1,43 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Randomness for `wasm32-unknown-unknown` targets running in a browser or in
+//! Node.js
+//!
+//! This module is available when compiling for `wasm32` with the `std`
+//! feature, which enables the `js` backend of `getrandom`.
+
+use rand::{CryptoRng, Error, RngCore};
+
+/// An RNG drawing from the Web Crypto API (`crypto.getRandomValues`), which
+/// can be passed wherever this library expects an `rng`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WebCryptoRng;
+
+impl RngCore for WebCryptoRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        if let Err(e) = self.try_fill_bytes(dest) {
+            panic!("Web Crypto API is unavailable: {}", e);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        getrandom::getrandom(dest).map_err(|e| Error::from(e.code()))
+    }
+}
+
+impl CryptoRng for WebCryptoRng {}